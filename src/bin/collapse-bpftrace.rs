@@ -0,0 +1,80 @@
+use std::io;
+use std::path::PathBuf;
+
+use env_logger::Env;
+use inferno::collapse::bpftrace::{Folder, Options};
+use inferno::collapse::Collapse;
+use inferno::filter::FoldRecursion;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "inferno-collapse-bpftrace",
+    about,
+    after_help = "\
+[1] This processes the maps of stacks printed by bpftrace, such as those of
+            bpftrace -e 'profile:hz:99 { @[kstack, ustack, comm] = count(); }'"
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Map keys list the user stack before the kernel stack, as in @[ustack, kstack]
+    #[structopt(long = "user-stack-first")]
+    user_stack_first: bool,
+
+    /// Silence all log output
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: usize,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Merge recursive calls into one frame: only where a function calls itself (direct), or
+    /// also through other functions (cycles)
+    #[structopt(
+        long = "fold-recursion",
+        possible_values = &["direct", "cycles"],
+        value_name = "STRING"
+    )]
+    fold_recursion: Option<FoldRecursion>,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// bpftrace output file, or STDIN if not specified
+    #[structopt(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.fold_recursion = self.fold_recursion;
+        options.user_stack_first = self.user_stack_first;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::from_args();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infile, options) = opt.into_parts();
+    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
+}
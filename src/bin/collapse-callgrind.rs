@@ -0,0 +1,83 @@
+use std::io;
+use std::path::PathBuf;
+
+use env_logger::Env;
+use inferno::collapse::callgrind::{Folder, Options};
+use inferno::collapse::Collapse;
+use inferno::filter::FoldRecursion;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "inferno-collapse-callgrind",
+    about,
+    after_help = "\
+[1] This processes the callgrind.out.<pid> files written by
+            valgrind --tool=callgrind ./mybin
+    Callgrind doesn't record whole stacks, so they are rebuilt from the call graph. Functions
+    called from several places are assumed to cost the same, relative to their callees, in
+    all of them."
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Silence all log output
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: usize,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Merge recursive calls into one frame: only where a function calls itself (direct), or
+    /// also through other functions (cycles)
+    #[structopt(
+        long = "fold-recursion",
+        possible_values = &["direct", "cycles"],
+        value_name = "STRING"
+    )]
+    fold_recursion: Option<FoldRecursion>,
+
+    /// Event to use as the count, as named on the events: line (e.g. Ir, Dr) [default: the first]
+    #[structopt(long = "event", value_name = "NAME")]
+    event: Option<String>,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// callgrind output file, or STDIN if not specified
+    #[structopt(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.fold_recursion = self.fold_recursion;
+        options.event = self.event;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::from_args();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infile, options) = opt.into_parts();
+    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
+}
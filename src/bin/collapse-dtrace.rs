@@ -4,6 +4,7 @@ use std::path::PathBuf;
 use env_logger::Env;
 use inferno::collapse::dtrace::{Folder, Options};
 use inferno::collapse::{Collapse, DEFAULT_NTHREADS};
+use inferno::filter::FoldRecursion;
 use lazy_static::lazy_static;
 use structopt::StructOpt;
 
@@ -30,6 +31,10 @@ struct Opt {
     #[structopt(long = "includeoffset")]
     includeoffset: bool,
 
+    /// Include functions inlined at symbolized addresses as separate frames (see --symbol-path)
+    #[structopt(long = "inline")]
+    inline: bool,
+
     /// Silence all log output
     #[structopt(short = "q", long = "quiet")]
     quiet: bool,
@@ -41,6 +46,15 @@ struct Opt {
     // *************** //
     // *** OPTIONS *** //
     // *************** //
+    /// Merge recursive calls into one frame: only where a function calls itself (direct), or
+    /// also through other functions (cycles)
+    #[structopt(
+        long = "fold-recursion",
+        possible_values = &["direct", "cycles"],
+        value_name = "STRING"
+    )]
+    fold_recursion: Option<FoldRecursion>,
+
     /// Number of threads to use.
     #[structopt(
         short = "n",
@@ -50,6 +64,10 @@ struct Opt {
     )]
     nthreads: usize,
 
+    /// Binary, or directory of binaries, to symbolize unresolved frames with (repeatable)
+    #[structopt(long = "symbol-path", value_name = "PATH", number_of_values = 1)]
+    symbol_path: Vec<PathBuf>,
+
     // ************ //
     // *** ARGS *** //
     // ************ //
@@ -61,8 +79,11 @@ struct Opt {
 impl Opt {
     fn into_parts(self) -> (Option<PathBuf>, Options) {
         let mut options = Options::default();
+        options.fold_recursion = self.fold_recursion;
         options.includeoffset = self.includeoffset;
         options.nthreads = self.nthreads;
+        options.symbol_path = self.symbol_path;
+        options.symbolize_inlines = self.inline;
         (self.infile, options)
     }
 }
@@ -4,6 +4,7 @@ use std::path::PathBuf;
 use env_logger::Env;
 use inferno::collapse::guess::{Folder, Options};
 use inferno::collapse::{Collapse, DEFAULT_NTHREADS};
+use inferno::filter::FoldRecursion;
 use lazy_static::lazy_static;
 use structopt::StructOpt;
 
@@ -34,6 +35,15 @@ struct Opt {
     // *************** //
     // *** OPTIONS *** //
     // *************** //
+    /// Merge recursive calls into one frame: only where a function calls itself (direct), or
+    /// also through other functions (cycles)
+    #[structopt(
+        long = "fold-recursion",
+        possible_values = &["direct", "cycles"],
+        value_name = "STRING"
+    )]
+    fold_recursion: Option<FoldRecursion>,
+
     /// Number of threads to use
     #[structopt(
         short = "n",
@@ -54,6 +64,7 @@ struct Opt {
 impl Opt {
     fn into_parts(self) -> (Option<PathBuf>, Options) {
         let mut options = Options::default();
+        options.fold_recursion = self.fold_recursion;
         options.nthreads = self.nthreads;
         (self.infile, options)
     }
@@ -0,0 +1,154 @@
+use std::io;
+use std::path::PathBuf;
+
+use env_logger::Env;
+use inferno::collapse::perf_data::{Folder, Options};
+use inferno::collapse::{Collapse, DEFAULT_NTHREADS};
+use inferno::filter::FoldRecursion;
+use lazy_static::lazy_static;
+use structopt::StructOpt;
+
+lazy_static! {
+    static ref NTHREADS: String = format!("{}", *DEFAULT_NTHREADS);
+}
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "inferno-collapse-perf-data",
+    about,
+    after_help = "\
+[1] This reads the perf.data file written by perf record directly, without perf script:
+        perf record -g -- ./program
+        inferno-collapse-perf-data --kallsyms /proc/kallsyms perf.data
+    Binaries are symbolized from their symbol tables, so they must not have been stripped.
+    Use --symfs when the profile was recorded on a different machine."
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Include raw addresses where symbols can't be found
+    #[structopt(long = "addrs")]
+    addrs: bool,
+
+    /// All annotations (--kernel --jit)
+    #[structopt(long = "all")]
+    all: bool,
+
+    /// Annotate jit functions with a `_[j]`
+    #[structopt(long = "jit")]
+    jit: bool,
+
+    /// Annotate kernel functions with a `_[k]`
+    #[structopt(long = "kernel")]
+    kernel: bool,
+
+    /// Weight samples by their event period instead of counting each one once
+    #[structopt(long = "period", alias = "weighted")]
+    period: bool,
+
+    /// Include PID with process names
+    #[structopt(long = "pid")]
+    pid: bool,
+
+    /// Include TID and PID with process names
+    #[structopt(long = "tid")]
+    tid: bool,
+
+    /// Silence all log output
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: usize,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Event filter [default: first encountered event]
+    #[structopt(long = "event-filter", value_name = "STRING")]
+    event_filter: Option<String>,
+
+    /// Merge recursive calls into one frame: only where a function calls itself (direct), or
+    /// also through other functions (cycles)
+    #[structopt(
+        long = "fold-recursion",
+        possible_values = &["direct", "cycles"],
+        value_name = "STRING"
+    )]
+    fold_recursion: Option<FoldRecursion>,
+
+    /// Copy of /proc/kallsyms used to name kernel frames
+    #[structopt(long = "kallsyms", value_name = "PATH")]
+    kallsyms: Option<PathBuf>,
+
+    /// Number of threads to use
+    #[structopt(
+        short = "n",
+        long = "nthreads",
+        default_value = &NTHREADS,
+        value_name = "UINT"
+    )]
+    nthreads: usize,
+
+    /// Directory of perf-<pid>.map files to name unresolved JIT frames with
+    #[structopt(long = "perf-map-dir", value_name = "DIR")]
+    perf_map_dir: Option<PathBuf>,
+
+    /// Look for binaries relative to this directory
+    #[structopt(long = "symfs", value_name = "DIR")]
+    symfs: Option<PathBuf>,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    #[structopt(value_name = "PATH")]
+    /// perf.data file, or STDIN if not specified
+    infile: Option<PathBuf>,
+
+    #[structopt(long = "skip-after", value_name = "STRING")]
+    /// If set, will omit all the parent stack frames of the frame with matched function name.
+    ///
+    /// Has no effect on the stack trace if no function is matched.
+    skip_after: Option<String>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.perf.fold_recursion = self.fold_recursion;
+        options.perf.include_pid = self.pid;
+        options.perf.include_tid = self.tid;
+        options.perf.include_addrs = self.addrs;
+        options.perf.annotate_jit = self.jit || self.all;
+        options.perf.annotate_kernel = self.kernel || self.all;
+        options.perf.event_filter = self.event_filter;
+        options.perf.nthreads = self.nthreads;
+        options.perf.skip_after = self.skip_after;
+        options.perf.use_period = self.period;
+        options.perf.perf_map_dir = self.perf_map_dir;
+        options.symfs = self.symfs;
+        options.kallsyms = self.kallsyms;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::from_args();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infile, options) = opt.into_parts();
+    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
+}
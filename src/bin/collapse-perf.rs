@@ -4,6 +4,7 @@ use std::path::PathBuf;
 use env_logger::Env;
 use inferno::collapse::perf::{Folder, Options};
 use inferno::collapse::{Collapse, DEFAULT_NTHREADS};
+use inferno::filter::FoldRecursion;
 use lazy_static::lazy_static;
 use structopt::StructOpt;
 
@@ -34,6 +35,10 @@ struct Opt {
     #[structopt(long = "all")]
     all: bool,
 
+    /// Include functions inlined at symbolized addresses as separate frames (see --symbol-path)
+    #[structopt(long = "inline")]
+    inline: bool,
+
     /// Annotate jit functions with a `_[j]`
     #[structopt(long = "jit")]
     jit: bool,
@@ -42,6 +47,10 @@ struct Opt {
     #[structopt(long = "kernel")]
     kernel: bool,
 
+    /// Weight samples by their event period instead of counting each one once
+    #[structopt(long = "period", alias = "weighted")]
+    period: bool,
+
     /// Include PID with process names
     #[structopt(long = "pid")]
     pid: bool,
@@ -65,6 +74,15 @@ struct Opt {
     #[structopt(long = "event-filter", value_name = "STRING")]
     event_filter: Option<String>,
 
+    /// Merge recursive calls into one frame: only where a function calls itself (direct), or
+    /// also through other functions (cycles)
+    #[structopt(
+        long = "fold-recursion",
+        possible_values = &["direct", "cycles"],
+        value_name = "STRING"
+    )]
+    fold_recursion: Option<FoldRecursion>,
+
     /// Number of threads to use
     #[structopt(
         short = "n",
@@ -74,6 +92,14 @@ struct Opt {
     )]
     nthreads: usize,
 
+    /// Directory of perf-<pid>.map files to name unresolved JIT frames with (not jitted-*.so files; use --symbol-path for those)
+    #[structopt(long = "perf-map-dir", value_name = "DIR")]
+    perf_map_dir: Option<PathBuf>,
+
+    /// Binary, or directory of binaries, to symbolize unresolved frames with (repeatable)
+    #[structopt(long = "symbol-path", value_name = "PATH", number_of_values = 1)]
+    symbol_path: Vec<PathBuf>,
+
     // ************ //
     // *** ARGS *** //
     // ************ //
@@ -91,6 +117,7 @@ struct Opt {
 impl Opt {
     fn into_parts(self) -> (Option<PathBuf>, Options) {
         let mut options = Options::default();
+        options.fold_recursion = self.fold_recursion;
         options.include_pid = self.pid;
         options.include_tid = self.tid;
         options.include_addrs = self.addrs;
@@ -99,6 +126,10 @@ impl Opt {
         options.event_filter = self.event_filter;
         options.nthreads = self.nthreads;
         options.skip_after = self.skip_after;
+        options.use_period = self.period;
+        options.perf_map_dir = self.perf_map_dir;
+        options.symbol_path = self.symbol_path;
+        options.symbolize_inlines = self.inline;
         (self.infile, options)
     }
 }
@@ -3,9 +3,15 @@ use std::path::PathBuf;
 
 use env_logger::Env;
 use inferno::collapse::sample::{Folder, Options};
-use inferno::collapse::Collapse;
+use inferno::collapse::{Collapse, DEFAULT_NTHREADS};
+use inferno::filter::FoldRecursion;
+use lazy_static::lazy_static;
 use structopt::StructOpt;
 
+lazy_static! {
+    static ref NTHREADS: String = format!("{}", *DEFAULT_NTHREADS);
+}
+
 #[derive(Debug, StructOpt)]
 #[structopt(
     name = "inferno-collapse-sample",
@@ -30,6 +36,27 @@ struct Opt {
     #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
     verbose: usize,
 
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Merge recursive calls into one frame: only where a function calls itself (direct), or
+    /// also through other functions (cycles)
+    #[structopt(
+        long = "fold-recursion",
+        possible_values = &["direct", "cycles"],
+        value_name = "STRING"
+    )]
+    fold_recursion: Option<FoldRecursion>,
+
+    /// Number of threads to use.
+    #[structopt(
+        short = "n",
+        long = "nthreads",
+        default_value = &NTHREADS,
+        value_name = "UINT"
+    )]
+    nthreads: usize,
+
     // ************ //
     // *** ARGS *** //
     // ************ //
@@ -41,7 +68,9 @@ struct Opt {
 impl Opt {
     fn into_parts(self) -> (Option<PathBuf>, Options) {
         let mut options = Options::default();
+        options.fold_recursion = self.fold_recursion;
         options.no_modules = self.no_modules;
+        options.nthreads = self.nthreads;
         (self.infile, options)
     }
 }
@@ -0,0 +1,132 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use env_logger::Env;
+use inferno::differential::{self, Options};
+use inferno::flamegraph;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "inferno-diff-multi",
+    about,
+    after_help = "\
+Compares a baseline folded stack profile against several variants of it, such
+as different builds of the same program. Writes a differential flame graph for
+each variant to the output directory, named after the variant file, and prints
+the stacks with the largest absolute and relative changes in each.
+
+  $ inferno-diff-multi -o diffs base.folded build-b.folded build-c.folded
+
+Frames are red where a variant has more samples than the baseline, and blue
+where it has fewer. Frame widths are based on the variant.
+
+You can use the inferno-collapse-* tools to generate the folded files."
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Normalize the baseline sample counts to each variant
+    #[structopt(short = "n", long = "normalize")]
+    normalize: bool,
+
+    /// Strip hex numbers (addresses)
+    #[structopt(short = "s", long = "strip-hex")]
+    strip_hex: bool,
+
+    /// Silence all log output
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: usize,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Directory to write the flame graphs to
+    #[structopt(
+        short = "o",
+        long = "output-dir",
+        value_name = "DIR",
+        default_value = ".",
+        parse(from_os_str)
+    )]
+    output_dir: PathBuf,
+
+    /// Number of stacks to list for each kind of change
+    #[structopt(long = "top", value_name = "UINT", default_value = "10")]
+    top: usize,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Path to the baseline folded stack profile
+    #[structopt(value_name = "BASELINE", parse(from_os_str))]
+    baseline: PathBuf,
+
+    /// Paths to the folded stack profiles to compare against the baseline
+    #[structopt(value_name = "VARIANT", parse(from_os_str), required = true)]
+    variants: Vec<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (PathBuf, Vec<PathBuf>, PathBuf, usize, Options) {
+        (
+            self.baseline,
+            self.variants,
+            self.output_dir,
+            self.top,
+            Options {
+                normalize: self.normalize,
+                strip_hex: self.strip_hex,
+            },
+        )
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::from_args();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (baseline, variants, output_dir, top, options) = opt.into_parts();
+
+    let mut comparisons = differential::compare_files(options, &baseline, &variants)?;
+    fs::create_dir_all(&output_dir)?;
+    for i in 0..comparisons.len() {
+        // Variants in different directories can have the same file name.
+        let name = &comparisons[i].name;
+        if comparisons[..i].iter().any(|c| &c.name == name) {
+            comparisons[i].name = format!("{}-{}", name, i + 1);
+        }
+
+        let comparison = &comparisons[i];
+        let mut flamegraph_options = flamegraph::Options::default();
+        flamegraph_options.title = format!("Differential: {}", comparison.name);
+        flamegraph_options.subtitle = Some(format!("Baseline: {}", baseline.display()));
+        let path = output_dir.join(format!("{}.svg", comparison.name));
+        let file = io::BufWriter::new(File::create(&path)?);
+        comparison
+            .write_flamegraph(&mut flamegraph_options, file)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    }
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    differential::write_summary(&comparisons, top, &mut stdout)?;
+    stdout.flush()
+}
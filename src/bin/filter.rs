@@ -0,0 +1,108 @@
+use std::io;
+use std::path::PathBuf;
+
+use env_logger::Env;
+use inferno::filter::{self, Filter, Rule};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "inferno-filter",
+    about,
+    after_help = "\
+Applies rules to each line of folded stack files, in order. Each rule is its
+name followed by its argument:
+
+  keep <REGEX>                    only keep stacks that match
+  drop <REGEX>                    drop stacks that match
+  drop-frames <REGEX>             remove the frames that match
+  cut-above <REGEX>               remove the frames called by the first match
+  cut-below <REGEX>               remove the frames calling the last match
+  replace <REGEX> <REPLACEMENT>   replace matches in each frame
+  max-depth <UINT>                remove frames deeper than this
+  fold-recursion direct|cycles    merge recursive calls into one frame
+
+Rules from --rule-file are applied before those given with --rule.
+
+  $ inferno-filter -r 'drop ^swapper' -r 'drop-frames ^std::' stacks.folded
+
+You can use the inferno-collapse-* tools to generate the folded files."
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Silence all log output
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: usize,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Rule to apply, e.g. 'drop ^idle'
+    #[structopt(short = "r", long = "rule", value_name = "RULE", number_of_values = 1)]
+    rules: Vec<Rule>,
+
+    /// File with one rule per line. Empty lines and lines starting with # are ignored.
+    #[structopt(
+        short = "f",
+        long = "rule-file",
+        value_name = "PATH",
+        parse(from_os_str),
+        number_of_values = 1
+    )]
+    rule_files: Vec<PathBuf>,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Folded stack files. With no PATH, or PATH is -, read STDIN.
+    #[structopt(name = "PATH", parse(from_os_str))]
+    infiles: Vec<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> io::Result<(Vec<PathBuf>, Filter)> {
+        let mut filter = Filter::default();
+        for path in &self.rule_files {
+            let rules = Filter::from_file(path).map_err(|e| {
+                io::Error::new(e.kind(), format!("Error reading {}: {}", path.display(), e))
+            })?;
+            for rule in rules.rules() {
+                filter.push(rule.clone());
+            }
+        }
+        for rule in self.rules {
+            filter.push(rule);
+        }
+        Ok((self.infiles, filter))
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::from_args();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infiles, filter) = opt.into_parts()?;
+
+    if atty::is(atty::Stream::Stdout) {
+        filter::from_files(&filter, &infiles, io::stdout().lock())
+    } else {
+        filter::from_files(&filter, &infiles, io::BufWriter::new(io::stdout().lock()))
+    }
+}
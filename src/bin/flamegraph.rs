@@ -2,8 +2,12 @@ use std::io;
 use std::path::{Path, PathBuf};
 
 use env_logger::Env;
+use inferno::filter::FoldRecursion;
 use inferno::flamegraph::color::{BackgroundColor, PaletteMap, SearchColor};
-use inferno::flamegraph::{self, defaults, Direction, Options, Palette, TextTruncateDirection};
+use inferno::flamegraph::{
+    self, defaults, DifferentialColors, Direction, Options, OutputFormat, Palette,
+    TextTruncateDirection,
+};
 
 #[cfg(feature = "nameattr")]
 use inferno::flamegraph::FuncFrameAttrsMap;
@@ -54,7 +58,7 @@ struct Opt {
     #[structopt(name = "no-sort", long = "no-sort")]
     no_sort: bool,
 
-    /// Pretty print XML with newlines and indentation.
+    /// Pretty print XML and JSON output with newlines and indentation.
     #[structopt(long = "pretty-xml")]
     pretty_xml: bool,
 
@@ -91,6 +95,16 @@ struct Opt {
     #[structopt(long = "colordiffusion", conflicts_with = "colors")]
     color_diffusion: bool,
 
+    /// Whether to color text output: always, never, or auto, which colors it when printing to a
+    /// terminal and NO_COLOR isn't set
+    #[structopt(
+        long = "color",
+        default_value = "auto",
+        possible_values = &["auto", "always", "never"],
+        value_name = "WHEN"
+    )]
+    color: String,
+
     /// Count type label
     #[structopt(
         long = "countname",
@@ -99,6 +113,16 @@ struct Opt {
     )]
     countname: String,
 
+    /// Color differential frames by the change in their self samples (self), in their samples
+    /// including callees (absolute), or by the ratio of their samples including callees (ratio)
+    #[structopt(
+        long = "diff-colors",
+        default_value = "self",
+        possible_values = &["self", "absolute", "ratio"],
+        value_name = "STRING"
+    )]
+    diff_colors: DifferentialColors,
+
     /// Factor to scale sample counts by
     #[structopt(
         long = "factor",
@@ -107,6 +131,20 @@ struct Opt {
     )]
     factor: f64,
 
+    /// Show the callers of functions matching <STRING> (a name or regex) merged below them, and
+    /// their callees above them
+    #[structopt(long = "focus", value_name = "STRING")]
+    focus: Option<String>,
+
+    /// Merge recursive calls into one frame: only where a function calls itself (direct), or
+    /// also through other functions (cycles)
+    #[structopt(
+        long = "fold-recursion",
+        possible_values = &["direct", "cycles"],
+        value_name = "STRING"
+    )]
+    fold_recursion: Option<FoldRecursion>,
+
     /// Font size
     #[structopt(
         long = "fontsize",
@@ -131,6 +169,16 @@ struct Opt {
     )]
     fontwidth: f64,
 
+    /// Output format: an svg image, an html page, d3-flame-graph (d3) or speedscope json, or text
+    /// for terminals
+    #[structopt(
+        long = "format",
+        default_value = defaults::FORMAT,
+        possible_values = &["svg", "html", "d3", "speedscope", "text"],
+        value_name = "STRING"
+    )]
+    format: OutputFormat,
+
     /// Height of each frame
     #[structopt(
         long = "height",
@@ -174,6 +222,10 @@ struct Opt {
     )]
     search_color: SearchColor,
 
+    /// Only color differential frames whose change has a p-value below this, e.g. 0.05
+    #[structopt(long = "significance", value_name = "FLOAT")]
+    significance: Option<f64>,
+
     /// Second level title (optional)
     #[structopt(long = "subtitle", value_name = "STRING")]
     subtitle: Option<String>,
@@ -186,7 +238,7 @@ struct Opt {
     )]
     title: String,
 
-    /// Width of image
+    /// Width of image, or columns of text output (defaults to the terminal width)
     #[structopt(long = "width", value_name = "UINT")]
     width: Option<usize>,
 
@@ -227,6 +279,8 @@ impl<'a> Opt {
             options.text_truncate_direction = TextTruncateDirection::Right;
         }
         options.negate_differentials = self.negate;
+        options.significance = self.significance;
+        options.differential_colors = self.diff_colors;
         options.factor = self.factor;
         options.pretty_xml = self.pretty_xml;
         options.no_sort = self.no_sort;
@@ -234,6 +288,9 @@ impl<'a> Opt {
         options.color_diffusion = self.color_diffusion;
         options.reverse_stack_order = self.reverse;
         options.flame_chart = self.flame_chart;
+        options.focus = self.focus;
+        options.fold_recursion = self.fold_recursion;
+        options.format = self.format;
 
         if self.flame_chart && self.title == defaults::TITLE {
             options.title = defaults::CHART_TITLE.to_owned();
@@ -296,10 +353,24 @@ fn main() -> quick_xml::Result<()> {
         Err(e) => panic!("Error reading {}: {:?}", PALETTE_MAP_FILE, e),
     };
 
+    let color = opt.color.clone();
     let (infiles, mut options) = opt.into_parts();
 
     options.palette_map = palette_map.as_mut();
 
+    // Fit text flame graphs to the terminal they're printed to.
+    if options.format == OutputFormat::Text && options.image_width.is_none() {
+        options.image_width = terminal_size::terminal_size().map(|(width, _)| width.0 as usize);
+    }
+    options.no_text_colors = match &*color {
+        "always" => false,
+        "never" => true,
+        _ => {
+            !atty::is(atty::Stream::Stdout)
+                || std::env::var_os("NO_COLOR").map_or(false, |value| !value.is_empty())
+        }
+    };
+
     if atty::is(atty::Stream::Stdout) {
         flamegraph::from_files(&mut options, &infiles, io::stdout().lock())?;
     } else {
@@ -342,7 +413,10 @@ fn save_consistent_palette_if_needed(
 #[cfg(test)]
 mod tests {
     use super::Opt;
-    use inferno::flamegraph::{color, Direction, Options, Palette, TextTruncateDirection};
+    use inferno::filter::FoldRecursion;
+    use inferno::flamegraph::{
+        color, DifferentialColors, Direction, Options, OutputFormat, Palette, TextTruncateDirection,
+    };
     use pretty_assertions::assert_eq;
     use std::path::PathBuf;
     use std::str::FromStr;
@@ -370,6 +444,8 @@ mod tests {
             "--cp",
             "--search-color",
             "#203040",
+            "--significance",
+            "0.01",
             "--title",
             "Test Title",
             "--subtitle",
@@ -386,6 +462,8 @@ mod tests {
             "13",
             "--fontwidth",
             "10.5",
+            "--format",
+            "html",
             "--countname",
             "test count name",
             "--nametype",
@@ -393,8 +471,14 @@ mod tests {
             "--notes",
             "Test notes",
             "--negate",
+            "--diff-colors",
+            "ratio",
             "--factor",
             "0.1",
+            "--focus",
+            "main",
+            "--fold-recursion",
+            "cycles",
             "--pretty-xml",
             "--reverse",
             "--no-javascript",
@@ -417,17 +501,22 @@ mod tests {
         expected_options.count_name = "test count name".to_string();
         expected_options.name_type = "test name type".to_string();
         expected_options.factor = 0.1;
+        expected_options.focus = Some("main".to_string());
+        expected_options.fold_recursion = Some(FoldRecursion::Cycles);
         expected_options.notes = "Test notes".to_string();
         expected_options.subtitle = Some("Test Subtitle".to_string());
         expected_options.bgcolors = Some(color::BackgroundColor::Blue);
         expected_options.hash = true;
         expected_options.direction = Direction::Inverted;
         expected_options.negate_differentials = true;
+        expected_options.significance = Some(0.01);
+        expected_options.differential_colors = DifferentialColors::Ratio;
         expected_options.pretty_xml = true;
         expected_options.no_sort = false;
         expected_options.reverse_stack_order = true;
         expected_options.no_javascript = true;
         expected_options.color_diffusion = false;
+        expected_options.format = OutputFormat::Html;
 
         assert_eq!(options, expected_options);
         assert_eq!(infiles.len(), 2, "expected 2 input files");
@@ -0,0 +1,158 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use env_logger::Env;
+use inferno::merge::{self, Input, Options};
+use structopt::clap::{Error, ErrorKind};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "inferno-merge",
+    about,
+    after_help = "\
+Merges folded stack profiles, for example taken on several hosts, into one by
+summing the sample counts of identical stacks. The output is sorted by stack.
+
+  $ inferno-merge --label --normalize host1.folded host2.folded | inferno-flamegraph > all.svg
+
+Each --weight applies to the PATH in the same position, and its counts are
+multiplied by it (after --normalize, if given).
+
+You can use the inferno-collapse-* tools to generate the folded files."
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Add the file name of each input as the bottom frame of its stacks
+    #[structopt(short = "l", long = "label")]
+    label: bool,
+
+    /// Scale each input to the same total sample count
+    #[structopt(short = "n", long = "normalize")]
+    normalize: bool,
+
+    /// Strip hex numbers (addresses)
+    #[structopt(short = "s", long = "strip-hex")]
+    strip_hex: bool,
+
+    /// Silence all log output
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: usize,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Factor to multiply the counts of each input by, given once per PATH
+    #[structopt(
+        short = "w",
+        long = "weight",
+        value_name = "FLOAT",
+        number_of_values = 1
+    )]
+    weights: Vec<f64>,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Folded stack files. With no PATH, or PATH is -, read STDIN.
+    #[structopt(name = "PATH", parse(from_os_str))]
+    infiles: Vec<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Vec<Input<PathBuf>>, Options) {
+        let Opt {
+            label: use_labels,
+            normalize,
+            strip_hex,
+            weights,
+            mut infiles,
+            ..
+        } = self;
+        if infiles.is_empty() {
+            infiles.push(PathBuf::from("-"));
+        }
+        if !weights.is_empty() && weights.len() != infiles.len() {
+            Error::with_description(
+                &format!(
+                    "got {} --weight values for {} inputs",
+                    weights.len(),
+                    infiles.len()
+                ),
+                ErrorKind::WrongNumberOfValues,
+            )
+            .exit();
+        }
+
+        let inputs = infiles
+            .into_iter()
+            .enumerate()
+            .map(|(i, path)| {
+                let mut input = Input::new(path);
+                if let Some(&weight) = weights.get(i) {
+                    input.weight = weight;
+                }
+                if use_labels {
+                    input.label = Some(label(&input.source));
+                }
+                input
+            })
+            .collect();
+        (
+            inputs,
+            Options {
+                normalize,
+                strip_hex,
+            },
+        )
+    }
+}
+
+// The file name without compression or folded file extensions, so host1.folded.gz becomes host1.
+fn label(path: &Path) -> String {
+    if path == Path::new("-") {
+        return "stdin".to_string();
+    }
+    let mut path = path.to_path_buf();
+    while let Some(ext) = path.extension() {
+        match ext.to_str() {
+            Some("gz" | "zst" | "xz" | "folded" | "txt") => {
+                path.set_extension("");
+            }
+            _ => break,
+        }
+    }
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::from_args();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (inputs, options) = opt.into_parts();
+
+    if atty::is(atty::Stream::Stdout) {
+        merge::from_files(options, &inputs, io::stdout().lock())
+    } else {
+        merge::from_files(options, &inputs, io::BufWriter::new(io::stdout().lock()))
+    }
+}
@@ -0,0 +1,108 @@
+use std::io;
+use std::path::PathBuf;
+
+use env_logger::Env;
+use inferno::report::{self, Format, Options, SortBy};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "inferno-report",
+    about,
+    after_help = "\
+Ranks the functions in folded stack profiles by their self samples (in which
+the function itself was running) or total samples (in which it was anywhere
+on the stack). Recursive functions are counted once per sample.
+
+  $ inferno-report --top 20 --sort total --callers stacks.folded
+
+You can use the inferno-collapse-* tools to generate the folded files."
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// List the functions each function called
+    #[structopt(long = "callees")]
+    callees: bool,
+
+    /// List the functions each function was called from
+    #[structopt(long = "callers")]
+    callers: bool,
+
+    /// Silence all log output
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: usize,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Output format
+    #[structopt(
+        long = "format",
+        default_value = "text",
+        possible_values = &["text", "csv", "json"],
+        value_name = "STRING"
+    )]
+    format: Format,
+
+    /// Rank functions by self or total samples
+    #[structopt(
+        long = "sort",
+        default_value = "self",
+        possible_values = &["self", "total"],
+        value_name = "STRING"
+    )]
+    sort: SortBy,
+
+    /// Only report the first <UINT> functions
+    #[structopt(long = "top", value_name = "UINT")]
+    top: Option<usize>,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Folded stack files. With no PATH, or PATH is -, read STDIN.
+    #[structopt(name = "PATH", parse(from_os_str))]
+    infiles: Vec<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Vec<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.format = self.format;
+        options.sort_by = self.sort;
+        options.top = self.top;
+        options.callers = self.callers;
+        options.callees = self.callees;
+        (self.infiles, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::from_args();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infiles, options) = opt.into_parts();
+
+    if atty::is(atty::Stream::Stdout) {
+        report::from_files(&options, &infiles, io::stdout().lock())
+    } else {
+        report::from_files(&options, &infiles, io::BufWriter::new(io::stdout().lock()))
+    }
+}
@@ -0,0 +1,825 @@
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::PathBuf;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{
+    Attribute, Color as TermColor, Print, ResetColor, SetAttribute, SetBackgroundColor,
+    SetForegroundColor,
+};
+use crossterm::{cursor, queue, terminal};
+use env_logger::Env;
+use inferno::collapse::guess;
+use inferno::collapse::{Collapse, DEFAULT_NTHREADS};
+use inferno::flamegraph::{self, defaults, Direction, Frame, Options, Palette};
+use lazy_static::lazy_static;
+use regex::Regex;
+use structopt::StructOpt;
+
+lazy_static! {
+    static ref NTHREADS: String = format!("{}", *DEFAULT_NTHREADS);
+}
+
+/// The color of frames that match the search, which is the default search color of SVGs.
+const SEARCH_COLOR: TermColor = TermColor::Rgb {
+    r: 230,
+    g: 0,
+    b: 230,
+};
+
+const HELP: &str = "arrows: move  enter: zoom  backspace: zoom out  esc: reset zoom  \
+                    /: search  n/N: next/previous match  i: invert  r: reverse  q: quit";
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "inferno-tui",
+    about,
+    after_help = "\
+[1] Input that isn't folded stacks is collapsed with inferno-collapse-guess first.
+                  "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Colors are selected by hashing the function name, weighting earlier characters more
+    /// heavily
+    #[structopt(long = "hash")]
+    hash: bool,
+
+    /// Plot the flame graph up-side-down
+    #[structopt(short = "i", long = "inverted")]
+    inverted: bool,
+
+    /// Switch differential hues (green<->red)
+    #[structopt(long = "negate")]
+    negate: bool,
+
+    /// Silence all log output
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// Generate stack-reversed flame graph
+    #[structopt(long = "reverse")]
+    reverse: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: usize,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Set color palette
+    #[structopt(
+        short = "c",
+        long = "colors",
+        default_value = defaults::COLORS,
+        possible_values = &["aqua","blue","green","hot","io","java","js","mem","orange","perl","purple","red","rust","wakeup","yellow"],
+        value_name = "STRING"
+    )]
+    colors: Palette,
+
+    /// Number of threads to use when collapsing input
+    #[structopt(
+        short = "n",
+        long = "nthreads",
+        default_value = &NTHREADS,
+        value_name = "UINT"
+    )]
+    nthreads: usize,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Folded stacks, or any other input inferno-collapse-guess accepts [1]. Reads STDIN if not
+    /// specified
+    #[structopt(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl<'a> Opt {
+    fn into_parts(self) -> (Option<PathBuf>, usize, Options<'a>) {
+        let mut options = Options::default();
+        options.colors = self.colors;
+        options.hash = self.hash;
+        if self.inverted {
+            options.direction = Direction::Inverted;
+        }
+        options.negate_differentials = self.negate;
+        options.reverse_stack_order = self.reverse;
+        // Frames that are too narrow to draw can still be zoomed into.
+        options.min_width = 0.0;
+        (self.infile, self.nthreads, options)
+    }
+}
+
+/// Copies input as is, so that folded stacks can be read (and decompressed) like other input.
+struct Passthrough;
+
+impl Collapse for Passthrough {
+    fn collapse<R, W>(&mut self, mut reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        io::copy(&mut reader, &mut writer)?;
+        Ok(())
+    }
+
+    fn is_applicable(&mut self, _input: &str) -> Option<bool> {
+        Some(true)
+    }
+}
+
+/// Reads the input and collapses it, unless it already is folded stacks.
+fn read_folded(infile: Option<&PathBuf>, nthreads: usize) -> io::Result<String> {
+    let mut input = Vec::new();
+    Passthrough.collapse_file(infile, &mut input)?;
+    if looks_folded(&String::from_utf8_lossy(&input)) {
+        return String::from_utf8(input).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+    }
+
+    let mut options = guess::Options::default();
+    options.nthreads = nthreads;
+    let mut folded = Vec::new();
+    guess::Folder::from(options).collapse(&input[..], &mut folded)?;
+    String::from_utf8(folded).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Whether the first lines of `input` look like folded stacks, which end with a sample count
+/// (or two, for differential input).
+fn looks_folded(input: &str) -> bool {
+    let is_count = |s: &str| s.parse::<f64>().is_ok();
+    let mut lines = input
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .take(10);
+    let mut any = false;
+    let all = lines.all(|line| {
+        any = true;
+        match line.trim().rsplit_once(' ') {
+            Some((rest, count)) if is_count(count) => {
+                let rest = rest.trim_end();
+                match rest.rsplit_once(' ') {
+                    Some((stack, count)) if is_count(count) => !stack.trim().is_empty(),
+                    _ => !rest.is_empty(),
+                }
+            }
+            _ => false,
+        }
+    });
+    any && all
+}
+
+/// What to do after a key was pressed.
+#[derive(Debug, PartialEq)]
+enum Action {
+    Continue,
+    Quit,
+}
+
+/// The state of the flame graph browser.
+struct App<'a> {
+    folded: String,
+    options: Options<'a>,
+    /// Ordered by depth, then by start.
+    frames: Vec<Frame>,
+    /// The range of `frames` at each depth.
+    rows: Vec<Range<usize>>,
+    /// The number of samples of each frame that aren't in any of its callees.
+    self_samples: Vec<usize>,
+    zoomed: usize,
+    selected: usize,
+    search: Option<Regex>,
+    /// The search being typed, if any.
+    prompt: Option<String>,
+    message: Option<String>,
+    /// The number of columns the frames were last drawn in.
+    width: usize,
+    /// The lowest depth on screen.
+    scroll: usize,
+}
+
+impl<'a> App<'a> {
+    fn new(folded: String, options: Options<'a>) -> quick_xml::Result<Self> {
+        let mut app = App {
+            folded,
+            options,
+            frames: Vec::new(),
+            rows: Vec::new(),
+            self_samples: Vec::new(),
+            zoomed: 0,
+            selected: 0,
+            search: None,
+            prompt: None,
+            message: None,
+            width: 80,
+            scroll: 0,
+        };
+        app.relayout()?;
+        Ok(app)
+    }
+
+    fn relayout(&mut self) -> quick_xml::Result<()> {
+        self.frames = flamegraph::layout(&mut self.options, self.folded.lines())?;
+
+        self.rows.clear();
+        for (i, frame) in self.frames.iter().enumerate() {
+            if frame.depth == self.rows.len() {
+                self.rows.push(i..i);
+            }
+            self.rows[frame.depth].end = i + 1;
+        }
+
+        self.self_samples = self.frames.iter().map(|f| f.end - f.start).collect();
+        for i in 0..self.frames.len() {
+            if let Some(parent) = self.parent(i) {
+                let frame = &self.frames[i];
+                self.self_samples[parent] -= frame.end - frame.start;
+            }
+        }
+
+        self.zoomed = 0;
+        self.selected = 0;
+        self.scroll = 0;
+        Ok(())
+    }
+
+    /// The frame `i` was called from.
+    fn parent(&self, i: usize) -> Option<usize> {
+        let frame = &self.frames[i];
+        let row = self.rows[frame.depth.checked_sub(1)?].clone();
+        let before = self.frames[row.clone()].partition_point(|f| f.start <= frame.start);
+        Some(row.start + before - 1)
+    }
+
+    /// Whether frame `i` is one the zoomed frame was called from, which are drawn full width.
+    fn is_zoom_ancestor(&self, i: usize) -> bool {
+        let (frame, zoomed) = (&self.frames[i], &self.frames[self.zoomed]);
+        frame.depth < zoomed.depth && frame.start <= zoomed.start && frame.end >= zoomed.end
+    }
+
+    /// The columns frame `i` is drawn in, if it's visible at all.
+    fn columns(&self, i: usize) -> Option<Range<usize>> {
+        if self.is_zoom_ancestor(i) {
+            return Some(0..self.width);
+        }
+        let (frame, zoomed) = (&self.frames[i], &self.frames[self.zoomed]);
+        if frame.depth < zoomed.depth || frame.start < zoomed.start || frame.end > zoomed.end {
+            return None;
+        }
+        let span = zoomed.end - zoomed.start;
+        let start = (frame.start - zoomed.start) * self.width / span;
+        let end = (frame.end - zoomed.start) * self.width / span;
+        if start == end {
+            None
+        } else {
+            Some(start..end)
+        }
+    }
+
+    fn is_match(&self, i: usize) -> bool {
+        match self.search {
+            Some(ref re) => re.is_match(&self.frames[i].name),
+            None => false,
+        }
+    }
+
+    fn select_parent(&mut self) {
+        if let Some(parent) = self.parent(self.selected) {
+            self.selected = parent;
+        }
+    }
+
+    /// Selects the widest callee of the selected frame, or the next frame towards the zoomed
+    /// frame if the selected frame is drawn full width.
+    fn select_child(&mut self) {
+        let depth = self.frames[self.selected].depth + 1;
+        let row = match self.rows.get(depth) {
+            Some(row) => row.clone(),
+            None => return,
+        };
+        let child = if self.is_zoom_ancestor(self.selected) {
+            let zoomed = &self.frames[self.zoomed];
+            let before = self.frames[row.clone()].partition_point(|f| f.start <= zoomed.start);
+            Some(row.start + before - 1)
+        } else {
+            let selected = &self.frames[self.selected];
+            row.filter(|&i| {
+                let frame = &self.frames[i];
+                frame.start >= selected.start
+                    && frame.end <= selected.end
+                    && self.columns(i).is_some()
+            })
+            .max_by_key(|&i| {
+                let frame = &self.frames[i];
+                // Prefer the leftmost of equally wide callees.
+                (frame.end - frame.start, std::cmp::Reverse(i))
+            })
+        };
+        if let Some(child) = child {
+            self.selected = child;
+        }
+    }
+
+    /// Selects the closest visible frame to the left or right at the same depth.
+    fn select_sibling(&mut self, right: bool) {
+        let row = self.rows[self.frames[self.selected].depth].clone();
+        let sibling = if right {
+            (self.selected + 1..row.end).find(|&i| self.columns(i).is_some())
+        } else {
+            (row.start..self.selected)
+                .rev()
+                .find(|&i| self.columns(i).is_some())
+        };
+        if let Some(sibling) = sibling {
+            self.selected = sibling;
+        }
+    }
+
+    /// Selects the next or previous visible frame that matches the search.
+    fn select_match(&mut self, forward: bool) {
+        let n = self.frames.len();
+        let found = (1..n)
+            .map(|offset| {
+                if forward {
+                    (self.selected + offset) % n
+                } else {
+                    (self.selected + n - offset) % n
+                }
+            })
+            .find(|&i| self.is_match(i) && self.columns(i).is_some());
+        match found {
+            Some(i) => self.selected = i,
+            None if self.search.is_some() => self.message = Some("No other matches".to_string()),
+            None => {}
+        }
+    }
+
+    fn zoom(&mut self, i: usize) {
+        self.zoomed = i;
+        self.selected = i;
+    }
+
+    fn set_search(&mut self, pattern: &str) {
+        if pattern.is_empty() {
+            self.search = None;
+            return;
+        }
+        match Regex::new(pattern) {
+            Ok(re) => {
+                self.search = Some(re);
+                if !self.is_match(self.selected) {
+                    self.select_match(true);
+                }
+            }
+            Err(e) => self.message = Some(format!("Invalid search: {}", e)),
+        }
+    }
+
+    /// The percentage of the zoomed frame's samples that are in frames matching the search.
+    fn matched_pct(&self) -> Option<f64> {
+        self.search.as_ref()?;
+        let zoomed = &self.frames[self.zoomed];
+        let mut matches: Vec<_> = (0..self.frames.len())
+            .filter(|&i| self.is_match(i) && !self.is_zoom_ancestor(i))
+            .map(|i| &self.frames[i])
+            .filter(|f| f.start >= zoomed.start && f.end <= zoomed.end)
+            .map(|f| (f.start, f.end))
+            .collect();
+        matches.sort_unstable();
+
+        // Don't count the samples of nested matches twice.
+        let mut matched = 0;
+        let mut covered = zoomed.start;
+        for (start, end) in matches {
+            if end > covered {
+                matched += end - start.max(covered);
+                covered = end;
+            }
+        }
+        Some(matched as f64 * 100.0 / (zoomed.end - zoomed.start) as f64)
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> quick_xml::Result<Action> {
+        self.message = None;
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Ok(Action::Quit);
+        }
+
+        if let Some(mut prompt) = self.prompt.take() {
+            match key.code {
+                KeyCode::Enter => self.set_search(&prompt),
+                KeyCode::Esc => {}
+                KeyCode::Backspace => {
+                    prompt.pop();
+                    self.prompt = Some(prompt);
+                }
+                KeyCode::Char(c) => {
+                    prompt.push(c);
+                    self.prompt = Some(prompt);
+                }
+                _ => self.prompt = Some(prompt),
+            }
+            return Ok(Action::Continue);
+        }
+
+        let inverted = self.options.direction == Direction::Inverted;
+        match key.code {
+            KeyCode::Char('q') => return Ok(Action::Quit),
+            KeyCode::Up if inverted => self.select_parent(),
+            KeyCode::Up => self.select_child(),
+            KeyCode::Down if inverted => self.select_child(),
+            KeyCode::Down => self.select_parent(),
+            KeyCode::Left => self.select_sibling(false),
+            KeyCode::Right => self.select_sibling(true),
+            KeyCode::Enter => self.zoom(self.selected),
+            KeyCode::Backspace => {
+                if let Some(parent) = self.parent(self.zoomed) {
+                    self.zoom(parent);
+                }
+            }
+            KeyCode::Esc | KeyCode::Char('0') => self.zoom(0),
+            KeyCode::Char('/') => self.prompt = Some(String::new()),
+            KeyCode::Char('n') => self.select_match(true),
+            KeyCode::Char('N') => self.select_match(false),
+            KeyCode::Char('i') => {
+                self.options.direction = if inverted {
+                    Direction::Straight
+                } else {
+                    Direction::Inverted
+                };
+            }
+            KeyCode::Char('r') => {
+                self.options.reverse_stack_order = !self.options.reverse_stack_order;
+                self.relayout()?;
+            }
+            _ => {}
+        }
+        Ok(Action::Continue)
+    }
+
+    fn render<W: Write>(&mut self, out: &mut W, width: u16, height: u16) -> io::Result<()> {
+        let (width, height) = (width as usize, height as usize);
+        self.width = width;
+        queue!(out, ResetColor, terminal::Clear(terminal::ClearType::All))?;
+        // Leave room for the title, the selected frame and the help line.
+        if height < 4 || width == 0 {
+            return Ok(());
+        }
+        let graph_height = height - 3;
+
+        // Scroll just enough to keep the selected frame on screen.
+        let depth = self.frames[self.selected].depth;
+        if depth < self.scroll {
+            self.scroll = depth;
+        } else if depth >= self.scroll + graph_height {
+            self.scroll = depth + 1 - graph_height;
+        }
+
+        let inverted = self.options.direction == Direction::Inverted;
+        let last = self.rows.len().min(self.scroll + graph_height);
+        for depth in self.scroll..last {
+            let y = if inverted {
+                1 + depth - self.scroll
+            } else {
+                graph_height - (depth - self.scroll)
+            };
+            for i in self.rows[depth].clone() {
+                let columns = match self.columns(i) {
+                    Some(columns) => columns,
+                    None => continue,
+                };
+                let frame = &self.frames[i];
+                // Leave the last column of wider frames blank to separate them from their
+                // neighbours.
+                let room = if columns.len() > 1 {
+                    columns.len() - 1
+                } else {
+                    1
+                };
+                let background = if self.is_match(i) {
+                    SEARCH_COLOR
+                } else {
+                    TermColor::Rgb {
+                        r: frame.color.r,
+                        g: frame.color.g,
+                        b: frame.color.b,
+                    }
+                };
+                queue!(
+                    out,
+                    cursor::MoveTo(columns.start as u16, y as u16),
+                    SetForegroundColor(TermColor::Black),
+                    SetBackgroundColor(background),
+                )?;
+                if i == self.selected {
+                    queue!(out, SetAttribute(Attribute::Reverse))?;
+                }
+                queue!(
+                    out,
+                    Print(format!("{:room$}", label(&frame.name, room), room = room)),
+                    SetAttribute(Attribute::Reset),
+                    ResetColor,
+                )?;
+            }
+        }
+
+        let mut title = if inverted {
+            "Icicle Graph"
+        } else {
+            "Flame Graph"
+        }
+        .to_string();
+        if self.options.reverse_stack_order {
+            title.push_str(" (reversed)");
+        }
+        let matched = match self.matched_pct() {
+            Some(pct) => format!("Matched: {:.1}%", pct),
+            None => String::new(),
+        };
+        let title = format!(
+            "{}{:>pad$}",
+            title,
+            matched,
+            pad = width.saturating_sub(title.chars().count())
+        );
+        queue!(
+            out,
+            cursor::MoveTo(0, 0),
+            SetAttribute(Attribute::Bold),
+            Print(truncate(&title, width)),
+            SetAttribute(Attribute::Reset),
+        )?;
+
+        queue!(
+            out,
+            cursor::MoveTo(0, (height - 2) as u16),
+            Print(truncate(&self.details(self.selected), width)),
+        )?;
+
+        let help = match (&self.prompt, &self.message) {
+            (Some(prompt), _) => format!("/{}", prompt),
+            (None, Some(message)) => message.clone(),
+            (None, None) => HELP.to_string(),
+        };
+        queue!(
+            out,
+            cursor::MoveTo(0, (height - 1) as u16),
+            SetAttribute(Attribute::Dim),
+            Print(truncate(&help, width)),
+            SetAttribute(Attribute::Reset),
+        )?;
+        Ok(())
+    }
+
+    /// Describes frame `i` with its total and self sample counts.
+    fn details(&self, i: usize) -> String {
+        let frame = &self.frames[i];
+        let all = self.frames[0].end as f64;
+        let total = frame.end - frame.start;
+        let self_samples = self.self_samples[i];
+        let mut details = format!(
+            "{}: {} {} ({:.2}%), self {} ({:.2}%)",
+            frame.name,
+            total,
+            self.options.count_name,
+            total as f64 * 100.0 / all,
+            self_samples,
+            self_samples as f64 * 100.0 / all,
+        );
+        if let Some(delta) = frame.delta {
+            details.push_str(&format!(", delta {:+}", delta));
+        }
+        details
+    }
+}
+
+/// Truncates `name` with `..` on the right so that it fits in `room` columns.
+fn label(name: &str, room: usize) -> String {
+    if name.chars().count() <= room {
+        name.to_string()
+    } else if room >= 3 {
+        let head: String = name.chars().take(room - 2).collect();
+        format!("{}..", head)
+    } else {
+        String::new()
+    }
+}
+
+fn truncate(s: &str, width: usize) -> String {
+    s.chars().take(width).collect()
+}
+
+/// Puts the terminal in raw mode on the alternate screen until dropped.
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn new() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        crossterm::execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(TerminalGuard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = crossterm::execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn run(app: &mut App<'_>) -> quick_xml::Result<()> {
+    let _guard = TerminalGuard::new()?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    loop {
+        let (width, height) = terminal::size()?;
+        app.render(&mut out, width, height)?;
+        out.flush()?;
+        // Keys are read from the terminal even if the input came from STDIN.
+        if let Event::Key(key) = event::read()? {
+            if app.handle_key(key)? == Action::Quit {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn main() -> quick_xml::Result<()> {
+    let opt = Opt::from_args();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infile, nthreads, options) = opt.into_parts();
+    let folded = read_folded(infile.as_ref(), nthreads)?;
+    let mut app = App::new(folded, options)?;
+    run(&mut app)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FOLDED: &str = "\
+main;parse;read 10
+main;parse;lex 30
+main;render 50
+main;render;draw 10
+idle 20
+";
+
+    fn app() -> App<'static> {
+        let mut options = Options::default();
+        options.min_width = 0.0;
+        App::new(FOLDED.to_string(), options).unwrap()
+    }
+
+    fn selected<'a>(app: &'a App<'_>) -> &'a str {
+        &app.frames[app.selected].name
+    }
+
+    fn press(app: &mut App<'_>, code: KeyCode) -> Action {
+        app.handle_key(KeyEvent::new(code, KeyModifiers::NONE))
+            .unwrap()
+    }
+
+    #[test]
+    fn counts_self_samples() {
+        let app = app();
+        let name = |i: usize| app.frames[i].name.as_str();
+        let counts: Vec<_> = (0..app.frames.len())
+            .map(|i| (name(i), app.self_samples[i]))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![
+                ("all", 0),
+                ("idle", 20),
+                ("main", 0),
+                ("parse", 0),
+                ("render", 50),
+                ("lex", 30),
+                ("read", 10),
+                ("draw", 10),
+            ]
+        );
+        assert_eq!(
+            app.details(4),
+            "render: 60 samples (50.00%), self 50 (41.67%)"
+        );
+    }
+
+    #[test]
+    fn navigates_frames() {
+        let mut app = app();
+        press(&mut app, KeyCode::Up);
+        assert_eq!(selected(&app), "main");
+        press(&mut app, KeyCode::Up);
+        assert_eq!(selected(&app), "render");
+        press(&mut app, KeyCode::Left);
+        assert_eq!(selected(&app), "parse");
+        press(&mut app, KeyCode::Left);
+        assert_eq!(selected(&app), "parse");
+        press(&mut app, KeyCode::Up);
+        assert_eq!(selected(&app), "lex");
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Left);
+        assert_eq!(selected(&app), "idle");
+
+        // Up and down swap places in icicle graphs.
+        press(&mut app, KeyCode::Char('i'));
+        press(&mut app, KeyCode::Up);
+        assert_eq!(selected(&app), "all");
+    }
+
+    #[test]
+    fn zooms_into_frames() {
+        let mut app = app();
+        app.width = 120;
+        press(&mut app, KeyCode::Up);
+        press(&mut app, KeyCode::Up);
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(selected(&app), "render");
+        assert_eq!(app.columns(app.selected), Some(0..120));
+        // Callers of the zoomed frame span the whole width, and other frames are hidden.
+        assert_eq!(app.columns(0), Some(0..120));
+        assert_eq!(app.columns(1), None);
+        assert_eq!(app.columns(3), None);
+        assert_eq!(app.columns(7), Some(100..120));
+
+        // Moving away from the zoomed frame follows the path to it.
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Up);
+        assert_eq!(selected(&app), "main");
+        press(&mut app, KeyCode::Left);
+        assert_eq!(selected(&app), "main");
+
+        press(&mut app, KeyCode::Backspace);
+        assert_eq!(selected(&app), "main");
+        press(&mut app, KeyCode::Esc);
+        assert_eq!(app.zoomed, 0);
+    }
+
+    #[test]
+    fn searches_frames() {
+        let mut app = app();
+        press(&mut app, KeyCode::Char('/'));
+        for c in "^(read|render)$".chars() {
+            press(&mut app, KeyCode::Char(c));
+        }
+        assert!(app.search.is_none());
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(selected(&app), "render");
+        assert_eq!(app.matched_pct().map(|pct| pct.round()), Some(58.0));
+        press(&mut app, KeyCode::Char('n'));
+        assert_eq!(selected(&app), "read");
+        press(&mut app, KeyCode::Char('N'));
+        assert_eq!(selected(&app), "render");
+
+        // Frames the zoomed frame was called from don't count towards the matched samples.
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(app.matched_pct(), Some(100.0));
+
+        press(&mut app, KeyCode::Char('/'));
+        press(&mut app, KeyCode::Char('('));
+        press(&mut app, KeyCode::Enter);
+        assert!(app.message.is_some());
+    }
+
+    #[test]
+    fn reverses_stacks() {
+        let mut app = app();
+        press(&mut app, KeyCode::Char('r'));
+        let names: Vec<_> = app.rows[1]
+            .clone()
+            .map(|i| app.frames[i].name.as_str())
+            .collect();
+        assert_eq!(names, vec!["draw", "idle", "lex", "read", "render"]);
+        assert_eq!(press(&mut app, KeyCode::Char('q')), Action::Quit);
+    }
+
+    #[test]
+    fn detects_folded_input() {
+        assert!(looks_folded(FOLDED));
+        assert!(looks_folded("main;a 1 2\nmain;b 3 4\n"));
+        assert!(!looks_folded(""));
+        assert!(!looks_folded(
+            "perf 1234 [000] 1.000: 1 cycles:\n\t7f00 main (/bin/foo)\n"
+        ));
+        assert!(!looks_folded("\n  libc.so`read\n  12\n"));
+    }
+}
@@ -0,0 +1,136 @@
+use std::io;
+
+use log::warn;
+
+use crate::collapse::common::{strip_address_and_offset, Occurrences};
+use crate::collapse::Collapse;
+use crate::filter::FoldRecursion;
+
+/// `bcc` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Merge recursive calls into a single frame: either only where a function calls itself
+    /// directly, or also for cycles through other functions.
+    ///
+    /// Default is `None`.
+    pub fold_recursion: Option<FoldRecursion>,
+}
+
+/// A stack collapser for the stacks printed by [BCC]'s `profile` and `offcputime` tools when
+/// they aren't given `-f`.
+///
+/// Each stack starts with the name of its process, like BCC's own folded output does. With
+/// `-d`, the kernel and user stacks are separated by a `--` line, which becomes a `-` frame
+/// between the user and the kernel frames that flame graphs draw in grey. Offsets and the
+/// addresses printed with `-a` are dropped from frames.
+///
+/// To construct one, either use `bcc::Folder::default()` or create an [`Options`] and use
+/// `bcc::Folder::from(options)`.
+///
+///   [BCC]: https://github.com/iovisor/bcc
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(1);
+        let mut process: Option<String> = None;
+        let mut frames: Vec<String> = Vec::new();
+        let mut line = Vec::new();
+        let mut nstacks = 0;
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            let l = String::from_utf8_lossy(&line);
+            let l = l.trim_end();
+
+            if !l.starts_with(char::is_whitespace) {
+                // Blank lines between stacks, or the banner and warnings the tools print.
+                frames.clear();
+                process = None;
+                continue;
+            }
+            let l = l.trim_start();
+
+            if l == "--" {
+                frames.push("-".to_string());
+            } else if let Some(name) = process_name(l) {
+                process = Some(name);
+            } else if let Ok(count) = l.parse::<usize>() {
+                nstacks += self.on_stack_end(process.take(), &mut frames, count, &mut occurrences);
+            } else {
+                // Semicolons separate frames in the folded format.
+                frames.push(strip_address_and_offset(l).replace(';', ":"));
+            }
+        }
+
+        if nstacks == 0 {
+            warn!("File contains no stacks");
+        }
+        occurrences.write_and_clear(writer, self.opt.fold_recursion)
+    }
+
+    /// Check for the line with the process of a stack, followed by the count of that stack.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let mut last_line_was_process = false;
+        for line in input.lines() {
+            if last_line_was_process {
+                return Some(line.trim().parse::<usize>().is_ok());
+            }
+            last_line_was_process =
+                line.starts_with(char::is_whitespace) && process_name(line.trim()).is_some();
+        }
+        None
+    }
+}
+
+impl Folder {
+    /// Records the stack made up of `frames`, if any, and returns how many stacks were recorded.
+    fn on_stack_end(
+        &self,
+        process: Option<String>,
+        frames: &mut Vec<String>,
+        count: usize,
+        occurrences: &mut Occurrences,
+    ) -> usize {
+        if frames.is_empty() && process.is_none() {
+            return 0;
+        }
+        let mut stack = process.unwrap_or_else(|| "[unknown]".to_string());
+        // BCC prints the innermost frame first, and the kernel frames before the user frames.
+        for frame in frames.iter().rev() {
+            stack.push(';');
+            stack.push_str(frame);
+        }
+        occurrences.insert_or_add(stack, count);
+        frames.clear();
+        1
+    }
+}
+
+/// Returns the process name in the line that follows a stack, like the `mybin` of
+/// `-                mybin (12347)`.
+fn process_name(line: &str) -> Option<String> {
+    let rest = line.strip_prefix("- ")?.trim_start();
+    let rest = rest.strip_suffix(')')?;
+    let start = rest.rfind(" (")?;
+    if !rest[start + 2..].bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(rest[..start].replace(';', ":"))
+}
@@ -0,0 +1,183 @@
+use std::io;
+
+use log::warn;
+
+use crate::collapse::common::{strip_address_and_offset, Occurrences};
+use crate::collapse::Collapse;
+use crate::filter::FoldRecursion;
+
+/// `bpftrace` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Merge recursive calls into a single frame: either only where a function calls itself
+    /// directly, or also for cycles through other functions.
+    ///
+    /// Default is `None`.
+    pub fold_recursion: Option<FoldRecursion>,
+
+    /// Take the last of two stacks in a map key to be the kernel stack, as in
+    /// `@[ustack, kstack]`. Otherwise the first one is, as in `@[kstack, ustack]`.
+    ///
+    /// Default is `false`.
+    pub user_stack_first: bool,
+}
+
+/// A stack collapser for the maps of stacks printed by [`bpftrace`], like those of
+/// `bpftrace -e 'profile:hz:99 { @[kstack, ustack, comm] = count(); }'`.
+///
+/// Each map entry becomes one stack, weighted by its value. Keys that aren't stacks, like `comm`
+/// or `pid`, become the first frames of the stack, in the order they appear in the key. When a
+/// key holds both a kernel and a user stack, the user stack comes first, followed by a `-` frame
+/// and the kernel stack, which flame graphs draw in grey like the upstream Perl tools do.
+///
+/// Offsets and the addresses and modules of `ustack(perf)` are dropped from frames. Entries whose
+/// value isn't a count, like those of `hist()`, are skipped.
+///
+/// To construct one, either use `bpftrace::Folder::default()` or create an [`Options`] and use
+/// `bpftrace::Folder::from(options)`.
+///
+///   [`bpftrace`]: https://github.com/iovisor/bpftrace
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+/// The parts of the map entry that is being read.
+#[derive(Default)]
+struct Entry {
+    /// Parts of the key that aren't stacks.
+    scalars: Vec<String>,
+    /// Stacks in the key, each with its innermost frame first.
+    stacks: Vec<Vec<String>>,
+    /// Frames of the stack that is being read.
+    frames: Vec<String>,
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(1);
+        let mut entry: Option<Entry> = None;
+        let mut line = Vec::new();
+        let mut nstacks = 0;
+        let mut nskipped = 0;
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            let l = String::from_utf8_lossy(&line);
+            let l = l.trim_end();
+
+            let key = if l.starts_with('@') {
+                // Maps of scalars, like `@count: 42`, have no key.
+                match l.find('[') {
+                    Some(i) => {
+                        entry = Some(Entry::default());
+                        &l[i + 1..]
+                    }
+                    None => continue,
+                }
+            } else {
+                match entry {
+                    Some(ref mut e) if l.starts_with(char::is_whitespace) => {
+                        let frame = l.trim();
+                        if !frame.is_empty() {
+                            // Semicolons separate frames in the folded format.
+                            e.frames
+                                .push(strip_address_and_offset(frame).replace(';', ":"));
+                        }
+                        continue;
+                    }
+                    Some(_) if !l.is_empty() => l,
+                    // Lines between entries, like `Attaching 1 probe...`.
+                    _ => continue,
+                }
+            };
+
+            let e = entry.as_mut().expect("a map entry has been started");
+            if !e.frames.is_empty() {
+                let frames = std::mem::take(&mut e.frames);
+                e.stacks.push(frames);
+            }
+
+            // The key ends with `]: ` and the value of the entry.
+            let (key, value) = match key.rfind("]:") {
+                Some(i) => (&key[..i], Some(key[i + 2..].trim())),
+                None => (key, None),
+            };
+            for scalar in key.split(',') {
+                let scalar = scalar.trim();
+                if !scalar.is_empty() {
+                    e.scalars.push(scalar.replace(';', ":"));
+                }
+            }
+
+            if let Some(value) = value {
+                let e = entry.take().expect("a map entry has been started");
+                match value.parse::<usize>() {
+                    Ok(count) => nstacks += self.on_entry_end(e, count, &mut occurrences),
+                    Err(_) => nskipped += 1,
+                }
+            }
+        }
+
+        if nskipped != 0 {
+            warn!("Skipped {} map entries without a count", nskipped);
+        }
+        if nstacks == 0 {
+            warn!("File contains no map entries");
+        }
+        occurrences.write_and_clear(writer, self.opt.fold_recursion)
+    }
+
+    /// Check for the start of a map entry with a key, and for the value that ends it.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let mut in_entry = false;
+        for line in input.lines() {
+            if line.starts_with('@') && line.contains('[') {
+                in_entry = true;
+            }
+            if in_entry && line.contains("]: ") {
+                return Some(true);
+            }
+        }
+        None
+    }
+}
+
+impl Folder {
+    /// Records the stack of a map entry, if any, and returns how many stacks were recorded.
+    fn on_entry_end(&self, entry: Entry, count: usize, occurrences: &mut Occurrences) -> usize {
+        if entry.scalars.is_empty() && entry.stacks.is_empty() {
+            return 0;
+        }
+        let mut frames: Vec<&str> = entry.scalars.iter().map(String::as_str).collect();
+
+        // The stack closest to the root goes first, so the kernel stack comes last.
+        let mut stacks: Vec<&Vec<String>> = entry.stacks.iter().collect();
+        if !self.opt.user_stack_first {
+            stacks.reverse();
+        }
+        for (i, stack) in stacks.into_iter().enumerate() {
+            if i != 0 {
+                frames.push("-");
+            }
+            // bpftrace prints the innermost frame first.
+            frames.extend(stack.iter().rev().map(String::as_str));
+        }
+
+        occurrences.insert_or_add(frames.join(";"), count);
+        1
+    }
+}
@@ -0,0 +1,417 @@
+use std::io;
+
+use ahash::AHashMap;
+use log::{info, warn};
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::filter::FoldRecursion;
+
+// The first line of files that follow the format specification.
+static FORMAT_LINE: &str = "# callgrind format";
+
+// Files that don't start with the format line still name the tool that wrote them.
+static CREATOR_LINE: &str = "creator: callgrind";
+
+// Stacks that would be weighted by less than this are not followed any further, since they would
+// round away to nothing anyway. This keeps us from walking every path of large call graphs.
+const MIN_WEIGHT: f64 = 0.5;
+
+/// `callgrind` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Merge recursive calls into a single frame: either only where a function calls itself
+    /// directly, or also for cycles through other functions.
+    ///
+    /// Default is `None`.
+    pub fold_recursion: Option<FoldRecursion>,
+
+    /// The event to use as the count for each stack, named as in the `events:` line of the file
+    /// (e.g., `Ir`, `Dr`, `Bc`).
+    ///
+    /// If this option is set to `None`, the first event is used, which is `Ir` (instructions
+    /// executed) unless callgrind was told to collect something else.
+    ///
+    /// Default is `None`.
+    pub event: Option<String>,
+}
+
+/// A stack collapser for the `callgrind.out.<pid>` files written by [Valgrind's callgrind].
+///
+/// Callgrind does not record stacks. It records the self cost of every function, and for every
+/// call from one function to another, the inclusive cost of that call. This collapser rebuilds
+/// stacks from that call graph, which is only an approximation:
+///
+///  - Starting from the functions that are never called, each function's cost is split between
+///    itself and its callees in proportion to their recorded costs. That proportion is the same
+///    no matter which path led to the function, so if `a` and `b` both call `c`, and `c` is only
+///    slow when called from `a`, the flame graph will still show it as equally slow under both.
+///  - Calls back into a function that is already on the stack (recursion) are not followed, and
+///    their cost is shared out among the other callees instead.
+///  - Paths whose share of the cost rounds to zero are not followed.
+///
+/// Functions that only show up in recursion cycles that are never entered from outside start
+/// their own stacks. Names of functions are taken as they are, without their files or objects, so
+/// functions with the same name are merged.
+///
+/// To construct one, either use `callgrind::Folder::default()` or create an [`Options`] and use
+/// `callgrind::Folder::from(options)`.
+///
+///   [Valgrind's callgrind]: https://valgrind.org/docs/manual/cl-manual.html
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+/// The call graph of a profile, with costs for the selected event.
+#[derive(Default)]
+struct CallGraph {
+    names: Vec<String>,
+    ids: AHashMap<String, usize>,
+    self_costs: Vec<u64>,
+    /// The inclusive cost of the calls from each function to each of its callees.
+    calls: Vec<AHashMap<usize, u64>>,
+    called: Vec<bool>,
+}
+
+impl CallGraph {
+    fn function(&mut self, name: &str) -> usize {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let id = self.names.len();
+        // Semicolons separate frames in the folded format.
+        self.names.push(name.replace(';', ":"));
+        self.ids.insert(name.to_string(), id);
+        self.self_costs.push(0);
+        self.calls.push(AHashMap::default());
+        self.called.push(false);
+        id
+    }
+}
+
+/// What the next cost line means.
+#[derive(Clone, Copy, PartialEq)]
+enum CostLine {
+    /// The cost of the current function itself.
+    Exclusive,
+    /// The inclusive cost of a call to the given function.
+    Call(usize),
+    /// The position of a jump, which carries no cost.
+    Jump,
+}
+
+/// The state of the parser as it reads through a file.
+struct Parser<'a> {
+    wanted_event: Option<&'a str>,
+    /// The number of position columns at the start of each cost line.
+    npositions: usize,
+    /// The column of the selected event, once we've seen the `events:` line.
+    event: Option<usize>,
+    /// Function names by their compressed ids.
+    compressed: AHashMap<u64, usize>,
+    function: Option<usize>,
+    callee: Option<usize>,
+    next: CostLine,
+    graph: CallGraph,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut parser = Parser {
+            wanted_event: self.opt.event.as_deref(),
+            npositions: 1,
+            event: None,
+            compressed: AHashMap::default(),
+            function: None,
+            callee: None,
+            next: CostLine::Exclusive,
+            graph: CallGraph::default(),
+        };
+
+        let mut line = Vec::new();
+        let mut lineno = 0;
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            lineno += 1;
+            let l = String::from_utf8_lossy(&line);
+            parser.on_line(l.trim_end(), lineno)?;
+        }
+
+        if parser.graph.names.is_empty() {
+            warn!("File contains no functions");
+            return Ok(());
+        }
+
+        let mut occurrences = Occurrences::new(1);
+        rebuild_stacks(&parser.graph, &mut occurrences);
+        occurrences.write_and_clear(writer, self.opt.fold_recursion)
+    }
+
+    /// Check for the line that starts callgrind files, or for the header naming callgrind as the
+    /// tool that wrote the file.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        for line in input.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with(FORMAT_LINE) || line.starts_with(CREATOR_LINE) {
+                return Some(true);
+            }
+            // Other comments and header lines may come before the creator.
+            if !line.starts_with('#') && !is_header(line) {
+                return Some(false);
+            }
+        }
+        None
+    }
+}
+
+impl<'a> Parser<'a> {
+    fn on_line(&mut self, line: &str, lineno: usize) -> io::Result<()> {
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+
+        let first = line.as_bytes()[0];
+        if first.is_ascii_digit() || first == b'+' || first == b'-' || first == b'*' {
+            return self.on_cost_line(line, lineno);
+        }
+
+        if let Some((key, value)) = split_spec(line) {
+            match key {
+                "fn" => {
+                    self.function = Some(self.resolve(value, lineno)?);
+                    self.next = CostLine::Exclusive;
+                }
+                "cfn" => self.callee = Some(self.resolve(value, lineno)?),
+                "calls" => {
+                    let callee = match self.callee {
+                        Some(callee) => callee,
+                        None => {
+                            return invalid_data_error!("calls= without cfn= on line {}", lineno)
+                        }
+                    };
+                    self.next = CostLine::Call(callee);
+                }
+                "jump" | "jcnd" => self.next = CostLine::Jump,
+                // Files and objects are not part of the stacks we build.
+                _ => {}
+            }
+            return Ok(());
+        }
+
+        if let Some(events) = line.strip_prefix("events:") {
+            let events: Vec<&str> = events.split_whitespace().collect();
+            let event = match self.wanted_event {
+                Some(wanted) => match events.iter().position(|e| *e == wanted) {
+                    Some(event) => event,
+                    None => {
+                        return invalid_data_error!(
+                            "Unknown event {}; the file has events: {}",
+                            wanted,
+                            events.join(" ")
+                        )
+                    }
+                },
+                None if events.is_empty() => {
+                    return invalid_data_error!("No events on line {}", lineno)
+                }
+                None => 0,
+            };
+            info!("Using event: {}", events[event]);
+            self.event = Some(event);
+        } else if let Some(positions) = line.strip_prefix("positions:") {
+            self.npositions = positions.split_whitespace().count();
+        } else if !is_header(line) {
+            warn!("Ignoring unrecognized line {}: {}", lineno, line);
+        }
+        Ok(())
+    }
+
+    fn on_cost_line(&mut self, line: &str, lineno: usize) -> io::Result<()> {
+        let next = std::mem::replace(&mut self.next, CostLine::Exclusive);
+        if next == CostLine::Jump {
+            return Ok(());
+        }
+
+        let function = match self.function {
+            Some(function) => function,
+            None => return invalid_data_error!("Cost line before fn= on line {}", lineno),
+        };
+        let event = match self.event {
+            Some(event) => event,
+            None => return invalid_data_error!("Cost line before events: on line {}", lineno),
+        };
+
+        // Events missing from the end of a line have a cost of zero.
+        let cost = match line.split_whitespace().nth(self.npositions + event) {
+            Some(cost) => match cost.parse::<u64>() {
+                Ok(cost) => cost,
+                Err(_) => return invalid_data_error!("Invalid cost on line {}", lineno),
+            },
+            None => 0,
+        };
+
+        match next {
+            CostLine::Exclusive => self.graph.self_costs[function] += cost,
+            CostLine::Call(callee) => {
+                *self.graph.calls[function].entry(callee).or_insert(0) += cost;
+                self.graph.called[callee] = true;
+            }
+            CostLine::Jump => unreachable!(),
+        }
+        Ok(())
+    }
+
+    /// Looks up a function name, which may be compressed to `(id) name` the first time it
+    /// appears and to `(id)` after that.
+    fn resolve(&mut self, value: &str, lineno: usize) -> io::Result<usize> {
+        if let Some(rest) = value.strip_prefix('(') {
+            let (id, name) = match rest.find(')') {
+                Some(end) => (&rest[..end], rest[end + 1..].trim()),
+                None => return invalid_data_error!("Invalid name id on line {}", lineno),
+            };
+            let id = match id.parse::<u64>() {
+                Ok(id) => id,
+                Err(_) => return invalid_data_error!("Invalid name id on line {}", lineno),
+            };
+            if name.is_empty() {
+                return match self.compressed.get(&id) {
+                    Some(&function) => Ok(function),
+                    None => invalid_data_error!("Unknown name id {} on line {}", id, lineno),
+                };
+            }
+            let function = self.graph.function(name);
+            self.compressed.insert(id, function);
+            Ok(function)
+        } else {
+            Ok(self.graph.function(value))
+        }
+    }
+}
+
+/// Splits a specification line like `fn=(1) main` into its key and value.
+fn split_spec(line: &str) -> Option<(&str, &str)> {
+    let eq = line.find('=')?;
+    let key = &line[..eq];
+    if key.is_empty() || !key.bytes().all(|b| b.is_ascii_lowercase()) {
+        return None;
+    }
+    Some((key, line[eq + 1..].trim()))
+}
+
+/// Whether the line is a header line like `version: 1` or `cmd: ./mybin`.
+fn is_header(line: &str) -> bool {
+    match line.find(':') {
+        Some(colon) => line[..colon]
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_'),
+        None => false,
+    }
+}
+
+/// Walks the call graph down from the functions that are never called, splitting the cost of
+/// each function between itself and its callees.
+fn rebuild_stacks(graph: &CallGraph, occurrences: &mut Occurrences) {
+    let mut weights: AHashMap<String, f64> = AHashMap::default();
+    let mut path = Vec::new();
+    for root in roots(graph) {
+        let weight = graph.self_costs[root] + graph.calls[root].values().sum::<u64>();
+        walk(graph, root, weight as f64, &mut path, &mut weights);
+    }
+
+    for (stack, weight) in weights {
+        let count = weight.round() as usize;
+        if count != 0 {
+            occurrences.insert_or_add(stack, count);
+        }
+    }
+}
+
+/// Returns the functions that are never called, followed by one function of each recursion
+/// cycle that can't be reached from them.
+fn roots(graph: &CallGraph) -> Vec<usize> {
+    let mut reached = vec![false; graph.names.len()];
+    let mut roots = Vec::new();
+    let uncalled = (0..graph.names.len()).filter(|&f| !graph.called[f]);
+    let called = (0..graph.names.len()).filter(|&f| graph.called[f]);
+    for root in uncalled.chain(called) {
+        if reached[root] {
+            continue;
+        }
+        roots.push(root);
+        let mut queue = vec![root];
+        reached[root] = true;
+        while let Some(function) = queue.pop() {
+            for &callee in graph.calls[function].keys() {
+                if !reached[callee] {
+                    reached[callee] = true;
+                    queue.push(callee);
+                }
+            }
+        }
+    }
+    roots
+}
+
+fn walk(
+    graph: &CallGraph,
+    function: usize,
+    weight: f64,
+    path: &mut Vec<usize>,
+    weights: &mut AHashMap<String, f64>,
+) {
+    path.push(function);
+
+    // Calls back into the stack are recursion, which we don't follow.
+    let mut callees: Vec<(usize, u64)> = graph.calls[function]
+        .iter()
+        .filter(|(callee, _)| !path.contains(callee))
+        .map(|(&callee, &cost)| (callee, cost))
+        .collect();
+    // Keep the order in which weights are added up, and so their rounding, stable.
+    callees.sort_unstable();
+    let self_cost = graph.self_costs[function];
+    let total = self_cost + callees.iter().map(|(_, cost)| cost).sum::<u64>();
+
+    let self_weight = if total == 0 {
+        weight
+    } else {
+        weight * self_cost as f64 / total as f64
+    };
+    if self_weight > 0.0 {
+        let stack = path
+            .iter()
+            .map(|&f| graph.names[f].as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+        *weights.entry(stack).or_insert(0.0) += self_weight;
+    }
+
+    if total != 0 {
+        for (callee, cost) in callees {
+            let callee_weight = weight * cost as f64 / total as f64;
+            if callee_weight >= MIN_WEIGHT {
+                walk(graph, callee, callee_weight, path, weights);
+            }
+        }
+    }
+
+    path.pop();
+}
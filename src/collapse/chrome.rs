@@ -0,0 +1,283 @@
+use std::io;
+
+use ahash::AHashMap;
+use log::warn;
+use serde::Deserialize;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::filter::FoldRecursion;
+
+// The frame at the top of every `.cpuprofile` tree. It carries no information, so we drop it.
+static ROOT_FUNCTION: &str = "(root)";
+
+/// `chrome` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Merge recursive calls into a single frame: either only where a function calls itself
+    /// directly, or also for cycles through other functions.
+    ///
+    /// Default is `None`.
+    pub fold_recursion: Option<FoldRecursion>,
+
+    /// Don't annotate function names with the URL and line number of the script they came from.
+    ///
+    /// Default is `false`.
+    pub no_urls: bool,
+
+    /// Weight each sample by the time until the next sample (in microseconds) rather than
+    /// counting it once. This uses the `timeDeltas` array of the profile.
+    ///
+    /// Default is `false`.
+    pub time_weighted: bool,
+}
+
+/// A stack collapser for the `.cpuprofile` JSON files produced by Chrome DevTools and by
+/// Node.js's `--cpu-prof` flag.
+///
+/// To construct one, either use `chrome::Folder::default()` or create an [`Options`] and use
+/// `chrome::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Profile {
+    nodes: Vec<Node>,
+    #[serde(default)]
+    samples: Option<Vec<u64>>,
+    #[serde(default)]
+    time_deltas: Option<Vec<f64>>,
+    #[serde(default)]
+    end_time: Option<f64>,
+    #[serde(default)]
+    start_time: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Node {
+    id: u64,
+    call_frame: CallFrame,
+    #[serde(default)]
+    hit_count: Option<u64>,
+    #[serde(default)]
+    children: Option<Vec<u64>>,
+    #[serde(default)]
+    parent: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CallFrame {
+    function_name: String,
+    #[serde(default)]
+    url: String,
+    #[serde(default = "unknown_line")]
+    line_number: i64,
+}
+
+fn unknown_line() -> i64 {
+    -1
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut input = Vec::new();
+        reader.read_to_end(&mut input)?;
+        if input.iter().all(|b| b.is_ascii_whitespace()) {
+            warn!("File is empty");
+            return Ok(());
+        }
+
+        let profile: Profile = match serde_json::from_slice(&input) {
+            Ok(profile) => profile,
+            Err(e) => return invalid_data_error!("Invalid .cpuprofile JSON: {}", e),
+        };
+
+        let mut occurrences = Occurrences::new(1);
+        self.collapse_profile(&profile, &mut occurrences)?;
+        occurrences.write_and_clear(writer, self.opt.fold_recursion)
+    }
+
+    /// Check for the `callFrame` objects that make up a `.cpuprofile` node tree.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let input = input.trim_start();
+        if input.is_empty() {
+            return None;
+        }
+        if !input.starts_with('{') {
+            return Some(false);
+        }
+        if input.contains("\"callFrame\"") {
+            return Some(true);
+        }
+        None
+    }
+}
+
+impl Folder {
+    fn collapse_profile(&self, profile: &Profile, occurrences: &mut Occurrences) -> io::Result<()> {
+        let nodes: AHashMap<u64, &Node> = profile.nodes.iter().map(|n| (n.id, n)).collect();
+
+        // Newer profiles list each node's children, while those extracted from trace events
+        // instead give each node its parent. Normalize to parent links.
+        let mut parents: AHashMap<u64, u64> = AHashMap::default();
+        for node in &profile.nodes {
+            if let Some(parent) = node.parent {
+                parents.insert(node.id, parent);
+            }
+            for child in node.children.iter().flatten() {
+                parents.insert(*child, node.id);
+            }
+        }
+
+        // Figure out how much weight each node carries on its own.
+        let mut weights: AHashMap<u64, f64> = AHashMap::default();
+        match profile.samples {
+            Some(ref samples) if !samples.is_empty() => {
+                let durations = if self.opt.time_weighted {
+                    Some(Self::sample_durations(profile, samples.len())?)
+                } else {
+                    None
+                };
+                for (i, id) in samples.iter().enumerate() {
+                    let weight = durations.as_ref().map(|d| d[i]).unwrap_or(1.0);
+                    *weights.entry(*id).or_insert(0.0) += weight;
+                }
+            }
+            _ => {
+                // Old profiles only have per-node hit counts, so we can't weight by time.
+                if self.opt.time_weighted {
+                    warn!("Profile has no samples; falling back to hit counts");
+                }
+                for node in &profile.nodes {
+                    if let Some(hits) = node.hit_count {
+                        weights.insert(node.id, hits as f64);
+                    }
+                }
+            }
+        }
+
+        let mut stacks: AHashMap<u64, String> = AHashMap::default();
+        for (id, weight) in weights {
+            let count = weight.round() as usize;
+            if count == 0 {
+                continue;
+            }
+            if !nodes.contains_key(&id) {
+                return invalid_data_error!("Sample refers to unknown node id {}", id);
+            }
+            let stack = self.stack_for(id, &nodes, &parents, &mut stacks)?;
+            if !stack.is_empty() {
+                occurrences.insert_or_add(stack, count);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the time each sample lasted, i.e. the time until the next sample was taken.
+    ///
+    /// The last sample lasts until the end of the profile if we know when that is.
+    fn sample_durations(profile: &Profile, nsamples: usize) -> io::Result<Vec<f64>> {
+        let deltas = match profile.time_deltas {
+            Some(ref deltas) if deltas.len() == nsamples => deltas,
+            _ => {
+                return invalid_data_error!(
+                    "Profile must have one time delta per sample to weight by time"
+                )
+            }
+        };
+
+        let mut durations = Vec::with_capacity(nsamples);
+        // Deltas may be slightly negative when samples are reordered; don't count those.
+        durations.extend(deltas[1..].iter().map(|d| d.max(0.0)));
+        let last = match (profile.start_time, profile.end_time) {
+            (Some(start), Some(end)) => {
+                let last_sample_at = start + deltas.iter().sum::<f64>();
+                (end - last_sample_at).max(0.0)
+            }
+            _ => 0.0,
+        };
+        durations.push(last);
+        Ok(durations)
+    }
+
+    /// Builds the folded stack for the given node, caching the stacks of its ancestors.
+    fn stack_for(
+        &self,
+        id: u64,
+        nodes: &AHashMap<u64, &Node>,
+        parents: &AHashMap<u64, u64>,
+        stacks: &mut AHashMap<u64, String>,
+    ) -> io::Result<String> {
+        // Walk up until we find an ancestor whose stack we already know.
+        let mut path = Vec::new();
+        let mut current = Some(id);
+        let mut prefix = String::new();
+        while let Some(node_id) = current {
+            if let Some(stack) = stacks.get(&node_id) {
+                prefix = stack.clone();
+                break;
+            }
+            if path.len() > nodes.len() {
+                return invalid_data_error!("Cycle in node tree at node id {}", node_id);
+            }
+            path.push(node_id);
+            current = parents.get(&node_id).copied();
+        }
+
+        // Then walk back down, recording the stack of every node on the way.
+        for node_id in path.into_iter().rev() {
+            let node = match nodes.get(&node_id) {
+                Some(node) => node,
+                None => return invalid_data_error!("Unknown parent node id {}", node_id),
+            };
+            if node.call_frame.function_name != ROOT_FUNCTION || parents.contains_key(&node_id) {
+                if !prefix.is_empty() {
+                    prefix.push(';');
+                }
+                prefix.push_str(&self.frame_name(&node.call_frame));
+            }
+            stacks.insert(node_id, prefix.clone());
+        }
+
+        Ok(prefix)
+    }
+
+    fn frame_name(&self, frame: &CallFrame) -> String {
+        let func = if frame.function_name.is_empty() {
+            "(anonymous)"
+        } else {
+            &frame.function_name
+        };
+
+        let mut name = func.to_string();
+        if !self.opt.no_urls && !frame.url.is_empty() {
+            name.push(' ');
+            name.push_str(&frame.url);
+            if frame.line_number >= 0 {
+                // Line numbers in .cpuprofile files are zero-based.
+                name.push(':');
+                name.push_str(&(frame.line_number + 1).to_string());
+            }
+        }
+
+        // Semicolons separate frames in the folded format.
+        name.replace(';', ":")
+    }
+}
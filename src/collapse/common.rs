@@ -10,6 +10,8 @@ use ahash::AHashMap;
 use dashmap::DashMap;
 use lazy_static::lazy_static;
 
+use crate::filter::FoldRecursion;
+
 macro_rules! invalid_data_error {
     ($($arg:tt)*) => {{
         Err(io::Error::new(
@@ -147,10 +149,39 @@ pub trait CollapsePrivate: Send + Sized {
     /// Sets the number of threads to use.
     fn set_nthreads(&mut self, n: usize);
 
+    /// Returns which recursive calls to merge in the collapsed stacks, if any.
+    fn fold_recursion(&self) -> Option<FoldRecursion>;
+
     // *********************************************************** //
     // ******************** PROVIDED METHODS ********************* //
     // *********************************************************** //
 
+    /// Determine the start of a stack.
+    ///
+    /// Some formats, such as `sample` and `vtune`, print a call tree rather than one stack after
+    /// another. In a tree, no line marks the end of a stack; a stack only ends when the next line
+    /// starts a new one. For such formats, this method should return `true` if the provided line
+    /// starts a new stack; `false` otherwise. Unlike the line passed to `would_end_stack`, this
+    /// line is sent to the worker threads as part of the next chunk.
+    ///
+    /// Like `would_end_stack`, this method is called for every line of input data, and may keep
+    /// track of state on `self`. The default implementation always returns `false`.
+    fn would_start_stack(&mut self, _line: &[u8]) -> bool {
+        false
+    }
+
+    /// Complete two chunks of input data cut apart before a line that starts a stack.
+    ///
+    /// In a call tree, the first stack of a chunk shares frames with the last stack of the
+    /// previous one. This method is called when input data is cut before a line for which
+    /// `would_start_stack` returned `true`, and should append whatever the worker thread
+    /// processing `chunk` needs to see its input end properly, and write whatever the worker
+    /// thread processing `next_chunk` needs to know about the frames above its first line. The
+    /// line itself is added to `next_chunk` after this method returns.
+    ///
+    /// The default implementation does nothing.
+    fn write_chunk_context(&mut self, _chunk: &mut Vec<u8>, _next_chunk: &mut Vec<u8>) {}
+
     fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
     where
         R: io::BufRead,
@@ -170,7 +201,7 @@ pub trait CollapsePrivate: Send + Sized {
         }
 
         // Write results.
-        occurrences.write_and_clear(writer)
+        occurrences.write_and_clear(writer, self.fold_recursion())
     }
 
     #[cfg(not(feature = "multithreaded"))]
@@ -301,6 +332,25 @@ pub trait CollapsePrivate: Send + Sized {
                     break;
                 }
                 let line = &buf[index..index + n];
+                if self.would_start_stack(line) {
+                    // If we've reached the start of a stack, the one before it has ended.
+                    nstacks += 1;
+                    if nstacks == nstacks_per_job {
+                        // Cut the chunk before the line, and move the line over to the next one.
+                        let buf_capacity = usize::next_power_of_two(buf.capacity());
+                        let mut chunk = mem::replace(&mut buf, Vec::with_capacity(buf_capacity));
+                        let line = chunk.split_off(index);
+                        self.write_chunk_context(&mut chunk, &mut buf);
+                        buf.extend_from_slice(&line);
+                        if tx_input.send(chunk).is_err() {
+                            // See the comment on sending chunks below.
+                            break;
+                        }
+                        index = buf.len();
+                        nstacks = 0;
+                        continue;
+                    }
+                }
                 index += n;
                 if self.would_end_stack(line) {
                     // If we've reached the end of a stack, count it.
@@ -394,18 +444,6 @@ impl Occurrences {
         Occurrences::MultiThreaded(Arc::new(map))
     }
 
-    /// Inserts a key-count pair into the map. If the map did not have this key
-    /// present, `None` is returned. If the map did have this key present, the
-    /// value is updated, and the old value is returned.
-    pub(crate) fn insert(&mut self, key: String, count: usize) -> Option<usize> {
-        use self::Occurrences::*;
-        match self {
-            SingleThreaded(map) => map.insert(key, count),
-            #[cfg(feature = "multithreaded")]
-            MultiThreaded(arc) => arc.insert(key, count),
-        }
-    }
-
     /// Inserts a key-count pair into the map if the key does not already exist.
     /// If the key does already exist, adds count to the current value of the
     /// existing key.
@@ -427,19 +465,21 @@ impl Occurrences {
         }
     }
 
-    pub(crate) fn write_and_clear<W>(&mut self, mut writer: W) -> io::Result<()>
+    /// Writes the stacks and their counts, sorted by stack, and clears the map.
+    ///
+    /// With `fold_recursion`, the recursive calls in each stack are merged first, and the counts
+    /// of stacks that end up the same are added up.
+    pub(crate) fn write_and_clear<W>(
+        &mut self,
+        mut writer: W,
+        fold_recursion: Option<FoldRecursion>,
+    ) -> io::Result<()>
     where
         W: io::Write,
     {
         use self::Occurrences::*;
-        match self {
-            SingleThreaded(ref mut map) => {
-                let mut contents: Vec<_> = map.drain().collect();
-                contents.sort();
-                for (key, value) in contents {
-                    writeln!(writer, "{} {}", key, value)?;
-                }
-            }
+        let mut contents: Vec<(String, usize)> = match self {
+            SingleThreaded(ref mut map) => map.drain().collect(),
             #[cfg(feature = "multithreaded")]
             MultiThreaded(ref mut arc) => {
                 let map = match Arc::get_mut(arc) {
@@ -457,13 +497,23 @@ impl Occurrences {
                         ahash::RandomState::default(),
                     ),
                 );
-                let contents = map.iter().collect::<Vec<_>>();
-                let mut pairs = contents.iter().map(|pair| pair.pair()).collect::<Vec<_>>();
-                pairs.sort();
-                for (key, value) in pairs {
-                    writeln!(writer, "{} {}", key, value)?;
-                }
+                map.into_iter().collect()
             }
+        };
+        if let Some(fold_recursion) = fold_recursion {
+            let mut folded: AHashMap<String, usize> = AHashMap::default();
+            for (stack, count) in contents {
+                let folded_stack = match fold_recursion.fold_stack(&stack) {
+                    Cow::Owned(folded_stack) => Some(folded_stack),
+                    Cow::Borrowed(_) => None,
+                };
+                *folded.entry(folded_stack.unwrap_or(stack)).or_insert(0) += count;
+            }
+            contents = folded.into_iter().collect();
+        }
+        contents.sort();
+        for (key, value) in contents {
+            writeln!(writer, "{} {}", key, value)?;
         }
         writer.flush()?;
         Ok(())
@@ -564,6 +614,73 @@ pub(crate) fn fix_partially_demangled_rust_symbol(symbol: &str) -> Cow<str> {
     Cow::Owned(demangled)
 }
 
+/// Strips the argument list, or source location, in parentheses at the end of a frame, like the
+/// `(0xc000012345, 0x1)` of `main.worker(0xc000012345, 0x1)`.
+///
+/// The call operator of names like `Closure::operator()(int)` is kept.
+pub(crate) fn strip_trailing_arguments(frame: &str) -> &str {
+    if !frame.ends_with(')') {
+        return frame;
+    }
+    let mut depth = 0usize;
+    for (i, c) in frame.char_indices().rev() {
+        match c {
+            ')' => depth += 1,
+            '(' => {
+                depth -= 1;
+                if depth == 0 {
+                    let name = &frame[..i];
+                    if name.is_empty() || name.ends_with("operator") {
+                        return frame;
+                    }
+                    return name;
+                }
+            }
+            _ => {}
+        }
+    }
+    frame
+}
+
+/// Returns the symbol of a frame printed by bpftrace or BCC, without the address in front of it,
+/// the module after it, or its offset, like the `main` of `7f2f4c6b3e5a main+20 (/usr/bin/app)`
+/// or of `main+0x14`.
+///
+/// Frames that are only an address, like `0x7f2f4c6b3e5a`, are kept as they are.
+pub(crate) fn strip_address_and_offset(frame: &str) -> &str {
+    let mut frame = frame.trim();
+
+    // Addresses are printed in hex without a `0x` in front of the symbol (bpftrace's `perf` mode
+    // and BCC's `-a`).
+    if let Some((address, rest)) = frame.split_once(' ') {
+        if address.bytes().all(|b| b.is_ascii_hexdigit())
+            && address.bytes().any(|b| b.is_ascii_digit())
+        {
+            frame = rest.trim_start();
+        }
+    }
+
+    // bpftrace's `perf` mode gives the module in parentheses, like `([kernel.kallsyms])`.
+    if let Some(start) = frame.rfind(" (") {
+        let module = &frame[start + 2..];
+        if module.ends_with(')') && (module.starts_with('/') || module.starts_with('[')) {
+            frame = &frame[..start];
+        }
+    }
+
+    if let Some(plus) = frame.rfind('+') {
+        let offset = &frame[plus + 1..];
+        let is_offset = match offset.strip_prefix("0x") {
+            Some(hex) => !hex.is_empty() && hex.bytes().all(|b| b.is_ascii_hexdigit()),
+            None => !offset.is_empty() && offset.bytes().all(|b| b.is_ascii_digit()),
+        };
+        if is_offset && plus != 0 {
+            frame = &frame[..plus];
+        }
+    }
+    frame
+}
+
 #[cfg(test)]
 pub(crate) mod testing {
     use std::collections::HashMap;
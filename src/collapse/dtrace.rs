@@ -1,15 +1,25 @@
 use std::borrow::Cow;
 use std::collections::VecDeque;
 use std::io::{self, prelude::*};
+use std::path::PathBuf;
+use std::sync::Arc;
 
 use log::warn;
 
 use crate::collapse::common::{self, CollapsePrivate, Occurrences};
+use crate::collapse::symbolize::{Address, Symbolizer};
+use crate::filter::FoldRecursion;
 
 /// `dtrace` folder configuration options.
 #[derive(Clone, Debug)]
 #[non_exhaustive]
 pub struct Options {
+    /// Merge recursive calls into a single frame: either only where a function calls itself
+    /// directly, or also for cycles through other functions.
+    ///
+    /// Default is `None`.
+    pub fold_recursion: Option<FoldRecursion>,
+
     /// Include function offset (except leafs).
     ///
     /// Default is `false`.
@@ -19,13 +29,31 @@ pub struct Options {
     ///
     /// Default is the number of logical cores on your machine.
     pub nthreads: usize,
+
+    /// Binaries, or directories of binaries, to symbolize unresolved frames (like
+    /// ``libfoo.so`0x4011a6``) with. Binaries are looked up by file name in each directory.
+    /// Addresses are looked up as the binary was linked.
+    ///
+    /// Requires the `symbolize` feature.
+    ///
+    /// Default is empty, which disables symbolization.
+    pub symbol_path: Vec<PathBuf>,
+
+    /// When symbolizing unresolved frames, include the functions inlined at the address as
+    /// separate frames (annotated with `_[i]`), using the binary's DWARF debug info.
+    ///
+    /// Default is `false`.
+    pub symbolize_inlines: bool,
 }
 
 impl Default for Options {
     fn default() -> Self {
         Self {
+            fold_recursion: None,
             includeoffset: false,
             nthreads: *common::DEFAULT_NTHREADS,
+            symbol_path: Vec::new(),
+            symbolize_inlines: false,
         }
     }
 }
@@ -47,6 +75,9 @@ pub struct Folder {
     /// Keep track of stack string size while we consume a stack
     stack_str_size: usize,
 
+    /// Resolves unresolved frames, if a symbol path was given. Shared between threads.
+    symbolizer: Option<Arc<Symbolizer>>,
+
     opt: Options,
 }
 
@@ -55,11 +86,20 @@ impl From<Options> for Folder {
         if opt.nthreads == 0 {
             opt.nthreads = 1;
         }
+        let symbolizer = if opt.symbol_path.is_empty() {
+            None
+        } else {
+            Some(Arc::new(Symbolizer::new(
+                opt.symbol_path.clone(),
+                opt.symbolize_inlines,
+            )))
+        };
         Self {
             cache_inlines: Vec::new(),
             nstacks_per_job: common::DEFAULT_NSTACKS_PER_JOB,
             stack: VecDeque::default(),
             stack_str_size: 0,
+            symbolizer,
             opt,
         }
     }
@@ -235,6 +275,7 @@ impl CollapsePrivate for Folder {
             nstacks_per_job: self.nstacks_per_job,
             stack: VecDeque::default(),
             stack_str_size: 0,
+            symbolizer: self.symbolizer.clone(),
             opt: self.opt.clone(),
         }
     }
@@ -254,6 +295,10 @@ impl CollapsePrivate for Folder {
     fn set_nthreads(&mut self, n: usize) {
         self.opt.nthreads = n;
     }
+
+    fn fold_recursion(&self) -> Option<FoldRecursion> {
+        self.opt.fold_recursion
+    }
 }
 
 impl Folder {
@@ -322,6 +367,26 @@ impl Folder {
         Cow::Borrowed(frame)
     }
 
+    // Resolves a frame that DTrace couldn't, using the symbol path if one was given:
+    //
+    //     demo`0x4011a6
+    fn symbolize(&self, line: &str) -> Option<String> {
+        let symbolizer = self.symbolizer.as_ref()?;
+        let mut parts = line.splitn(2, '`');
+        let (module, address) = (parts.next()?, parts.next()?);
+        let address = u64::from_str_radix(address.strip_prefix("0x")?, 16).ok()?;
+
+        // Callers' addresses are return addresses, which may already belong to the next line or
+        // function, so look up the call instruction before them instead.
+        let address = if self.stack.is_empty() {
+            address
+        } else {
+            address.saturating_sub(1)
+        };
+        let func = symbolizer.symbolize(module, Address::Virtual(address))?;
+        Some(format!("{}`{}", module, func))
+    }
+
     // we have a stack line that shows one stack entry from the preceeding event, like:
     //
     //     unix`tsc_gethrtimeunscaled+0x21
@@ -330,6 +395,9 @@ impl Folder {
     //     unix`sys_syscall+0x10e
     //       1
     fn on_stack_line(&mut self, line: &str) {
+        let symbolized = self.symbolize(line);
+        let line = symbolized.as_deref().unwrap_or(line);
+
         let (has_inlines, could_be_cpp, has_semicolon, mut frame) = if self.opt.includeoffset {
             (true, true, true, line)
         } else {
@@ -520,8 +588,15 @@ mod tests {
         loop {
             let nstacks_per_job = rng.gen_range(1..=500);
             let options = Options {
+                fold_recursion: if rng.gen() {
+                    Some(FoldRecursion::Cycles)
+                } else {
+                    None
+                },
                 includeoffset: rng.gen(),
                 nthreads: rng.gen_range(2..=32),
+                symbol_path: Vec::new(),
+                symbolize_inlines: false,
             };
 
             for (path, input) in inputs.iter() {
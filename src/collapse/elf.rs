@@ -0,0 +1,226 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use ahash::AHashMap;
+#[cfg(feature = "symbolize")]
+use object::{Object, ObjectSection, ObjectSegment, ObjectSymbol, SymbolKind};
+
+/// A function symbol covering the addresses `start..end`.
+#[derive(Clone, Debug)]
+struct Symbol {
+    start: u64,
+    end: u64,
+    /// Whether `end` came from the symbol's size, rather than being a guess.
+    sized: bool,
+    name: String,
+}
+
+/// A loadable segment, used to translate file offsets into virtual addresses.
+#[derive(Clone, Debug)]
+struct Segment {
+    file_offset: u64,
+    file_size: u64,
+    address: u64,
+}
+
+/// The function symbols of a single binary (or of the kernel), sorted by address.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SymbolTable {
+    symbols: Vec<Symbol>,
+    segments: Vec<Segment>,
+}
+
+impl SymbolTable {
+    /// Reads the static and dynamic symbol tables of the ELF file at `path`.
+    #[cfg(feature = "symbolize")]
+    pub(crate) fn from_elf<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        Self::parse_elf(&data)
+    }
+
+    /// Reading ELF files needs the `symbolize` feature, so without it this always fails.
+    #[cfg(not(feature = "symbolize"))]
+    pub(crate) fn from_elf<P: AsRef<Path>>(_path: P) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "inferno was built without the `symbolize` feature",
+        ))
+    }
+
+    #[cfg(feature = "symbolize")]
+    pub(crate) fn parse_elf(data: &[u8]) -> io::Result<Self> {
+        let file = match object::File::parse(data) {
+            Ok(file) => file,
+            Err(e) => return invalid_data_error!("Unable to parse ELF file: {}", e),
+        };
+
+        let mut symbols = Vec::new();
+        for symbol in file.symbols().chain(file.dynamic_symbols()) {
+            if symbol.kind() != SymbolKind::Text || symbol.address() == 0 {
+                continue;
+            }
+            if let Ok(name) = symbol.name() {
+                if name.is_empty() {
+                    continue;
+                }
+                // Symbols without a size (like `_fini`) extend at most to the end of their section.
+                let end = if symbol.size() > 0 {
+                    symbol.address() + symbol.size()
+                } else {
+                    symbol
+                        .section_index()
+                        .and_then(|index| file.section_by_index(index).ok())
+                        .map(|section| section.address() + section.size())
+                        .unwrap_or(0)
+                };
+                symbols.push(Symbol {
+                    start: symbol.address(),
+                    end,
+                    sized: symbol.size() > 0,
+                    name: demangle(name),
+                });
+            }
+        }
+
+        let segments = file
+            .segments()
+            .filter_map(|segment| {
+                let (file_offset, file_size) = segment.file_range();
+                if file_size == 0 {
+                    return None;
+                }
+                Some(Segment {
+                    file_offset,
+                    file_size,
+                    address: segment.address(),
+                })
+            })
+            .collect();
+
+        Ok(Self::new(symbols, segments))
+    }
+
+    /// Reads kernel symbols in the format of `/proc/kallsyms`.
+    pub(crate) fn from_kallsyms<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let mut symbols = Vec::new();
+        for line in contents.lines() {
+            // ffffffff81000000 T _text
+            // ffffffffc0a3a010 t nf_conntrack_in	[nf_conntrack]
+            let mut fields = line.split_whitespace();
+            let (address, kind, name) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(address), Some(kind), Some(name)) => (address, kind, name),
+                _ => continue,
+            };
+            if !kind.eq_ignore_ascii_case("t") && !kind.eq_ignore_ascii_case("w") {
+                continue;
+            }
+            if let Ok(start) = u64::from_str_radix(address, 16) {
+                if start != 0 {
+                    symbols.push(Symbol {
+                        start,
+                        end: 0,
+                        sized: false,
+                        name: name.to_string(),
+                    });
+                }
+            }
+        }
+        Ok(Self::new(symbols, Vec::new()))
+    }
+
+    /// Reads JIT symbols in the format of `/tmp/perf-<pid>.map`: a hexadecimal start address
+    /// and size, followed by the symbol's name, on each line.
+    pub(crate) fn from_perf_map<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        // Code may be recompiled at the same address, in which case the last entry wins.
+        let mut symbols = AHashMap::default();
+        for line in contents.lines() {
+            // 7f722d142778 3c Ljava/io/PrintStream;::print
+            let mut fields = line.trim().splitn(3, ' ');
+            let (start, size, name) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(start), Some(size), Some(name)) => (start, size, name),
+                _ => continue,
+            };
+            let hex = |s: &str| u64::from_str_radix(s.trim_start_matches("0x"), 16).ok();
+            if let (Some(start), Some(size)) = (hex(start), hex(size)) {
+                let symbol = Symbol {
+                    start,
+                    end: start.saturating_add(size),
+                    sized: true,
+                    name: name.trim().to_string(),
+                };
+                symbols.insert(start, symbol);
+            }
+        }
+        Ok(Self::new(
+            symbols.into_iter().map(|(_, symbol)| symbol).collect(),
+            Vec::new(),
+        ))
+    }
+
+    fn new(mut symbols: Vec<Symbol>, segments: Vec<Segment>) -> Self {
+        symbols.sort_by(|a, b| a.start.cmp(&b.start).then(b.end.cmp(&a.end)));
+        symbols.dedup_by_key(|symbol| symbol.start);
+
+        // Symbols without a size (like those from kallsyms) extend to the next symbol, unless
+        // their section ends first.
+        for i in 0..symbols.len() {
+            if !symbols[i].sized {
+                let next = symbols
+                    .get(i + 1)
+                    .map(|next| next.start)
+                    .unwrap_or(u64::MAX);
+                if symbols[i].end <= symbols[i].start || symbols[i].end > next {
+                    symbols[i].end = next;
+                }
+            }
+        }
+
+        Self { symbols, segments }
+    }
+
+    /// Translates an offset into the file to the virtual address it is loaded at.
+    ///
+    /// Returns the offset unchanged if the file has no segment covering it.
+    pub(crate) fn file_offset_to_address(&self, offset: u64) -> u64 {
+        self.segments
+            .iter()
+            .find(|s| offset >= s.file_offset && offset < s.file_offset + s.file_size)
+            .map(|s| offset - s.file_offset + s.address)
+            .unwrap_or(offset)
+    }
+
+    /// Returns the name of the function containing `address`, if any.
+    pub(crate) fn lookup(&self, address: u64) -> Option<&str> {
+        let i = match self.symbols.binary_search_by(|s| s.start.cmp(&address)) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(i) => i - 1,
+        };
+        let symbol = &self.symbols[i];
+        if address < symbol.end {
+            Some(&symbol.name)
+        } else {
+            None
+        }
+    }
+}
+
+/// Demangles Rust and C++ symbol names, leaving other names untouched.
+#[cfg(feature = "symbolize")]
+pub(crate) fn demangle(name: &str) -> String {
+    if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+        // The alternate format leaves off the trailing hash.
+        return format!("{:#}", demangled);
+    }
+    if name.starts_with("_Z") {
+        if let Ok(symbol) = cpp_demangle::Symbol::new(name) {
+            if let Ok(demangled) = symbol.demangle(&Default::default()) {
+                return demangled;
+            }
+        }
+    }
+    name.to_string()
+}
@@ -0,0 +1,167 @@
+use std::io;
+
+use log::warn;
+
+use crate::collapse::common::{strip_trailing_arguments, Occurrences};
+use crate::collapse::Collapse;
+use crate::filter::FoldRecursion;
+
+/// `eu-stack` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Merge recursive calls into a single frame: either only where a function calls itself
+    /// directly, or also for cycles through other functions.
+    ///
+    /// Default is `None`.
+    pub fold_recursion: Option<FoldRecursion>,
+
+    /// Start each stack with the thread it came from. `eu-stack` doesn't print thread names, so
+    /// threads are named by their id, like `TID 12346`.
+    ///
+    /// Default is `false`.
+    pub include_thread: bool,
+}
+
+/// A stack collapser for the output of [`eu-stack`] from elfutils, for "poor man's profiling"
+/// with repeated thread dumps.
+///
+/// Identical stacks are counted across all the dumps in the input, so a file that holds the
+/// output of many `eu-stack -p <pid>` runs gives a profile of where the threads spent their time.
+/// Frame numbers, addresses, modules (`-m`), source locations (`-s`) and the parameter types of
+/// C++ functions are dropped, leaving only function names.
+///
+/// To construct one, either use `eu_stack::Folder::default()` or create an [`Options`] and use
+/// `eu_stack::Folder::from(options)`.
+///
+///   [`eu-stack`]: https://sourceware.org/elfutils/
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(1);
+        let mut thread: Option<String> = None;
+        let mut frames: Vec<String> = Vec::new();
+        let mut line = Vec::new();
+        let mut nstacks = 0;
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            let l = String::from_utf8_lossy(&line);
+            let l = l.trim_end();
+
+            if let Some(frame) = parse_frame(l) {
+                frames.push(frame);
+                continue;
+            }
+            // With `-s`, the source location of a frame is on an indented line of its own.
+            if !frames.is_empty() && l.starts_with(char::is_whitespace) {
+                continue;
+            }
+
+            // Anything else ends the current stack.
+            nstacks += self.on_stack_end(thread.as_deref(), &mut frames, &mut occurrences);
+            thread = l
+                .strip_prefix("TID ")
+                .and_then(|tid| tid.strip_suffix(':'))
+                .map(|tid| format!("TID {}", tid));
+        }
+        nstacks += self.on_stack_end(thread.as_deref(), &mut frames, &mut occurrences);
+
+        if nstacks == 0 {
+            warn!("File contains no stacks");
+        }
+        occurrences.write_and_clear(writer, self.opt.fold_recursion)
+    }
+
+    /// Check for the `PID` and `TID` lines that come before the stacks of each thread.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        for line in input.lines() {
+            let line = line.trim_end();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(pid) = line.strip_prefix("PID ") {
+                return Some(pid.contains(" - "));
+            }
+            if let Some(tid) = line.strip_prefix("TID ") {
+                return Some(tid.ends_with(':'));
+            }
+            return Some(false);
+        }
+        None
+    }
+}
+
+impl Folder {
+    /// Records the stack made up of `frames`, if any, and returns how many stacks were recorded.
+    fn on_stack_end(
+        &self,
+        thread: Option<&str>,
+        frames: &mut Vec<String>,
+        occurrences: &mut Occurrences,
+    ) -> usize {
+        if frames.is_empty() {
+            return 0;
+        }
+        let mut stack = String::new();
+        if self.opt.include_thread {
+            stack.push_str(thread.unwrap_or("[unknown]"));
+            stack.push(';');
+        }
+        // eu-stack prints the innermost frame first.
+        for (i, frame) in frames.iter().rev().enumerate() {
+            if i != 0 {
+                stack.push(';');
+            }
+            stack.push_str(frame);
+        }
+        occurrences.insert_or_add(stack, 1);
+        frames.clear();
+        1
+    }
+}
+
+/// Parses a frame line like `#1  0x00007f1b3a8c1f5d pthread_mutex_lock - /lib64/libpthread.so.0`
+/// into the name of its function.
+fn parse_frame(line: &str) -> Option<String> {
+    let rest = line.strip_prefix('#')?;
+    let digits = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+    if digits == 0 || !rest[digits..].starts_with(' ') {
+        return None;
+    }
+    let rest = rest[digits..].trim_start();
+
+    let rest = match rest.strip_prefix("0x") {
+        Some(rest) => rest.trim_start_matches(|c: char| c.is_ascii_hexdigit()),
+        None => return None,
+    };
+    // Modules are printed last, after a dash, when asked for with `-m`.
+    let name = match rest.rfind(" - ") {
+        Some(i) => &rest[..i],
+        None => rest,
+    };
+    // Demangled C++ names end with their parameter types.
+    let name = strip_trailing_arguments(name.trim());
+
+    if name.is_empty() || name == "??" {
+        return Some("[unknown]".to_string());
+    }
+    // Semicolons separate frames in the folded format.
+    Some(name.replace(';', ":"))
+}
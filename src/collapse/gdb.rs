@@ -0,0 +1,206 @@
+use std::io;
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::filter::FoldRecursion;
+
+// Each thread of `thread apply all bt` starts with a line like
+// `Thread 2 (Thread 0x7f1b2c7fe700 (LWP 12347) "worker"):`.
+static THREAD_PREFIX: &str = "Thread ";
+
+/// `gdb` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Merge recursive calls into a single frame: either only where a function calls itself
+    /// directly, or also for cycles through other functions.
+    ///
+    /// Default is `None`.
+    pub fold_recursion: Option<FoldRecursion>,
+
+    /// Start each stack with the name of its thread, or `[unknown]` for threads gdb didn't name.
+    ///
+    /// Default is `false`.
+    pub include_thread: bool,
+}
+
+/// A stack collapser for backtraces printed by gdb's `thread apply all bt` (or `bt`), for
+/// "poor man's profiling" with repeated thread dumps.
+///
+/// Identical stacks are counted across all the dumps in the input, so a file that holds the
+/// output of many gdb runs gives a profile of where the threads spent their time. Frame numbers,
+/// addresses, arguments and source locations are dropped, leaving only function names.
+///
+/// To construct one, either use `gdb::Folder::default()` or create an [`Options`] and use
+/// `gdb::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(1);
+        let mut thread: Option<String> = None;
+        let mut frames: Vec<String> = Vec::new();
+        let mut line = Vec::new();
+        let mut nstacks = 0;
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            let l = String::from_utf8_lossy(&line);
+            let l = l.trim();
+
+            if let Some(frame) = parse_frame(l) {
+                frames.push(frame);
+                continue;
+            }
+            // gdb wraps the source location of frames with long argument lists onto the next line.
+            if !frames.is_empty() && (l.starts_with("at ") || l.starts_with("from ")) {
+                continue;
+            }
+
+            // Anything else ends the current stack.
+            nstacks += self.on_stack_end(thread.as_deref(), &mut frames, &mut occurrences);
+            if is_thread_header(l) {
+                thread = Some(thread_name(l));
+            } else if l.is_empty() {
+                thread = None;
+            }
+        }
+        nstacks += self.on_stack_end(thread.as_deref(), &mut frames, &mut occurrences);
+
+        if nstacks == 0 {
+            warn!("File contains no backtraces");
+        }
+        occurrences.write_and_clear(writer, self.opt.fold_recursion)
+    }
+
+    /// Check for the thread headers of `thread apply all bt`, or for the first frame of a
+    /// backtrace in gdb's format.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        for line in input.lines() {
+            let line = line.trim();
+            if is_thread_header(line) {
+                return Some(true);
+            }
+            if line.starts_with("#0 ") {
+                // eu-stack frames also look like this, but never say where the function is.
+                return Some(line.contains(" in ") || line.contains(") at "));
+            }
+        }
+        None
+    }
+}
+
+impl Folder {
+    /// Records the stack made up of `frames`, if any, and returns how many stacks were recorded.
+    fn on_stack_end(
+        &self,
+        thread: Option<&str>,
+        frames: &mut Vec<String>,
+        occurrences: &mut Occurrences,
+    ) -> usize {
+        if frames.is_empty() {
+            return 0;
+        }
+        let mut stack = String::new();
+        if self.opt.include_thread {
+            stack.push_str(thread.unwrap_or("[unknown]"));
+            stack.push(';');
+        }
+        // gdb prints the innermost frame first.
+        for (i, frame) in frames.iter().rev().enumerate() {
+            if i != 0 {
+                stack.push(';');
+            }
+            stack.push_str(frame);
+        }
+        occurrences.insert_or_add(stack, 1);
+        frames.clear();
+        1
+    }
+}
+
+fn is_thread_header(line: &str) -> bool {
+    match line.strip_prefix(THREAD_PREFIX) {
+        Some(rest) => {
+            let digits = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+            digits != 0 && rest[digits..].starts_with(" (")
+        }
+        None => false,
+    }
+}
+
+/// Returns the quoted thread name at the end of a thread header, or `[unknown]`.
+fn thread_name(line: &str) -> String {
+    let line = line.strip_suffix(':').unwrap_or(line);
+    let line = line.strip_suffix(')').unwrap_or(line);
+    if let Some(end) = line.strip_suffix('"') {
+        if let Some(start) = end.rfind('"') {
+            return end[start + 1..].replace(';', ":");
+        }
+    }
+    "[unknown]".to_string()
+}
+
+/// Parses a frame line like `#2  0x0000000000401234 in worker_loop (arg=0x0) at worker.c:42`
+/// into the name of its function.
+fn parse_frame(line: &str) -> Option<String> {
+    let rest = line.strip_prefix('#')?;
+    let digits = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+    if digits == 0 || !rest[digits..].starts_with(' ') {
+        return None;
+    }
+    let mut rest = rest[digits..].trim_start();
+
+    // Frames that aren't at the start of a line of source code also give their address.
+    if rest.starts_with("0x") {
+        rest = match rest.find(" in ") {
+            Some(i) => &rest[i + 4..],
+            None => return Some("[unknown]".to_string()),
+        };
+    }
+
+    let name = strip_arguments(rest);
+    let name = if name.is_empty() || name == "??" {
+        "[unknown]"
+    } else {
+        name
+    };
+    // Semicolons separate frames in the folded format.
+    Some(name.replace(';', ":"))
+}
+
+/// Returns the function name that comes before the argument list of a gdb frame.
+///
+/// The argument list is the first ` (` outside of template brackets, so that names like
+/// `std::function<void (int)>::operator()` are kept whole.
+fn strip_arguments(frame: &str) -> &str {
+    let bytes = frame.as_bytes();
+    let mut depth = 0usize;
+    for (i, &b) in bytes.iter().enumerate() {
+        match b {
+            b'<' => depth += 1,
+            b'>' => depth = depth.saturating_sub(1),
+            b'(' if depth == 0 && i > 0 && bytes[i - 1] == b' ' => return frame[..i - 1].trim(),
+            _ => {}
+        }
+    }
+    // Frames like `<signal handler called>` have no arguments.
+    frame.trim()
+}
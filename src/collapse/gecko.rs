@@ -0,0 +1,255 @@
+use std::io;
+
+use ahash::AHashMap;
+use log::warn;
+use serde::Deserialize;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::filter::FoldRecursion;
+
+// The name we use for threads and processes that the profile doesn't name.
+static UNKNOWN: &str = "[unknown]";
+
+/// `gecko` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Merge recursive calls into a single frame: either only where a function calls itself
+    /// directly, or also for cycles through other functions.
+    ///
+    /// Default is `None`.
+    pub fold_recursion: Option<FoldRecursion>,
+
+    /// Prefix each stack with the name of the process its thread belongs to.
+    ///
+    /// Default is `false`.
+    pub include_process: bool,
+
+    /// Prefix each stack with the name of its thread.
+    ///
+    /// If `include_process` is also set, the thread name comes after the process name.
+    ///
+    /// Default is `false`.
+    pub include_thread: bool,
+}
+
+/// A stack collapser for profiles in the processed JSON format of the [Firefox Profiler], as
+/// written by samply and saved from profiler.firefox.com.
+///
+/// The samples of all threads are collapsed together, each weighted by its entry in the
+/// `weight` column if the profile has one. Use [`Options::include_thread`] and
+/// [`Options::include_process`] to tell the threads apart.
+///
+/// The raw format that Firefox itself records (with `schema` and `data` tables) is not
+/// supported; load such profiles into the Firefox Profiler and download them again to get the
+/// processed format.
+///
+/// To construct one, either use `gecko::Folder::default()` or create an [`Options`] and use
+/// `gecko::Folder::from(options)`.
+///
+///   [Firefox Profiler]: https://profiler.firefox.com/
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Profile {
+    threads: Vec<Thread>,
+    // Newer profiles share one string array between all threads.
+    #[serde(default)]
+    shared: Option<Shared>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Shared {
+    string_array: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Thread {
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    process_name: Option<String>,
+    samples: Samples,
+    stack_table: StackTable,
+    frame_table: FrameTable,
+    func_table: FuncTable,
+    #[serde(default)]
+    string_array: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Samples {
+    stack: Vec<Option<usize>>,
+    #[serde(default)]
+    weight: Option<Vec<f64>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StackTable {
+    frame: Vec<usize>,
+    prefix: Vec<Option<usize>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FrameTable {
+    func: Vec<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FuncTable {
+    name: Vec<usize>,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut input = Vec::new();
+        reader.read_to_end(&mut input)?;
+        if input.iter().all(|b| b.is_ascii_whitespace()) {
+            warn!("File is empty");
+            return Ok(());
+        }
+
+        let profile: Profile = match serde_json::from_slice(&input) {
+            Ok(profile) => profile,
+            Err(e) => return invalid_data_error!("Invalid Gecko profile JSON: {}", e),
+        };
+
+        let mut occurrences = Occurrences::new(1);
+        for thread in &profile.threads {
+            let strings = match (&thread.string_array, &profile.shared) {
+                (Some(strings), _) => strings,
+                (None, Some(shared)) => &shared.string_array,
+                (None, None) => return invalid_data_error!("Profile has no string array"),
+            };
+            self.collapse_thread(thread, strings, &mut occurrences)?;
+        }
+        occurrences.write_and_clear(writer, self.opt.fold_recursion)
+    }
+
+    /// Check for the `stackTable` objects of the threads in a processed profile.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let input = input.trim_start();
+        if input.is_empty() {
+            return None;
+        }
+        if !input.starts_with('{') {
+            return Some(false);
+        }
+        if input.contains("\"stackTable\"") {
+            return Some(true);
+        }
+        None
+    }
+}
+
+impl Folder {
+    fn collapse_thread(
+        &self,
+        thread: &Thread,
+        strings: &[String],
+        occurrences: &mut Occurrences,
+    ) -> io::Result<()> {
+        let samples = &thread.samples;
+        if let Some(ref weights) = samples.weight {
+            if weights.len() != samples.stack.len() {
+                return invalid_data_error!("Thread must have one weight per sample");
+            }
+        }
+
+        // Add up the weight of each stack first, so that every stack is only built once.
+        let mut weights: AHashMap<usize, f64> = AHashMap::default();
+        for (i, stack) in samples.stack.iter().enumerate() {
+            // Samples taken while the thread was outside of any known code have no stack.
+            if let Some(stack) = stack {
+                let weight = samples.weight.as_ref().map(|w| w[i]).unwrap_or(1.0);
+                *weights.entry(*stack).or_insert(0.0) += weight;
+            }
+        }
+
+        let mut prefix = String::new();
+        if self.opt.include_process {
+            prefix.push_str(&sanitize(thread.process_name.as_deref()));
+            prefix.push(';');
+        }
+        if self.opt.include_thread {
+            prefix.push_str(&sanitize(thread.name.as_deref()));
+            prefix.push(';');
+        }
+
+        let mut frames = Vec::new();
+        for (stack, weight) in weights {
+            let count = weight.round() as usize;
+            if count == 0 {
+                continue;
+            }
+
+            frames.clear();
+            let mut current = Some(stack);
+            while let Some(index) = current {
+                if frames.len() > thread.stack_table.frame.len() {
+                    return invalid_data_error!("Cycle in stack table at stack {}", index);
+                }
+                let frame = match thread.stack_table.frame.get(index) {
+                    Some(&frame) => frame,
+                    None => return invalid_data_error!("Sample refers to unknown stack {}", index),
+                };
+                let func = match thread.frame_table.func.get(frame) {
+                    Some(&func) => func,
+                    None => return invalid_data_error!("Stack refers to unknown frame {}", frame),
+                };
+                let name = match thread.func_table.name.get(func) {
+                    Some(&name) => name,
+                    None => {
+                        return invalid_data_error!("Frame refers to unknown function {}", func)
+                    }
+                };
+                match strings.get(name) {
+                    Some(name) => frames.push(sanitize(Some(name))),
+                    None => {
+                        return invalid_data_error!("Function refers to unknown string {}", name)
+                    }
+                }
+                current = thread.stack_table.prefix.get(index).copied().flatten();
+            }
+
+            // We walked from the leaf to the root.
+            let mut folded = prefix.clone();
+            for (i, frame) in frames.iter().rev().enumerate() {
+                if i != 0 {
+                    folded.push(';');
+                }
+                folded.push_str(frame);
+            }
+            occurrences.insert_or_add(folded, count);
+        }
+
+        Ok(())
+    }
+}
+
+fn sanitize(name: Option<&str>) -> String {
+    match name {
+        Some(name) if !name.is_empty() => {
+            // Semicolons separate frames in the folded format.
+            name.replace(';', ":")
+        }
+        _ => UNKNOWN.to_string(),
+    }
+}
@@ -0,0 +1,160 @@
+use std::io;
+
+use log::warn;
+
+use crate::collapse::common::{strip_trailing_arguments, Occurrences};
+use crate::collapse::Collapse;
+use crate::filter::FoldRecursion;
+
+// Each goroutine starts with a line like `goroutine 18 [chan receive, 5 minutes]:`.
+static GOROUTINE_PREFIX: &str = "goroutine ";
+
+/// `goroutine` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Merge recursive calls into a single frame: either only where a function calls itself
+    /// directly, or also for cycles through other functions.
+    ///
+    /// Default is `None`.
+    pub fold_recursion: Option<FoldRecursion>,
+
+    /// Start each stack with the state of its goroutine, like `running`, `chan receive` or
+    /// `IO wait`. How long the goroutine has been in that state is left out.
+    ///
+    /// Default is `false`.
+    pub include_state: bool,
+}
+
+/// A stack collapser for Go goroutine dumps, as printed when a Go program gets `SIGQUIT` or
+/// panics, or as served by `/debug/pprof/goroutine?debug=2`, for "poor man's profiling" with
+/// repeated dumps.
+///
+/// Identical stacks are counted across all the dumps in the input. Arguments, source locations
+/// and `created by` lines are dropped, leaving only function names.
+///
+/// To construct one, either use `goroutine::Folder::default()` or create an [`Options`] and use
+/// `goroutine::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(1);
+        // The state of the goroutine whose stack we're reading, if we're reading one.
+        let mut state: Option<String> = None;
+        let mut in_created_by = false;
+        let mut frames: Vec<String> = Vec::new();
+        let mut line = Vec::new();
+        let mut nstacks = 0;
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            let l = String::from_utf8_lossy(&line);
+            let l = l.trim_end();
+
+            if l.is_empty() {
+                nstacks += self.on_stack_end(state.as_deref(), &mut frames, &mut occurrences);
+                state = None;
+                continue;
+            }
+            if let Some(s) = goroutine_state(l) {
+                nstacks += self.on_stack_end(state.as_deref(), &mut frames, &mut occurrences);
+                state = Some(s);
+                in_created_by = false;
+                continue;
+            }
+            if state.is_none() {
+                // Not part of a goroutine, like the `SIGQUIT: quit` line or register dumps.
+                continue;
+            }
+
+            if l.starts_with(char::is_whitespace) {
+                // The source location of the line before.
+                continue;
+            }
+            if l.starts_with("created by ") {
+                in_created_by = true;
+                continue;
+            }
+            if in_created_by || l.starts_with("...") {
+                // Frames past `created by`, or a note that frames were elided.
+                continue;
+            }
+            // Semicolons separate frames in the folded format.
+            frames.push(strip_trailing_arguments(l).replace(';', ":"));
+        }
+        nstacks += self.on_stack_end(state.as_deref(), &mut frames, &mut occurrences);
+
+        if nstacks == 0 {
+            warn!("File contains no goroutines");
+        }
+        occurrences.write_and_clear(writer, self.opt.fold_recursion)
+    }
+
+    /// Check for the line that starts each goroutine.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        if input.lines().any(|line| goroutine_state(line).is_some()) {
+            return Some(true);
+        }
+        None
+    }
+}
+
+impl Folder {
+    /// Records the stack made up of `frames`, if any, and returns how many stacks were recorded.
+    fn on_stack_end(
+        &self,
+        state: Option<&str>,
+        frames: &mut Vec<String>,
+        occurrences: &mut Occurrences,
+    ) -> usize {
+        if frames.is_empty() {
+            return 0;
+        }
+        let mut stack = String::new();
+        if self.opt.include_state {
+            stack.push_str(state.unwrap_or("[unknown]"));
+            stack.push(';');
+        }
+        // Goroutine dumps list the innermost frame first.
+        for (i, frame) in frames.iter().rev().enumerate() {
+            if i != 0 {
+                stack.push(';');
+            }
+            stack.push_str(frame);
+        }
+        occurrences.insert_or_add(stack, 1);
+        frames.clear();
+        1
+    }
+}
+
+/// Returns the state of a goroutine from its header, like `chan receive` for
+/// `goroutine 18 [chan receive, 5 minutes]:`.
+fn goroutine_state(line: &str) -> Option<String> {
+    let rest = line.strip_prefix(GOROUTINE_PREFIX)?;
+    let digits = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+    if digits == 0 {
+        return None;
+    }
+    let rest = rest[digits..].strip_prefix(" [")?;
+    let end = rest.find(']')?;
+    // Details like how long the goroutine has waited, or `locked to thread`, come after a comma.
+    let state = rest[..end].split(',').next().unwrap_or("").trim();
+    Some(state.replace(';', ":"))
+}
@@ -3,7 +3,11 @@ use std::io::{self, Cursor};
 
 use log::{error, info};
 
-use crate::collapse::{self, dtrace, perf, sample, vtune, Collapse};
+use crate::collapse::{
+    self, bcc, bpftrace, callgrind, chrome, dtrace, eu_stack, gdb, gecko, goroutine, jstack, perf,
+    perf_data, pprof, sample, speedscope, vtune, Collapse,
+};
+use crate::filter::FoldRecursion;
 
 const LINES_PER_ITERATION: usize = 10;
 
@@ -11,6 +15,12 @@ const LINES_PER_ITERATION: usize = 10;
 #[derive(Clone, Debug)]
 #[non_exhaustive]
 pub struct Options {
+    /// Merge recursive calls into a single frame: either only where a function calls itself
+    /// directly, or also for cycles through other functions.
+    ///
+    /// Default is `None`.
+    pub fold_recursion: Option<FoldRecursion>,
+
     /// The number of threads to use.
     ///
     /// Default is the number of logical cores on your machine.
@@ -20,6 +30,7 @@ pub struct Options {
 impl Default for Options {
     fn default() -> Self {
         Self {
+            fold_recursion: None,
             nthreads: *collapse::DEFAULT_NTHREADS,
         }
     }
@@ -55,6 +66,7 @@ impl Collapse for Folder {
     {
         let mut dtrace = {
             let options = dtrace::Options {
+                fold_recursion: self.opt.fold_recursion,
                 nthreads: self.opt.nthreads,
                 ..Default::default()
             };
@@ -62,31 +74,134 @@ impl Collapse for Folder {
         };
         let mut perf = {
             let options = perf::Options {
+                fold_recursion: self.opt.fold_recursion,
                 nthreads: self.opt.nthreads,
                 ..Default::default()
             };
             perf::Folder::from(options)
         };
-        let mut sample = sample::Folder::default();
-        let mut vtune = vtune::Folder::default();
+        let mut perf_data = {
+            let mut options = perf_data::Options::default();
+            options.perf.fold_recursion = self.opt.fold_recursion;
+            options.perf.nthreads = self.opt.nthreads;
+            perf_data::Folder::from(options)
+        };
+        let mut sample = {
+            let options = sample::Options {
+                fold_recursion: self.opt.fold_recursion,
+                nthreads: self.opt.nthreads,
+                ..Default::default()
+            };
+            sample::Folder::from(options)
+        };
+        let mut vtune = {
+            let options = vtune::Options {
+                fold_recursion: self.opt.fold_recursion,
+                nthreads: self.opt.nthreads,
+                ..Default::default()
+            };
+            vtune::Folder::from(options)
+        };
+        let mut chrome = {
+            let options = chrome::Options {
+                fold_recursion: self.opt.fold_recursion,
+                ..Default::default()
+            };
+            chrome::Folder::from(options)
+        };
+        let mut pprof = {
+            let options = pprof::Options {
+                fold_recursion: self.opt.fold_recursion,
+                ..Default::default()
+            };
+            pprof::Folder::from(options)
+        };
+        let mut gecko = {
+            let options = gecko::Options {
+                fold_recursion: self.opt.fold_recursion,
+                ..Default::default()
+            };
+            gecko::Folder::from(options)
+        };
+        let mut speedscope = {
+            let options = speedscope::Options {
+                fold_recursion: self.opt.fold_recursion,
+                ..Default::default()
+            };
+            speedscope::Folder::from(options)
+        };
+        let mut callgrind = {
+            let options = callgrind::Options {
+                fold_recursion: self.opt.fold_recursion,
+                ..Default::default()
+            };
+            callgrind::Folder::from(options)
+        };
+        let mut gdb = {
+            let options = gdb::Options {
+                fold_recursion: self.opt.fold_recursion,
+                ..Default::default()
+            };
+            gdb::Folder::from(options)
+        };
+        let mut eu_stack = {
+            let options = eu_stack::Options {
+                fold_recursion: self.opt.fold_recursion,
+                ..Default::default()
+            };
+            eu_stack::Folder::from(options)
+        };
+        let mut jstack = {
+            let options = jstack::Options {
+                fold_recursion: self.opt.fold_recursion,
+                ..Default::default()
+            };
+            jstack::Folder::from(options)
+        };
+        let mut goroutine = {
+            let options = goroutine::Options {
+                fold_recursion: self.opt.fold_recursion,
+                ..Default::default()
+            };
+            goroutine::Folder::from(options)
+        };
+        let mut bpftrace = {
+            let options = bpftrace::Options {
+                fold_recursion: self.opt.fold_recursion,
+                ..Default::default()
+            };
+            bpftrace::Folder::from(options)
+        };
+        let mut bcc = {
+            let options = bcc::Options {
+                fold_recursion: self.opt.fold_recursion,
+            };
+            bcc::Folder::from(options)
+        };
 
         // Each Collapse impl gets its own flag in this array.
         // It gets set to true when the impl has been ruled out.
-        let mut not_applicable = [false; 4];
+        let mut not_applicable = [false; 16];
 
-        let mut buffer = String::new();
+        // Some formats (like pprof) are binary, so we buffer raw bytes and only hand the
+        // collapsers a lossily decoded view of them.
+        let mut buffer = Vec::new();
         loop {
             let mut eof = false;
             for _ in 0..LINES_PER_ITERATION {
-                if reader.read_line(&mut buffer)? == 0 {
+                if reader.read_until(b'\n', &mut buffer)? == 0 {
                     eof = true;
                 }
             }
+            let input = String::from_utf8_lossy(&buffer);
 
             macro_rules! try_collapse_impl {
                 ($collapse:ident, $index:expr) => {
+                    try_collapse_impl!($collapse, $index, $collapse.is_applicable(&input))
+                };
+                ($collapse:ident, $index:expr, $is_applicable:expr) => {
                     if !not_applicable[$index] {
-                        match $collapse.is_applicable(&buffer) {
+                        match $is_applicable {
                             Some(false) => {
                                 // We can rule this collapser out.
                                 not_applicable[$index] = true;
@@ -94,6 +209,7 @@ impl Collapse for Folder {
                             Some(true) => {
                                 // We found a collapser that works! Let's use it.
                                 info!("Using {} collapser", stringify!($collapse));
+                                drop(input);
                                 let cursor = Cursor::new(buffer).chain(reader);
                                 return $collapse.collapse(cursor, writer);
                             }
@@ -102,10 +218,24 @@ impl Collapse for Folder {
                     }
                 };
             }
+            // perf.data is recognized by its magic bytes, so check it before the text formats.
+            try_collapse_impl!(perf_data, 6);
             try_collapse_impl!(perf, 0);
             try_collapse_impl!(dtrace, 1);
             try_collapse_impl!(sample, 2);
             try_collapse_impl!(vtune, 3);
+            try_collapse_impl!(chrome, 4);
+            // Compressed profiles only survive as raw bytes.
+            try_collapse_impl!(pprof, 5, pprof.is_applicable_bytes(&buffer));
+            try_collapse_impl!(gecko, 7);
+            try_collapse_impl!(speedscope, 8);
+            try_collapse_impl!(callgrind, 9);
+            try_collapse_impl!(gdb, 10);
+            try_collapse_impl!(eu_stack, 11);
+            try_collapse_impl!(jstack, 12);
+            try_collapse_impl!(goroutine, 13);
+            try_collapse_impl!(bpftrace, 14);
+            try_collapse_impl!(bcc, 15);
 
             if eof {
                 break;
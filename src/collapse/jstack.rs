@@ -0,0 +1,144 @@
+use std::io;
+
+use log::warn;
+
+use crate::collapse::common::{strip_trailing_arguments, Occurrences};
+use crate::collapse::Collapse;
+use crate::filter::FoldRecursion;
+
+// The line that starts every thread dump.
+static DUMP_LINE: &str = "Full thread dump";
+
+/// `jstack` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Merge recursive calls into a single frame: either only where a function calls itself
+    /// directly, or also for cycles through other functions.
+    ///
+    /// Default is `None`.
+    pub fold_recursion: Option<FoldRecursion>,
+
+    /// Start each stack with the name of its thread.
+    ///
+    /// Default is `false`.
+    pub include_thread: bool,
+}
+
+/// A stack collapser for the Java thread dumps printed by `jstack`, `jcmd <pid> Thread.print` or
+/// `kill -3`, for "poor man's profiling" with repeated thread dumps.
+///
+/// Identical stacks are counted across all the dumps in the input, so a file that holds many
+/// dumps of the same JVM gives a profile of where the threads spent their time. Source locations
+/// and lock annotations are dropped, leaving only method names. Threads without any Java frames,
+/// like the JVM's own GC and compiler threads, are skipped.
+///
+/// To construct one, either use `jstack::Folder::default()` or create an [`Options`] and use
+/// `jstack::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(1);
+        let mut thread: Option<String> = None;
+        let mut frames: Vec<String> = Vec::new();
+        let mut line = Vec::new();
+        let mut nstacks = 0;
+        loop {
+            line.clear();
+            if reader.read_until(b'\n', &mut line)? == 0 {
+                break;
+            }
+            let l = String::from_utf8_lossy(&line);
+            let l = l.trim_end();
+
+            if let Some(frame) = l.trim_start().strip_prefix("at ") {
+                // Semicolons separate frames in the folded format.
+                frames.push(strip_trailing_arguments(frame).replace(';', ":"));
+                continue;
+            }
+            // Lock annotations like `- locked <0x000000070ff1a2b8> (a java.lang.Object)` and the
+            // thread state come between frames.
+            let indented = l.starts_with(char::is_whitespace);
+            if indented && !l.trim().is_empty() {
+                continue;
+            }
+
+            // Anything else ends the current stack.
+            nstacks += self.on_stack_end(thread.as_deref(), &mut frames, &mut occurrences);
+            thread = thread_name(l);
+        }
+        nstacks += self.on_stack_end(thread.as_deref(), &mut frames, &mut occurrences);
+
+        if nstacks == 0 {
+            warn!("File contains no Java stacks");
+        }
+        occurrences.write_and_clear(writer, self.opt.fold_recursion)
+    }
+
+    /// Check for the line that starts a thread dump, or for a thread header.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        for line in input.lines() {
+            if line.starts_with(DUMP_LINE) {
+                return Some(true);
+            }
+            if line.starts_with('"') && line.contains(" nid=") {
+                return Some(true);
+            }
+        }
+        None
+    }
+}
+
+impl Folder {
+    /// Records the stack made up of `frames`, if any, and returns how many stacks were recorded.
+    fn on_stack_end(
+        &self,
+        thread: Option<&str>,
+        frames: &mut Vec<String>,
+        occurrences: &mut Occurrences,
+    ) -> usize {
+        if frames.is_empty() {
+            return 0;
+        }
+        let mut stack = String::new();
+        if self.opt.include_thread {
+            stack.push_str(thread.unwrap_or("[unknown]"));
+            stack.push(';');
+        }
+        // jstack prints the innermost frame first.
+        for (i, frame) in frames.iter().rev().enumerate() {
+            if i != 0 {
+                stack.push(';');
+            }
+            stack.push_str(frame);
+        }
+        occurrences.insert_or_add(stack, 1);
+        frames.clear();
+        1
+    }
+}
+
+/// Returns the quoted name at the start of a thread header like
+/// `"main" #1 prio=5 os_prio=0 tid=0x00007f8c4c00a800 nid=0x1c03 waiting on condition`.
+fn thread_name(line: &str) -> Option<String> {
+    let rest = line.strip_prefix('"')?;
+    // Thread names may themselves contain quotes, so look for the quote that ends the name.
+    let end = rest
+        .rfind("\" ")
+        .or_else(|| rest.strip_suffix('"').map(str::len))?;
+    Some(rest[..end].replace(';', ":"))
+}
@@ -1,6 +1,34 @@
 #[macro_use]
 pub(crate) mod common;
 
+/// Stack collapsing for the stacks printed by [BCC](https://github.com/iovisor/bcc)'s `profile` and `offcputime` tools.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod bcc;
+
+/// Stack collapsing for maps of stacks printed by [`bpftrace`](https://github.com/iovisor/bpftrace).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod bpftrace;
+
+/// Stack collapsing for the `callgrind.out.<pid>` files written by [Valgrind's callgrind](https://valgrind.org/docs/manual/cl-manual.html).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod callgrind;
+
+/// Stack collapsing for the `.cpuprofile` files written by [Chrome DevTools](https://developer.chrome.com/docs/devtools/) and by Node.js's `--cpu-prof` flag.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod chrome;
+
 /// Stack collapsing for the output of [`dtrace`](https://www.joyent.com/dtrace).
 ///
 /// See the [crate-level documentation] for details.
@@ -8,9 +36,47 @@ pub(crate) mod common;
 ///   [crate-level documentation]: ../../index.html
 pub mod dtrace;
 
+/// Stack collapsing for repeated thread dumps taken with [`eu-stack`](https://sourceware.org/elfutils/).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod eu_stack;
+
+/// Stack collapsing for profiles in the processed JSON format of the [Firefox Profiler](https://profiler.firefox.com/).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod gecko;
+
+/// Internal ELF and kallsyms symbol lookup helpers
+pub(crate) mod elf;
+
+/// Stack collapsing for repeated thread dumps taken with [gdb](https://www.sourceware.org/gdb/)'s `thread apply all bt`.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod gdb;
+
+/// Stack collapsing for repeated Go goroutine dumps, as printed on `SIGQUIT`.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod goroutine;
+
 /// Attempts to use whichever Collapse implementation is appropriate for a given input
 pub mod guess;
 
+/// Stack collapsing for repeated Java thread dumps taken with [`jstack`](https://docs.oracle.com/en/java/javase/17/docs/specs/man/jstack.html).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod jstack;
+
 /// Stack collapsing for the output of [`perf script`](https://linux.die.net/man/1/perf-script).
 ///
 /// See the [crate-level documentation] for details.
@@ -18,6 +84,20 @@ pub mod guess;
 ///   [crate-level documentation]: ../../index.html
 pub mod perf;
 
+/// Stack collapsing for the binary `perf.data` files written by [`perf record`](https://linux.die.net/man/1/perf-record).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod perf_data;
+
+/// Stack collapsing for profiles in the [pprof](https://github.com/google/pprof) protobuf format.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod pprof;
+
 /// Internal string match helper functions for perf
 pub(crate) mod matcher;
 
@@ -28,6 +108,16 @@ pub(crate) mod matcher;
 ///   [crate-level documentation]: ../../index.html
 pub mod sample;
 
+/// Stack collapsing for [speedscope](https://www.speedscope.app/) JSON files.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod speedscope;
+
+/// Internal offline symbolization of unresolved frames
+pub(crate) mod symbolize;
+
 /// Stack collapsing for the output of [`VTune`](https://software.intel.com/en-us/vtune-amplifier-help-command-line-interface).
 ///
 /// See the [crate-level documentation] for details.
@@ -45,6 +135,7 @@ use std::io;
 use std::path::Path;
 
 use self::common::{CollapsePrivate, CAPACITY_READER};
+use crate::compression;
 
 /// The abstract behavior of stack collapsing.
 ///
@@ -67,6 +158,8 @@ pub trait Collapse {
 
     /// Collapses the contents of the provided file (or of STDIN if `infile` is `None`) and
     /// writes folded stack lines to provided `writer`.
+    ///
+    /// Input compressed with gzip, zstd or xz is decompressed on the fly.
     fn collapse_file<P, W>(&mut self, infile: Option<P>, writer: W) -> io::Result<()>
     where
         P: AsRef<Path>,
@@ -76,13 +169,13 @@ pub trait Collapse {
             Some(ref path) => {
                 let file = File::open(path)?;
                 let reader = io::BufReader::with_capacity(CAPACITY_READER, file);
-                self.collapse(reader, writer)
+                self.collapse(compression::decompress(reader)?, writer)
             }
             None => {
                 let stdin = io::stdin();
                 let stdin_guard = stdin.lock();
                 let reader = io::BufReader::with_capacity(CAPACITY_READER, stdin_guard);
-                self.collapse(reader, writer)
+                self.collapse(compression::decompress(reader)?, writer)
             }
         }
     }
@@ -1,8 +1,12 @@
 use std::collections::VecDeque;
 use std::io::{self, BufRead};
+use std::path::PathBuf;
+use std::sync::Arc;
 
 use crate::collapse::common::{self, CollapsePrivate, Occurrences};
 use crate::collapse::matcher::is_kernel;
+use crate::collapse::symbolize::{Address, PerfMaps, Symbolizer};
+use crate::filter::FoldRecursion;
 
 const TIDY_GENERIC: bool = true;
 const TIDY_JAVA: bool = true;
@@ -21,6 +25,13 @@ mod logging {
     pub(super) fn weird_stack_line(line: &str) {
         warn!("Weird stack line: {}", line);
     }
+
+    pub(super) fn event_line_without_period(line: &str) {
+        warn!(
+            "Event line has no sample period, counting its samples as 1: {}",
+            line
+        );
+    }
 }
 
 #[derive(PartialEq)]
@@ -50,6 +61,12 @@ pub struct Options {
     /// Default is `None`.
     pub event_filter: Option<String>,
 
+    /// Merge recursive calls into a single frame: either only where a function calls itself
+    /// directly, or also for cycles through other functions.
+    ///
+    /// Default is `None`.
+    pub fold_recursion: Option<FoldRecursion>,
+
     /// Include raw addresses (e.g., `0xbfff0836`) where symbols can't be found.
     ///
     /// Default is `false`.
@@ -66,6 +83,18 @@ pub struct Options {
     /// Default is `false`.
     pub include_tid: bool,
 
+    /// Directory holding the `perf-<pid>.map` files of JIT-compiled code, which is used to name
+    /// `[unknown]` frames in `/tmp/perf-<pid>.map` that `perf script` could not resolve (for
+    /// example because the profile was recorded on another machine). Map files are read the
+    /// first time a frame of their process is seen.
+    ///
+    /// Frames in the `jitted-<pid>-<n>.so` files written by `perf inject --jit` are not resolved
+    /// from map files. Those are ELF files, so add their directory to [`Options::symbol_path`]
+    /// instead.
+    ///
+    /// Default is `None`.
+    pub perf_map_dir: Option<PathBuf>,
+
     /// The number of threads to use.
     ///
     /// Default is the number of logical cores on your machine.
@@ -76,6 +105,35 @@ pub struct Options {
     /// In case no function is matched the whole stack is returned.
     /// Default is `None`.
     pub skip_after: Option<String>,
+
+    /// Binaries, or directories of binaries, to symbolize `[unknown]` frames with. Binaries are
+    /// looked up by the path they were profiled at relative to each directory, and then by file
+    /// name. Separate debug info is found through `.gnu_debuglink` and `.build-id` directories.
+    ///
+    /// Addresses are looked up as the binary was linked, which is right for executables that
+    /// aren't position-independent. For shared libraries and PIEs, run `perf script` with
+    /// `-F +dsoff` so that it prints the offset into the binary along with its path.
+    ///
+    /// Requires the `symbolize` feature.
+    ///
+    /// Default is empty, which disables symbolization.
+    pub symbol_path: Vec<PathBuf>,
+
+    /// When symbolizing `[unknown]` frames, include the functions inlined at the address as
+    /// separate frames (annotated with `_[i]`), using the binary's DWARF debug info.
+    ///
+    /// Default is `false`.
+    pub symbolize_inlines: bool,
+
+    /// Weight each sample by the event period reported by `perf script` (e.g., the `257597` in
+    /// `vote 913 72.176760: 257597 cycles:uppp:`) instead of counting it once. This gives
+    /// correct results when samples were recorded with varying frequencies or with `-c`.
+    ///
+    /// Event lines that do not include a period are counted as 1, and a warning is logged the
+    /// first time this happens.
+    ///
+    /// Default is `false`.
+    pub use_period: bool,
 }
 
 impl Default for Options {
@@ -84,11 +142,16 @@ impl Default for Options {
             annotate_jit: false,
             annotate_kernel: false,
             event_filter: None,
+            fold_recursion: None,
             include_addrs: false,
             include_pid: false,
             include_tid: false,
             nthreads: *common::DEFAULT_NTHREADS,
+            perf_map_dir: None,
             skip_after: None,
+            symbol_path: Vec::new(),
+            symbolize_inlines: false,
+            use_period: false,
         }
     }
 }
@@ -119,6 +182,9 @@ pub struct Folder {
     /// The number of stacks per job to send to the threadpool.
     nstacks_per_job: usize,
 
+    /// The weight of the current event. Always 1 unless `use_period` is set.
+    period: usize,
+
     /// Current comm name.
     ///
     /// Called pname after original stackcollapse-perf source.
@@ -130,6 +196,15 @@ pub struct Folder {
     /// Function entries on the stack in this entry thus far.
     stack: VecDeque<String>,
 
+    /// Resolves `[unknown]` JIT frames, if a perf map directory was given. Shared between threads.
+    perf_maps: Option<Arc<PerfMaps>>,
+
+    /// Resolves `[unknown]` frames, if a symbol path was given. Shared between threads.
+    symbolizer: Option<Arc<Symbolizer>>,
+
+    /// Whether we have already warned about an event line without a period.
+    warned_missing_period: bool,
+
     // Options...
     opt: Options,
 }
@@ -140,14 +215,30 @@ impl From<Options> for Folder {
             opt.nthreads = 1;
         }
         opt.include_pid = opt.include_pid || opt.include_tid;
+        let symbolizer = if opt.symbol_path.is_empty() {
+            None
+        } else {
+            Some(Arc::new(Symbolizer::new(
+                opt.symbol_path.clone(),
+                opt.symbolize_inlines,
+            )))
+        };
+        let perf_maps = opt
+            .perf_map_dir
+            .clone()
+            .map(|dir| Arc::new(PerfMaps::new(dir)));
         Self {
             cache_line: Vec::default(),
             event_filter: opt.event_filter.clone(),
             in_event: false,
             nstacks_per_job: common::DEFAULT_NSTACKS_PER_JOB,
+            perf_maps,
+            period: 1,
             pname: String::default(),
             stack_filter: StackFilter::Keep,
             stack: VecDeque::default(),
+            symbolizer,
+            warned_missing_period: false,
             opt,
         }
     }
@@ -259,9 +350,13 @@ impl CollapsePrivate for Folder {
             event_filter: self.event_filter.clone(),
             in_event: false,
             nstacks_per_job: self.nstacks_per_job,
+            perf_maps: self.perf_maps.clone(),
+            period: 1,
             pname: String::new(),
             stack_filter: StackFilter::Keep,
             stack: VecDeque::default(),
+            symbolizer: self.symbolizer.clone(),
+            warned_missing_period: self.warned_missing_period,
             opt: self.opt.clone(),
         }
     }
@@ -281,6 +376,10 @@ impl CollapsePrivate for Folder {
     fn set_nthreads(&mut self, n: usize) {
         self.opt.nthreads = n;
     }
+
+    fn fold_recursion(&self) -> Option<FoldRecursion> {
+        self.opt.fold_recursion
+    }
 }
 
 impl Folder {
@@ -375,9 +474,27 @@ impl Folder {
 
         if let Some((comm, pid, tid, end)) = Self::event_line_parts(line) {
             let mut by_colons = line[end..].splitn(3, ':').skip(1);
-            let event = by_colons
-                .next()
-                .and_then(|has_event| has_event.rsplit(' ').next());
+            let mut has_event = by_colons.next().map(|s| s.rsplitn(2, ' '));
+            let event = has_event.as_mut().and_then(|words| words.next());
+            if self.opt.use_period {
+                // the period, if present, is the word right before the event name:
+                //
+                //     vote   913    72.176760:     257597 cycles:uppp:
+                let period = has_event
+                    .and_then(|mut words| words.next())
+                    .and_then(|period| period.trim().rsplit(' ').next())
+                    .and_then(|period| period.parse::<usize>().ok());
+                match period {
+                    Some(period) => self.period = period,
+                    None => {
+                        if !self.warned_missing_period {
+                            logging::event_line_without_period(line);
+                            self.warned_missing_period = true;
+                        }
+                        self.period = 1;
+                    }
+                }
+            }
             if let Some(event) = event {
                 if let Some(ref event_filter) = self.event_filter {
                     if event != event_filter {
@@ -503,6 +620,13 @@ impl Folder {
                 return;
             }
 
+            let symbolized = if rawfunc == "[unknown]" {
+                self.symbolize(pc, module)
+            } else {
+                None
+            };
+            let rawfunc = symbolized.as_deref().unwrap_or(rawfunc);
+
             // perf mostly demangles Rust symbols,
             // but this will fix the things it gets wrong
             let rawfunc = common::fix_partially_demangled_rust_symbol(rawfunc);
@@ -560,6 +684,51 @@ impl Folder {
         }
     }
 
+    // Resolves an unknown frame using the symbol path or perf map directory, if one was given.
+    // Frames are either
+    //
+    //     401136 [unknown] (/usr/bin/demo)
+    //
+    // or, with `perf script -F +dsoff`, include the offset into the binary:
+    //
+    //     7f0000001136 [unknown] (/usr/lib/libdemo.so+0x1136)
+    //
+    // or are in JIT-compiled code:
+    //
+    //     7f722d142778 [unknown] (/tmp/perf-19982.map)
+    fn symbolize(&self, pc: &str, module: &str) -> Option<String> {
+        // Callers' addresses are return addresses, which may already belong to the next line or
+        // function, so look up the call instruction before them instead.
+        let is_leaf = self.stack.is_empty() && self.cache_line.is_empty();
+
+        if let Some(pid) = PerfMaps::pid_of(module) {
+            let perf_maps = self.perf_maps.as_ref()?;
+            let address = u64::from_str_radix(pc, 16).ok()?;
+            let address = if is_leaf {
+                address
+            } else {
+                address.saturating_sub(1)
+            };
+            return perf_maps.symbolize(pid, address);
+        }
+
+        let symbolizer = self.symbolizer.as_ref()?;
+        let (module, address) = match module.rfind("+0x") {
+            Some(offset) => {
+                let file_offset = u64::from_str_radix(&module[(offset + 3)..], 16).ok()?;
+                (&module[..offset], Address::FileOffset(file_offset))
+            }
+            None => (module, Address::Virtual(u64::from_str_radix(pc, 16).ok()?)),
+        };
+
+        let address = match address {
+            _ if is_leaf => address,
+            Address::Virtual(a) => Address::Virtual(a.saturating_sub(1)),
+            Address::FileOffset(o) => Address::FileOffset(o.saturating_sub(1)),
+        };
+        symbolizer.symbolize(module, address)
+    }
+
     fn after_event(&mut self, occurrences: &mut Occurrences) {
         // end of stack, so emit stack entry
         if !self.stack.is_empty() {
@@ -583,11 +752,12 @@ impl Folder {
             stack_str.pop();
 
             // count it!
-            occurrences.insert_or_add(stack_str, 1);
+            occurrences.insert_or_add(stack_str, self.period);
         }
 
         // reset for the next event
         self.in_event = false;
+        self.period = 1;
         self.stack_filter = StackFilter::Keep;
         self.stack.clear();
     }
@@ -844,11 +1014,20 @@ mod tests {
                 annotate_jit: rng.gen(),
                 annotate_kernel: rng.gen(),
                 event_filter: None,
+                fold_recursion: if rng.gen() {
+                    Some(FoldRecursion::Cycles)
+                } else {
+                    None
+                },
                 include_addrs: rng.gen(),
                 include_pid: rng.gen(),
                 include_tid: rng.gen(),
                 nthreads: rng.gen_range(2..=32),
+                perf_map_dir: None,
                 skip_after: None,
+                symbol_path: Vec::new(),
+                symbolize_inlines: false,
+                use_period: rng.gen(),
             };
 
             for (path, input) in inputs.iter() {
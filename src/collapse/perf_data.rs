@@ -0,0 +1,920 @@
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use ahash::AHashMap;
+use log::{debug, warn};
+
+use crate::collapse::common::CAPACITY_READER;
+use crate::collapse::elf::SymbolTable;
+use crate::collapse::perf;
+use crate::collapse::Collapse;
+use crate::compression;
+
+// Every perf.data file starts with these bytes (for little-endian recordings).
+const MAGIC: &[u8] = b"PERFILE2";
+
+// The header of a regular perf.data file is 104 bytes, while that of one written to a pipe
+// (`perf record -o -`) is only 16 bytes, and is followed directly by records.
+const PIPE_HEADER_SIZE: u64 = 16;
+
+// Records are decoded into `perf script` text until at least this many bytes of it are buffered.
+const SCRIPT_CHUNK_SIZE: usize = 64 * 1024;
+
+// Record types, see `include/uapi/linux/perf_event.h` and `tools/perf/util/event.h`.
+const RECORD_MMAP: u32 = 1;
+const RECORD_COMM: u32 = 3;
+const RECORD_FORK: u32 = 7;
+const RECORD_SAMPLE: u32 = 9;
+const RECORD_MMAP2: u32 = 10;
+const RECORD_HEADER_ATTR: u32 = 64;
+const RECORD_HEADER_FEATURE: u32 = 80;
+
+// Bits of `perf_event_attr.sample_type`, in the order their fields appear in a sample.
+const SAMPLE_IDENTIFIER: u64 = 1 << 16;
+const SAMPLE_IP: u64 = 1 << 0;
+const SAMPLE_TID: u64 = 1 << 1;
+const SAMPLE_TIME: u64 = 1 << 2;
+const SAMPLE_ADDR: u64 = 1 << 3;
+const SAMPLE_ID: u64 = 1 << 6;
+const SAMPLE_STREAM_ID: u64 = 1 << 9;
+const SAMPLE_CPU: u64 = 1 << 7;
+const SAMPLE_PERIOD: u64 = 1 << 8;
+const SAMPLE_READ: u64 = 1 << 4;
+const SAMPLE_CALLCHAIN: u64 = 1 << 5;
+
+// Bits of `perf_event_attr.read_format`.
+const FORMAT_TOTAL_TIME_ENABLED: u64 = 1 << 0;
+const FORMAT_TOTAL_TIME_RUNNING: u64 = 1 << 1;
+const FORMAT_ID: u64 = 1 << 2;
+const FORMAT_GROUP: u64 = 1 << 3;
+const FORMAT_LOST: u64 = 1 << 4;
+
+// Bits of the flags word of `perf_event_attr`.
+const ATTR_EXCLUDE_USER: u64 = 1 << 4;
+const ATTR_EXCLUDE_KERNEL: u64 = 1 << 5;
+const ATTR_EXCLUDE_HV: u64 = 1 << 6;
+const ATTR_PRECISE_IP_SHIFT: u64 = 15;
+
+const MISC_CPUMODE_MASK: u16 = 0x7;
+const MISC_KERNEL: u16 = 1;
+const MISC_COMM_EXEC: u16 = 1 << 13;
+
+// Callchain entries at or above this value mark the context of the entries that follow.
+const CONTEXT_MAX: u64 = -4095i64 as u64;
+const CONTEXT_KERNEL: u64 = -128i64 as u64;
+const CONTEXT_USER: u64 = -512i64 as u64;
+
+// Feature section holding the names of the recorded events.
+const FEATURE_EVENT_DESC: usize = 12;
+
+// Kernel mappings are recorded for this pid (-1).
+const KERNEL_PID: u32 = u32::MAX;
+
+/// `perf.data` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// How to fold the decoded samples. These options have the same meaning as they do for the
+    /// [`perf`](crate::collapse::perf) collapser.
+    pub perf: perf::Options,
+
+    /// Directory to look up binaries in, as if it were the root of the machine the profile was
+    /// recorded on (like `perf report --symfs`). If this option is set to `None`, binaries are
+    /// looked up at the paths they were recorded with. Frames are only named with the `symbolize`
+    /// feature.
+    ///
+    /// Default is `None`.
+    pub symfs: Option<PathBuf>,
+
+    /// A copy of `/proc/kallsyms` from the machine the profile was recorded on, used to name
+    /// kernel frames. If this option is set to `None`, kernel frames are left unnamed.
+    ///
+    /// Default is `None`.
+    pub kallsyms: Option<PathBuf>,
+}
+
+/// A stack collapser for the binary `perf.data` files written by `perf record`.
+///
+/// Samples are read directly from the file, without `perf script`, and are symbolized using the
+/// symbol tables of the binaries they were recorded in (see [`Options::symfs`]). The decoded
+/// samples are then folded exactly like the [`perf`](crate::collapse::perf) collapser folds the
+/// output of `perf script`.
+///
+/// Records are decoded as they are read, so the file is never held in memory whole, except when
+/// a regular (not pipe mode) recording is read from a stream that can't seek, like STDIN.
+///
+/// Only the parts of the format needed for stack collapsing are supported: event attributes and
+/// names, and `MMAP`, `MMAP2`, `COMM`, `FORK` and `SAMPLE` records.
+///
+/// To construct one, either use `perf_data::Folder::default()` or create an [`Options`] and use
+/// `perf_data::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut header = [0; PIPE_HEADER_SIZE as usize];
+        let n = read_full(&mut reader, &mut header)?;
+        if n == 0 {
+            warn!("File is empty");
+            return Ok(());
+        }
+        if !header[..n].starts_with(MAGIC) {
+            return invalid_data_error!("Not a perf.data file (or not a little-endian one)");
+        }
+        if n < header.len() {
+            return invalid_data_error!("perf.data file is truncated");
+        }
+
+        if u64::from_le_bytes(header[8..].try_into().unwrap()) == PIPE_HEADER_SIZE {
+            // In pipe mode, the records follow the header, so they can be decoded as they're read.
+            let decoder = Decoder::new(&self.opt)?;
+            return self.collapse_records(decoder, reader, writer);
+        }
+
+        // Otherwise the names of the events come after the samples, so the file has to be read
+        // out of order. Without a way to seek in the input, that means reading all of it.
+        let mut input = header.to_vec();
+        reader.read_to_end(&mut input)?;
+        self.collapse_seekable(io::Cursor::new(input), writer)
+    }
+
+    /// Collapses the contents of the provided file (or of STDIN if `infile` is `None`) and
+    /// writes folded stack lines to provided `writer`.
+    ///
+    /// Uncompressed files are read out of order instead of into memory. Input compressed with
+    /// gzip, zstd or xz is decompressed on the fly.
+    fn collapse_file<P, W>(&mut self, infile: Option<P>, writer: W) -> io::Result<()>
+    where
+        P: AsRef<Path>,
+        W: io::Write,
+    {
+        match infile {
+            Some(ref path) => {
+                let file = File::open(path)?;
+                let mut reader = io::BufReader::with_capacity(CAPACITY_READER, file);
+                if reader.fill_buf()?.starts_with(MAGIC) {
+                    return self.collapse_seekable(reader, writer);
+                }
+                self.collapse(compression::decompress(reader)?, writer)
+            }
+            None => {
+                let stdin = io::stdin();
+                let stdin_guard = stdin.lock();
+                let reader = io::BufReader::with_capacity(CAPACITY_READER, stdin_guard);
+                self.collapse(compression::decompress(reader)?, writer)
+            }
+        }
+    }
+
+    /// Check for the magic bytes at the start of every perf.data file.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let input = input.as_bytes();
+        if input.len() < MAGIC.len() {
+            return if MAGIC.starts_with(input) {
+                None
+            } else {
+                Some(false)
+            };
+        }
+        Some(input.starts_with(MAGIC))
+    }
+}
+
+impl Folder {
+    /// Collapses a perf.data file that can be read out of order.
+    fn collapse_seekable<R, W>(&self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead + Seek,
+        W: io::Write,
+    {
+        let mut decoder = Decoder::new(&self.opt)?;
+        let (start, end) = decoder.read_sections(&mut Sections::new(&mut reader)?)?;
+        reader.seek(SeekFrom::Start(start))?;
+        self.collapse_records(decoder, reader.take(end - start), writer)
+    }
+
+    /// Collapses the samples among the records read from `records`.
+    fn collapse_records<R, W>(&self, decoder: Decoder<'_>, records: R, writer: W) -> io::Result<()>
+    where
+        R: io::Read,
+        W: io::Write,
+    {
+        // Decode the samples into the same text `perf script` would produce, and let the perf
+        // collapser take it from there so the two produce identical output.
+        let script = Script {
+            decoder,
+            records,
+            body: Vec::new(),
+            buf: Vec::new(),
+            pos: 0,
+            done: false,
+        };
+        perf::Folder::from(self.opt.perf.clone()).collapse(script, writer)
+    }
+}
+
+/// A recorded event and the information needed to decode its samples.
+#[derive(Clone, Debug, Default)]
+struct Attr {
+    ty: u32,
+    config: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    ids: Vec<u64>,
+    name: Option<String>,
+}
+
+impl Attr {
+    fn parse(buf: &[u8]) -> io::Result<Self> {
+        let mut bytes = Bytes::new(buf);
+        let ty = bytes.u32()?;
+        bytes.skip(4)?; // size
+        let config = bytes.u64()?;
+        bytes.skip(8)?; // sample_period
+        let sample_type = bytes.u64()?;
+        let read_format = bytes.u64()?;
+        let flags = bytes.u64()?;
+        Ok(Attr {
+            ty,
+            config,
+            sample_type,
+            read_format,
+            flags,
+            ids: Vec::new(),
+            name: None,
+        })
+    }
+
+    /// Names the event the way `perf` would if the file didn't tell us its name.
+    fn fallback_name(&self) -> String {
+        let base = match (self.ty, self.config) {
+            (0, 0) => "cycles".to_string(),
+            (0, 1) => "instructions".to_string(),
+            (0, 2) => "cache-references".to_string(),
+            (0, 3) => "cache-misses".to_string(),
+            (0, 4) => "branches".to_string(),
+            (0, 5) => "branch-misses".to_string(),
+            (1, 0) => "cpu-clock".to_string(),
+            (1, 1) => "task-clock".to_string(),
+            (1, 2) => "page-faults".to_string(),
+            (1, 3) => "context-switches".to_string(),
+            (1, 4) => "cpu-migrations".to_string(),
+            (1, 5) => "minor-faults".to_string(),
+            (1, 6) => "major-faults".to_string(),
+            (ty, config) => format!("raw-{}-{:#x}", ty, config),
+        };
+
+        let mut modifiers = String::new();
+        if self.flags & ATTR_EXCLUDE_KERNEL != 0 && self.flags & ATTR_EXCLUDE_HV != 0 {
+            modifiers.push('u');
+        } else if self.flags & ATTR_EXCLUDE_USER != 0 {
+            modifiers.push('k');
+        }
+        for _ in 0..((self.flags >> ATTR_PRECISE_IP_SHIFT) & 0x3) {
+            modifiers.push('p');
+        }
+
+        if modifiers.is_empty() {
+            base
+        } else {
+            format!("{}:{}", base, modifiers)
+        }
+    }
+}
+
+/// A memory mapping of a file (or of the kernel) into a process.
+#[derive(Clone, Debug)]
+struct Map {
+    start: u64,
+    end: u64,
+    pgoff: u64,
+    filename: String,
+}
+
+/// The fields of a sample record that we care about.
+#[derive(Debug, Default)]
+struct Sample {
+    ip: Option<u64>,
+    pid: u32,
+    tid: u32,
+    time: u64,
+    period: Option<u64>,
+    callchain: Vec<u64>,
+}
+
+struct Decoder<'a> {
+    opt: &'a Options,
+    attrs: Vec<Attr>,
+    comms: AHashMap<u32, String>,
+    maps: AHashMap<u32, Vec<Map>>,
+    binaries: AHashMap<String, Option<SymbolTable>>,
+    kallsyms: Option<SymbolTable>,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(opt: &'a Options) -> io::Result<Self> {
+        let kallsyms = match opt.kallsyms {
+            Some(ref path) => Some(SymbolTable::from_kallsyms(path)?),
+            None => None,
+        };
+        Ok(Decoder {
+            opt,
+            attrs: Vec::new(),
+            comms: AHashMap::default(),
+            maps: AHashMap::default(),
+            binaries: AHashMap::default(),
+            kallsyms,
+        })
+    }
+
+    /// Reads the header of a perf.data file, along with the attributes and names of its events,
+    /// and returns where its records start and end.
+    fn read_sections<R: Read + Seek>(&mut self, file: &mut Sections<R>) -> io::Result<(u64, u64)> {
+        let start = file.read(0, PIPE_HEADER_SIZE.min(file.len))?;
+        if !start.starts_with(MAGIC) {
+            return invalid_data_error!("Not a perf.data file (or not a little-endian one)");
+        }
+        let header_size = Bytes::new(&start[MAGIC.len()..]).u64()?;
+        if header_size == PIPE_HEADER_SIZE {
+            // In pipe mode, attributes and features arrive as records.
+            return Ok((PIPE_HEADER_SIZE, file.len));
+        }
+
+        let header = file.read(0, header_size)?;
+        let mut header = Bytes::new(&header);
+        header.skip(MAGIC.len() + 8)?;
+        let attr_size = header.u64()?;
+        let (attrs_offset, attrs_size) = (header.u64()?, header.u64()?);
+        let (data_offset, data_size) = (header.u64()?, header.u64()?);
+        header.skip(16)?; // event_types
+        let features = [header.u64()?, header.u64()?, header.u64()?, header.u64()?];
+
+        let data_end = section_end(data_offset, data_size)?;
+        self.read_attrs(file, attr_size, attrs_offset, attrs_size)?;
+        self.read_features(file, &features, data_end)?;
+        file.check(data_offset, data_end)?;
+        Ok((data_offset, data_end))
+    }
+
+    fn read_attrs<R: Read + Seek>(
+        &mut self,
+        file: &mut Sections<R>,
+        attr_size: u64,
+        offset: u64,
+        size: u64,
+    ) -> io::Result<()> {
+        if attr_size <= 16 {
+            return invalid_data_error!("Invalid attribute size {} in perf.data", attr_size);
+        }
+        let attrs = file.read(offset, section_end(offset, size)?)?;
+        for entry in attrs.chunks(attr_size as usize) {
+            // Each entry is a perf_event_attr followed by the file section holding its ids.
+            let (attr, ids) = entry.split_at(entry.len().saturating_sub(16));
+            let mut attr = Attr::parse(attr)?;
+            let mut ids = Bytes::new(ids);
+            let (ids_offset, ids_size) = (ids.u64()?, ids.u64()?);
+            let ids = file.read(ids_offset, section_end(ids_offset, ids_size)?)?;
+            let mut ids = Bytes::new(&ids);
+            while !ids.is_empty() {
+                attr.ids.push(ids.u64()?);
+            }
+            self.attrs.push(attr);
+        }
+        Ok(())
+    }
+
+    fn read_features<R: Read + Seek>(
+        &mut self,
+        file: &mut Sections<R>,
+        features: &[u64; 4],
+        offset: u64,
+    ) -> io::Result<()> {
+        // The sections of all present features follow the data, in order of their feature bit.
+        let nfeatures: u64 = features
+            .iter()
+            .map(|bits| u64::from(bits.count_ones()))
+            .sum();
+        let size = match nfeatures.checked_mul(16) {
+            Some(size) => size,
+            None => return invalid_data_error!("Invalid feature bits in perf.data"),
+        };
+        let sections = file.read(offset, section_end(offset, size)?)?;
+        let mut sections = Bytes::new(&sections);
+        for bit in 0..256 {
+            if features[bit / 64] & (1 << (bit % 64)) == 0 {
+                continue;
+            }
+            let (offset, size) = (sections.u64()?, sections.u64()?);
+            if bit == FEATURE_EVENT_DESC {
+                self.read_event_desc(&file.read(offset, section_end(offset, size)?)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_event_desc(&mut self, buf: &[u8]) -> io::Result<()> {
+        let mut desc = Bytes::new(buf);
+        let nr = desc.u32()?;
+        let attr_size = desc.u32()? as usize;
+        for i in 0..nr as usize {
+            desc.skip(attr_size)?;
+            let nr_ids = desc.u32()?;
+            let name = desc.string()?;
+            let mut ids = Vec::with_capacity(nr_ids as usize);
+            for _ in 0..nr_ids {
+                ids.push(desc.u64()?);
+            }
+
+            // Match descriptions to attributes by id, falling back to their order.
+            let index = self
+                .attrs
+                .iter()
+                .position(|attr| attr.ids.iter().any(|id| ids.contains(id)))
+                .unwrap_or(i);
+            if let Some(attr) = self.attrs.get_mut(index) {
+                attr.name = Some(name);
+            }
+        }
+        Ok(())
+    }
+
+    fn on_record<W: Write>(
+        &mut self,
+        ty: u32,
+        misc: u16,
+        body: &[u8],
+        writer: &mut W,
+    ) -> io::Result<()> {
+        let mut bytes = Bytes::new(body);
+        match ty {
+            RECORD_SAMPLE => {
+                if self.attrs.is_empty() {
+                    return invalid_data_error!("Sample found before any event attributes");
+                }
+                let (attr, sample) = self.parse_sample(body)?;
+                let event = attr.name.clone().unwrap_or_else(|| attr.fallback_name());
+                self.write_sample(&event, misc, &sample, writer)?;
+            }
+            RECORD_MMAP | RECORD_MMAP2 => {
+                let pid = bytes.u32()?;
+                bytes.skip(4)?; // tid
+                let start = bytes.u64()?;
+                let len = bytes.u64()?;
+                let pgoff = bytes.u64()?;
+                if ty == RECORD_MMAP2 {
+                    // device and inode (or build id), protection and flags
+                    bytes.skip(32)?;
+                }
+                let filename = bytes.c_string()?;
+                let maps = self.maps.entry(pid).or_default();
+                maps.push(Map {
+                    start,
+                    end: start.saturating_add(len),
+                    pgoff,
+                    filename,
+                });
+            }
+            RECORD_COMM => {
+                let pid = bytes.u32()?;
+                let tid = bytes.u32()?;
+                let comm = bytes.c_string()?;
+                if misc & MISC_COMM_EXEC != 0 {
+                    // The process was replaced; its new mappings will follow.
+                    self.maps.remove(&pid);
+                }
+                self.comms.insert(tid, comm);
+            }
+            RECORD_FORK => {
+                let pid = bytes.u32()?;
+                let ppid = bytes.u32()?;
+                let tid = bytes.u32()?;
+                let ptid = bytes.u32()?;
+                if let Some(comm) = self.comms.get(&ptid).cloned() {
+                    self.comms.entry(tid).or_insert(comm);
+                }
+                if pid != ppid {
+                    if let Some(maps) = self.maps.get(&ppid).cloned() {
+                        self.maps.insert(pid, maps);
+                    }
+                }
+            }
+            RECORD_HEADER_ATTR => {
+                let mut attr = Attr::parse(body)?;
+                let size = u32::from_le_bytes(slice(body, 4, 8)?.try_into().unwrap()) as usize;
+                bytes.skip(size)?;
+                while !bytes.is_empty() {
+                    attr.ids.push(bytes.u64()?);
+                }
+                self.attrs.push(attr);
+            }
+            RECORD_HEADER_FEATURE if bytes.u64()? == FEATURE_EVENT_DESC as u64 => {
+                self.read_event_desc(&body[8..])?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn parse_sample(&self, body: &[u8]) -> io::Result<(&Attr, Sample)> {
+        // All events must agree on where the sample id is, so use the first to find it.
+        let attr = if self.attrs.len() == 1 {
+            &self.attrs[0]
+        } else {
+            let sample_type = self.attrs[0].sample_type;
+            let id_offset = if sample_type & SAMPLE_IDENTIFIER != 0 {
+                Some(0)
+            } else if sample_type & SAMPLE_ID != 0 {
+                let preceding = [SAMPLE_IP, SAMPLE_TID, SAMPLE_TIME, SAMPLE_ADDR];
+                Some(8 * preceding.iter().filter(|&&b| sample_type & b != 0).count() as u64)
+            } else {
+                None
+            };
+            let id = match id_offset {
+                Some(offset) => {
+                    u64::from_le_bytes(slice(body, offset, offset + 8)?.try_into().unwrap())
+                }
+                None => return invalid_data_error!("Cannot tell which event a sample belongs to"),
+            };
+            match self.attrs.iter().find(|attr| attr.ids.contains(&id)) {
+                Some(attr) => attr,
+                None => return invalid_data_error!("Sample has unknown event id {}", id),
+            }
+        };
+
+        let sample_type = attr.sample_type;
+        let mut sample = Sample::default();
+        let mut bytes = Bytes::new(body);
+        if sample_type & SAMPLE_IDENTIFIER != 0 {
+            bytes.skip(8)?;
+        }
+        if sample_type & SAMPLE_IP != 0 {
+            sample.ip = Some(bytes.u64()?);
+        }
+        if sample_type & SAMPLE_TID != 0 {
+            sample.pid = bytes.u32()?;
+            sample.tid = bytes.u32()?;
+        }
+        if sample_type & SAMPLE_TIME != 0 {
+            sample.time = bytes.u64()?;
+        }
+        for &field in &[SAMPLE_ADDR, SAMPLE_ID, SAMPLE_STREAM_ID, SAMPLE_CPU] {
+            if sample_type & field != 0 {
+                bytes.skip(8)?;
+            }
+        }
+        if sample_type & SAMPLE_PERIOD != 0 {
+            sample.period = Some(bytes.u64()?);
+        }
+        if sample_type & SAMPLE_READ != 0 {
+            let format = attr.read_format;
+            let per_value =
+                1 + ((format & FORMAT_ID != 0) as usize) + ((format & FORMAT_LOST != 0) as usize);
+            let times = ((format & FORMAT_TOTAL_TIME_ENABLED != 0) as usize)
+                + ((format & FORMAT_TOTAL_TIME_RUNNING != 0) as usize);
+            if format & FORMAT_GROUP != 0 {
+                let nr = bytes.u64()? as usize;
+                bytes.skip(8 * (times + nr * per_value))?;
+            } else {
+                bytes.skip(8 * (times + per_value))?;
+            }
+        }
+        if sample_type & SAMPLE_CALLCHAIN != 0 {
+            let nr = bytes.u64()?;
+            for _ in 0..nr {
+                sample.callchain.push(bytes.u64()?);
+            }
+        }
+        Ok((attr, sample))
+    }
+
+    fn write_sample<W: Write>(
+        &mut self,
+        event: &str,
+        misc: u16,
+        sample: &Sample,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        let ips = if !sample.callchain.is_empty() {
+            sample.callchain.clone()
+        } else if let Some(ip) = sample.ip {
+            vec![ip]
+        } else {
+            return Ok(());
+        };
+
+        let comm = self
+            .comms
+            .get(&sample.tid)
+            .or_else(|| self.comms.get(&sample.pid))
+            .map(|s| s.as_str())
+            .unwrap_or(":-1");
+        write!(
+            writer,
+            "{} {}/{} {}.{:06}: ",
+            comm,
+            sample.pid,
+            sample.tid,
+            sample.time / 1_000_000_000,
+            (sample.time % 1_000_000_000) / 1000
+        )?;
+        if let Some(period) = sample.period {
+            write!(writer, "{} ", period)?;
+        }
+        writeln!(writer, "{}:", event)?;
+
+        let mut in_kernel = misc & MISC_CPUMODE_MASK == MISC_KERNEL;
+        for ip in ips {
+            if ip >= CONTEXT_MAX {
+                match ip {
+                    CONTEXT_KERNEL => in_kernel = true,
+                    CONTEXT_USER => in_kernel = false,
+                    _ => {}
+                }
+                continue;
+            }
+            let (symbol, dso) = self.resolve(sample.pid, ip, in_kernel);
+            writeln!(
+                writer,
+                "\t{:x} {} ({})",
+                ip,
+                symbol.as_deref().unwrap_or("[unknown]"),
+                dso.as_deref().unwrap_or("[unknown]")
+            )?;
+        }
+        writeln!(writer)?;
+        Ok(())
+    }
+
+    /// Finds the name of the function and of the binary an address falls into.
+    fn resolve(&mut self, pid: u32, ip: u64, in_kernel: bool) -> (Option<String>, Option<String>) {
+        let pid = if in_kernel { KERNEL_PID } else { pid };
+        let map = self
+            .maps
+            .get(&pid)
+            .and_then(|maps| maps.iter().rev().find(|m| ip >= m.start && ip < m.end))
+            .cloned();
+
+        if in_kernel {
+            let symbol = self
+                .kallsyms
+                .as_ref()
+                .and_then(|kallsyms| kallsyms.lookup(ip))
+                .map(|s| s.to_string());
+            let dso = match map {
+                // The main kernel mapping is named after the symbol it starts at.
+                Some(ref map) if map.filename.starts_with("[kernel.kallsyms]") => {
+                    "[kernel.kallsyms]".to_string()
+                }
+                Some(map) => map.filename,
+                None => "[kernel.kallsyms]".to_string(),
+            };
+            return (symbol, Some(dso));
+        }
+
+        let map = match map {
+            Some(map) => map,
+            None => return (None, None),
+        };
+        if map.filename == "//anon" || map.filename.starts_with("[anon") {
+            // Like perf, attribute anonymous executable memory to the process's JIT map.
+            return (None, Some(format!("/tmp/perf-{}.map", pid)));
+        }
+
+        let symbols = self.symbols_for(&map.filename);
+        let symbol = symbols.and_then(|symbols| {
+            let address = symbols.file_offset_to_address(ip - map.start + map.pgoff);
+            symbols.lookup(address).map(|s| s.to_string())
+        });
+        (symbol, Some(map.filename))
+    }
+
+    fn symbols_for(&mut self, filename: &str) -> Option<&SymbolTable> {
+        if !self.binaries.contains_key(filename) {
+            let path = match self.opt.symfs {
+                Some(ref root) => root.join(filename.trim_start_matches('/')),
+                None => PathBuf::from(filename),
+            };
+            let symbols = match SymbolTable::from_elf(&path) {
+                Ok(symbols) => Some(symbols),
+                Err(e) => {
+                    if filename.starts_with('/') {
+                        warn!("Unable to read symbols from {}: {}", path.display(), e);
+                    } else {
+                        debug!("Unable to read symbols from {}: {}", path.display(), e);
+                    }
+                    None
+                }
+            };
+            self.binaries.insert(filename.to_string(), symbols);
+        }
+        self.binaries.get(filename).and_then(|s| s.as_ref())
+    }
+}
+
+/// The `perf script` text for the records of a perf.data file, decoded a chunk at a time as it is
+/// read.
+struct Script<'a, R> {
+    decoder: Decoder<'a>,
+    records: R,
+    /// The body of the record being decoded.
+    body: Vec<u8>,
+    /// Decoded text, of which everything before `pos` has been read.
+    buf: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl<'a, R: Read> Script<'a, R> {
+    /// Decodes records until a chunk of text is buffered, or until the records run out.
+    fn decode_chunk(&mut self) -> io::Result<()> {
+        self.buf.clear();
+        self.pos = 0;
+        while !self.done && self.buf.len() < SCRIPT_CHUNK_SIZE {
+            let mut header = [0; 8];
+            match read_full(&mut self.records, &mut header)? {
+                0 => {
+                    self.done = true;
+                    break;
+                }
+                8 => {}
+                _ => return invalid_data_error!("perf.data file is truncated"),
+            }
+            let mut header = Bytes::new(&header);
+            let ty = header.u32()?;
+            let misc = header.u16()?;
+            let size = header.u16()? as usize;
+            if size < 8 {
+                return invalid_data_error!("Invalid record size {} in perf.data", size);
+            }
+            self.body.resize(size - 8, 0);
+            if read_full(&mut self.records, &mut self.body)? < self.body.len() {
+                return invalid_data_error!("perf.data file is truncated");
+            }
+            self.decoder
+                .on_record(ty, misc, &self.body, &mut self.buf)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, R: Read> Read for Script<'a, R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let text = self.fill_buf()?;
+            let n = text.len().min(out.len());
+            out[..n].copy_from_slice(&text[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<'a, R: Read> BufRead for Script<'a, R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.buf.len() && !self.done {
+            self.decode_chunk()?;
+        }
+        Ok(&self.buf[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.buf.len());
+    }
+}
+
+/// A perf.data file whose sections are read out of order.
+struct Sections<R> {
+    reader: R,
+    len: u64,
+}
+
+impl<R: Read + Seek> Sections<R> {
+    fn new(mut reader: R) -> io::Result<Self> {
+        let len = reader.seek(SeekFrom::End(0))?;
+        Ok(Sections { reader, len })
+    }
+
+    /// Checks that the section from `start` to `end` lies within the file.
+    fn check(&self, start: u64, end: u64) -> io::Result<()> {
+        if start.max(end) > self.len {
+            return invalid_data_error!("perf.data file is truncated");
+        }
+        if start > end {
+            return invalid_data_error!("Invalid section {}..{} in perf.data", start, end);
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, start: u64, end: u64) -> io::Result<Vec<u8>> {
+        self.check(start, end)?;
+        self.reader.seek(SeekFrom::Start(start))?;
+        let mut buf = vec![0; (end - start) as usize];
+        self.reader.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+/// Returns where the section of `size` bytes at `offset` of a perf.data file ends, or an error if
+/// that is past the largest possible offset.
+fn section_end(offset: u64, size: u64) -> io::Result<u64> {
+    match offset.checked_add(size) {
+        Some(end) => Ok(end),
+        None => invalid_data_error!("Invalid section {}+{} in perf.data", offset, size),
+    }
+}
+
+/// Reads into `buf` until it is full or the reader runs out, and returns how much was read.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match reader.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(read) => n += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+fn slice(buf: &[u8], start: u64, end: u64) -> io::Result<&[u8]> {
+    if start.max(end) > buf.len() as u64 {
+        return invalid_data_error!("perf.data file is truncated");
+    }
+    if start > end {
+        return invalid_data_error!("Invalid section {}..{} in perf.data", start, end);
+    }
+    Ok(&buf[start as usize..end as usize])
+}
+
+/// A little-endian reader over a byte slice.
+struct Bytes<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Bytes<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Bytes { buf }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.buf.len() < n {
+            return invalid_data_error!("Unexpected end of perf.data record");
+        }
+        let (taken, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(taken)
+    }
+
+    fn skip(&mut self, n: usize) -> io::Result<()> {
+        self.take(n).map(|_| ())
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /// A NUL-terminated string padded to a multiple of 8 bytes, taking up the rest of a record.
+    fn c_string(&mut self) -> io::Result<String> {
+        let rest = self.take(self.buf.len())?;
+        let end = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+        Ok(String::from_utf8_lossy(&rest[..end]).into_owned())
+    }
+
+    /// A `perf_header_string`: a length followed by that many bytes of NUL-padded string.
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len)?;
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+    }
+}
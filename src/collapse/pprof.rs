@@ -0,0 +1,510 @@
+use std::io;
+#[cfg(feature = "compression")]
+use std::io::Read;
+
+use ahash::AHashMap;
+#[cfg(feature = "compression")]
+use flate2::read::GzDecoder;
+use log::{info, warn};
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::filter::FoldRecursion;
+
+// Every gzip stream starts with these two bytes.
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// `pprof` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Merge recursive calls into a single frame: either only where a function calls itself
+    /// directly, or also for cycles through other functions.
+    ///
+    /// Default is `None`.
+    pub fold_recursion: Option<FoldRecursion>,
+
+    /// The sample value to use as the count for each stack, given either as an index into the
+    /// profile's `sample_type` list or as the name of a sample type (e.g., `samples`, `cpu`,
+    /// `alloc_space`). This mirrors the `-sample_index` flag of `go tool pprof`.
+    ///
+    /// If this option is set to `None`, the profile's default sample type is used if it has one,
+    /// and the last sample type otherwise.
+    ///
+    /// Default is `None`.
+    pub sample_index: Option<String>,
+}
+
+/// A stack collapser for profiles in the [pprof protobuf format], such as the `profile.pb.gz`
+/// files served by Go's `net/http/pprof`.
+///
+/// Both gzip-compressed and uncompressed profiles are supported, though compressed ones need the
+/// `compression` feature. Inlined functions are expanded into separate frames.
+///
+/// To construct one, either use `pprof::Folder::default()` or create an [`Options`] and use
+/// `pprof::Folder::from(options)`.
+///
+///   [pprof protobuf format]: https://github.com/google/pprof/blob/main/proto/profile.proto
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut input = Vec::new();
+        reader.read_to_end(&mut input)?;
+        if input.is_empty() {
+            warn!("File is empty");
+            return Ok(());
+        }
+
+        if input.starts_with(GZIP_MAGIC) {
+            input = gunzip(&input)?;
+        }
+
+        let profile = Profile::decode(&input)?;
+        let mut occurrences = Occurrences::new(1);
+        self.collapse_profile(&profile, &mut occurrences)?;
+        occurrences.write_and_clear(writer, self.opt.fold_recursion)
+    }
+
+    /// Check for the `sample_type` field that starts an uncompressed profile.
+    ///
+    /// Gzip-compressed profiles can't be recognized from the lossily decoded text this is given,
+    /// so [`guess`](crate::collapse::guess) looks at the raw bytes with `is_applicable_bytes`.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        self.is_applicable_bytes(input.as_bytes())
+    }
+}
+
+impl Folder {
+    /// Check for the `sample_type` field that starts a profile, after decompressing the start of
+    /// the input if it is gzip-compressed.
+    pub(crate) fn is_applicable_bytes(&mut self, input: &[u8]) -> Option<bool> {
+        if input.starts_with(GZIP_MAGIC) {
+            gzipped_starts_with_sample_type(input)
+        } else {
+            starts_with_sample_type(input)
+        }
+    }
+
+    fn collapse_profile(&self, profile: &Profile, occurrences: &mut Occurrences) -> io::Result<()> {
+        if profile.sample_types.is_empty() {
+            return invalid_data_error!("Profile has no sample types");
+        }
+        let index = self.sample_index(profile)?;
+        let (ty, unit) = profile.sample_types[index];
+        info!(
+            "Using sample type: {} ({})",
+            profile.string(ty)?,
+            profile.string(unit)?
+        );
+
+        let mut frames = Vec::new();
+        for sample in &profile.samples {
+            let value = match sample.values.get(index) {
+                Some(&value) => value,
+                None => return invalid_data_error!("Sample is missing value {}", index),
+            };
+            if value <= 0 {
+                continue;
+            }
+
+            // Locations are listed leaf first, and within a location the inlined functions come
+            // before the function they were inlined into.
+            frames.clear();
+            for id in sample.location_ids.iter().rev() {
+                let location = match profile.locations.get(id) {
+                    Some(location) => location,
+                    None => return invalid_data_error!("Sample refers to unknown location {}", id),
+                };
+                if location.lines.is_empty() {
+                    frames.push(profile.unsymbolized_name(location)?);
+                    continue;
+                }
+                for line in location.lines.iter().rev() {
+                    let function = match profile.functions.get(&line.function_id) {
+                        Some(function) => function,
+                        None => {
+                            return invalid_data_error!(
+                                "Location {} refers to unknown function {}",
+                                id,
+                                line.function_id
+                            )
+                        }
+                    };
+                    frames.push(profile.string(function.name)?.replace(';', ":"));
+                }
+            }
+
+            if !frames.is_empty() {
+                occurrences.insert_or_add(frames.join(";"), value as usize);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn sample_index(&self, profile: &Profile) -> io::Result<usize> {
+        let requested = match self.opt.sample_index {
+            Some(ref requested) => requested,
+            None => {
+                if profile.default_sample_type != 0 {
+                    let default = profile
+                        .sample_types
+                        .iter()
+                        .position(|(ty, _)| *ty == profile.default_sample_type);
+                    if let Some(index) = default {
+                        return Ok(index);
+                    }
+                }
+                return Ok(profile.sample_types.len() - 1);
+            }
+        };
+
+        if let Ok(index) = requested.parse::<usize>() {
+            if index >= profile.sample_types.len() {
+                return invalid_data_error!(
+                    "Sample index {} is out of range; profile has {} sample types",
+                    index,
+                    profile.sample_types.len()
+                );
+            }
+            return Ok(index);
+        }
+
+        for (index, (ty, _)) in profile.sample_types.iter().enumerate() {
+            if profile.string(*ty)? == requested {
+                return Ok(index);
+            }
+        }
+        let available = profile
+            .sample_types
+            .iter()
+            .map(|(ty, _)| profile.string(*ty))
+            .collect::<io::Result<Vec<_>>>()?;
+        invalid_data_error!(
+            "Profile has no sample type named {}; available types are: {}",
+            requested,
+            available.join(", ")
+        )
+    }
+}
+
+/// The subset of a decoded `perftools.profiles.Profile` message that we need to fold stacks.
+#[derive(Debug, Default)]
+struct Profile {
+    /// `(type, unit)` string indices of each sample value.
+    sample_types: Vec<(i64, i64)>,
+    samples: Vec<Sample>,
+    /// Mapping id to the string index of the mapped file name.
+    mappings: AHashMap<u64, i64>,
+    locations: AHashMap<u64, Location>,
+    functions: AHashMap<u64, Function>,
+    strings: Vec<String>,
+    default_sample_type: i64,
+}
+
+#[derive(Debug, Default)]
+struct Sample {
+    location_ids: Vec<u64>,
+    values: Vec<i64>,
+}
+
+#[derive(Debug, Default)]
+struct Location {
+    mapping_id: u64,
+    address: u64,
+    lines: Vec<Line>,
+}
+
+#[derive(Debug, Default)]
+struct Line {
+    function_id: u64,
+}
+
+#[derive(Debug, Default)]
+struct Function {
+    name: i64,
+}
+
+impl Profile {
+    fn decode(buf: &[u8]) -> io::Result<Self> {
+        let mut profile = Profile::default();
+        let mut fields = Fields::new(buf);
+        while let Some((number, value)) = fields.next_field()? {
+            match number {
+                1 => {
+                    let mut ty = (0, 0);
+                    let mut fields = Fields::new(value.bytes()?);
+                    while let Some((number, value)) = fields.next_field()? {
+                        match number {
+                            1 => ty.0 = value.varint()? as i64,
+                            2 => ty.1 = value.varint()? as i64,
+                            _ => {}
+                        }
+                    }
+                    profile.sample_types.push(ty);
+                }
+                2 => profile.samples.push(Sample::decode(value.bytes()?)?),
+                3 => {
+                    let (mut id, mut filename) = (0, 0);
+                    let mut fields = Fields::new(value.bytes()?);
+                    while let Some((number, value)) = fields.next_field()? {
+                        match number {
+                            1 => id = value.varint()?,
+                            5 => filename = value.varint()? as i64,
+                            _ => {}
+                        }
+                    }
+                    profile.mappings.insert(id, filename);
+                }
+                4 => {
+                    let (id, location) = Location::decode(value.bytes()?)?;
+                    profile.locations.insert(id, location);
+                }
+                5 => {
+                    let (mut id, mut function) = (0, Function::default());
+                    let mut fields = Fields::new(value.bytes()?);
+                    while let Some((number, value)) = fields.next_field()? {
+                        match number {
+                            1 => id = value.varint()?,
+                            2 => function.name = value.varint()? as i64,
+                            _ => {}
+                        }
+                    }
+                    profile.functions.insert(id, function);
+                }
+                6 => {
+                    let s = String::from_utf8_lossy(value.bytes()?).into_owned();
+                    profile.strings.push(s);
+                }
+                14 => profile.default_sample_type = value.varint()? as i64,
+                _ => {}
+            }
+        }
+        Ok(profile)
+    }
+
+    fn string(&self, index: i64) -> io::Result<&str> {
+        match self.strings.get(index as usize) {
+            Some(s) if index >= 0 => Ok(s),
+            _ => invalid_data_error!("String table index {} is out of range", index),
+        }
+    }
+
+    // Locations without line information have not been symbolized. Mirror what the perf
+    // collapser does for unknown frames and use the name of the binary they belong to.
+    fn unsymbolized_name(&self, location: &Location) -> io::Result<String> {
+        if let Some(&filename) = self.mappings.get(&location.mapping_id) {
+            let filename = self.string(filename)?;
+            if !filename.is_empty() {
+                let module = &filename[filename.rfind('/').map(|i| i + 1).unwrap_or(0)..];
+                return Ok(format!("[{}]", module.replace(';', ":")));
+            }
+        }
+        Ok(format!("{:#x}", location.address))
+    }
+}
+
+impl Sample {
+    fn decode(buf: &[u8]) -> io::Result<Self> {
+        let mut sample = Sample::default();
+        let mut fields = Fields::new(buf);
+        while let Some((number, value)) = fields.next_field()? {
+            match number {
+                1 => value.repeated_varint(|v| sample.location_ids.push(v))?,
+                2 => value.repeated_varint(|v| sample.values.push(v as i64))?,
+                _ => {}
+            }
+        }
+        Ok(sample)
+    }
+}
+
+impl Location {
+    fn decode(buf: &[u8]) -> io::Result<(u64, Self)> {
+        let (mut id, mut location) = (0, Location::default());
+        let mut fields = Fields::new(buf);
+        while let Some((number, value)) = fields.next_field()? {
+            match number {
+                1 => id = value.varint()?,
+                2 => location.mapping_id = value.varint()?,
+                3 => location.address = value.varint()?,
+                4 => {
+                    let mut line = Line::default();
+                    let mut fields = Fields::new(value.bytes()?);
+                    while let Some((number, value)) = fields.next_field()? {
+                        if number == 1 {
+                            line.function_id = value.varint()?;
+                        }
+                    }
+                    location.lines.push(line);
+                }
+                _ => {}
+            }
+        }
+        Ok((id, location))
+    }
+}
+
+/// Returns whether `input` starts like a profile, with its first sample type: field 1
+/// (length-delimited), containing field 1 (`type`, varint) and field 2 (`unit`, varint).
+///
+/// Returns `None` if `input` is too short to tell.
+fn starts_with_sample_type(input: &[u8]) -> Option<bool> {
+    if input.len() < 5 {
+        return None;
+    }
+    Some(input[0] == 0x0a && input[1] < 0x80 && input[2] == 0x08 && input[4] == 0x10)
+}
+
+/// Like [`starts_with_sample_type`], for the start of a gzip stream.
+#[cfg(feature = "compression")]
+fn gzipped_starts_with_sample_type(input: &[u8]) -> Option<bool> {
+    let mut decoder = GzDecoder::new(input);
+    let mut start = [0; 5];
+    let mut len = 0;
+    while len < start.len() {
+        match decoder.read(&mut start[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            // We may only have been given part of the compressed stream.
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(_) => return Some(false),
+        }
+    }
+    starts_with_sample_type(&start[..len])
+}
+
+/// Gzipped profiles can't be read without the `compression` feature.
+#[cfg(not(feature = "compression"))]
+fn gzipped_starts_with_sample_type(_input: &[u8]) -> Option<bool> {
+    Some(false)
+}
+
+#[cfg(feature = "compression")]
+fn gunzip(input: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    GzDecoder::new(input).read_to_end(&mut decompressed)?;
+    Ok(decompressed)
+}
+
+#[cfg(not(feature = "compression"))]
+fn gunzip(_input: &[u8]) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "inferno was built without the `compression` feature, so gzipped profiles can't be read",
+    ))
+}
+
+/// A single field value in the protobuf wire format.
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+impl<'a> Value<'a> {
+    fn varint(&self) -> io::Result<u64> {
+        match *self {
+            Value::Varint(v) => Ok(v),
+            _ => invalid_data_error!("Expected a varint field in pprof profile"),
+        }
+    }
+
+    fn bytes(&self) -> io::Result<&'a [u8]> {
+        match *self {
+            Value::Bytes(b) => Ok(b),
+            _ => invalid_data_error!("Expected a length-delimited field in pprof profile"),
+        }
+    }
+
+    /// Repeated scalar fields may be encoded one value per field, or packed into a single
+    /// length-delimited field.
+    fn repeated_varint<F>(&self, mut f: F) -> io::Result<()>
+    where
+        F: FnMut(u64),
+    {
+        match *self {
+            Value::Varint(v) => f(v),
+            Value::Bytes(b) => {
+                let mut packed = Fields::new(b);
+                while !packed.buf.is_empty() {
+                    f(packed.varint()?);
+                }
+            }
+            Value::Fixed => {
+                return invalid_data_error!("Expected a varint field in pprof profile");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A minimal reader for the protobuf wire format.
+struct Fields<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Fields { buf }
+    }
+
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut value = 0u64;
+        for (i, byte) in self.buf.iter().enumerate().take(10) {
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                self.buf = &self.buf[i + 1..];
+                return Ok(value);
+            }
+        }
+        invalid_data_error!("Invalid varint in pprof profile")
+    }
+
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.buf.len() < n {
+            return invalid_data_error!("Unexpected end of pprof profile");
+        }
+        let (taken, rest) = self.buf.split_at(n);
+        self.buf = rest;
+        Ok(taken)
+    }
+
+    fn next_field(&mut self) -> io::Result<Option<(u64, Value<'a>)>> {
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let value = match key & 0x7 {
+            0 => Value::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                Value::Fixed
+            }
+            2 => {
+                let len = self.varint()? as usize;
+                Value::Bytes(self.take(len)?)
+            }
+            5 => {
+                self.take(4)?;
+                Value::Fixed
+            }
+            ty => return invalid_data_error!("Unsupported wire type {} in pprof profile", ty),
+        };
+        Ok(Some((key >> 3, value)))
+    }
+}
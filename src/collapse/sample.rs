@@ -2,8 +2,8 @@ use std::io::{self, BufRead};
 
 use log::warn;
 
-use crate::collapse::common::{self, Occurrences};
-use crate::collapse::Collapse;
+use crate::collapse::common::{self, CollapsePrivate, Occurrences};
+use crate::filter::FoldRecursion;
 
 // The set of symbols to ignore for 'waiting' threads, for ease of use.
 // This will hide waiting threads from the view, making it easier to
@@ -29,20 +29,41 @@ static START_LINE: &str = "Call graph:";
 static END_LINE: &str = "Total number in stack";
 
 /// `sample` folder configuration options.
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug)]
 #[non_exhaustive]
 pub struct Options {
+    /// Merge recursive calls into a single frame: either only where a function calls itself
+    /// directly, or also for cycles through other functions.
+    ///
+    /// Default is `None`.
+    pub fold_recursion: Option<FoldRecursion>,
+
     /// Don't include modules with function names.
     ///
     /// Default is `false`.
     pub no_modules: bool,
+
+    /// The number of threads to use.
+    ///
+    /// Default is the number of logical cores on your machine.
+    pub nthreads: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            fold_recursion: None,
+            no_modules: false,
+            nthreads: *common::DEFAULT_NTHREADS,
+        }
+    }
 }
 
 /// A stack collapser for the output of `sample` on macOS.
 ///
 /// To construct one, either use `sample::Folder::default()` or create an [`Options`] and use
 /// `sample::Folder::from(options)`.
-#[derive(Clone, Default)]
+#[derive(Clone)]
 pub struct Folder {
     /// Number of samples for the current stack frame.
     current_samples: usize,
@@ -50,31 +71,84 @@ pub struct Folder {
     /// Function on the stack in this entry thus far.
     stack: Vec<String>,
 
+    /// Whether the input has a call graph at all.
+    has_call_graph: bool,
+
+    /// The number of stacks per job to send to the threadpool.
+    nstacks_per_job: usize,
+
+    /// Lines of the call graph leading to the current line, used to cut the call graph into
+    /// chunks for the threadpool.
+    chunk_context: Vec<Vec<u8>>,
+
+    /// Whether we've read past the end of the call graph while cutting it into chunks.
+    past_call_graph: bool,
+
     opt: Options,
 }
 
-impl Collapse for Folder {
-    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+impl From<Options> for Folder {
+    fn from(mut opt: Options) -> Self {
+        if opt.nthreads == 0 {
+            opt.nthreads = 1;
+        }
+        Self {
+            current_samples: 0,
+            stack: Vec::default(),
+            has_call_graph: false,
+            nstacks_per_job: common::DEFAULT_NSTACKS_PER_JOB,
+            chunk_context: Vec::default(),
+            past_call_graph: false,
+            opt,
+        }
+    }
+}
+
+impl Default for Folder {
+    fn default() -> Self {
+        Options::default().into()
+    }
+}
+
+impl CollapsePrivate for Folder {
+    fn pre_process<R>(&mut self, reader: &mut R, _: &mut Occurrences) -> io::Result<()>
     where
         R: io::BufRead,
-        W: io::Write,
     {
+        self.chunk_context.clear();
+        self.past_call_graph = false;
+
         // Consume the header...
         let mut line = Vec::new();
         loop {
             line.clear();
             if reader.read_until(0x0A, &mut line)? == 0 {
                 warn!("File ended before start of call graph");
+                self.has_call_graph = false;
                 return Ok(());
             };
             let l = String::from_utf8_lossy(&line);
             if l.starts_with(START_LINE) {
-                break;
+                self.has_call_graph = true;
+                return Ok(());
             }
         }
+    }
+
+    fn collapse_single_threaded<R>(
+        &mut self,
+        mut reader: R,
+        occurrences: &mut Occurrences,
+    ) -> io::Result<()>
+    where
+        R: io::BufRead,
+    {
+        if !self.has_call_graph {
+            return Ok(());
+        }
 
         // Process the data...
-        let mut occurrences = Occurrences::new(1);
+        let mut line = Vec::new();
         loop {
             line.clear();
             if reader.read_until(0x0A, &mut line)? == 0 {
@@ -85,18 +159,15 @@ impl Collapse for Folder {
             if line.is_empty() {
                 continue;
             } else if line.starts_with("    ") {
-                self.on_line(line, &mut occurrences)?;
+                self.on_line(line, occurrences)?;
             } else if line.starts_with(END_LINE) {
-                self.write_stack(&mut occurrences);
+                self.write_stack(occurrences);
                 break;
             } else {
                 return invalid_data_error!("Stack line doesn't start with 4 spaces:\n{}", line);
             }
         }
 
-        // Write the results...
-        occurrences.write_and_clear(writer)?;
-
         // Reset the state...
         self.current_samples = 0;
         self.stack.clear();
@@ -127,14 +198,88 @@ impl Collapse for Folder {
         }
         None
     }
-}
 
-impl From<Options> for Folder {
-    fn from(opt: Options) -> Self {
-        Folder {
-            opt,
-            ..Default::default()
+    // The call graph is a tree, so no line ends a stack on its own; see `would_start_stack`.
+    fn would_end_stack(&mut self, _line: &[u8]) -> bool {
+        false
+    }
+
+    // A line starts a new stack if it's no deeper than the line before it, which means that line
+    // was a leaf. We keep track of the lines leading to the current one so that a chunk starting
+    // in the middle of a thread can be given the frames above it.
+    fn would_start_stack(&mut self, line: &[u8]) -> bool {
+        if self.past_call_graph {
+            return false;
+        }
+
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end();
+        if line.starts_with(END_LINE) {
+            self.past_call_graph = true;
+            return false;
+        } else if !line.starts_with("    ") {
+            return false;
+        }
+
+        // Malformed lines are left for the worker threads to report.
+        let depth = match line[4..].find(|c| !Self::is_indent_char(c)) {
+            Some(indent_chars) if indent_chars % 2 == 0 => indent_chars / 2 + 1,
+            _ => return false,
+        };
+        let prev_depth = self.chunk_context.len();
+        if depth > prev_depth + 1 {
+            return false;
         }
+
+        self.chunk_context.truncate(depth - 1);
+        self.chunk_context.push(format!("{}\n", line).into_bytes());
+        depth <= prev_depth
+    }
+
+    fn write_chunk_context(&mut self, chunk: &mut Vec<u8>, next_chunk: &mut Vec<u8>) {
+        // End the call graph of the first chunk, so its last stack is written out rather than
+        // treated as the file ending early.
+        chunk.extend_from_slice(END_LINE.as_bytes());
+        chunk.push(b'\n');
+
+        // And start the next one from the frames above its first line.
+        if let Some((_, parents)) = self.chunk_context.split_last() {
+            for parent in parents {
+                next_chunk.extend_from_slice(parent);
+            }
+        }
+    }
+
+    fn clone_and_reset_stack_context(&self) -> Self {
+        Self {
+            current_samples: 0,
+            stack: Vec::default(),
+            has_call_graph: self.has_call_graph,
+            nstacks_per_job: self.nstacks_per_job,
+            chunk_context: Vec::default(),
+            past_call_graph: false,
+            opt: self.opt.clone(),
+        }
+    }
+
+    fn nstacks_per_job(&self) -> usize {
+        self.nstacks_per_job
+    }
+
+    fn set_nstacks_per_job(&mut self, n: usize) {
+        self.nstacks_per_job = n;
+    }
+
+    fn nthreads(&self) -> usize {
+        self.opt.nthreads
+    }
+
+    fn set_nthreads(&mut self, n: usize) {
+        self.opt.nthreads = n;
+    }
+
+    fn fold_recursion(&self) -> Option<FoldRecursion> {
+        self.opt.fold_recursion
     }
 }
 
@@ -242,6 +387,135 @@ impl Folder {
                 }
             }
         }
-        occurrences.insert(self.stack.join(";"), self.current_samples);
+        occurrences.insert_or_add(self.stack.join(";"), self.current_samples);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use lazy_static::lazy_static;
+    use rand::prelude::*;
+
+    use super::*;
+    use crate::collapse::common;
+    use crate::collapse::Collapse;
+
+    lazy_static! {
+        static ref INPUT: Vec<PathBuf> = {
+            [
+                "./tests/data/collapse-sample/sample.txt",
+                "./tests/data/collapse-sample/large.txt.gz",
+            ]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+        };
+    }
+
+    #[test]
+    fn test_collapse_multi_sample() -> io::Result<()> {
+        let mut folder = Folder::default();
+        common::testing::test_collapse_multi(&mut folder, &INPUT)
+    }
+
+    #[test]
+    fn test_collapse_multi_sample_one_stack_per_job() -> io::Result<()> {
+        let mut folder = Folder {
+            nstacks_per_job: 1,
+            ..Folder::default()
+        };
+        common::testing::test_collapse_multi(&mut folder, &INPUT)
+    }
+
+    #[test]
+    fn test_collapse_multi_sample_end_before_call_graph_end() {
+        let path = "./tests/data/collapse-sample/end-before-call-graph-end.txt";
+        let input = std::fs::read(path).unwrap();
+        let mut folder = Folder {
+            nstacks_per_job: 1,
+            opt: Options {
+                nthreads: 12,
+                ..Options::default()
+            },
+            ..Folder::default()
+        };
+        let error =
+            <Folder as Collapse>::collapse(&mut folder, &input[..], io::sink()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    /// Varies the nstacks_per_job parameter and outputs the 10 fastests configurations by file.
+    ///
+    /// Command: `cargo test bench_nstacks_sample --release -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_nstacks_sample() -> io::Result<()> {
+        let mut folder = Folder::default();
+        common::testing::bench_nstacks(&mut folder, &INPUT)
+    }
+
+    #[test]
+    #[ignore]
+    /// Fuzz test the multithreaded collapser.
+    ///
+    /// Command: `cargo test fuzz_collapse_sample --release -- --ignored --nocapture`
+    fn fuzz_collapse_sample() -> io::Result<()> {
+        let seed = thread_rng().gen::<u64>();
+        println!("Random seed: {}", seed);
+        let mut rng = SmallRng::seed_from_u64(seed);
+
+        let mut buf_actual = Vec::new();
+        let mut buf_expected = Vec::new();
+        let mut count = 0;
+
+        let inputs = common::testing::read_inputs(&INPUT)?;
+
+        loop {
+            let nstacks_per_job = rng.gen_range(1..=500);
+            let options = Options {
+                fold_recursion: if rng.gen() {
+                    Some(FoldRecursion::Cycles)
+                } else {
+                    None
+                },
+                no_modules: rng.gen(),
+                nthreads: rng.gen_range(2..=32),
+            };
+
+            for (path, input) in inputs.iter() {
+                buf_actual.clear();
+                buf_expected.clear();
+
+                let mut folder = {
+                    let mut options = options.clone();
+                    options.nthreads = 1;
+                    Folder::from(options)
+                };
+                folder.nstacks_per_job = nstacks_per_job;
+                <Folder as Collapse>::collapse(&mut folder, &input[..], &mut buf_expected)?;
+                let expected = std::str::from_utf8(&buf_expected[..]).unwrap();
+
+                let mut folder = Folder::from(options.clone());
+                folder.nstacks_per_job = nstacks_per_job;
+                <Folder as Collapse>::collapse(&mut folder, &input[..], &mut buf_actual)?;
+                let actual = std::str::from_utf8(&buf_actual[..]).unwrap();
+
+                if actual != expected {
+                    eprintln!(
+                        "Failed on file: {}\noptions: {:#?}\n",
+                        path.display(),
+                        options
+                    );
+                    assert_eq!(actual, expected);
+                }
+            }
+
+            count += 1;
+            if count % 10 == 0 {
+                println!("Successfully ran {} fuzz tests.", count);
+            }
+        }
     }
 }
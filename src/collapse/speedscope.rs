@@ -0,0 +1,269 @@
+use std::io;
+
+use ahash::AHashMap;
+use log::warn;
+use serde::Deserialize;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::filter::FoldRecursion;
+
+// Every speedscope file points at the schema of the format.
+static SCHEMA: &str = "speedscope.app/file-format-schema.json";
+
+/// `speedscope` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Merge recursive calls into a single frame: either only where a function calls itself
+    /// directly, or also for cycles through other functions.
+    ///
+    /// Default is `None`.
+    pub fold_recursion: Option<FoldRecursion>,
+
+    /// Don't start each stack with the name of the profile it came from.
+    ///
+    /// Default is `false`.
+    pub no_profile_names: bool,
+}
+
+/// A stack collapser for [speedscope] JSON files, as written by py-spy, rbspy, dotnet-trace and
+/// other profilers.
+///
+/// Both `sampled` and `evented` profiles are supported. The stacks of a sampled profile are
+/// counted by their weights. For an evented profile, the time between one open or close event
+/// and the next is counted towards the stack that was open at the time, so each stack is
+/// weighted by its self time in the unit of the profile.
+///
+/// Each stack starts with the name of the profile it came from, since files often hold one
+/// profile per thread, unless [`Options::no_profile_names`] is set.
+///
+/// To construct one, either use `speedscope::Folder::default()` or create an [`Options`] and
+/// use `speedscope::Folder::from(options)`.
+///
+///   [speedscope]: https://www.speedscope.app/
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct File {
+    shared: Shared,
+    profiles: Vec<Profile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Shared {
+    frames: Vec<Frame>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Frame {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum Profile {
+    Sampled {
+        #[serde(default)]
+        name: String,
+        samples: Vec<Vec<usize>>,
+        weights: Vec<f64>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Evented {
+        #[serde(default)]
+        name: String,
+        end_value: f64,
+        events: Vec<Event>,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+struct Event {
+    #[serde(rename = "type")]
+    kind: EventKind,
+    at: f64,
+    frame: usize,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+enum EventKind {
+    #[serde(rename = "O")]
+    Open,
+    #[serde(rename = "C")]
+    Close,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut input = Vec::new();
+        reader.read_to_end(&mut input)?;
+        if input.iter().all(|b| b.is_ascii_whitespace()) {
+            warn!("File is empty");
+            return Ok(());
+        }
+
+        let file: File = match serde_json::from_slice(&input) {
+            Ok(file) => file,
+            Err(e) => return invalid_data_error!("Invalid speedscope JSON: {}", e),
+        };
+
+        let frames: Vec<String> = file
+            .shared
+            .frames
+            .iter()
+            .map(|frame| frame_name(&frame.name))
+            .collect();
+
+        let mut occurrences = Occurrences::new(1);
+        for profile in &file.profiles {
+            // Add up the weight of each stack first, then round once, since the weights of
+            // evented profiles are often fractions of their unit.
+            let mut weights: AHashMap<Vec<usize>, f64> = AHashMap::default();
+            let name = match profile {
+                Profile::Sampled {
+                    name,
+                    samples,
+                    weights: sample_weights,
+                } => {
+                    if samples.len() != sample_weights.len() {
+                        return invalid_data_error!(
+                            "Profile {} must have one weight per sample",
+                            name
+                        );
+                    }
+                    for (stack, weight) in samples.iter().zip(sample_weights) {
+                        *weights.entry(stack.clone()).or_insert(0.0) += weight;
+                    }
+                    name
+                }
+                Profile::Evented {
+                    name,
+                    end_value,
+                    events,
+                } => {
+                    Self::weigh_events(name, events, *end_value, &mut weights)?;
+                    name
+                }
+            };
+
+            for (stack, weight) in weights {
+                let count = weight.round() as usize;
+                if count == 0 || stack.is_empty() {
+                    continue;
+                }
+                let mut folded = String::new();
+                if !self.opt.no_profile_names {
+                    folded.push_str(&frame_name(name));
+                    folded.push(';');
+                }
+                for (i, frame) in stack.iter().enumerate() {
+                    let frame = match frames.get(*frame) {
+                        Some(frame) => frame,
+                        None => return invalid_data_error!("Unknown frame index {}", frame),
+                    };
+                    if i != 0 {
+                        folded.push(';');
+                    }
+                    folded.push_str(frame);
+                }
+                occurrences.insert_or_add(folded, count);
+            }
+        }
+        occurrences.write_and_clear(writer, self.opt.fold_recursion)
+    }
+
+    /// Check for the schema URL that speedscope files start with.
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let input = input.trim_start();
+        if input.is_empty() {
+            return None;
+        }
+        if !input.starts_with('{') {
+            return Some(false);
+        }
+        if input.contains(SCHEMA) {
+            return Some(true);
+        }
+        None
+    }
+}
+
+impl Folder {
+    /// Turns the open and close events of an evented profile into the self time of each stack.
+    fn weigh_events(
+        name: &str,
+        events: &[Event],
+        end_value: f64,
+        weights: &mut AHashMap<Vec<usize>, f64>,
+    ) -> io::Result<()> {
+        let mut stack = Vec::new();
+        let mut last_at = None;
+        for event in events {
+            if let Some(last_at) = last_at {
+                if event.at < last_at {
+                    return invalid_data_error!(
+                        "Events of profile {} are not ordered by time",
+                        name
+                    );
+                }
+                if !stack.is_empty() {
+                    *weights.entry(stack.clone()).or_insert(0.0) += event.at - last_at;
+                }
+            }
+            last_at = Some(event.at);
+
+            match event.kind {
+                EventKind::Open => stack.push(event.frame),
+                EventKind::Close => match stack.pop() {
+                    Some(frame) if frame == event.frame => {}
+                    Some(frame) => {
+                        return invalid_data_error!(
+                            "Close event for frame {} while frame {} is open in profile {}",
+                            event.frame,
+                            frame,
+                            name
+                        )
+                    }
+                    None => {
+                        return invalid_data_error!(
+                            "Close event for frame {} without an open frame in profile {}",
+                            event.frame,
+                            name
+                        )
+                    }
+                },
+            }
+        }
+
+        // Frames that are never closed last until the end of the profile.
+        if let Some(last_at) = last_at {
+            if !stack.is_empty() {
+                warn!("Closing frames left open at the end of profile {}", name);
+                *weights.entry(stack).or_insert(0.0) += (end_value - last_at).max(0.0);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn frame_name(name: &str) -> String {
+    if name.is_empty() {
+        return "[unknown]".to_string();
+    }
+    // Semicolons separate frames in the folded format.
+    name.replace(';', ":")
+}
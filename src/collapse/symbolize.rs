@@ -0,0 +1,306 @@
+#[cfg(feature = "symbolize")]
+use std::ffi::OsStr;
+#[cfg(feature = "symbolize")]
+use std::fs;
+#[cfg(feature = "symbolize")]
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use ahash::AHashMap;
+#[cfg(feature = "symbolize")]
+use log::debug;
+use log::warn;
+#[cfg(feature = "symbolize")]
+use object::Object;
+
+use crate::collapse::elf::SymbolTable;
+
+/// Where an address to be symbolized points into a binary.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Address {
+    /// A virtual address, as the binary was linked.
+    Virtual(u64),
+    /// An offset into the binary's file, like the `+0x1234` printed by `perf script -F +dsoff`.
+    FileOffset(u64),
+}
+
+/// The symbols and debug information of a single binary.
+#[cfg(feature = "symbolize")]
+struct Binary {
+    symbols: SymbolTable,
+    /// Symbols from separate debug info, for when the binary itself was stripped.
+    debug_symbols: Option<SymbolTable>,
+    /// DWARF lookups fill caches inside the loader, so each binary's debug info has its own lock.
+    dwarf: Option<Mutex<addr2line::Loader>>,
+}
+
+/// Resolves addresses in binaries to function names after the fact, using the symbol tables and
+/// DWARF debug information of binaries found in a search path.
+///
+/// Binaries are loaded lazily the first time one of their addresses is looked up, and are shared
+/// between all the threads of a collapser.
+#[cfg(feature = "symbolize")]
+pub(crate) struct Symbolizer {
+    search_path: Vec<PathBuf>,
+    inlines: bool,
+    binaries: Mutex<AHashMap<String, Option<Arc<Binary>>>>,
+}
+
+#[cfg(feature = "symbolize")]
+impl Symbolizer {
+    /// Creates a symbolizer that looks for binaries in `search_path`.
+    ///
+    /// Each entry of the search path may either be a binary, or a directory that holds binaries
+    /// by name or by the full path they were profiled at. If `inlines` is set, addresses inside
+    /// inlined functions resolve to the whole chain of inlined calls.
+    pub(crate) fn new(search_path: Vec<PathBuf>, inlines: bool) -> Self {
+        Symbolizer {
+            search_path,
+            inlines,
+            binaries: Mutex::new(AHashMap::default()),
+        }
+    }
+
+    /// Resolves `address` in the binary `module` (as named by the profiler).
+    ///
+    /// Returns the function's name, or, with inlines enabled, the names of the function and the
+    /// functions inlined into it separated by `->` (outermost first), or `None` if the binary or
+    /// address could not be found.
+    pub(crate) fn symbolize(&self, module: &str, address: Address) -> Option<String> {
+        if module.is_empty() || module.starts_with('[') {
+            // Pseudo-modules like [unknown], [vdso] and [kernel.kallsyms].
+            return None;
+        }
+
+        let binary = self.binary(module)?;
+
+        let address = match address {
+            Address::Virtual(address) => address,
+            Address::FileOffset(offset) => binary.symbols.file_offset_to_address(offset),
+        };
+
+        if let Some(ref dwarf) = binary.dwarf {
+            if let Some(frames) = self.dwarf_frames(&dwarf.lock().unwrap(), address) {
+                return Some(frames);
+            }
+        }
+        binary
+            .symbols
+            .lookup(address)
+            .or_else(|| binary.debug_symbols.as_ref()?.lookup(address))
+            .map(|name| name.to_string())
+    }
+
+    /// Returns the loaded binary for `module`, loading it on first use.
+    ///
+    /// The cache is only locked to look the binary up and to insert it, so threads can load and
+    /// symbolize in different binaries at the same time.
+    fn binary(&self, module: &str) -> Option<Arc<Binary>> {
+        if let Some(binary) = self.binaries.lock().unwrap().get(module) {
+            return binary.clone();
+        }
+        let binary = self.load(module).map(Arc::new);
+        // Another thread may have loaded the same binary in the meantime; keep the first one.
+        self.binaries
+            .lock()
+            .unwrap()
+            .entry(module.to_string())
+            .or_insert(binary)
+            .clone()
+    }
+
+    fn dwarf_frames(&self, dwarf: &addr2line::Loader, address: u64) -> Option<String> {
+        let mut frames = dwarf.find_frames(address).ok()?;
+        let mut names = Vec::new();
+        // Frames are returned innermost first; the last one is the function itself.
+        while let Ok(Some(frame)) = frames.next() {
+            if let Some(function) = frame.function {
+                if let Ok(name) = function.demangle() {
+                    names.push(name.into_owned());
+                }
+            }
+        }
+
+        if names.is_empty() {
+            return None;
+        }
+        if !self.inlines {
+            return names.pop();
+        }
+        names.reverse();
+        Some(names.join("->"))
+    }
+
+    fn load(&self, module: &str) -> Option<Binary> {
+        let path = match self.find_binary(module) {
+            Some(path) => path,
+            None => {
+                debug!("No binary found for {} in the symbol search path", module);
+                return None;
+            }
+        };
+
+        let symbols = match SymbolTable::from_elf(&path) {
+            Ok(symbols) => symbols,
+            Err(e) => {
+                warn!("Unable to read symbols from {}: {}", path.display(), e);
+                return None;
+            }
+        };
+
+        let data = fs::read(&path).ok()?;
+        let file = object::File::parse(&*data).ok()?;
+        let debug_file = if file.section_by_name(".debug_info").is_some() {
+            Some(path.clone())
+        } else {
+            self.find_debug_file(&path, &file)
+        };
+
+        let debug_symbols = match debug_file {
+            Some(ref debug_file) if debug_file != &path => SymbolTable::from_elf(debug_file).ok(),
+            _ => None,
+        };
+        let dwarf = debug_file.and_then(|debug_file| match addr2line::Loader::new(&debug_file) {
+            Ok(loader) => Some(Mutex::new(loader)),
+            Err(e) => {
+                warn!(
+                    "Unable to read debug info from {}: {}",
+                    debug_file.display(),
+                    e
+                );
+                None
+            }
+        });
+
+        debug!("Loaded symbols for {} from {}", module, path.display());
+        Some(Binary {
+            symbols,
+            debug_symbols,
+            dwarf,
+        })
+    }
+
+    /// Looks for a binary by the path it was profiled at, and then by its file name.
+    fn find_binary(&self, module: &str) -> Option<PathBuf> {
+        let relative = module.trim_start_matches('/');
+        let name = file_name(module);
+        self.search_path.iter().find_map(|entry| {
+            if entry.is_file() {
+                return Some(entry.clone())
+                    .filter(|entry| entry.file_name() == Some(OsStr::new(name)));
+            }
+            [entry.join(relative), entry.join(name)]
+                .iter()
+                .find(|candidate| candidate.is_file())
+                .cloned()
+        })
+    }
+
+    /// Looks for split debug info, by `.gnu_debuglink` next to the binary or in the search path,
+    /// and by build id in `.build-id` directories of the search path.
+    fn find_debug_file(&self, binary: &Path, file: &object::File<'_>) -> Option<PathBuf> {
+        let mut candidates = Vec::new();
+        if let Ok(Some((link, _crc))) = file.gnu_debuglink() {
+            let link = String::from_utf8_lossy(link).into_owned();
+            if let Some(dir) = binary.parent() {
+                candidates.push(dir.join(&link));
+                candidates.push(dir.join(".debug").join(&link));
+            }
+            for entry in self.search_path.iter().filter(|entry| entry.is_dir()) {
+                candidates.push(entry.join(&link));
+            }
+        }
+        if let Ok(Some(build_id)) = file.build_id() {
+            if build_id.len() > 1 {
+                let hex: String = build_id.iter().map(|b| format!("{:02x}", b)).collect();
+                for entry in self.search_path.iter().filter(|entry| entry.is_dir()) {
+                    candidates.push(
+                        entry
+                            .join(".build-id")
+                            .join(&hex[..2])
+                            .join(format!("{}.debug", &hex[2..])),
+                    );
+                }
+            }
+        }
+        candidates
+            .into_iter()
+            .find(|candidate| candidate != binary && candidate.is_file())
+    }
+}
+
+/// Stands in for the symbolizer when the `symbolize` feature is disabled, and leaves every frame
+/// unresolved.
+#[cfg(not(feature = "symbolize"))]
+pub(crate) struct Symbolizer;
+
+#[cfg(not(feature = "symbolize"))]
+impl Symbolizer {
+    pub(crate) fn new(_search_path: Vec<PathBuf>, _inlines: bool) -> Self {
+        warn!("Unable to symbolize frames: inferno was built without the `symbolize` feature");
+        Symbolizer
+    }
+
+    pub(crate) fn symbolize(&self, _module: &str, _address: Address) -> Option<String> {
+        None
+    }
+}
+
+fn file_name(module: &str) -> &str {
+    &module[module.rfind('/').map(|i| i + 1).unwrap_or(0)..]
+}
+
+/// Resolves addresses of JIT-compiled code using the `/tmp/perf-<pid>.map` files written by
+/// runtimes like the JVM (through perf-map-agent) and Node.js (with `--perf-basic-prof`).
+///
+/// Map files are loaded lazily, once per process, and are shared between all the threads of a
+/// collapser.
+pub(crate) struct PerfMaps {
+    dir: PathBuf,
+    maps: Mutex<AHashMap<u32, Option<Arc<SymbolTable>>>>,
+}
+
+impl PerfMaps {
+    /// Creates a resolver that reads `perf-<pid>.map` files from `dir`.
+    pub(crate) fn new(dir: PathBuf) -> Self {
+        PerfMaps {
+            dir,
+            maps: Mutex::new(AHashMap::default()),
+        }
+    }
+
+    /// Returns the pid of a perf map module name like `/tmp/perf-19982.map`.
+    pub(crate) fn pid_of(module: &str) -> Option<u32> {
+        file_name(module)
+            .strip_prefix("perf-")?
+            .strip_suffix(".map")?
+            .parse()
+            .ok()
+    }
+
+    /// Resolves `address` in the JIT-compiled code of the process `pid`.
+    pub(crate) fn symbolize(&self, pid: u32, address: u64) -> Option<String> {
+        self.map(pid)?.lookup(address).map(|name| name.to_string())
+    }
+
+    /// Returns the map of the process `pid`, loading it on first use.
+    ///
+    /// Like [`Symbolizer`]'s binaries, the cache is only locked to look the map up and to insert
+    /// it.
+    fn map(&self, pid: u32) -> Option<Arc<SymbolTable>> {
+        if let Some(map) = self.maps.lock().unwrap().get(&pid) {
+            return map.clone();
+        }
+        let path = self.dir.join(format!("perf-{}.map", pid));
+        let map = match SymbolTable::from_perf_map(&path) {
+            Ok(map) => Some(Arc::new(map)),
+            Err(e) => {
+                warn!("Unable to read perf map {}: {}", path.display(), e);
+                None
+            }
+        };
+        // Another thread may have loaded the same map in the meantime; keep the first one.
+        self.maps.lock().unwrap().entry(pid).or_insert(map).clone()
+    }
+}
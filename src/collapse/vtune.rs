@@ -2,40 +2,89 @@ use std::io::{self, BufRead};
 
 use log::warn;
 
-use crate::collapse::common::Occurrences;
-use crate::collapse::Collapse;
+use crate::collapse::common::{self, CollapsePrivate, Occurrences};
+use crate::filter::FoldRecursion;
 
 // The call graph begins after this line.
 static HEADER: &str = "Function Stack,CPU Time:Self,Module";
 
 /// `vtune` folder configuration options.
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug)]
 #[non_exhaustive]
 pub struct Options {
+    /// Merge recursive calls into a single frame: either only where a function calls itself
+    /// directly, or also for cycles through other functions.
+    ///
+    /// Default is `None`.
+    pub fold_recursion: Option<FoldRecursion>,
+
     /// Don't include modules with function names.
     ///
     /// Default is `false`.
     pub no_modules: bool,
+
+    /// The number of threads to use.
+    ///
+    /// Default is the number of logical cores on your machine.
+    pub nthreads: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            fold_recursion: None,
+            no_modules: false,
+            nthreads: *common::DEFAULT_NTHREADS,
+        }
+    }
 }
 
 /// A stack collapser for CSV call graphs created with the VTune `amplxe-cl` tool.
 ///
 /// To construct one, either use `vtune::Folder::default()` or create an [`Options`] and use
 /// `vtune::Folder::from(options)`.
-#[derive(Clone, Default)]
+#[derive(Clone)]
 pub struct Folder {
     /// Function on the stack in this entry thus far.
     stack: Vec<String>,
 
+    /// The number of stacks per job to send to the threadpool.
+    nstacks_per_job: usize,
+
+    /// Lines of the call graph leading to the current line, without their CPU time, used to cut
+    /// the call graph into chunks for the threadpool.
+    chunk_context: Vec<Vec<u8>>,
+
     opt: Options,
 }
 
-impl Collapse for Folder {
-    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+impl From<Options> for Folder {
+    fn from(mut opt: Options) -> Self {
+        if opt.nthreads == 0 {
+            opt.nthreads = 1;
+        }
+        Self {
+            stack: Vec::default(),
+            nstacks_per_job: common::DEFAULT_NSTACKS_PER_JOB,
+            chunk_context: Vec::default(),
+            opt,
+        }
+    }
+}
+
+impl Default for Folder {
+    fn default() -> Self {
+        Options::default().into()
+    }
+}
+
+impl CollapsePrivate for Folder {
+    fn pre_process<R>(&mut self, reader: &mut R, _: &mut Occurrences) -> io::Result<()>
     where
         R: io::BufRead,
-        W: io::Write,
     {
+        self.chunk_context.clear();
+
         // Consume the header...
         let mut line = Vec::new();
         loop {
@@ -46,12 +95,21 @@ impl Collapse for Folder {
             };
             let l = String::from_utf8_lossy(&line);
             if l.starts_with(HEADER) {
-                break;
+                return Ok(());
             }
         }
+    }
 
+    fn collapse_single_threaded<R>(
+        &mut self,
+        mut reader: R,
+        occurrences: &mut Occurrences,
+    ) -> io::Result<()>
+    where
+        R: io::BufRead,
+    {
         // Process the data...
-        let mut occurrences = Occurrences::new(1);
+        let mut line = Vec::new();
         loop {
             line.clear();
             if reader.read_until(0x0A, &mut line)? == 0 {
@@ -62,13 +120,10 @@ impl Collapse for Folder {
             if line.is_empty() {
                 continue;
             } else {
-                self.on_line(line, &mut occurrences)?;
+                self.on_line(line, occurrences)?;
             }
         }
 
-        // Write the results...
-        occurrences.write_and_clear(writer)?;
-
         // Reset the state...
         self.stack.clear();
         Ok(())
@@ -94,14 +149,85 @@ impl Collapse for Folder {
         }
         None
     }
-}
 
-impl From<Options> for Folder {
-    fn from(opt: Options) -> Self {
-        Folder {
-            opt,
-            ..Default::default()
+    // The call graph is a tree, so no line ends a stack on its own; see `would_start_stack`.
+    fn would_end_stack(&mut self, _line: &[u8]) -> bool {
+        false
+    }
+
+    // A line starts a new stack if it's no deeper than the line before it, which means that line
+    // was a leaf. We keep track of the lines leading to the current one so that a chunk starting
+    // in the middle of the tree can be given the frames above it.
+    fn would_start_stack(&mut self, line: &[u8]) -> bool {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end();
+        let spaces = match line.find(|c| c != ' ') {
+            Some(spaces) => spaces,
+            None => return false,
+        };
+
+        // Malformed lines are left for the worker threads to report.
+        let prev_depth = self.chunk_context.len();
+        let depth = spaces + 1;
+        if depth > prev_depth + 1 {
+            return false;
         }
+        let (func, rest) = if line[spaces..].starts_with('"') {
+            match line.find("\",") {
+                Some(end) => line.split_at(end + 2),
+                None => return false,
+            }
+        } else {
+            match line.find(',') {
+                Some(end) => line.split_at(end + 1),
+                None => return false,
+            }
+        };
+        let module = rest.find(',').map(|start| &rest[start..]).unwrap_or("");
+
+        // These lines are only there to give the frames of the next chunk their parents, so
+        // their own CPU time must not be counted again.
+        self.chunk_context.truncate(depth - 1);
+        self.chunk_context
+            .push(format!("{}0{}\n", func, module).into_bytes());
+        depth <= prev_depth
+    }
+
+    fn write_chunk_context(&mut self, _chunk: &mut Vec<u8>, next_chunk: &mut Vec<u8>) {
+        if let Some((_, parents)) = self.chunk_context.split_last() {
+            for parent in parents {
+                next_chunk.extend_from_slice(parent);
+            }
+        }
+    }
+
+    fn clone_and_reset_stack_context(&self) -> Self {
+        Self {
+            stack: Vec::default(),
+            nstacks_per_job: self.nstacks_per_job,
+            chunk_context: Vec::default(),
+            opt: self.opt.clone(),
+        }
+    }
+
+    fn nstacks_per_job(&self) -> usize {
+        self.nstacks_per_job
+    }
+
+    fn set_nstacks_per_job(&mut self, n: usize) {
+        self.nstacks_per_job = n;
+    }
+
+    fn nthreads(&self) -> usize {
+        self.opt.nthreads
+    }
+
+    fn set_nthreads(&mut self, n: usize) {
+        self.opt.nthreads = n;
+    }
+
+    fn fold_recursion(&self) -> Option<FoldRecursion> {
+        self.opt.fold_recursion
     }
 }
 
@@ -167,6 +293,115 @@ impl Folder {
     }
 
     fn write_stack(&self, occurrences: &mut Occurrences, time: usize) {
-        occurrences.insert(self.stack.join(";"), time);
+        occurrences.insert_or_add(self.stack.join(";"), time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use lazy_static::lazy_static;
+    use rand::prelude::*;
+
+    use super::*;
+    use crate::collapse::common;
+    use crate::collapse::Collapse;
+
+    lazy_static! {
+        static ref INPUT: Vec<PathBuf> = {
+            ["./tests/data/collapse-vtune/vtune.csv"]
+                .iter()
+                .map(PathBuf::from)
+                .collect::<Vec<_>>()
+        };
+    }
+
+    #[test]
+    fn test_collapse_multi_vtune() -> io::Result<()> {
+        let mut folder = Folder::default();
+        common::testing::test_collapse_multi(&mut folder, &INPUT)
+    }
+
+    #[test]
+    fn test_collapse_multi_vtune_one_stack_per_job() -> io::Result<()> {
+        let mut folder = Folder {
+            nstacks_per_job: 1,
+            ..Folder::default()
+        };
+        common::testing::test_collapse_multi(&mut folder, &INPUT)
+    }
+
+    /// Varies the nstacks_per_job parameter and outputs the 10 fastests configurations by file.
+    ///
+    /// Command: `cargo test bench_nstacks_vtune --release -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_nstacks_vtune() -> io::Result<()> {
+        let mut folder = Folder::default();
+        common::testing::bench_nstacks(&mut folder, &INPUT)
+    }
+
+    #[test]
+    #[ignore]
+    /// Fuzz test the multithreaded collapser.
+    ///
+    /// Command: `cargo test fuzz_collapse_vtune --release -- --ignored --nocapture`
+    fn fuzz_collapse_vtune() -> io::Result<()> {
+        let seed = thread_rng().gen::<u64>();
+        println!("Random seed: {}", seed);
+        let mut rng = SmallRng::seed_from_u64(seed);
+
+        let mut buf_actual = Vec::new();
+        let mut buf_expected = Vec::new();
+        let mut count = 0;
+
+        let inputs = common::testing::read_inputs(&INPUT)?;
+
+        loop {
+            let nstacks_per_job = rng.gen_range(1..=500);
+            let options = Options {
+                fold_recursion: if rng.gen() {
+                    Some(FoldRecursion::Cycles)
+                } else {
+                    None
+                },
+                no_modules: rng.gen(),
+                nthreads: rng.gen_range(2..=32),
+            };
+
+            for (path, input) in inputs.iter() {
+                buf_actual.clear();
+                buf_expected.clear();
+
+                let mut folder = {
+                    let mut options = options.clone();
+                    options.nthreads = 1;
+                    Folder::from(options)
+                };
+                folder.nstacks_per_job = nstacks_per_job;
+                <Folder as Collapse>::collapse(&mut folder, &input[..], &mut buf_expected)?;
+                let expected = std::str::from_utf8(&buf_expected[..]).unwrap();
+
+                let mut folder = Folder::from(options.clone());
+                folder.nstacks_per_job = nstacks_per_job;
+                <Folder as Collapse>::collapse(&mut folder, &input[..], &mut buf_actual)?;
+                let actual = std::str::from_utf8(&buf_actual[..]).unwrap();
+
+                if actual != expected {
+                    eprintln!(
+                        "Failed on file: {}\noptions: {:#?}\n",
+                        path.display(),
+                        options
+                    );
+                    assert_eq!(actual, expected);
+                }
+            }
+
+            count += 1;
+            if count % 10 == 0 {
+                println!("Successfully ran {} fuzz tests.", count);
+            }
+        }
     }
 }
@@ -0,0 +1,58 @@
+use std::io::{self, BufRead};
+
+#[cfg(feature = "compression")]
+use flate2::bufread::MultiGzDecoder;
+#[cfg(feature = "compression")]
+use xz2::bufread::XzDecoder;
+
+#[cfg(feature = "compression")]
+const CAPACITY_READER: usize = 128 * 1024;
+
+#[cfg(feature = "compression")]
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+#[cfg(feature = "compression")]
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+#[cfg(feature = "compression")]
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+
+/// Wraps `reader` in a decompressor if its contents start with the magic bytes of gzip, zstd or
+/// xz data, so that compressed profiles can be read as if they weren't.
+///
+/// Input that isn't compressed is passed through untouched.
+#[cfg(feature = "compression")]
+pub(crate) fn decompress<'a, R>(mut reader: R) -> io::Result<Box<dyn BufRead + 'a>>
+where
+    R: BufRead + 'a,
+{
+    let start = reader.fill_buf()?;
+    if start.starts_with(GZIP_MAGIC) {
+        let decoder = MultiGzDecoder::new(reader);
+        Ok(Box::new(io::BufReader::with_capacity(
+            CAPACITY_READER,
+            decoder,
+        )))
+    } else if start.starts_with(ZSTD_MAGIC) {
+        let decoder = zstd::stream::read::Decoder::with_buffer(reader)?;
+        Ok(Box::new(io::BufReader::with_capacity(
+            CAPACITY_READER,
+            decoder,
+        )))
+    } else if start.starts_with(XZ_MAGIC) {
+        let decoder = XzDecoder::new_multi_decoder(reader);
+        Ok(Box::new(io::BufReader::with_capacity(
+            CAPACITY_READER,
+            decoder,
+        )))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Without the `compression` feature, all input is passed through untouched.
+#[cfg(not(feature = "compression"))]
+pub(crate) fn decompress<'a, R>(reader: R) -> io::Result<Box<dyn BufRead + 'a>>
+where
+    R: BufRead + 'a,
+{
+    Ok(Box::new(reader))
+}
@@ -1,3 +1,5 @@
+mod multi;
+
 use std::fs::File;
 use std::io::{self, prelude::*};
 use std::path::Path;
@@ -5,6 +7,10 @@ use std::path::Path;
 use ahash::AHashMap;
 use log::warn;
 
+use crate::compression;
+
+pub use self::multi::{compare_files, compare_readers, write_summary, Comparison, StackDelta};
+
 const READER_CAPACITY: usize = 128 * 1024;
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -60,7 +66,8 @@ where
 /// Produce an output that can be used to generate a differential flame graph from
 /// a before and an after profile.
 ///
-/// See [`from_readers`] for the input and output formats.
+/// See [`from_readers`] for the input and output formats. Files compressed with gzip, zstd or xz
+/// are decompressed on the fly.
 pub fn from_files<P1, P2, W>(
     opt: Options,
     file_before: P1,
@@ -76,7 +83,12 @@ where
     let reader1 = io::BufReader::with_capacity(READER_CAPACITY, file1);
     let file2 = File::open(file_after)?;
     let reader2 = io::BufReader::with_capacity(READER_CAPACITY, file2);
-    from_readers(opt, reader1, reader2, writer)
+    from_readers(
+        opt,
+        compression::decompress(reader1)?,
+        compression::decompress(reader2)?,
+        writer,
+    )
 }
 
 // Populate stack_counts based on lines from the reader and returns the sum of the sample counts.
@@ -131,7 +143,7 @@ where
 }
 
 // Parse stack and sample count from line.
-fn parse_line(
+pub(crate) fn parse_line(
     line: &str,
     strip_hex: bool,
     stripped_fractional_samples: &mut bool,
@@ -0,0 +1,375 @@
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::path::Path;
+
+use ahash::AHashMap;
+use log::warn;
+
+use super::{parse_line, Options, READER_CAPACITY};
+use crate::compression;
+use crate::flamegraph;
+
+/// The sample counts of one stack in a baseline profile and in one of its variants.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackDelta {
+    /// The semicolon-separated frames of the stack.
+    pub stack: String,
+    /// Samples of the stack in the baseline, normalized if [`Options::normalize`] is set.
+    pub baseline: usize,
+    /// Samples of the stack in the variant.
+    pub variant: usize,
+}
+
+impl StackDelta {
+    /// The change in samples from the baseline to the variant.
+    pub fn delta(&self) -> isize {
+        self.variant as isize - self.baseline as isize
+    }
+
+    /// The change in samples relative to the baseline, so `0.5` is 50% more samples.
+    ///
+    /// This is infinite for stacks that only appear in the variant.
+    pub fn relative_delta(&self) -> f64 {
+        if self.baseline == 0 {
+            f64::INFINITY
+        } else {
+            self.delta() as f64 / self.baseline as f64
+        }
+    }
+}
+
+/// The differences between a baseline profile and one variant of it.
+///
+/// Produced by [`compare_readers`] and [`compare_files`].
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    /// The name of the variant, such as its file name.
+    pub name: String,
+    /// The total sample count of the baseline, normalized if [`Options::normalize`] is set.
+    pub baseline_total: usize,
+    /// The total sample count of the variant.
+    pub variant_total: usize,
+    /// Every stack that appears in the baseline or the variant, sorted by stack.
+    pub stacks: Vec<StackDelta>,
+}
+
+impl Comparison {
+    /// Write three-column folded stack lines, like [`from_readers`](super::from_readers) does,
+    /// that can be passed to [`flamegraph::from_reader`] to plot the differential.
+    pub fn write_folded<W>(&self, mut writer: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        for s in &self.stacks {
+            writeln!(writer, "{} {} {}", s.stack, s.baseline, s.variant)?;
+        }
+        Ok(())
+    }
+
+    /// Plot a differential flame graph of this comparison.
+    ///
+    /// Frame widths are based on the variant, and frames are red where the variant has more
+    /// samples than the baseline and blue where it has fewer.
+    pub fn write_flamegraph<W>(
+        &self,
+        opt: &mut flamegraph::Options<'_>,
+        writer: W,
+    ) -> quick_xml::Result<()>
+    where
+        W: Write,
+    {
+        let lines: Vec<String> = self
+            .stacks
+            .iter()
+            .map(|s| format!("{} {} {}", s.stack, s.baseline, s.variant))
+            .collect();
+        flamegraph::from_lines(opt, lines.iter().map(String::as_str), writer)
+    }
+
+    /// The `n` stacks whose sample count changed the most, largest change first.
+    pub fn largest_absolute_changes(&self, n: usize) -> Vec<&StackDelta> {
+        let mut changed: Vec<_> = self.stacks.iter().filter(|s| s.delta() != 0).collect();
+        changed.sort_by(|a, b| {
+            b.delta()
+                .abs()
+                .cmp(&a.delta().abs())
+                .then_with(|| a.stack.cmp(&b.stack))
+        });
+        changed.truncate(n);
+        changed
+    }
+
+    /// The `n` stacks whose sample count changed the most relative to the baseline, largest
+    /// change first.
+    ///
+    /// Stacks that only appear in the variant come first, ordered by their sample count.
+    pub fn largest_relative_changes(&self, n: usize) -> Vec<&StackDelta> {
+        let mut changed: Vec<_> = self.stacks.iter().filter(|s| s.delta() != 0).collect();
+        changed.sort_by(|a, b| {
+            b.relative_delta()
+                .abs()
+                .partial_cmp(&a.relative_delta().abs())
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| b.delta().abs().cmp(&a.delta().abs()))
+                .then_with(|| a.stack.cmp(&b.stack))
+        });
+        changed.truncate(n);
+        changed
+    }
+}
+
+/// Compare a baseline profile against several variants of it.
+///
+/// All readers are expected to contain folded stack lines, as described in
+/// [`from_readers`](super::from_readers). Each variant is given along with its name, and one
+/// [`Comparison`] is returned for each, in the same order. If [`Options::normalize`] is set,
+/// the baseline is scaled to the total sample count of each variant in turn.
+pub fn compare_readers<R1, R2>(
+    opt: Options,
+    baseline: R1,
+    variants: Vec<(String, R2)>,
+) -> io::Result<Vec<Comparison>>
+where
+    R1: BufRead,
+    R2: BufRead,
+{
+    let (baseline, baseline_total) = parse_stack_counts(opt, baseline)?;
+    let mut comparisons = Vec::with_capacity(variants.len());
+    for (name, reader) in variants {
+        let (mut variant, variant_total) = parse_stack_counts(opt, reader)?;
+        let scale = |count: usize| {
+            if opt.normalize && baseline_total != variant_total && baseline_total != 0 {
+                (count as f64 * variant_total as f64 / baseline_total as f64) as usize
+            } else {
+                count
+            }
+        };
+
+        let mut stacks = Vec::with_capacity(baseline.len().max(variant.len()));
+        for (stack, &count) in &baseline {
+            stacks.push(StackDelta {
+                variant: variant.remove(stack).unwrap_or(0),
+                stack: stack.clone(),
+                baseline: scale(count),
+            });
+        }
+        stacks.extend(variant.into_iter().map(|(stack, count)| StackDelta {
+            stack,
+            baseline: 0,
+            variant: count,
+        }));
+        stacks.sort_unstable_by(|a, b| a.stack.cmp(&b.stack));
+
+        comparisons.push(Comparison {
+            name,
+            baseline_total: scale(baseline_total),
+            variant_total,
+            stacks,
+        });
+    }
+    Ok(comparisons)
+}
+
+/// Compare the baseline profile at one path against the variants at several others.
+///
+/// See [`compare_readers`] for details. Each variant is named after its file name, without the
+/// extensions of folded or compressed files, so `build-a.folded.gz` is named `build-a`.
+pub fn compare_files<P1, P2>(
+    opt: Options,
+    baseline: P1,
+    variants: &[P2],
+) -> io::Result<Vec<Comparison>>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let baseline = open(baseline.as_ref())?;
+    let mut readers = Vec::with_capacity(variants.len());
+    for path in variants {
+        let path = path.as_ref();
+        readers.push((variant_name(path), open(path)?));
+    }
+    compare_readers(opt, baseline, readers)
+}
+
+/// Write the stacks with the largest absolute and relative changes in each comparison.
+///
+/// At most `top` stacks are listed per variant for each kind of change.
+pub fn write_summary<W>(comparisons: &[Comparison], top: usize, mut writer: W) -> io::Result<()>
+where
+    W: Write,
+{
+    for (i, comparison) in comparisons.iter().enumerate() {
+        if i != 0 {
+            writeln!(writer)?;
+        }
+        writeln!(
+            writer,
+            "{}: {} samples, baseline {} samples",
+            comparison.name, comparison.variant_total, comparison.baseline_total
+        )?;
+
+        writeln!(writer, "  largest absolute changes:")?;
+        for s in comparison.largest_absolute_changes(top) {
+            writeln!(
+                writer,
+                "    {:+10} {:>8} -> {:<8} {}",
+                s.delta(),
+                s.baseline,
+                s.variant,
+                s.stack
+            )?;
+        }
+
+        writeln!(writer, "  largest relative changes:")?;
+        for s in comparison.largest_relative_changes(top) {
+            let relative = if s.baseline == 0 {
+                "new".to_string()
+            } else {
+                format!("{:+.1}%", s.relative_delta() * 100.0)
+            };
+            writeln!(
+                writer,
+                "    {:>10} {:>8} -> {:<8} {}",
+                relative, s.baseline, s.variant, s.stack
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn open(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+    compression::decompress(io::BufReader::with_capacity(READER_CAPACITY, file))
+}
+
+fn variant_name(path: &Path) -> String {
+    let mut path = path.to_path_buf();
+    while let Some(ext) = path.extension() {
+        match ext.to_str() {
+            Some("gz" | "zst" | "xz" | "folded" | "txt") => {
+                path.set_extension("");
+            }
+            _ => break,
+        }
+    }
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+// Sum the sample counts per stack, and return them along with the total sample count.
+fn parse_stack_counts<R>(
+    opt: Options,
+    mut reader: R,
+) -> io::Result<(AHashMap<String, usize>, usize)>
+where
+    R: BufRead,
+{
+    let mut stack_counts: AHashMap<String, usize> = AHashMap::default();
+    let mut total = 0;
+    let mut line = Vec::new();
+    let mut stripped_fractional_samples = false;
+    loop {
+        line.clear();
+
+        if reader.read_until(0x0A, &mut line)? == 0 {
+            break;
+        }
+
+        let l = String::from_utf8_lossy(&line);
+        if let Some((stack, count)) =
+            parse_line(&l, opt.strip_hex, &mut stripped_fractional_samples)
+        {
+            *stack_counts.entry(stack).or_default() += count;
+            total += count;
+        } else {
+            warn!("Unable to parse line: {}", l);
+        }
+    }
+
+    Ok((stack_counts, total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compare(opt: Options, baseline: &str, variants: &[&str]) -> Vec<Comparison> {
+        let variants = variants
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (format!("v{}", i + 1), v.as_bytes()))
+            .collect();
+        compare_readers(opt, baseline.as_bytes(), variants).unwrap()
+    }
+
+    fn delta(stack: &str, baseline: usize, variant: usize) -> StackDelta {
+        StackDelta {
+            stack: stack.to_string(),
+            baseline,
+            variant,
+        }
+    }
+
+    #[test]
+    fn one_comparison_per_variant() {
+        let comparisons = compare(
+            Options::default(),
+            "main;a 10\nmain;b 5\n",
+            &["main;a 10\nmain;c 1\n", "main;b 20\n"],
+        );
+        assert_eq!(comparisons.len(), 2);
+        assert_eq!(comparisons[0].name, "v1");
+        assert_eq!(
+            comparisons[0].stacks,
+            vec![
+                delta("main;a", 10, 10),
+                delta("main;b", 5, 0),
+                delta("main;c", 0, 1)
+            ]
+        );
+        assert_eq!(comparisons[1].name, "v2");
+        assert_eq!(comparisons[1].baseline_total, 15);
+        assert_eq!(comparisons[1].variant_total, 20);
+        assert_eq!(
+            comparisons[1].stacks,
+            vec![delta("main;a", 10, 0), delta("main;b", 5, 20)]
+        );
+    }
+
+    #[test]
+    fn normalize_per_variant() {
+        let opt = Options {
+            normalize: true,
+            ..Default::default()
+        };
+        let comparisons = compare(opt, "main;a 10\n", &["main;a 20\n", "main;a 5\n"]);
+        assert_eq!(comparisons[0].stacks, vec![delta("main;a", 20, 20)]);
+        assert_eq!(comparisons[0].baseline_total, 20);
+        assert_eq!(comparisons[1].stacks, vec![delta("main;a", 5, 5)]);
+    }
+
+    #[test]
+    fn largest_changes() {
+        let comparisons = compare(
+            Options::default(),
+            "main;a 100\nmain;b 2\nmain;c 7\n",
+            &["main;a 150\nmain;b 6\nmain;c 7\nmain;d 1\n"],
+        );
+        let comparison = &comparisons[0];
+        assert_eq!(
+            comparison.largest_absolute_changes(2),
+            vec![&delta("main;a", 100, 150), &delta("main;b", 2, 6)]
+        );
+        assert_eq!(
+            comparison.largest_relative_changes(3),
+            vec![
+                &delta("main;d", 0, 1),
+                &delta("main;b", 2, 6),
+                &delta("main;a", 100, 150)
+            ]
+        );
+    }
+}
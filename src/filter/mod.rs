@@ -0,0 +1,638 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use ahash::AHashMap;
+use regex::Regex;
+
+use crate::compression;
+
+const READER_CAPACITY: usize = 128 * 1024;
+
+/// A rule that rewrites or removes folded stacks.
+///
+/// Frames are matched one at a time, without the `;` separators. Stacks are matched as the
+/// `;`-separated frames, without the sample counts.
+///
+/// Rules can be parsed from a line of text: the rule's name, whitespace, and its argument. For
+/// example, `drop-frames ^std::` or `replace ^(\w+)::h[0-9a-f]{16}$ $1`.
+#[derive(Debug, Clone)]
+pub enum Rule {
+    /// Only keep the stacks this regular expression matches (`keep`).
+    Keep(Regex),
+
+    /// Drop the stacks this regular expression matches (`drop`).
+    Drop(Regex),
+
+    /// Remove the frames this regular expression matches from all stacks (`drop-frames`).
+    ///
+    /// The samples of a removed frame are attributed to its caller and callees as usual.
+    DropFrames(Regex),
+
+    /// Remove the frames above, i.e. called by, the first frame this regular expression matches
+    /// (`cut-above`).
+    ///
+    /// Their samples are attributed to the matching frame.
+    CutAbove(Regex),
+
+    /// Remove the frames below, i.e. calling, the last frame this regular expression matches
+    /// (`cut-below`).
+    ///
+    /// This is a generalization of [`perf::Options::skip_after`] that works with any folded input.
+    ///
+    ///   [`perf::Options::skip_after`]: crate::collapse::perf::Options::skip_after
+    CutBelow(Regex),
+
+    /// Replace all matches of a regular expression in each frame (`replace`).
+    ///
+    /// The replacement can refer to capture groups with `$1` or `${name}`, as described in
+    /// [`Regex::replace_all`]. Frames that end up empty are removed. When parsed from text, the
+    /// regular expression and the replacement are separated by whitespace, so use `\s` to match
+    /// whitespace in the regular expression.
+    Replace(Regex, String),
+
+    /// Remove the frames deeper than this many frames (`max-depth`).
+    ///
+    /// Their samples are attributed to the deepest remaining frame.
+    MaxDepth(usize),
+
+    /// Merge recursive calls into a single frame (`fold-recursion direct` or
+    /// `fold-recursion cycles`).
+    FoldRecursion(FoldRecursion),
+}
+
+/// Which recursive calls to merge into a single frame.
+///
+/// Deeply recursive code otherwise produces towers of the same frames. Only the stacks change;
+/// all samples are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FoldRecursion {
+    /// Merge runs of a function calling itself, so that `a;a;a;b` becomes `a;b`.
+    Direct,
+
+    /// Also merge cycles through other functions: when a function is already on the stack, the
+    /// frames since its earlier call are removed, so that `a;b;c;b;d` becomes `a;b;d`.
+    Cycles,
+}
+
+impl FromStr for FoldRecursion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "direct" => Ok(FoldRecursion::Direct),
+            "cycles" => Ok(FoldRecursion::Cycles),
+            _ => Err(format!("unknown recursion folding mode: {}", s)),
+        }
+    }
+}
+
+impl FoldRecursion {
+    /// Merge the recursive calls in a stack of `;`-separated frames, without sample counts.
+    pub fn fold_stack(self, stack: &str) -> Cow<'_, str> {
+        let mut frames: Vec<&str> = stack.split(';').collect();
+        if self.fold(&mut frames) {
+            Cow::Owned(frames.join(";"))
+        } else {
+            Cow::Borrowed(stack)
+        }
+    }
+
+    /// Merges the recursive calls in `frames`, and returns whether there were any.
+    fn fold<T: AsRef<str> + PartialEq>(self, frames: &mut Vec<T>) -> bool {
+        let len = frames.len();
+        match self {
+            FoldRecursion::Direct => frames.dedup(),
+            FoldRecursion::Cycles => {
+                // Indices of the frames that are kept, and where in `kept` each frame is.
+                let mut kept: Vec<usize> = Vec::with_capacity(len);
+                let mut positions: AHashMap<&str, usize> = AHashMap::default();
+                for (i, frame) in frames.iter().enumerate() {
+                    if let Some(&pos) = positions.get(frame.as_ref()) {
+                        for &j in &kept[pos + 1..] {
+                            positions.remove(frames[j].as_ref());
+                        }
+                        kept.truncate(pos + 1);
+                    } else {
+                        positions.insert(frame.as_ref(), kept.len());
+                        kept.push(i);
+                    }
+                }
+                if kept.len() != len {
+                    let mut kept = kept.into_iter().peekable();
+                    let mut i = 0;
+                    frames.retain(|_| {
+                        let keep = kept.peek() == Some(&i);
+                        if keep {
+                            kept.next();
+                        }
+                        i += 1;
+                        keep
+                    });
+                }
+            }
+        }
+        frames.len() != len
+    }
+}
+
+impl PartialEq for Rule {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Rule::Keep(a), Rule::Keep(b))
+            | (Rule::Drop(a), Rule::Drop(b))
+            | (Rule::DropFrames(a), Rule::DropFrames(b))
+            | (Rule::CutAbove(a), Rule::CutAbove(b))
+            | (Rule::CutBelow(a), Rule::CutBelow(b)) => a.as_str() == b.as_str(),
+            (Rule::Replace(a, ra), Rule::Replace(b, rb)) => a.as_str() == b.as_str() && ra == rb,
+            (Rule::MaxDepth(a), Rule::MaxDepth(b)) => a == b,
+            (Rule::FoldRecursion(a), Rule::FoldRecursion(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, arg) = match s.find(char::is_whitespace) {
+            Some(i) => (&s[..i], s[i..].trim_start()),
+            None => (s, ""),
+        };
+        if arg.is_empty() {
+            return Err(format!("missing argument for filter rule: {}", s));
+        }
+        let regex = |re: &str| Regex::new(re).map_err(|e| e.to_string());
+        match name {
+            "keep" => Ok(Rule::Keep(regex(arg)?)),
+            "drop" => Ok(Rule::Drop(regex(arg)?)),
+            "drop-frames" => Ok(Rule::DropFrames(regex(arg)?)),
+            "cut-above" => Ok(Rule::CutAbove(regex(arg)?)),
+            "cut-below" => Ok(Rule::CutBelow(regex(arg)?)),
+            "replace" => {
+                let (re, replacement) = match arg.find(char::is_whitespace) {
+                    Some(i) => (&arg[..i], arg[i..].trim_start()),
+                    None => (arg, ""),
+                };
+                Ok(Rule::Replace(regex(re)?, replacement.to_string()))
+            }
+            "max-depth" => match arg.parse() {
+                Ok(0) | Err(_) => Err(format!("invalid maximum depth: {}", arg)),
+                Ok(depth) => Ok(Rule::MaxDepth(depth)),
+            },
+            "fold-recursion" => Ok(Rule::FoldRecursion(arg.parse()?)),
+            _ => Err(format!("unknown filter rule: {}", name)),
+        }
+    }
+}
+
+/// An ordered list of [`Rule`]s that are applied to each folded stack line in turn.
+///
+/// Each rule sees the stack as the previous rules left it. Lines that aren't folded stacks are
+/// passed through unchanged. Stacks are not merged after they have been rewritten, so the same
+/// stack can appear on several lines; the other tools add up their samples.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Filter {
+    rules: Vec<Rule>,
+}
+
+impl From<Vec<Rule>> for Filter {
+    fn from(rules: Vec<Rule>) -> Self {
+        Filter { rules }
+    }
+}
+
+impl Filter {
+    /// Parse rules from a file.
+    ///
+    /// Each line should contain one rule as described in [`Rule`]. Empty lines and lines starting
+    /// with `#` are ignored.
+    pub fn from_file(path: &Path) -> io::Result<Filter> {
+        let file = io::BufReader::new(File::open(path)?);
+        Filter::from_reader(file)
+    }
+
+    /// Parse rules from a `BufRead`.
+    ///
+    /// Each line should contain one rule as described in [`Rule`]. Empty lines and lines starting
+    /// with `#` are ignored.
+    pub fn from_reader<R: BufRead>(reader: R) -> io::Result<Filter> {
+        let mut filter = Filter::default();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = line.parse().map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", i + 1, e))
+            })?;
+            filter.push(rule);
+        }
+        Ok(filter)
+    }
+
+    /// Add a rule, to be applied after all the current ones.
+    pub fn push(&mut self, rule: Rule) {
+        self.rules.push(rule);
+    }
+
+    /// The rules, in the order they are applied.
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Whether there are no rules, so that all lines are left as they are.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Apply the rules to a folded stack line (without the trailing newline).
+    ///
+    /// Returns `None` if the stack was dropped, or no frames were left.
+    pub fn apply<'l>(&self, line: &'l str) -> Option<Cow<'l, str>> {
+        if self.rules.is_empty() {
+            return Some(Cow::Borrowed(line));
+        }
+        let (stack, samples) = match split_samples(line) {
+            Some(parts) => parts,
+            None => return Some(Cow::Borrowed(line)),
+        };
+
+        let mut frames: Vec<Cow<'l, str>> = stack.split(';').map(Cow::Borrowed).collect();
+        let mut changed = false;
+        for rule in &self.rules {
+            match rule {
+                Rule::Keep(re) | Rule::Drop(re) => {
+                    let is_match = if changed {
+                        re.is_match(&frames.join(";"))
+                    } else {
+                        re.is_match(stack)
+                    };
+                    if is_match != matches!(rule, Rule::Keep(_)) {
+                        return None;
+                    }
+                }
+                Rule::DropFrames(re) => {
+                    let len = frames.len();
+                    frames.retain(|frame| !re.is_match(frame));
+                    changed |= frames.len() != len;
+                }
+                Rule::CutAbove(re) => {
+                    if let Some(i) = frames.iter().position(|frame| re.is_match(frame)) {
+                        changed |= i + 1 != frames.len();
+                        frames.truncate(i + 1);
+                    }
+                }
+                Rule::CutBelow(re) => {
+                    if let Some(i) = frames.iter().rposition(|frame| re.is_match(frame)) {
+                        changed |= i != 0;
+                        frames.drain(..i);
+                    }
+                }
+                Rule::Replace(re, replacement) => {
+                    for frame in &mut frames {
+                        let replaced = match re.replace_all(frame, replacement.as_str()) {
+                            Cow::Owned(replaced) => replaced,
+                            Cow::Borrowed(_) => continue,
+                        };
+                        *frame = Cow::Owned(replaced);
+                        changed = true;
+                    }
+                    frames.retain(|frame| !frame.is_empty());
+                }
+                Rule::MaxDepth(depth) => {
+                    changed |= frames.len() > *depth;
+                    frames.truncate(*depth);
+                }
+                Rule::FoldRecursion(fold_recursion) => {
+                    changed |= fold_recursion.fold(&mut frames);
+                }
+            }
+            if frames.is_empty() {
+                return None;
+            }
+        }
+
+        if !changed {
+            return Some(Cow::Borrowed(line));
+        }
+        let mut filtered = frames.join(";");
+        filtered.push(' ');
+        filtered.push_str(samples);
+        Some(Cow::Owned(filtered))
+    }
+
+    /// Wrap `writer` so that the rules are applied to the folded stack lines written to it.
+    ///
+    /// This lets the rules be applied to the output of a [`Collapse`] implementation as it is
+    /// written.
+    ///
+    ///   [`Collapse`]: crate::collapse::Collapse
+    pub fn writer<W: Write>(&self, writer: W) -> FilterWriter<'_, W> {
+        FilterWriter {
+            filter: self,
+            inner: Some(writer),
+            buffer: Vec::new(),
+        }
+    }
+}
+
+/// Splits a folded stack line into the stack and its one or two (for differentials) sample
+/// counts.
+fn split_samples(line: &str) -> Option<(&str, &str)> {
+    let is_samples = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit() || b == b'.');
+    let line = line.trim_end();
+    let (stack, last) = line.rsplit_once(' ')?;
+    if !is_samples(last) {
+        return None;
+    }
+    let stack = match stack.trim_end().rsplit_once(' ') {
+        Some((rest, first)) if is_samples(first) => rest,
+        _ => stack,
+    };
+    let stack = stack.trim_end();
+    if stack.is_empty() {
+        None
+    } else {
+        Some((stack, line[stack.len()..].trim_start()))
+    }
+}
+
+/// A writer that applies a [`Filter`] to the folded stack lines written to it.
+///
+/// Created by [`Filter::writer`]. Lines are passed on to the wrapped writer once their newline
+/// has been written. A final line without a newline is passed on when the `FilterWriter` is
+/// dropped or [`into_inner`](FilterWriter::into_inner) is called.
+#[derive(Debug)]
+pub struct FilterWriter<'f, W: Write> {
+    filter: &'f Filter,
+    inner: Option<W>,
+    buffer: Vec<u8>,
+}
+
+impl<'f, W: Write> FilterWriter<'f, W> {
+    /// Write out any unfinished line, and return the wrapped writer.
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.write_remaining()?;
+        self.flush()?;
+        Ok(self.inner.take().expect("inner writer is only taken once"))
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        let line = String::from_utf8_lossy(line);
+        let line = line.strip_suffix('\r').unwrap_or(&*line);
+        if let Some(line) = self.filter.apply(line) {
+            let inner = self
+                .inner
+                .as_mut()
+                .expect("inner writer is only taken once");
+            inner.write_all(line.as_bytes())?;
+            inner.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn write_remaining(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            let line = std::mem::take(&mut self.buffer);
+            self.write_line(&line)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'f, W: Write> Write for FilterWriter<'f, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while let Some(i) = rest.iter().position(|&b| b == b'\n') {
+            if self.buffer.is_empty() {
+                self.write_line(&rest[..i])?;
+            } else {
+                self.buffer.extend_from_slice(&rest[..i]);
+                let line = std::mem::take(&mut self.buffer);
+                self.write_line(&line)?;
+            }
+            rest = &rest[i + 1..];
+        }
+        self.buffer.extend_from_slice(rest);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.inner.as_mut() {
+            Some(inner) => inner.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'f, W: Write> Drop for FilterWriter<'f, W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            // Like `BufWriter`, errors can't be reported here.
+            let _ = self.write_remaining();
+            let _ = self.flush();
+        }
+    }
+}
+
+/// Apply `filter` to the folded stack lines from a reader, and write the remaining lines.
+pub fn from_reader<R, W>(filter: &Filter, mut reader: R, writer: W) -> io::Result<()>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut writer = filter.writer(writer);
+    io::copy(&mut reader, &mut writer)?;
+    writer.into_inner()?;
+    Ok(())
+}
+
+/// Apply `filter` to the folded stack lines from readers, and write the remaining lines.
+///
+/// The readers are read one after the other.
+pub fn from_readers<R, W>(filter: &Filter, readers: R, writer: W) -> io::Result<()>
+where
+    R: IntoIterator,
+    R::Item: BufRead,
+    W: Write,
+{
+    let mut writer = filter.writer(writer);
+    for mut reader in readers {
+        io::copy(&mut reader, &mut writer)?;
+        // Don't join the last line of one reader with the first line of the next.
+        writer.write_remaining()?;
+    }
+    writer.into_inner()?;
+    Ok(())
+}
+
+/// Apply `filter` to the folded stack lines from files, and write the remaining lines.
+///
+/// Reads from STDIN if `files` is empty or `-`. Files compressed with gzip, zstd or xz are
+/// decompressed on the fly.
+pub fn from_files<W: Write>(filter: &Filter, files: &[PathBuf], writer: W) -> io::Result<()> {
+    if files.is_empty() || files.len() == 1 && files[0].to_str() == Some("-") {
+        let stdin = io::stdin();
+        let r = io::BufReader::with_capacity(READER_CAPACITY, stdin.lock());
+        return from_reader(filter, compression::decompress(r)?, writer);
+    }
+
+    let stdin = io::stdin();
+    let mut stdin_added = false;
+    let mut readers: Vec<Box<dyn BufRead>> = Vec::with_capacity(files.len());
+    for infile in files {
+        if infile.to_str() == Some("-") {
+            if !stdin_added {
+                let r = io::BufReader::with_capacity(READER_CAPACITY, stdin.lock());
+                readers.push(compression::decompress(r)?);
+                stdin_added = true;
+            }
+        } else {
+            let r = io::BufReader::with_capacity(READER_CAPACITY, File::open(infile)?);
+            readers.push(compression::decompress(r)?);
+        }
+    }
+    from_readers(filter, readers, writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(rules: &[&str]) -> Filter {
+        Filter::from(
+            rules
+                .iter()
+                .map(|rule| rule.parse().unwrap())
+                .collect::<Vec<Rule>>(),
+        )
+    }
+
+    #[test]
+    fn parse_rules() {
+        let input = "\
+# Hide the idle loop
+drop   ^idle;
+
+replace ^(\\w+)::h[0-9a-f]{16}$ $1
+max-depth 20
+";
+        let filter = Filter::from_reader(input.as_bytes()).unwrap();
+        assert_eq!(
+            filter.rules(),
+            &[
+                Rule::Drop(Regex::new("^idle;").unwrap()),
+                Rule::Replace(
+                    Regex::new("^(\\w+)::h[0-9a-f]{16}$").unwrap(),
+                    "$1".to_string()
+                ),
+                Rule::MaxDepth(20),
+            ]
+        );
+
+        for invalid in &[
+            "keep",
+            "trim foo",
+            "max-depth 0",
+            "drop-frames (",
+            "max-depth x",
+        ] {
+            assert!(invalid.parse::<Rule>().is_err(), "{}", invalid);
+        }
+        let err = Filter::from_reader("keep a\nkeep (\n".as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("line 2: "));
+    }
+
+    #[test]
+    fn apply_rules() {
+        let line = "main;run;std::io::read;sys_read_[k] 12";
+        let cases: &[(&[&str], Option<&str>)] = &[
+            (&[], Some(line)),
+            (&["keep ;run;"], Some(line)),
+            (&["keep ^run"], None),
+            (&["drop _\\[k\\]$"], None),
+            (&["drop-frames ^std::"], Some("main;run;sys_read_[k] 12")),
+            (&["drop-frames ."], None),
+            (&["cut-above ^run$"], Some("main;run 12")),
+            (
+                &["cut-below ^run$"],
+                Some("run;std::io::read;sys_read_[k] 12"),
+            ),
+            (&["cut-below ^nothing$"], Some(line)),
+            (
+                &["replace ^std::.* std"],
+                Some("main;run;std;sys_read_[k] 12"),
+            ),
+            (
+                &["replace ^run$"],
+                Some("main;std::io::read;sys_read_[k] 12"),
+            ),
+            (&["max-depth 2"], Some("main;run 12")),
+            (&["max-depth 4"], Some(line)),
+            // Later rules see the stacks as the earlier ones left them.
+            (
+                &["drop-frames ^run$", "keep ^main;std"],
+                Some("main;std::io::read;sys_read_[k] 12"),
+            ),
+            (&["cut-below ^run$", "keep ^main"], None),
+        ];
+        for (rules, expected) in cases {
+            assert_eq!(
+                filter(rules).apply(line).as_deref(),
+                *expected,
+                "{:?}",
+                rules
+            );
+        }
+
+        let filter = filter(&["cut-above ^b$"]);
+        assert_eq!(filter.apply("a;b;c 3 4").as_deref(), Some("a;b 3 4"));
+        assert_eq!(filter.apply("a;b;c 1.5").as_deref(), Some("a;b 1.5"));
+        assert_eq!(filter.apply("not a stack").as_deref(), Some("not a stack"));
+        assert_eq!(filter.apply("").as_deref(), Some(""));
+    }
+
+    #[test]
+    fn fold_recursion() {
+        let cases = &[
+            ("a;a;a;b", "a;b", "a;b"),
+            ("a;b;a;b;c", "a;b;a;b;c", "a;b;c"),
+            ("a;b;c;b;d", "a;b;c;b;d", "a;b;d"),
+            ("a;b;c;a;c", "a;b;c;a;c", "a;c"),
+            ("a;b;b;c;c;b", "a;b;c;b", "a;b"),
+        ];
+        for (stack, direct, cycles) in cases {
+            assert_eq!(FoldRecursion::Direct.fold_stack(stack), *direct);
+            assert_eq!(FoldRecursion::Cycles.fold_stack(stack), *cycles);
+        }
+        assert!(matches!(
+            FoldRecursion::Cycles.fold_stack("a;b;c"),
+            Cow::Borrowed("a;b;c")
+        ));
+
+        let filter = filter(&["fold-recursion cycles", "max-depth 2"]);
+        assert_eq!(filter.apply("a;b;a;b;c 5").as_deref(), Some("a;b 5"));
+        assert!("fold-recursion sideways".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn writer() {
+        let filter = filter(&["drop ^idle", "cut-above ^b$"]);
+        let mut writer = filter.writer(Vec::new());
+        writer.write_all(b"a;b;c 1\nidle 2\r\na;").unwrap();
+        writer.write_all(b"b;d 3\na;x").unwrap();
+        writer.write_all(b";b 4").unwrap();
+        let output = writer.into_inner().unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "a;b 1\na;b 3\na;x;b 4\n"
+        );
+    }
+}
@@ -234,24 +234,42 @@ function zoom_parent(e) {
         zoom_parent(c[i]);
     }
 }
+// Which side of the focused frames a frame is on: 1 for their callees, -1 for their callers, and
+// 0 for the focused frames themselves. Without focus, all frames are callees of the root.
+function focus_side(rect) {
+    if (frames.attributes.focus_y == undefined) return 1;
+    var y = parseFloat(rect.attributes.y.value);
+    var focus_y = parseFloat(frames.attributes.focus_y.value);
+    if (y == focus_y) return 0;
+    return (y < focus_y) != inverted ? 1 : -1;
+}
 function zoom(node) {
-    var attr = find_child(node, "rect").attributes;
+    var rect = find_child(node, "rect");
+    var attr = rect.attributes;
     var width = parseInt(attr["fg:w"].value);
     var xmin = parseInt(attr["fg:x"].value);
     var xmax = xmin + width;
     var ymin = parseFloat(attr.y.value);
+    var side = focus_side(rect);
     unzoombtn.classList.remove("hide");
     var el = frames.children;
     for (var i = 0; i < el.length; i++) {
         var e = el[i];
-        var a = find_child(e, "rect").attributes;
+        var r = find_child(e, "rect");
+        var a = r.attributes;
         var ex = parseInt(a["fg:x"].value);
         var ew = parseInt(a["fg:w"].value);
-        // Is it an ancestor
-        if (!inverted) {
-            var upstack = parseFloat(a.y.value) > ymin;
-        } else {
-            var upstack = parseFloat(a.y.value) < ymin;
+        // The other side of the focused frames isn't related to the zoomed frame
+        if (side != 0 && focus_side(r) == -side) {
+            e.classList.add("hide");
+            continue;
+        }
+        // Is it an ancestor. For callers of focused frames, those are towards the focused frames.
+        var upstack = false;
+        if (side != 0 && (!inverted) == (side > 0)) {
+            upstack = parseFloat(a.y.value) > ymin;
+        } else if (side != 0) {
+            upstack = parseFloat(a.y.value) < ymin;
         }
         if (upstack) {
             // Direct ancestor
@@ -328,6 +346,15 @@ function search(term) {
         var rect = find_child(e, "rect");
         if (func == null || rect == null)
             continue;
+        // Callers of focused frames are laid out differently, so only highlight them
+        if (focus_side(rect) < 0) {
+            if (func.match(re)) {
+                orig_save(rect, "fill");
+                rect.attributes.fill.value = searchcolor;
+                searching = 1;
+            }
+            continue;
+        }
         // Save max width. Only works as we have a root frame
         var w = parseInt(rect.attributes["fg:w"].value);
         if (w > maxwidth)
@@ -0,0 +1,230 @@
+(function () {
+    "use strict";
+
+    var data = JSON.parse(document.getElementById("flamegraph").textContent);
+    var chart = document.getElementById("chart");
+    var tooltip = document.getElementById("tooltip");
+    var breadcrumb = document.getElementById("breadcrumb");
+    var unzoomButton = document.getElementById("unzoom");
+    var searchInput = document.getElementById("search");
+    var matchedText = document.getElementById("matched");
+
+    var frameHeight = parseFloat(getComputedStyle(document.documentElement).getPropertyValue("--frame-height"));
+    var frames = data.frames.map(function (f) {
+        return { depth: f[0], start: f[1], end: f[2], name: f[3], info: f[4], color: f[5], element: null };
+    });
+    // Graphs focused on a function have no frame that spans all samples at depth 0.
+    var root = frames.find(function (frame) {
+        return frame.depth === 0 && frame.start === 0 && frame.end === data.total_samples;
+    }) || { depth: -1, start: 0, end: data.total_samples, name: "", info: "", element: null };
+    var zoomed = root;
+
+    // Build the frames once; zooming and searching only update their position and classes.
+    chart.style.height = (data.max_depth + 1) * frameHeight + "px";
+    var fragment = document.createDocumentFragment();
+    frames.forEach(function (frame, i) {
+        var element = document.createElement("div");
+        element.className = "frame";
+        element.style.background = frame.color;
+        element.style[data.inverted ? "top" : "bottom"] = frame.depth * frameHeight + "px";
+        element.dataset.index = i;
+        var label = document.createElement("span");
+        var text = document.createElement("span");
+        text.textContent = frame.name;
+        label.appendChild(text);
+        element.appendChild(label);
+        frame.element = element;
+        fragment.appendChild(element);
+    });
+    chart.appendChild(fragment);
+
+    function frameOf(target) {
+        var element = target.closest && target.closest(".frame");
+        return element ? frames[element.dataset.index] : null;
+    }
+
+    // Which side of the focused frames a frame is on: 1 for their callees, -1 for their callers
+    // (at lower depths), and 0 for the focused frames themselves. Without focus, all frames are
+    // callees of the root.
+    function side(frame) {
+        if (data.focus_depth === undefined || frame.depth > data.focus_depth) {
+            return 1;
+        }
+        return frame.depth === data.focus_depth ? 0 : -1;
+    }
+
+    // The frames on the path from the root (or the focused frame) up to and including `frame`.
+    function pathTo(frame) {
+        var callers = side(frame) < 0;
+        return frames.filter(function (other) {
+            var between = callers
+                ? other.depth >= frame.depth && other.depth <= data.focus_depth
+                : other.depth <= frame.depth && side(other) >= 0;
+            return between && other.start <= frame.start && other.end >= frame.end;
+        }).sort(function (a, b) { return callers ? b.depth - a.depth : a.depth - b.depth; });
+    }
+
+    function layout() {
+        var span = zoomed.end - zoomed.start;
+        var width = chart.clientWidth;
+        var zoomedSide = zoomed === root ? 0 : side(zoomed);
+        frames.forEach(function (frame) {
+            var style = frame.element.style;
+            // For callers of focused frames, the path to the focused frames is at higher depths.
+            var isParent = zoomedSide > 0
+                ? frame.depth < zoomed.depth
+                : zoomedSide < 0 && frame.depth > zoomed.depth;
+            var visible, left, right;
+            if (zoomedSide !== 0 && side(frame) === -zoomedSide) {
+                // The other side of the focused frames isn't related to the zoomed frame.
+                visible = false;
+            } else if (isParent) {
+                // Frames the zoomed frame was called from span the whole width.
+                visible = frame.start <= zoomed.start && frame.end >= zoomed.end;
+                left = 0;
+                right = 1;
+            } else {
+                visible = frame.start >= zoomed.start && frame.end <= zoomed.end;
+                left = (frame.start - zoomed.start) / span;
+                right = (frame.end - zoomed.start) / span;
+                // Don't bother drawing frames that are less than a pixel wide.
+                visible = visible && (right - left) * width >= 1;
+            }
+            frame.element.classList.toggle("parent", isParent);
+            if (!visible) {
+                style.display = "none";
+                return;
+            }
+            style.display = "";
+            style.left = left * 100 + "%";
+            style.width = (right - left) * 100 + "%";
+        });
+    }
+
+    function updateBreadcrumb() {
+        breadcrumb.textContent = "";
+        if (zoomed === root) {
+            return;
+        }
+        pathTo(zoomed).forEach(function (frame, i) {
+            if (i > 0) {
+                var separator = document.createElement("span");
+                separator.className = "separator";
+                separator.textContent = "›";
+                breadcrumb.appendChild(separator);
+            }
+            var button = document.createElement("button");
+            button.type = "button";
+            button.textContent = frame.name;
+            button.title = frame.info;
+            button.addEventListener("click", function () { zoom(frame); });
+            breadcrumb.appendChild(button);
+        });
+    }
+
+    function zoom(frame) {
+        zoomed = frame;
+        unzoomButton.disabled = frame === root;
+        layout();
+        updateBreadcrumb();
+        search(searchInput.value);
+    }
+
+    function search(term) {
+        var re = null;
+        searchInput.classList.remove("invalid");
+        if (term) {
+            try {
+                re = new RegExp(term);
+            } catch (e) {
+                searchInput.classList.add("invalid");
+            }
+        }
+
+        // Count matched samples within the zoomed frame, without counting nested matches twice.
+        var matches = [];
+        frames.forEach(function (frame) {
+            var match = re !== null && re.test(frame.name);
+            frame.element.classList.toggle("match", match);
+            // Callers of focused frames are laid out differently, so they can't be compared with
+            // the other matches.
+            if (match && side(frame) >= 0 && frame.start >= zoomed.start && frame.end <= zoomed.end) {
+                matches.push(frame);
+            }
+        });
+        if (re === null) {
+            matchedText.textContent = "";
+            return;
+        }
+        matches.sort(function (a, b) { return a.start - b.start || a.depth - b.depth; });
+        var matched = 0;
+        var covered = zoomed.start;
+        matches.forEach(function (frame) {
+            if (frame.end > covered) {
+                matched += frame.end - Math.max(frame.start, covered);
+                covered = frame.end;
+            }
+        });
+        var pct = 100 * matched / (zoomed.end - zoomed.start);
+        matchedText.textContent = "Matched: " + (pct === 100 ? "100" : pct.toFixed(1)) + "%";
+    }
+
+    function showTooltip(frame, event) {
+        tooltip.textContent = data.nametype + " " + frame.info;
+        tooltip.hidden = false;
+        moveTooltip(event);
+    }
+
+    function moveTooltip(event) {
+        var x = event.clientX + 12;
+        var y = event.clientY + 12;
+        if (x + tooltip.offsetWidth > window.innerWidth) {
+            x = Math.max(0, event.clientX - tooltip.offsetWidth - 12);
+        }
+        if (y + tooltip.offsetHeight > window.innerHeight) {
+            y = Math.max(0, event.clientY - tooltip.offsetHeight - 12);
+        }
+        tooltip.style.left = x + "px";
+        tooltip.style.top = y + "px";
+    }
+
+    chart.addEventListener("click", function (event) {
+        var frame = frameOf(event.target);
+        if (frame) {
+            zoom(frame);
+        }
+    });
+    chart.addEventListener("mouseover", function (event) {
+        var frame = frameOf(event.target);
+        if (frame) {
+            showTooltip(frame, event);
+        }
+    });
+    chart.addEventListener("mousemove", function (event) {
+        if (!tooltip.hidden) {
+            moveTooltip(event);
+        }
+    });
+    chart.addEventListener("mouseleave", function () {
+        tooltip.hidden = true;
+    });
+    unzoomButton.addEventListener("click", function () { zoom(root); });
+    searchInput.addEventListener("input", function () { search(searchInput.value); });
+    window.addEventListener("resize", layout);
+    window.addEventListener("keydown", function (event) {
+        if (((event.ctrlKey || event.metaKey) && event.key === "f") || event.key === "F3") {
+            event.preventDefault();
+            searchInput.focus();
+            searchInput.select();
+        } else if (event.key === "Escape") {
+            if (document.activeElement === searchInput && searchInput.value) {
+                searchInput.value = "";
+                search(null);
+            } else {
+                zoom(root);
+            }
+        }
+    });
+
+    zoom(root);
+})();
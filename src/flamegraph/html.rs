@@ -0,0 +1,257 @@
+use std::borrow::Cow;
+use std::io::prelude::*;
+
+use str_stack::StrStack;
+
+use super::merge::TimedFrame;
+use super::{color, svg, Direction, Options, TextTruncateDirection};
+
+pub(super) fn write_error<W: Write>(
+    writer: &mut W,
+    opt: &Options<'_>,
+    message: &str,
+) -> quick_xml::Result<()> {
+    write_head(writer, opt)?;
+    writeln!(writer, "<body>")?;
+    writeln!(writer, "<p id=\"error\">{}</p>", escape(message))?;
+    writeln!(writer, "</body>")?;
+    writeln!(writer, "</html>")?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes a self-contained HTML document that draws `frames` on the client side.
+///
+/// The frames are embedded as JSON, one array of `[depth, start, end, name, info, color]` per
+/// line, where `start` and `end` are in samples. With [`Options::focus`], the depth of the
+/// focused frames is included too, since the frames at lower depths are their callers.
+pub(super) fn write_flamegraph<W: Write>(
+    mut writer: W,
+    opt: &mut Options<'_>,
+    frames: Vec<TimedFrame<'_>>,
+    timemax: usize,
+    depthmax: usize,
+    delta_max: usize,
+    focus_depth: Option<usize>,
+) -> quick_xml::Result<()> {
+    write_head(&mut writer, opt)?;
+    writeln!(writer, "<body>")?;
+    writeln!(writer, "<header>")?;
+    writeln!(writer, "<h1 id=\"title\">{}</h1>", escape(&opt.title))?;
+    if let Some(ref subtitle) = opt.subtitle {
+        writeln!(writer, "<p id=\"subtitle\">{}</p>", escape(subtitle))?;
+    }
+    writeln!(writer, "<div id=\"controls\">")?;
+    writeln!(
+        writer,
+        "<button id=\"unzoom\" type=\"button\" disabled>Reset Zoom</button>"
+    )?;
+    writeln!(writer, "<span id=\"matched\"></span>")?;
+    writeln!(
+        writer,
+        "<input id=\"search\" type=\"search\" placeholder=\"Search (regex)\" aria-label=\"Search\">"
+    )?;
+    writeln!(writer, "</div>")?;
+    writeln!(
+        writer,
+        "<nav id=\"breadcrumb\" aria-label=\"Zoomed path\"></nav>"
+    )?;
+    writeln!(writer, "</header>")?;
+    writeln!(
+        writer,
+        "<main id=\"chart\" class=\"{}\"></main>",
+        match opt.text_truncate_direction {
+            TextTruncateDirection::Left => "truncate-left",
+            TextTruncateDirection::Right => "truncate-right",
+        }
+    )?;
+    writeln!(writer, "<div id=\"tooltip\" role=\"tooltip\" hidden></div>")?;
+
+    writeln!(
+        writer,
+        "<script type=\"application/json\" id=\"flamegraph\">"
+    )?;
+    let mut buffer = String::new();
+    write_json_str(&mut buffer, &opt.name_type);
+    write!(
+        writer,
+        "{{\"nametype\":{},\"inverted\":{},\"total_samples\":{},\"max_depth\":{},",
+        buffer,
+        opt.direction == Direction::Inverted,
+        timemax,
+        depthmax,
+    )?;
+    if let Some(focus_depth) = focus_depth {
+        write!(writer, "\"focus_depth\":{},", focus_depth)?;
+    }
+    writeln!(writer, "\"frames\":[")?;
+
+    let mut thread_rng = super::rand::thread_rng();
+    let mut info_buffer = StrStack::new();
+    let mut samples_txt_buffer = num_format::Buffer::default();
+    let widthpertime_pct = 100.0 / timemax as f64;
+    for (i, frame) in frames.iter().enumerate() {
+        let width_pct = (frame.end_time - frame.start_time) as f64 * widthpertime_pct;
+        let color = super::frame_color(opt, frame, width_pct, delta_max, &mut thread_rng);
+        let info = super::write_info(
+            opt,
+            &mut info_buffer,
+            &mut samples_txt_buffer,
+            frame,
+            timemax,
+        );
+        let info = title(opt, frame, &info_buffer[info]);
+        let name = super::frame_name(frame);
+
+        buffer.clear();
+        buffer.push_str(if i == 0 { "[" } else { ",\n[" });
+        buffer.push_str(itoa::Buffer::new().format(frame.location.depth));
+        buffer.push(',');
+        buffer.push_str(itoa::Buffer::new().format(frame.start_time));
+        buffer.push(',');
+        buffer.push_str(itoa::Buffer::new().format(frame.end_time));
+        buffer.push(',');
+        write_json_str(&mut buffer, name);
+        buffer.push(',');
+        write_json_str(&mut buffer, info);
+        buffer.push_str(&format!(",\"rgb({},{},{})\"]", color.r, color.g, color.b));
+        writer.write_all(buffer.as_bytes())?;
+        info_buffer.clear();
+    }
+    writeln!(writer, "\n]}}")?;
+    writeln!(writer, "</script>")?;
+
+    if !opt.no_javascript {
+        writeln!(writer, "<script>")?;
+        writer.write_all(include_str!("html.js").as_bytes())?;
+        writeln!(writer, "</script>")?;
+    }
+    writeln!(writer, "</body>")?;
+    writeln!(writer, "</html>")?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_head<W: Write>(writer: &mut W, opt: &Options<'_>) -> quick_xml::Result<()> {
+    let (bgcolor1, bgcolor2) = color::bgcolor_for(opt.bgcolors, opt.colors);
+    let width = match opt.image_width {
+        Some(width) => format!("{}px", width - 2 * super::XPAD),
+        None => "auto".to_string(),
+    };
+
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(writer, "<html lang=\"en\">")?;
+    writeln!(writer, "<head>")?;
+    writeln!(writer, "<meta charset=\"utf-8\">")?;
+    writeln!(
+        writer,
+        "<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">"
+    )?;
+    writeln!(writer, "<title>{}</title>", escape(&opt.title))?;
+    writeln!(
+        writer,
+        "<!-- Flame graph stack visualization. \
+         See https://github.com/brendangregg/FlameGraph for latest version, \
+         and http://www.brendangregg.com/flamegraphs.html for examples. -->"
+    )?;
+    writeln!(
+        writer,
+        "<!-- NOTES: {} -->",
+        escape(&opt.notes).replace("--", "&#45;&#45;")
+    )?;
+    writeln!(writer, "<style>")?;
+    writeln!(
+        writer,
+        ":root {{ --font-family:{}; --font-size:{}px; --frame-height:{}px; --search-color:{}; \
+         --background:linear-gradient({}, {}); --width:{}; --padding:{}px; }}",
+        // A `<` could close the style element, so escape it for CSS.
+        svg::enquote('"', &opt.font_type).replace('<', "\\3c "),
+        opt.font_size,
+        opt.frame_height,
+        opt.search_color,
+        bgcolor1,
+        bgcolor2,
+        width,
+        super::XPAD,
+    )?;
+    writer.write_all(include_str!("html.css").as_bytes())?;
+    writeln!(writer, "</style>")?;
+    writeln!(writer, "</head>")?;
+    Ok(())
+}
+
+#[cfg(feature = "nameattr")]
+fn title<'a>(opt: &'a Options<'_>, frame: &TimedFrame<'_>, info: &'a str) -> &'a str {
+    opt.func_frameattrs
+        .frameattrs_for_func(frame.location.function)
+        .and_then(|frame_attributes| frame_attributes.title.as_deref())
+        .unwrap_or(info)
+}
+
+#[cfg(not(feature = "nameattr"))]
+fn title<'a>(_opt: &Options<'_>, _frame: &TimedFrame<'_>, info: &'a str) -> &'a str {
+    info
+}
+
+/// Escapes text for use in HTML content and attribute values.
+fn escape(s: &str) -> Cow<'_, str> {
+    if !s.contains(&['&', '<', '>', '"', '\''][..]) {
+        return Cow::Borrowed(s);
+    }
+    let mut escaped = String::with_capacity(s.len() + 16);
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Appends `s` as a JSON string literal that is also safe to embed in a `<script>` element.
+fn write_json_str(buffer: &mut String, s: &str) {
+    use std::fmt::Write;
+
+    buffer.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => buffer.push_str("\\\""),
+            '\\' => buffer.push_str("\\\\"),
+            '\n' => buffer.push_str("\\n"),
+            '\r' => buffer.push_str("\\r"),
+            '\t' => buffer.push_str("\\t"),
+            // `<` and `>` would allow the string to close the script element, and U+2028 and
+            // U+2029 aren't valid in JavaScript string literals in older engines.
+            '<' | '>' | '&' | '\u{2028}' | '\u{2029}' => {
+                let _ = write!(buffer, "\\u{:04x}", c as u32);
+            }
+            c if (c as u32) < 0x20 => {
+                let _ = write!(buffer, "\\u{:04x}", c as u32);
+            }
+            c => buffer.push(c),
+        }
+    }
+    buffer.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape, write_json_str};
+
+    #[test]
+    fn json_strings_cannot_close_the_script_element() {
+        let mut buffer = String::new();
+        write_json_str(&mut buffer, "foo</script>\"bar\"\\\n");
+        assert_eq!(buffer, r#""foo\u003c/script\u003e\"bar\"\\\n""#);
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(escape("a::<b & 'c'>"), "a::&lt;b &amp; &#39;c&#39;&gt;");
+        assert_eq!(escape("plain"), "plain");
+    }
+}
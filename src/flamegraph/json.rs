@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::io::prelude::*;
+
+use serde::Serialize;
+
+use super::merge::TimedFrame;
+use super::Options;
+
+const SPEEDSCOPE_SCHEMA: &str = "https://www.speedscope.app/file-format-schema.json";
+
+/// A node of the nested format read by [d3-flame-graph](https://github.com/spiermar/d3-flame-graph).
+#[derive(Debug, Serialize)]
+struct D3Node<'a> {
+    name: &'a str,
+    value: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delta: Option<isize>,
+    children: Vec<D3Node<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SpeedscopeFile<'a> {
+    #[serde(rename = "$schema")]
+    schema: &'static str,
+    shared: SpeedscopeShared<'a>,
+    profiles: Vec<SpeedscopeProfile<'a>>,
+    name: &'a str,
+    active_profile_index: usize,
+    exporter: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct SpeedscopeShared<'a> {
+    frames: Vec<SpeedscopeFrame<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct SpeedscopeFrame<'a> {
+    name: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SpeedscopeProfile<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    name: &'a str,
+    unit: &'static str,
+    start_value: usize,
+    end_value: usize,
+    samples: Vec<Vec<usize>>,
+    weights: Vec<usize>,
+}
+
+/// Writes the frames as the nested `{name, value, children}` objects read by d3-flame-graph.
+///
+/// Differential flame graphs also get the `delta` of each frame, which d3-flame-graph uses to
+/// color frames in differential mode.
+pub(super) fn write_d3<W: Write>(
+    writer: W,
+    opt: &Options<'_>,
+    frames: Vec<TimedFrame<'_>>,
+) -> quick_xml::Result<()> {
+    let root = tree(opt, frames);
+    write(writer, opt, &root)
+}
+
+/// Writes the frames as a [speedscope](https://www.speedscope.app) profile of the `sampled` type.
+///
+/// Each frame that has time of its own becomes one weighted sample. Time of frames that were
+/// pruned for being too narrow is attributed to their callers. Speedscope has no notion of
+/// differentials, so only the second sample count of differential input is kept.
+pub(super) fn write_speedscope<W: Write>(
+    writer: W,
+    opt: &Options<'_>,
+    frames: Vec<TimedFrame<'_>>,
+) -> quick_xml::Result<()> {
+    let root = tree(opt, frames);
+
+    let mut frame_ids = HashMap::new();
+    let mut shared = SpeedscopeShared { frames: Vec::new() };
+    let mut profile = SpeedscopeProfile {
+        kind: "sampled",
+        name: &opt.title,
+        unit: "none",
+        start_value: 0,
+        end_value: 0,
+        samples: Vec::new(),
+        weights: Vec::new(),
+    };
+    // The `all` root frame is left out of the samples.
+    let mut stack = Vec::new();
+    for child in &root.children {
+        add_samples(child, &mut stack, &mut frame_ids, &mut shared, &mut profile);
+    }
+    profile.end_value = profile.weights.iter().sum();
+
+    let file = SpeedscopeFile {
+        schema: SPEEDSCOPE_SCHEMA,
+        shared,
+        profiles: vec![profile],
+        name: &opt.title,
+        active_profile_index: 0,
+        exporter: "inferno",
+    };
+    write(writer, opt, &file)
+}
+
+fn add_samples<'a>(
+    node: &D3Node<'a>,
+    stack: &mut Vec<usize>,
+    frame_ids: &mut HashMap<&'a str, usize>,
+    shared: &mut SpeedscopeShared<'a>,
+    profile: &mut SpeedscopeProfile<'_>,
+) {
+    let id = *frame_ids.entry(node.name).or_insert_with(|| {
+        shared.frames.push(SpeedscopeFrame { name: node.name });
+        shared.frames.len() - 1
+    });
+    stack.push(id);
+
+    let mut self_value = node.value;
+    for child in &node.children {
+        self_value = self_value.saturating_sub(child.value);
+        add_samples(child, stack, frame_ids, shared, profile);
+    }
+    if self_value > 0 {
+        profile.samples.push(stack.clone());
+        profile.weights.push(self_value);
+    }
+
+    stack.pop();
+}
+
+/// Nests the frames, which must include the `all` frame at depth 0, into a tree.
+fn tree<'a>(opt: &Options<'_>, mut frames: Vec<TimedFrame<'a>>) -> D3Node<'a> {
+    // Callers start no later than their callees, so this puts every frame right after its
+    // caller and the callees that come before it.
+    frames.sort_unstable_by_key(|frame| (frame.start_time, frame.location.depth));
+
+    let mut stack: Vec<D3Node<'a>> = Vec::new();
+    for frame in frames {
+        while stack.len() > frame.location.depth {
+            let node = stack.pop().unwrap();
+            stack.last_mut().unwrap().children.push(node);
+        }
+
+        let name = super::frame_name(&frame);
+        // Rounded the same way as the sample counts shown in SVGs.
+        let value = ((frame.end_time - frame.start_time) as f64 * opt.factor).round() as usize;
+        let delta = frame.delta.map(|delta| {
+            let delta = (delta as f64 * opt.factor).round() as isize;
+            if opt.negate_differentials {
+                -delta
+            } else {
+                delta
+            }
+        });
+        stack.push(D3Node {
+            name,
+            value,
+            delta,
+            children: Vec::new(),
+        });
+    }
+    while stack.len() > 1 {
+        let node = stack.pop().unwrap();
+        stack.last_mut().unwrap().children.push(node);
+    }
+    stack.pop().expect("frames always include the root frame")
+}
+
+fn write<W: Write, T: Serialize>(
+    mut writer: W,
+    opt: &Options<'_>,
+    value: &T,
+) -> quick_xml::Result<()> {
+    let result = if opt.pretty_xml {
+        serde_json::to_writer_pretty(&mut writer, value)
+    } else {
+        serde_json::to_writer(&mut writer, value)
+    };
+    result.map_err(|e| quick_xml::Error::Io(e.into()))?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    Ok(())
+}
@@ -16,21 +16,35 @@ pub(super) struct TimedFrame<'a> {
     pub(super) start_time: usize,
     pub(super) end_time: usize,
     pub(super) delta: Option<isize>,
+    /// For differential flame graphs, how likely the frame's change is to be noise, given the
+    /// total samples of each profile. The change is in self samples or in samples including
+    /// callees, depending on `Options::differential_colors`. Lower is more significant.
+    pub(super) p_value: Option<f64>,
+    /// For differential flame graphs, the self samples of the frame in the first and second
+    /// profile.
+    pub(super) counts: Option<(usize, usize)>,
+    /// The samples of the frame, including its callees, in the first profile of a differential.
+    pub(super) original_samples: usize,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub(super) struct FrameTime {
     pub(super) start_time: usize,
     pub(super) delta: Option<isize>,
+    counts: Option<(usize, usize)>,
+    start_original_time: usize,
 }
 
+#[allow(clippy::too_many_arguments)]
 fn flow<'a, LI, TI>(
     tmp: &mut HashMap<Frame<'a>, FrameTime>,
     frames: &mut Vec<TimedFrame<'a>>,
     last: LI,
     this: TI,
     time: usize,
+    original_time: usize,
     delta: Option<isize>,
+    counts: Option<(usize, usize)>,
 ) where
     LI: IntoIterator<Item = &'a str>,
     TI: IntoIterator<Item = &'a str>,
@@ -70,6 +84,9 @@ fn flow<'a, LI, TI>(
             start_time: frame_time.start_time,
             end_time: time,
             delta: frame_time.delta,
+            p_value: None,
+            counts: frame_time.counts,
+            original_samples: original_time - frame_time.start_original_time,
         };
         frames.push(frame);
     }
@@ -87,11 +104,17 @@ fn flow<'a, LI, TI>(
             Some(_) if !is_last => Some(0),
             d => d,
         };
+        let counts = match counts {
+            Some(_) if !is_last => Some((0, 0)),
+            c => c,
+        };
         let frame_time = FrameTime {
             start_time: time,
             // For some reason the Perl version does a `+=` for `delta`, but I can't figure out why.
             // See https://github.com/brendangregg/FlameGraph/blob/1b1c6deede9c33c5134c920bdb7a44cc5528e9a7/flamegraph.pl#L588
             delta,
+            counts,
+            start_original_time: original_time,
         };
 
         //eprintln!("stored tmp for time {}: {:?}", time, key);
@@ -114,9 +137,11 @@ where
     I: IntoIterator<Item = &'a str>,
 {
     let mut time = 0;
+    // Like `time`, but counting the samples of the first profile of a differential.
+    let mut original_time = 0;
     let mut ignored = 0;
     let mut last = "";
-    let mut tmp = Default::default();
+    let mut tmp: HashMap<Frame<'a>, FrameTime> = Default::default();
     let mut frames = Default::default();
     let mut delta = None;
     let mut delta_max = 1;
@@ -143,6 +168,7 @@ where
         // Usually there will only be one samples column at the end of a line,
         // but for differentials there will be two. When there are two we compute the
         // delta between them and use the second one.
+        let mut counts = None;
         let nsamples =
             if let Some(samples) = parse_nsamples(&mut line, &mut stripped_fractional_samples) {
                 // See if there's also a differential column present
@@ -151,6 +177,7 @@ where
                 {
                     delta = Some(samples as isize - original_samples as isize);
                     delta_max = std::cmp::max(delta.unwrap().abs() as usize, delta_max);
+                    counts = Some((original_samples, samples));
                 }
                 samples
             } else {
@@ -164,12 +191,39 @@ where
         }
         let stack = line;
 
+        // A repeated stack continues its last frame, so add to the self samples of that frame.
+        if stack == last {
+            if let Some((before, after)) = counts {
+                let key = Frame {
+                    function: stack.rsplit(';').next().unwrap_or(stack),
+                    depth: stack.split(';').count(),
+                };
+                if let Some(FrameTime {
+                    counts: Some(ref mut frame_counts),
+                    ..
+                }) = tmp.get_mut(&key)
+                {
+                    frame_counts.0 += before;
+                    frame_counts.1 += after;
+                }
+            }
+        }
+
         // inject empty first-level stack frame to capture "all"
         let this = iter::once("").chain(stack.split(';'));
         if last.is_empty() {
             // need to special-case this, because otherwise iter("") + "".split(';') == ["", ""]
             //eprintln!("flow(_, {}, {})", stack, time);
-            flow(&mut tmp, &mut frames, None, this, time, delta);
+            flow(
+                &mut tmp,
+                &mut frames,
+                None,
+                this,
+                time,
+                original_time,
+                delta,
+                counts,
+            );
         } else {
             //eprintln!("flow({}, {}, {})", last, stack, time);
             flow(
@@ -178,12 +232,17 @@ where
                 iter::once("").chain(last.split(';')),
                 this,
                 time,
+                original_time,
                 delta,
+                counts,
             );
         }
 
         last = stack;
         time += nsamples;
+        if let Some((original_samples, _)) = counts {
+            original_time += original_samples;
+        }
         prev_line = Some(line);
     }
 
@@ -195,13 +254,48 @@ where
             iter::once("").chain(last.split(';')),
             None,
             time,
+            original_time,
             delta,
+            None,
         );
     }
 
     Ok((frames, time, ignored, delta_max))
 }
 
+// Two-sided p-value of `before` samples out of `total_before` becoming `after` out of
+// `total_after`, if the frame's share of the samples did not actually change.
+//
+// Given the `before + after` samples of the frame, each one landing in the second profile is a
+// coin flip weighted by the profile totals, so this is a binomial test. It uses the normal
+// approximation with a continuity correction, which is plenty for coloring frames.
+pub(super) fn p_value(before: usize, after: usize, total_before: usize, total_after: usize) -> f64 {
+    let n = (before + after) as f64;
+    if total_before == 0 || total_after == 0 || n == 0.0 {
+        return 1.0;
+    }
+    let p = total_after as f64 / (total_before + total_after) as f64;
+    let mean = n * p;
+    let variance = mean * (1.0 - p);
+    let z = ((after as f64 - mean).abs() - 0.5).max(0.0) / variance.sqrt();
+    erfc(z / std::f64::consts::SQRT_2)
+}
+
+// Complementary error function for x >= 0, with a fractional error below 1.2e-7.
+// From Numerical Recipes in C, section 6.2.
+fn erfc(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.5 * x);
+    t * (-x * x - 1.265_512_23
+        + t * (1.000_023_68
+            + t * (0.374_091_96
+                + t * (0.096_784_18
+                    + t * (-0.186_288_06
+                        + t * (0.278_868_07
+                            + t * (-1.135_203_98
+                                + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77)))))))))
+        .exp()
+}
+
 // Parse and remove the number of samples from the end of a line.
 fn parse_nsamples(line: &mut &str, stripped_fractional_samples: &mut bool) -> Option<usize> {
     if let Some((samplesi, doti)) = rfind_samples(line) {
@@ -8,9 +8,12 @@ macro_rules! args {
 mod attrs;
 
 pub mod color;
+mod html;
+mod json;
 mod merge;
 mod rand;
 mod svg;
+mod text;
 
 use std::fs::File;
 use std::io::prelude::*;
@@ -23,6 +26,7 @@ use log::{error, warn};
 use num_format::Locale;
 use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
 use quick_xml::Writer;
+use regex::Regex;
 use str_stack::StrStack;
 
 #[cfg(feature = "nameattr")]
@@ -34,6 +38,8 @@ pub use self::attrs::FuncFrameAttrsMap;
 pub use self::color::Palette;
 use self::color::{Color, SearchColor};
 use self::svg::{Dimension, StyleOptions};
+use crate::compression;
+use crate::filter::{Filter, FoldRecursion, Rule};
 
 const XPAD: usize = 10; // pad left and right
 const FRAMEPAD: usize = 1; // vertical padding for frames
@@ -86,7 +92,8 @@ pub mod defaults {
         FONT_WIDTH: f64 = 0.59,
         COUNT_NAME: &str = "samples",
         NAME_TYPE: &str = "Function:",
-        FACTOR: f64 = 1.0
+        FACTOR: f64 = 1.0,
+        FORMAT: &str = "svg"
     }
 }
 
@@ -151,6 +158,8 @@ pub struct Options<'a> {
     /// Width of the flame graph
     ///
     /// Defaults to None, which means the width will be "fluid".
+    ///
+    /// For [`OutputFormat::Text`], this is the width in columns, and defaults to 100.
     pub image_width: Option<usize>,
 
     /// Height of each frame.
@@ -201,6 +210,22 @@ pub struct Options<'a> {
     /// [differential]: http://www.brendangregg.com/blog/2014-11-09/differential-flame-graphs.html
     pub negate_differentials: bool,
 
+    /// What the colors of differential flame graphs are based on.
+    ///
+    /// Defaults to [`DifferentialColors::SelfDelta`].
+    pub differential_colors: DifferentialColors,
+
+    /// Only color the frames of differential flame graphs whose change is statistically
+    /// significant, that is, whose p-value is below this threshold (e.g. `0.05`).
+    ///
+    /// Each frame's change in samples, as counted by [`Options::differential_colors`], is tested
+    /// against the total samples of the two profiles, so small changes in rarely sampled stacks,
+    /// which are likely just noise, are left white. The p-value is shown in the details of each
+    /// changed frame.
+    ///
+    /// Defaults to None, which colors every changed frame.
+    pub significance: Option<f64>,
+
     /// Factor to scale sample counts by in the flame graph.
     ///
     /// This option can be useful if the sample data has fractional sample counts since the fractional
@@ -213,7 +238,7 @@ pub struct Options<'a> {
     /// [Default value](defaults::FACTOR).
     pub factor: f64,
 
-    /// Pretty print XML with newlines and indentation.
+    /// Pretty print XML and JSON output with newlines and indentation.
     pub pretty_xml: bool,
 
     /// Don't sort the input lines.
@@ -249,6 +274,35 @@ pub struct Options<'a> {
     ///
     /// Note that stack is not sorted and will be reversed
     pub flame_chart: bool,
+
+    /// Only show the stacks through the functions this regular expression matches, or with
+    /// exactly this name, as a "sandwich" of their callers and callees.
+    ///
+    /// The functions called by the focused functions are merged across all call sites into a
+    /// regular flame graph rooted at the focused functions. The functions they were called from
+    /// are merged towards the focused functions and drawn as an inverted graph on the other side
+    /// of them. JSON output only includes the callees. `reverse_stack_order` and `flame_chart`
+    /// are ignored when this is set.
+    pub focus: Option<String>,
+
+    /// Rules that rewrite or remove stacks before they are merged into frames.
+    ///
+    /// Default is no rules, which leaves the stacks as they are.
+    pub filter: Filter,
+
+    /// Merge recursive calls into a single frame: either only where a function calls itself
+    /// directly, or also for cycles through other functions. This is done after applying
+    /// `filter`.
+    ///
+    /// Default is `None`.
+    pub fold_recursion: Option<FoldRecursion>,
+
+    /// The format to write the flame graph in.
+    pub format: OutputFormat,
+
+    /// Don't color [`OutputFormat::Text`] flame graphs with ANSI escape codes, for output that
+    /// isn't shown in a terminal, or terminals that don't support 24-bit color.
+    pub no_text_colors: bool,
 }
 
 impl<'a> Options<'a> {
@@ -269,6 +323,22 @@ impl<'a> Options<'a> {
         }
     }
 
+    /// Calculate the top and bottom of frames at `depth`
+    pub(super) fn frame_y(&self, imageheight: usize, depth: usize) -> (usize, usize) {
+        match self.direction {
+            Direction::Straight => {
+                let y1 = imageheight - self.ypad2() - (depth + 1) * self.frame_height + FRAMEPAD;
+                let y2 = imageheight - self.ypad2() - depth * self.frame_height;
+                (y1, y2)
+            }
+            Direction::Inverted => {
+                let y1 = self.ypad1() + depth * self.frame_height;
+                let y2 = self.ypad1() + (depth + 1) * self.frame_height - FRAMEPAD;
+                (y1, y2)
+            }
+        }
+    }
+
     /// Calculate pad bottom, including labels
     pub(super) fn ypad2(&self) -> usize {
         if self.direction == Direction::Straight {
@@ -305,12 +375,19 @@ impl<'a> Default for Options<'a> {
             palette_map: Default::default(),
             direction: Default::default(),
             negate_differentials: Default::default(),
+            differential_colors: Default::default(),
+            significance: Default::default(),
             pretty_xml: Default::default(),
             no_sort: Default::default(),
             reverse_stack_order: Default::default(),
             no_javascript: Default::default(),
             color_diffusion: Default::default(),
             flame_chart: Default::default(),
+            focus: Default::default(),
+            filter: Default::default(),
+            fold_recursion: Default::default(),
+            format: Default::default(),
+            no_text_colors: Default::default(),
 
             #[cfg(feature = "nameattr")]
             func_frameattrs: Default::default(),
@@ -354,6 +431,89 @@ impl Default for TextTruncateDirection {
     }
 }
 
+/// The format a flame graph is written in.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum OutputFormat {
+    /// An SVG image with embedded ECMAScript for searching and zooming.
+    #[default]
+    Svg,
+
+    /// A self-contained HTML document.
+    ///
+    /// Next to the frames, the page has a search box, a reset-zoom button, a panel with details
+    /// of the hovered frame and a breadcrumb of the zoomed path. Unlike the SVG, it keeps working
+    /// in viewers that strip or sandbox scripts in SVG images.
+    Html,
+
+    /// The nested `{name, value, children}` JSON read by
+    /// [d3-flame-graph](https://github.com/spiermar/d3-flame-graph).
+    ///
+    /// Frames of differential flame graphs also have a `delta`.
+    D3,
+
+    /// A JSON [speedscope](https://www.speedscope.app) profile of the `sampled` type.
+    Speedscope,
+
+    /// Rows of text colored with ANSI escape codes, for viewing in a terminal.
+    ///
+    /// Each frame is labeled with its name and percentage of samples. The width is given in
+    /// columns by [`Options::image_width`].
+    Text,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "svg" => Ok(OutputFormat::Svg),
+            "html" => Ok(OutputFormat::Html),
+            "d3" => Ok(OutputFormat::D3),
+            "speedscope" => Ok(OutputFormat::Speedscope),
+            "text" => Ok(OutputFormat::Text),
+            unknown => Err(format!("unknown output format: {}", unknown)),
+        }
+    }
+}
+
+/// What the colors of a differential flame graph are based on.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum DifferentialColors {
+    /// The change in the self samples of each frame, excluding its callees.
+    ///
+    /// This is what the Perl version does, and it points at the functions that changed rather
+    /// than at everything that calls them. The details of each frame show the change as a
+    /// percentage of all samples.
+    #[default]
+    SelfDelta,
+
+    /// The change in the samples of each frame including its callees.
+    ///
+    /// The details of each frame show the change as a percentage of all samples, followed by
+    /// `total`.
+    Absolute,
+
+    /// The ratio of the samples of each frame, including its callees, in the second profile to
+    /// those in the first.
+    ///
+    /// Frames are fully red at four times as many samples, or when new, and fully blue at a
+    /// quarter of them. The details of each frame show the ratio, such as `1.50x`.
+    Ratio,
+}
+
+impl FromStr for DifferentialColors {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "self" => Ok(DifferentialColors::SelfDelta),
+            "absolute" => Ok(DifferentialColors::Absolute),
+            "ratio" => Ok(DifferentialColors::Ratio),
+            unknown => Err(format!("unknown differential colors: {}", unknown)),
+        }
+    }
+}
+
 struct Rectangle {
     x1_samples: usize,
     x1_pct: f64,
@@ -384,7 +544,8 @@ impl Rectangle {
 /// flame graph uses the difference between the two sample counts to show how the sample counts for
 /// each stack has changed between the first and second profiling.
 ///
-/// The resulting flame graph will be written out to `writer` in SVG format.
+/// The resulting flame graph will be written out to `writer` in the format selected by
+/// [`Options::format`], which is SVG by default.
 ///
 /// [differential flame graph]: http://www.brendangregg.com/blog/2014-11-09/differential-flame-graphs.html
 #[allow(clippy::cognitive_complexity)]
@@ -393,55 +554,10 @@ where
     I: IntoIterator<Item = &'a str>,
     W: Write,
 {
-    let mut reversed = StrStack::new();
-    let (mut frames, time, ignored, delta_max) = if opt.reverse_stack_order {
-        if opt.no_sort {
-            warn!(
-                "Input lines are always sorted when `reverse_stack_order` is `true`. \
-                 The `no_sort` option is being ignored."
-            );
-        }
-        // Reverse order of stacks and sort.
-        let mut stack = String::new();
-        for line in lines {
-            stack.clear();
-            let samples_idx = merge::rfind_samples(line)
-                .map(|(i, _)| i)
-                .unwrap_or_else(|| line.len());
-            let samples_idx = merge::rfind_samples(&line[..samples_idx - 1])
-                .map(|(i, _)| i)
-                .unwrap_or(samples_idx);
-            for (i, func) in line[..samples_idx].trim().split(';').rev().enumerate() {
-                if i != 0 {
-                    stack.push(';');
-                }
-                stack.push_str(func);
-            }
-            stack.push(' ');
-            stack.push_str(&line[samples_idx..]);
-            reversed.push(&stack);
-        }
-        let mut reversed: Vec<&str> = reversed.iter().collect();
-        reversed.sort_unstable();
-        merge::frames(reversed, false)?
-    } else if opt.flame_chart {
-        // In flame chart mode, just reverse the data so time moves from left to right.
-        let mut lines: Vec<&str> = lines.into_iter().collect();
-        lines.reverse();
-        merge::frames(lines, true)?
-    } else if opt.no_sort {
-        // Lines don't need sorting.
-        merge::frames(lines, false)?
-    } else {
-        // Sort lines by default.
-        let mut lines: Vec<&str> = lines.into_iter().collect();
-        lines.sort_unstable();
-        merge::frames(lines, false)?
-    };
-
-    if ignored != 0 {
-        warn!("Ignored {} lines with invalid format", ignored);
-    }
+    let mut filtered_buffer = StrStack::new();
+    let mut lines_buffer = StrStack::new();
+    let (mut frames, time, delta_max, focus_depth) =
+        merge_lines(opt, lines, &mut filtered_buffer, &mut lines_buffer)?;
 
     let mut buffer = StrStack::new();
 
@@ -454,21 +570,30 @@ where
 
     if time == 0 {
         error!("No stack counts found");
-        // emit an error message SVG, for tools automating flamegraph use
-        let imageheight = opt.font_size * 5;
-        svg::write_header(&mut svg, imageheight, opt)?;
-        svg::write_str(
-            &mut svg,
-            &mut buffer,
-            svg::TextItem {
-                x: Dimension::Percent(50.0),
-                y: (opt.font_size * 2) as f64,
-                text: "ERROR: No valid input provided to flamegraph".into(),
-                extra: None,
-            },
-        )?;
-        svg.write_event(Event::End(BytesEnd::borrowed(b"svg")))?;
-        svg.write_event(Event::Eof)?;
+        // emit an error message, for tools automating flamegraph use
+        let message = "ERROR: No valid input provided to flamegraph";
+        match opt.format {
+            OutputFormat::Svg => {
+                let imageheight = opt.font_size * 5;
+                svg::write_header(&mut svg, imageheight, opt)?;
+                svg::write_str(
+                    &mut svg,
+                    &mut buffer,
+                    svg::TextItem {
+                        x: Dimension::Percent(50.0),
+                        y: (opt.font_size * 2) as f64,
+                        text: message.into(),
+                        extra: None,
+                    },
+                )?;
+                svg.write_event(Event::End(BytesEnd::borrowed(b"svg")))?;
+                svg.write_event(Event::Eof)?;
+            }
+            OutputFormat::Html => html::write_error(svg.inner(), opt, message)?,
+            OutputFormat::Text => writeln!(svg.inner(), "{}", message)?,
+            // There is no way to show an error in JSON output, so write nothing.
+            OutputFormat::D3 | OutputFormat::Speedscope => {}
+        }
         return Err(quick_xml::Error::Io(io::Error::new(
             io::ErrorKind::InvalidData,
             "No stack counts found",
@@ -478,18 +603,34 @@ where
     let image_width = opt.image_width.unwrap_or(DEFAULT_IMAGE_WIDTH) as f64;
     let timemax = time;
     let widthpertime_pct = 100.0 / timemax as f64;
-    let minwidth_time = opt.min_width / widthpertime_pct;
-
-    // prune blocks that are too narrow
-    let mut depthmax = 0;
-    frames.retain(|frame| {
-        if ((frame.end_time - frame.start_time) as f64) < minwidth_time {
-            false
-        } else {
-            depthmax = std::cmp::max(depthmax, frame.location.depth);
-            true
+    let depthmax = prune(opt, &mut frames, timemax);
+
+    match opt.format {
+        OutputFormat::Svg => {}
+        OutputFormat::Html => {
+            return html::write_flamegraph(
+                svg.into_inner(),
+                opt,
+                frames,
+                timemax,
+                depthmax,
+                delta_max,
+                focus_depth,
+            );
         }
-    });
+        OutputFormat::D3 => return json::write_d3(svg.into_inner(), opt, frames),
+        OutputFormat::Speedscope => return json::write_speedscope(svg.into_inner(), opt, frames),
+        OutputFormat::Text => {
+            return text::write_flamegraph(
+                svg.into_inner(),
+                opt,
+                frames,
+                timemax,
+                depthmax,
+                delta_max,
+            );
+        }
+    }
 
     // draw canvas, and embed interactive JavaScript program
     let imageheight = ((depthmax + 1) * opt.frame_height) + opt.ypad1() + opt.ypad2();
@@ -519,13 +660,20 @@ where
     // create frames container
     let container_x = format!("{}", XPAD);
     let container_width = format!("{}", image_width as usize - XPAD - XPAD);
+    let total_samples = format!("{}", timemax);
+    let mut container_attributes = vec![
+        ("id", "frames"),
+        ("x", container_x.as_str()),
+        ("width", &container_width),
+        ("total_samples", &total_samples),
+    ];
+    // Let the JavaScript tell the callers and callees of a focused function apart.
+    let focus_y = focus_depth.map(|depth| format!("{}", opt.frame_y(imageheight, depth).0));
+    if let Some(ref focus_y) = focus_y {
+        container_attributes.push(("focus_y", focus_y));
+    }
     svg.write_event(Event::Start(
-        BytesStart::borrowed_name(b"svg").with_attributes(vec![
-            ("id", "frames"),
-            ("x", &container_x),
-            ("width", &container_width),
-            ("total_samples", &format!("{}", timemax)),
-        ]),
+        BytesStart::borrowed_name(b"svg").with_attributes(container_attributes),
     ))?;
 
     // draw frames
@@ -534,19 +682,7 @@ where
         let x1_pct = frame.start_time as f64 * widthpertime_pct;
         let x2_pct = frame.end_time as f64 * widthpertime_pct;
 
-        let (y1, y2) = match opt.direction {
-            Direction::Straight => {
-                let y1 = imageheight - opt.ypad2() - (frame.location.depth + 1) * opt.frame_height
-                    + FRAMEPAD;
-                let y2 = imageheight - opt.ypad2() - frame.location.depth * opt.frame_height;
-                (y1, y2)
-            }
-            Direction::Inverted => {
-                let y1 = opt.ypad1() + frame.location.depth * opt.frame_height;
-                let y2 = opt.ypad1() + (frame.location.depth + 1) * opt.frame_height - FRAMEPAD;
-                (y1, y2)
-            }
-        };
+        let (y1, y2) = opt.frame_y(imageheight, frame.location.depth);
 
         let rect = Rectangle {
             x1_pct,
@@ -557,48 +693,7 @@ where
             y2,
         };
 
-        // The rounding here can differ from the Perl version when the fractional part is `0.5`.
-        // The Perl version does `my $samples = sprintf "%.0f", ($etime - $stime) * $factor;`,
-        // but this can format in strange ways as shown in these examples:
-        //     `sprintf "%.0f", 1.5` produces "2"
-        //     `sprintf "%.0f", 2.5` produces "2"
-        //     `sprintf "%.0f", 3.5` produces "4"
-        let samples = ((frame.end_time - frame.start_time) as f64 * opt.factor).round() as usize;
-
-        // add thousands separators to `samples`
-        let _ = samples_txt_buffer.write_formatted(&samples, &Locale::en);
-        let samples_txt = samples_txt_buffer.as_str();
-
-        let info = if frame.location.function.is_empty() && frame.location.depth == 0 {
-            write!(buffer, "all ({} {}, 100%)", samples_txt, opt.count_name)
-        } else {
-            let pct = (100 * samples) as f64 / (timemax as f64 * opt.factor);
-            let function = deannotate(frame.location.function);
-            match frame.delta {
-                None => write!(
-                    buffer,
-                    "{} ({} {}, {:.2}%)",
-                    function, samples_txt, opt.count_name, pct
-                ),
-                // Special case delta == 0 so we don't format percentage with a + sign.
-                Some(delta) if delta == 0 => write!(
-                    buffer,
-                    "{} ({} {}, {:.2}%; 0.00%)",
-                    function, samples_txt, opt.count_name, pct,
-                ),
-                Some(mut delta) => {
-                    if opt.negate_differentials {
-                        delta = -delta;
-                    }
-                    let delta_pct = (100 * delta) as f64 / (timemax as f64 * opt.factor);
-                    write!(
-                        buffer,
-                        "{} ({} {}, {:.2}%; {:+.2}%)",
-                        function, samples_txt, opt.count_name, pct, delta_pct
-                    )
-                }
-            }
-        };
+        let info = write_info(opt, &mut buffer, &mut samples_txt_buffer, &frame, timemax);
 
         let (has_href, title) = write_container_start(
             opt,
@@ -613,39 +708,7 @@ where
         svg.write_event(Event::Text(BytesText::from_plain_str(title)))?;
         svg.write_event(Event::End(BytesEnd::borrowed(b"title")))?;
 
-        // select the color of the rectangle
-        let color = if frame.location.function == "--" {
-            color::VDGREY
-        } else if frame.location.function == "-" {
-            color::DGREY
-        } else if opt.color_diffusion {
-            // We want to visually highlight high priority regions for
-            // optimization: wider frames are redder. Typically when optimizing,
-            // a frame that is 50% of width is high priority, so it seems wrong
-            // to give it half the saturation of 100%. So we use sqrt to make
-            // the red dropoff less linear.
-            color::color_scale((((x2_pct - x1_pct) / 100.0).sqrt() * 2000.0) as isize, 2000)
-        } else if let Some(mut delta) = frame.delta {
-            if opt.negate_differentials {
-                delta = -delta;
-            }
-            color::color_scale(delta, delta_max)
-        } else if let Some(ref mut palette_map) = opt.palette_map {
-            let colors = opt.colors;
-            let hash = opt.hash;
-            let deterministic = opt.deterministic;
-            palette_map.find_color_for(frame.location.function, |name| {
-                color::color(colors, hash, deterministic, name, &mut thread_rng)
-            })
-        } else {
-            color::color(
-                opt.colors,
-                opt.hash,
-                opt.deterministic,
-                frame.location.function,
-                &mut thread_rng,
-            )
-        };
+        let color = frame_color(opt, &frame, x2_pct - x1_pct, delta_max, &mut thread_rng);
         filled_rectangle(&mut svg, &mut buffer, &rect, color, &mut cache_rect)?;
 
         let fitchars = (rect.width_pct() as f64
@@ -702,6 +765,511 @@ where
     Ok(())
 }
 
+/// A frame of a flame graph, as laid out by [`layout`].
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Frame {
+    /// The name of the function, without annotations like `_[k]`.
+    ///
+    /// The frame at depth 0 that spans all samples is named `all`.
+    pub name: String,
+
+    /// How many frames are between this frame and the `all` frame.
+    pub depth: usize,
+
+    /// The number of samples to the left of the frame.
+    pub start: usize,
+
+    /// The number of samples to the left of the frame's right edge.
+    pub end: usize,
+
+    /// For differential flame graphs, the change in the number of samples of the frame, either
+    /// its self samples or including its callees, as set by [`Options::differential_colors`].
+    ///
+    /// This is negated if [`Options::negate_differentials`] is set.
+    pub delta: Option<isize>,
+
+    /// For differential flame graphs, the p-value of the change in the frame's samples, either
+    /// its self samples or including its callees, as set by [`Options::differential_colors`].
+    ///
+    /// See [`Options::significance`].
+    pub p_value: Option<f64>,
+
+    /// The color of the frame.
+    pub color: Color,
+
+    /// The details of the frame, like `main (10 samples, 50.00%)`.
+    pub info: String,
+}
+
+/// Lay out a flame graph from an iterator over folded stack lines, without drawing it.
+///
+/// This is for tools that present flame graphs in their own way. The frames are merged, sorted,
+/// pruned and colored just like in the other output formats, and are returned ordered by depth
+/// and then by start. See [`from_lines`] for the expected format of each line.
+pub fn layout<'a, I>(opt: &mut Options<'_>, lines: I) -> quick_xml::Result<Vec<Frame>>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut filtered_buffer = StrStack::new();
+    let mut lines_buffer = StrStack::new();
+    let (mut frames, timemax, delta_max, _) =
+        merge_lines(opt, lines, &mut filtered_buffer, &mut lines_buffer)?;
+    if timemax == 0 {
+        error!("No stack counts found");
+        return Err(quick_xml::Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "No stack counts found",
+        )));
+    }
+    prune(opt, &mut frames, timemax);
+    frames.sort_unstable_by_key(|frame| (frame.location.depth, frame.start_time));
+
+    let mut thread_rng = rand::thread_rng();
+    let mut buffer = StrStack::new();
+    let mut samples_txt_buffer = num_format::Buffer::default();
+    let widthpertime_pct = 100.0 / timemax as f64;
+    let frames = frames
+        .iter()
+        .map(|frame| {
+            let width_pct = (frame.end_time - frame.start_time) as f64 * widthpertime_pct;
+            let color = frame_color(opt, frame, width_pct, delta_max, &mut thread_rng);
+            let info = write_info(opt, &mut buffer, &mut samples_txt_buffer, frame, timemax);
+            let info = buffer[info].to_string();
+            buffer.clear();
+            Frame {
+                name: frame_name(frame).to_string(),
+                depth: frame.location.depth,
+                start: frame.start_time,
+                end: frame.end_time,
+                delta: frame.delta.map(|delta| {
+                    if opt.negate_differentials {
+                        -delta
+                    } else {
+                        delta
+                    }
+                }),
+                p_value: frame.p_value,
+                color,
+                info,
+            }
+        })
+        .collect();
+    Ok(frames)
+}
+
+/// Merges folded stack lines into frames, filtering, sorting or reversing them first as the
+/// options say.
+///
+/// Returns the frames, the total number of samples, the largest delta and, with
+/// [`Options::focus`], the depth of the focused frames. Lines rewritten by [`Options::filter`]
+/// or [`Options::fold_recursion`] are stored in `filtered`, and other rewritten lines in
+/// `buffer`.
+fn merge_lines<'a, 'l: 'a, 'r: 'a, I>(
+    opt: &Options<'_>,
+    lines: I,
+    filtered: &'r mut StrStack,
+    buffer: &'r mut StrStack,
+) -> quick_xml::Result<(Vec<merge::TimedFrame<'a>>, usize, usize, Option<usize>)>
+where
+    I: IntoIterator<Item = &'l str>,
+{
+    if opt.filter.is_empty() && opt.fold_recursion.is_none() {
+        return merge_stacks(opt, lines, buffer);
+    }
+    let mut filter = opt.filter.clone();
+    if let Some(fold_recursion) = opt.fold_recursion {
+        filter.push(Rule::FoldRecursion(fold_recursion));
+    }
+    for line in lines {
+        if let Some(line) = filter.apply(line) {
+            filtered.push(&line);
+        }
+    }
+    let filtered: &'r StrStack = filtered;
+    merge_stacks(opt, filtered.iter(), buffer)
+}
+
+/// Merges folded stack lines into frames like [`merge_lines`], without filtering them.
+fn merge_stacks<'a, 'l: 'a, 'r: 'a, I>(
+    opt: &Options<'_>,
+    lines: I,
+    buffer: &'r mut StrStack,
+) -> quick_xml::Result<(Vec<merge::TimedFrame<'a>>, usize, usize, Option<usize>)>
+where
+    I: IntoIterator<Item = &'l str>,
+{
+    let mut focus_depth = None;
+    let (mut frames, time, ignored, delta_max) = if let Some(ref focus) = opt.focus {
+        if opt.reverse_stack_order || opt.flame_chart {
+            warn!(
+                "The `reverse_stack_order` and `flame_chart` options are being ignored \
+                 because `focus` is set."
+            );
+        }
+        let (frames, time, ignored, delta_max, depth) = merge_focused(opt, focus, lines, buffer)?;
+        focus_depth = depth;
+        (frames, time, ignored, delta_max)
+    } else if opt.reverse_stack_order {
+        if opt.no_sort {
+            warn!(
+                "Input lines are always sorted when `reverse_stack_order` is `true`. \
+                 The `no_sort` option is being ignored."
+            );
+        }
+        // Reverse order of stacks and sort.
+        let mut stack = String::new();
+        for line in lines {
+            stack.clear();
+            let samples_idx = samples_index(line);
+            for (i, func) in line[..samples_idx].trim().split(';').rev().enumerate() {
+                if i != 0 {
+                    stack.push(';');
+                }
+                stack.push_str(func);
+            }
+            stack.push(' ');
+            stack.push_str(&line[samples_idx..]);
+            buffer.push(&stack);
+        }
+        let reversed: &'r StrStack = buffer;
+        let mut reversed: Vec<&str> = reversed.iter().collect();
+        reversed.sort_unstable();
+        merge::frames(reversed, false)?
+    } else if opt.flame_chart {
+        // In flame chart mode, just reverse the data so time moves from left to right.
+        let mut lines: Vec<&str> = lines.into_iter().collect();
+        lines.reverse();
+        merge::frames(lines, true)?
+    } else if opt.no_sort {
+        // Lines don't need sorting.
+        merge::frames(lines, false)?
+    } else {
+        // Sort lines by default.
+        let mut lines: Vec<&str> = lines.into_iter().collect();
+        lines.sort_unstable();
+        merge::frames(lines, false)?
+    };
+
+    if ignored != 0 {
+        warn!("Ignored {} lines with invalid format", ignored);
+    }
+    let delta_max = set_differentials(opt, &mut frames, delta_max);
+    Ok((frames, time, delta_max, focus_depth))
+}
+
+/// Sets the delta and p-value of the frames of a differential flame graph to match
+/// [`Options::differential_colors`], and returns the largest delta.
+fn set_differentials(
+    opt: &Options<'_>,
+    frames: &mut [merge::TimedFrame<'_>],
+    delta_max: usize,
+) -> usize {
+    let (total_before, total_after) = frames
+        .iter()
+        .filter_map(|frame| frame.counts)
+        .fold((0, 0), |(b, a), (before, after)| (b + before, a + after));
+    let mut delta_max = match opt.differential_colors {
+        DifferentialColors::SelfDelta => delta_max,
+        DifferentialColors::Absolute | DifferentialColors::Ratio => 1,
+    };
+    for frame in frames {
+        let (before, after) = match (opt.differential_colors, frame.counts) {
+            (_, None) => continue,
+            (DifferentialColors::SelfDelta, Some(counts)) => counts,
+            (_, Some(_)) => (frame.original_samples, frame.end_time - frame.start_time),
+        };
+        if opt.differential_colors != DifferentialColors::SelfDelta {
+            let delta = after as isize - before as isize;
+            delta_max = delta_max.max(delta.unsigned_abs());
+            frame.delta = Some(delta);
+        }
+        frame.p_value = Some(merge::p_value(before, after, total_before, total_after));
+    }
+    delta_max
+}
+
+/// The ratio of the samples of a differential frame in the second profile to those in the first,
+/// or the other way around if [`Options::negate_differentials`] is set. Returns `None` if the
+/// frame has no samples in the profile being compared against.
+fn sample_ratio(opt: &Options<'_>, frame: &merge::TimedFrame<'_>) -> Option<f64> {
+    let (mut before, mut after) = (frame.original_samples, frame.end_time - frame.start_time);
+    if opt.negate_differentials {
+        std::mem::swap(&mut before, &mut after);
+    }
+    if before == 0 {
+        None
+    } else {
+        Some(after as f64 / before as f64)
+    }
+}
+
+/// Returns the index of the first (or only) sample count of a folded stack line.
+fn samples_index(line: &str) -> usize {
+    let samples_idx = merge::rfind_samples(line)
+        .map(|(i, _)| i)
+        .unwrap_or_else(|| line.len());
+    merge::rfind_samples(&line[..samples_idx.saturating_sub(1)])
+        .map(|(i, _)| i)
+        .unwrap_or(samples_idx)
+}
+
+/// Merges the stacks through the first frame that matches `focus` into a "sandwich" of the
+/// callees and the callers of the focused frames.
+///
+/// The callees are merged as if the stacks started at the focused frame, and the callers as if
+/// the stacks were reversed and ended at it. The two are then stacked on top of each other so
+/// that their focused frames, which span the same samples, overlap. The callers end up at the
+/// lower depths, starting with the outermost callers at depth 0. Returns the frames like
+/// [`merge::frames`] does, and the depth of the focused frames.
+#[allow(clippy::type_complexity)]
+fn merge_focused<'a, 'l: 'a, 'r: 'a, I>(
+    opt: &Options<'_>,
+    focus: &str,
+    lines: I,
+    buffer: &'r mut StrStack,
+) -> quick_xml::Result<(
+    Vec<merge::TimedFrame<'a>>,
+    usize,
+    usize,
+    usize,
+    Option<usize>,
+)>
+where
+    I: IntoIterator<Item = &'l str>,
+{
+    let re = match Regex::new(focus) {
+        Ok(re) => Some(re),
+        Err(e) => {
+            warn!(
+                "Only focusing on functions named exactly {} since it isn't a valid regular \
+                 expression: {}",
+                focus, e
+            );
+            None
+        }
+    };
+    let is_focus = |func: &str| {
+        func == focus || deannotate(func) == focus || matches!(&re, Some(re) if re.is_match(func))
+    };
+
+    let lines: Vec<&str> = lines.into_iter().collect();
+    let mut stacks = Vec::new();
+    for line in &lines {
+        let samples_idx = samples_index(line);
+        let funcs: Vec<&str> = line[..samples_idx].trim().split(';').collect();
+        // Recursive calls of the focused function stay in the callees, so that their samples
+        // are only counted once.
+        if let Some(i) = funcs.iter().position(|func| is_focus(func)) {
+            stacks.push((funcs, i, &line[samples_idx..]));
+        }
+    }
+    if stacks.is_empty() {
+        warn!("No stacks have a function matching the focus {}", focus);
+    }
+
+    let mut stack = String::new();
+    for (funcs, i, samples) in &stacks {
+        stack.clear();
+        stack.push_str(&funcs[*i..].join(";"));
+        stack.push(' ');
+        stack.push_str(samples);
+        buffer.push(&stack);
+    }
+    for (funcs, i, samples) in &stacks {
+        stack.clear();
+        for (j, func) in funcs[..=*i].iter().rev().enumerate() {
+            if j != 0 {
+                stack.push(';');
+            }
+            stack.push_str(func);
+        }
+        stack.push(' ');
+        stack.push_str(samples);
+        buffer.push(&stack);
+    }
+
+    let buffer: &'r StrStack = buffer;
+    let mut callees: Vec<&str> = buffer.iter().take(stacks.len()).collect();
+    callees.sort_unstable();
+    let (mut frames, time, ignored, delta_max) = merge::frames(callees, false)?;
+    // JSON output is a tree with a single root, so there's no room for the callers.
+    if time == 0 || matches!(opt.format, OutputFormat::D3 | OutputFormat::Speedscope) {
+        return Ok((frames, time, ignored, delta_max, None));
+    }
+
+    let mut callers: Vec<&str> = buffer.iter().skip(stacks.len()).collect();
+    callers.sort_unstable();
+    let (callers, _, _, callers_delta_max) = merge::frames(callers, false)?;
+
+    // Both graphs have an unnamed root frame at depth 0 and the focused frames at depth 1.
+    let callers_depth = callers.iter().map(|f| f.location.depth).max().unwrap_or(1);
+    // The focused function is at depth 0 when it has no callers.
+    let focus_depth = callers_depth - 1;
+    frames.retain(|frame| frame.location.depth != 0);
+    for frame in &mut frames {
+        frame.location.depth = frame.location.depth - 1 + focus_depth;
+    }
+    frames.extend(
+        callers
+            .into_iter()
+            .filter(|frame| frame.location.depth > 1)
+            .map(|mut frame| {
+                frame.location.depth = callers_depth - frame.location.depth;
+                frame
+            }),
+    );
+    Ok((
+        frames,
+        time,
+        ignored,
+        delta_max.max(callers_delta_max),
+        Some(focus_depth),
+    ))
+}
+
+/// Removes the frames narrower than `min_width`, and returns the depth of the deepest remaining frame.
+fn prune(opt: &Options<'_>, frames: &mut Vec<merge::TimedFrame<'_>>, timemax: usize) -> usize {
+    let widthpertime_pct = 100.0 / timemax as f64;
+    let minwidth_time = opt.min_width / widthpertime_pct;
+
+    // prune blocks that are too narrow
+    let mut depthmax = 0;
+    frames.retain(|frame| {
+        if ((frame.end_time - frame.start_time) as f64) < minwidth_time {
+            false
+        } else {
+            depthmax = std::cmp::max(depthmax, frame.location.depth);
+            true
+        }
+    });
+    depthmax
+}
+
+/// Writes the details of a frame, as shown when it is hovered, to `buffer`.
+fn write_info(
+    opt: &Options<'_>,
+    buffer: &mut StrStack,
+    samples_txt_buffer: &mut num_format::Buffer,
+    frame: &merge::TimedFrame<'_>,
+    timemax: usize,
+) -> usize {
+    // The rounding here can differ from the Perl version when the fractional part is `0.5`.
+    // The Perl version does `my $samples = sprintf "%.0f", ($etime - $stime) * $factor;`,
+    // but this can format in strange ways as shown in these examples:
+    //     `sprintf "%.0f", 1.5` produces "2"
+    //     `sprintf "%.0f", 2.5` produces "2"
+    //     `sprintf "%.0f", 3.5` produces "4"
+    let samples = ((frame.end_time - frame.start_time) as f64 * opt.factor).round() as usize;
+
+    // add thousands separators to `samples`
+    let _ = samples_txt_buffer.write_formatted(&samples, &Locale::en);
+    let samples_txt = samples_txt_buffer.as_str();
+
+    if frame.location.function.is_empty() && frame.location.depth == 0 {
+        write!(buffer, "all ({} {}, 100%)", samples_txt, opt.count_name)
+    } else {
+        let pct = (100 * samples) as f64 / (timemax as f64 * opt.factor);
+        let function = deannotate(frame.location.function);
+        match frame.delta {
+            None => write!(
+                buffer,
+                "{} ({} {}, {:.2}%)",
+                function, samples_txt, opt.count_name, pct
+            ),
+            // Special case delta == 0 so we don't format percentage with a + sign.
+            Some(delta) if delta == 0 => write!(
+                buffer,
+                "{} ({} {}, {:.2}%; 0.00%)",
+                function, samples_txt, opt.count_name, pct,
+            ),
+            Some(mut delta) => {
+                if opt.negate_differentials {
+                    delta = -delta;
+                }
+                let delta_pct = (100 * delta) as f64 / (timemax as f64 * opt.factor);
+                let mut change = match opt.differential_colors {
+                    DifferentialColors::SelfDelta => format!("{:+.2}%", delta_pct),
+                    DifferentialColors::Absolute => format!("{:+.2}% total", delta_pct),
+                    DifferentialColors::Ratio => match sample_ratio(opt, frame) {
+                        Some(ratio) => format!("{:.2}x", ratio),
+                        None => "new".to_string(),
+                    },
+                };
+                match frame.p_value {
+                    Some(p_value) if opt.significance.is_some() && p_value < 0.001 => {
+                        change.push_str(", p < 0.001")
+                    }
+                    Some(p_value) if opt.significance.is_some() => {
+                        change.push_str(&format!(", p = {:.3}", p_value))
+                    }
+                    _ => {}
+                }
+                write!(
+                    buffer,
+                    "{} ({} {}, {:.2}%; {})",
+                    function, samples_txt, opt.count_name, pct, change
+                )
+            }
+        }
+    }
+}
+
+/// Selects the color of a frame that spans `width_pct` percent of the flame graph.
+fn frame_color(
+    opt: &mut Options<'_>,
+    frame: &merge::TimedFrame<'_>,
+    width_pct: f64,
+    delta_max: usize,
+    thread_rng: &mut impl FnMut() -> f32,
+) -> Color {
+    if frame.location.function == "--" {
+        color::VDGREY
+    } else if frame.location.function == "-" {
+        color::DGREY
+    } else if opt.color_diffusion {
+        // We want to visually highlight high priority regions for
+        // optimization: wider frames are redder. Typically when optimizing,
+        // a frame that is 50% of width is high priority, so it seems wrong
+        // to give it half the saturation of 100%. So we use sqrt to make
+        // the red dropoff less linear.
+        color::color_scale(((width_pct / 100.0).sqrt() * 2000.0) as isize, 2000)
+    } else if let Some(mut delta) = frame.delta {
+        if opt.negate_differentials {
+            delta = -delta;
+        }
+        match (opt.significance, frame.p_value) {
+            (Some(threshold), Some(p_value)) if p_value >= threshold => {
+                color::color_scale(0, delta_max)
+            }
+            _ if opt.differential_colors == DifferentialColors::Ratio && frame.counts.is_some() => {
+                // Saturate at a ratio of 4, or 1/4, on a log scale. Unlike `clamp`, `max` and
+                // `min` turn a NaN ratio into one of the bounds.
+                #[allow(clippy::manual_clamp)]
+                let ratio =
+                    sample_ratio(opt, frame).map_or(2.0, |ratio| ratio.log2().max(-2.0).min(2.0));
+                color::color_scale((ratio * 1000.0) as isize, 2000)
+            }
+            _ => color::color_scale(delta, delta_max),
+        }
+    } else if let Some(ref mut palette_map) = opt.palette_map {
+        let colors = opt.colors;
+        let hash = opt.hash;
+        let deterministic = opt.deterministic;
+        palette_map.find_color_for(frame.location.function, |name| {
+            color::color(colors, hash, deterministic, name, &mut *thread_rng)
+        })
+    } else {
+        color::color(
+            opt.colors,
+            opt.hash,
+            opt.deterministic,
+            frame.location.function,
+            thread_rng,
+        )
+    }
+}
+
 #[cfg(feature = "nameattr")]
 fn write_container_start<'a, W: Write>(
     opt: &'a Options<'a>,
@@ -773,7 +1341,8 @@ fn write_container_attributes(event: &mut Event<'_>, frame_attributes: &FrameAtt
 ///
 /// See [`from_lines`] for the expected format of each line.
 ///
-/// The resulting flame graph will be written out to `writer` in SVG format.
+/// The resulting flame graph will be written out to `writer` in the format selected by
+/// [`Options::format`], which is SVG by default.
 pub fn from_reader<R, W>(opt: &mut Options<'_>, reader: R, writer: W) -> quick_xml::Result<()>
 where
     R: Read,
@@ -786,7 +1355,8 @@ where
 ///
 /// See [`from_lines`] for the expected format of each line.
 ///
-/// The resulting flame graph will be written out to `writer` in SVG format.
+/// The resulting flame graph will be written out to `writer` in the format selected by
+/// [`Options::format`], which is SVG by default.
 pub fn from_readers<R, W>(opt: &mut Options<'_>, readers: R, writer: W) -> quick_xml::Result<()>
 where
     R: IntoIterator,
@@ -805,7 +1375,8 @@ where
 /// Produce a flame graph from files that contain folded stack lines
 /// and write the result to provided `writer`.
 ///
-/// If files is empty, STDIN will be used as input.
+/// If files is empty, STDIN will be used as input. Input compressed with gzip, zstd or xz is
+/// decompressed on the fly.
 pub fn from_files<W: Write>(
     opt: &mut Options<'_>,
     files: &[PathBuf],
@@ -814,10 +1385,11 @@ pub fn from_files<W: Write>(
     if files.is_empty() || files.len() == 1 && files[0].to_str() == Some("-") {
         let stdin = io::stdin();
         let r = BufReader::with_capacity(128 * 1024, stdin.lock());
-        from_reader(opt, r, writer)
+        from_reader(opt, decompress(r)?, writer)
     } else if files.len() == 1 {
         let r = File::open(&files[0]).map_err(quick_xml::Error::Io)?;
-        from_reader(opt, r, writer)
+        let r = BufReader::with_capacity(128 * 1024, r);
+        from_reader(opt, decompress(r)?, writer)
     } else {
         let stdin = io::stdin();
         let mut stdin_added = false;
@@ -826,12 +1398,13 @@ pub fn from_files<W: Write>(
             if infile.to_str() == Some("-") {
                 if !stdin_added {
                     let r = BufReader::with_capacity(128 * 1024, stdin.lock());
-                    readers.push(Box::new(r));
+                    readers.push(Box::new(decompress(r)?));
                     stdin_added = true;
                 }
             } else {
                 let r = File::open(infile).map_err(quick_xml::Error::Io)?;
-                readers.push(Box::new(r));
+                let r = BufReader::with_capacity(128 * 1024, r);
+                readers.push(Box::new(decompress(r)?));
             }
         }
 
@@ -839,6 +1412,19 @@ pub fn from_files<W: Write>(
     }
 }
 
+fn decompress<'a, R: BufRead + 'a>(reader: R) -> quick_xml::Result<Box<dyn BufRead + 'a>> {
+    compression::decompress(reader).map_err(quick_xml::Error::Io)
+}
+
+/// The name frames are labeled with.
+fn frame_name<'a>(frame: &merge::TimedFrame<'a>) -> &'a str {
+    if frame.location.function.is_empty() && frame.location.depth == 0 {
+        "all"
+    } else {
+        deannotate(frame.location.function)
+    }
+}
+
 fn deannotate(f: &str) -> &str {
     if f.ends_with(']') {
         if let Some(ai) = f.rfind("_[") {
@@ -294,7 +294,7 @@ where
 
 // Imported from the `enquote` crate @ 1.0.3.
 // It's "unlicense" licensed, so that's fine.
-fn enquote(quote: char, s: &str) -> String {
+pub(super) fn enquote(quote: char, s: &str) -> String {
     // escapes any `quote` in `s`
     let escaped = s
         .chars()
@@ -0,0 +1,168 @@
+use std::io;
+use std::io::prelude::*;
+
+use super::merge::TimedFrame;
+use super::{Direction, Options, TextTruncateDirection};
+
+/// The width of text flame graphs, in columns, if none is given.
+const DEFAULT_WIDTH: usize = 100;
+
+/// A frame laid out on a row of the terminal.
+struct Cell {
+    start: usize,
+    /// The number of columns the frame is drawn in.
+    room: usize,
+    label: String,
+    color: super::Color,
+}
+
+/// Writes the frames as rows of text, one per stack depth, colored with ANSI escape codes unless
+/// [`Options::no_text_colors`] is set.
+///
+/// Each frame spans a number of columns proportional to its width and is labeled with its name
+/// and the percentage of samples it covers. Frames narrower than a column are left out.
+pub(super) fn write_flamegraph<W: Write>(
+    mut writer: W,
+    opt: &mut Options<'_>,
+    frames: Vec<TimedFrame<'_>>,
+    timemax: usize,
+    depthmax: usize,
+    delta_max: usize,
+) -> quick_xml::Result<()> {
+    let width = opt.image_width.unwrap_or(DEFAULT_WIDTH);
+    let mut thread_rng = super::rand::thread_rng();
+
+    let mut rows: Vec<Vec<Cell>> = (0..=depthmax).map(|_| Vec::new()).collect();
+    for frame in &frames {
+        let start = frame.start_time * width / timemax;
+        let end = frame.end_time * width / timemax;
+        if start == end {
+            continue;
+        }
+
+        let width_pct = (frame.end_time - frame.start_time) as f64 * 100.0 / timemax as f64;
+        let color = super::frame_color(opt, frame, width_pct, delta_max, &mut thread_rng);
+        let name = super::frame_name(frame);
+        // Leave the last column of wider frames blank to separate them from their neighbours.
+        let room = if end - start > 1 { end - start - 1 } else { 1 };
+        let label = label(name, width_pct, room, opt.text_truncate_direction);
+        rows[frame.location.depth].push(Cell {
+            start,
+            room,
+            label,
+            color,
+        });
+    }
+
+    writeln!(writer, "{}", opt.title)?;
+    if let Some(ref subtitle) = opt.subtitle {
+        writeln!(writer, "{}", subtitle)?;
+    }
+    match opt.direction {
+        Direction::Straight => {
+            for row in rows.iter_mut().rev() {
+                write_row(&mut writer, row, !opt.no_text_colors)?;
+            }
+        }
+        Direction::Inverted => {
+            for row in rows.iter_mut() {
+                write_row(&mut writer, row, !opt.no_text_colors)?;
+            }
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn write_row<W: Write>(writer: &mut W, row: &mut [Cell], colors: bool) -> io::Result<()> {
+    row.sort_unstable_by_key(|cell| cell.start);
+    let mut column = 0;
+    for (i, cell) in row.iter().enumerate() {
+        let gap = cell.start - column;
+        if colors {
+            let color = cell.color;
+            write!(
+                writer,
+                "{:gap$}\x1b[30;48;2;{};{};{}m{:room$}\x1b[0m",
+                "",
+                color.r,
+                color.g,
+                color.b,
+                cell.label,
+                gap = gap,
+                room = cell.room,
+            )?;
+        } else {
+            write!(writer, "{:gap$}", "", gap = gap)?;
+            if i + 1 == row.len() {
+                // Don't pad the last frame of a row when there's no background to fill in.
+                write!(writer, "{}", cell.label)?;
+            } else {
+                write!(writer, "{:room$}", cell.label, room = cell.room)?;
+            }
+        }
+        column = cell.start + cell.room;
+    }
+    writeln!(writer)
+}
+
+/// Labels a frame with its name and percentage, truncating the name to fit in `room` columns.
+fn label(name: &str, pct: f64, room: usize, truncate: TextTruncateDirection) -> String {
+    let pct = format!(" {:.2}%", pct);
+    let name_len = name.chars().count();
+    if name_len + pct.len() <= room {
+        return format!("{}{}", name, pct);
+    }
+
+    // Keep the percentage if at least one character of the name fits next to it.
+    let (room, pct) = if room >= pct.len() + 3 {
+        (room - pct.len(), pct)
+    } else {
+        (room, String::new())
+    };
+    let name = if name_len <= room {
+        name.to_string()
+    } else if room >= 3 {
+        // room for one char plus two dots
+        // TODO: use Unicode grapheme clusters instead
+        match truncate {
+            TextTruncateDirection::Left => {
+                let tail: String = name.chars().skip(name_len - (room - 2)).collect();
+                format!("..{}", tail)
+            }
+            TextTruncateDirection::Right => {
+                let head: String = name.chars().take(room - 2).collect();
+                format!("{}..", head)
+            }
+        }
+    } else {
+        // don't show the function name
+        String::new()
+    };
+    name + &pct
+}
+
+#[cfg(test)]
+mod tests {
+    use super::label;
+    use crate::flamegraph::TextTruncateDirection;
+
+    #[test]
+    fn labels_fit_their_frames() {
+        let left = TextTruncateDirection::Left;
+        let right = TextTruncateDirection::Right;
+        assert_eq!(label("main", 50.0, 20, left), "main 50.00%");
+        assert_eq!(
+            label("std::rt::lang_start", 50.0, 14, left),
+            "..start 50.00%"
+        );
+        assert_eq!(
+            label("std::rt::lang_start", 50.0, 14, right),
+            "std::.. 50.00%"
+        );
+        assert_eq!(label("std::rt::lang_start", 50.0, 9, right), "std::rt..");
+        assert_eq!(label("main", 50.0, 4, right), "main");
+        assert_eq!(label("main", 50.0, 2, right), "");
+    }
+}
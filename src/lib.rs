@@ -29,9 +29,16 @@
 //! Since profiling tools produce stack traces in a myriad of different formats, and the flame
 //! graph plotter expects input in a particular folded stack trace format, each profiler needs a
 //! separate collapse implementation. While the original Perl implementation supports _lots_ of
-//! profilers, Inferno currently only supports four: the widely used [`perf`] tool (specifically
-//! the output from `perf script`), [DTrace], [sample], and [VTune]. Support for xdebug is
-//! [hopefully coming soon], and [`bpftrace`] should get [native support] before too long.
+//! profilers, Inferno currently only supports nine: the widely used [`perf`] tool (specifically
+//! the output from `perf script`, or `perf.data` itself), [DTrace], [sample], [VTune], the `.cpuprofile` files
+//! written by [Chrome DevTools] and Node.js, [pprof] profiles, profiles in the formats of the
+//! [Firefox Profiler] and [speedscope], and [callgrind] output. It can also count the stacks in
+//! repeated thread dumps from gdb, `eu-stack`, `jstack` and Go programs, and read the stacks
+//! printed by [`bpftrace`] and [BCC]. Support for xdebug is [hopefully coming soon].
+//!
+//! All of the tools, including `inferno-flamegraph` and `inferno-diff-folded`, also accept input
+//! compressed with gzip, zstd or xz, so archived profiles don't need to be decompressed first.
+//! This is done by the default `compression` feature.
 //!
 //! Inferno supports profiles from applications written in any language, but we'll walk through an
 //! example with a Rust program. To profile a Rust application, you would first set
@@ -53,6 +60,31 @@
 //!
 //! For more advanced uses, see Brendan Gregg's excellent [perf examples] page.
 //!
+//! Profiles of stripped binaries are full of `[unknown]` frames. If you still have the
+//! unstripped binaries (or their separate debug info), pass them, or a directory holding them,
+//! with `--symbol-path` to name those frames after the fact, and add `--inline` to also show
+//! inlined functions. This works for `inferno-collapse-dtrace` as well. For shared libraries and
+//! position-independent executables, have `perf script` print offsets into each binary with
+//! `-F +dsoff`:
+//!
+//! ```console
+//! $ perf script -F +dsoff | inferno-collapse-perf --symbol-path ./debug-binaries > stacks.folded
+//! ```
+//!
+//! Similarly, frames in JIT-compiled code stay unnamed when the `/tmp/perf-<pid>.map` files
+//! written by the runtime weren't available to `perf script`. Copy them along with the profile
+//! and point `--perf-map-dir` at the directory holding them. The `jitted-<pid>-<n>.so` files that
+//! `perf inject --jit` writes are not map files; pass their directory to `--symbol-path` instead.
+//!
+//! If `perf script` is unavailable or slow, `perf.data` can also be read directly. This needs
+//! call chains recorded by the kernel (`-g` or `--call-graph lbr`, not `dwarf`), and binaries that
+//! still have their symbol tables:
+//!
+//! ```console
+//! # perf record -g target/release/mybin
+//! $ inferno-collapse-perf-data --kallsyms /proc/kallsyms perf.data > stacks.folded
+//! ```
+//!
 //! ### DTrace (macOS)
 //!
 //! ```console
@@ -82,6 +114,86 @@
 //! $ inferno-collapse-vtune result.csv > stacks.folded
 //! ```
 //!
+//! ### Chrome DevTools and Node.js
+//!
+//! ```console
+//! $ node --cpu-prof --cpu-prof-name=profile.cpuprofile target/app.js
+//! $ inferno-collapse-chrome profile.cpuprofile > stacks.folded
+//! ```
+//!
+//! Pass `--time` to weight each sample by how long it lasted rather than counting it once.
+//!
+//! ### pprof (Go)
+//!
+//! ```console
+//! $ curl -o profile.pb.gz http://localhost:6060/debug/pprof/profile?seconds=30
+//! $ inferno-collapse-pprof profile.pb.gz > stacks.folded
+//! ```
+//!
+//! Use `--sample-index` to pick which sample value to count, like `go tool pprof -sample_index`.
+//!
+//! ### Firefox Profiler and samply
+//!
+//! ```console
+//! $ samply record --save-only -o profile.json target/release/mybin
+//! $ inferno-collapse-gecko profile.json > stacks.folded
+//! ```
+//!
+//! Pass `--thread` or `--process` to start each stack with the name of its thread or process.
+//!
+//! ### speedscope (py-spy, rbspy, dotnet-trace)
+//!
+//! ```console
+//! $ py-spy record --format speedscope -o profile.speedscope.json -- python app.py
+//! $ inferno-collapse-speedscope profile.speedscope.json > stacks.folded
+//! ```
+//!
+//! Each stack starts with the name of the profile it came from, which is usually a thread. Pass
+//! `--no-profile-names` to merge the profiles instead.
+//!
+//! ### callgrind (Valgrind)
+//!
+//! ```console
+//! $ valgrind --tool=callgrind target/release/mybin
+//! $ inferno-collapse-callgrind --event Ir callgrind.out.<pid> > stacks.folded
+//! ```
+//!
+//! Callgrind only records how much each function calls each other function, not whole stacks, so
+//! the stacks are rebuilt from that call graph. This is an approximation: a function that is
+//! called from several places is assumed to behave the same in all of them.
+//!
+//! ### Thread dumps (gdb, eu-stack, jstack, Go)
+//!
+//! Without a profiler at hand, taking a few dozen thread dumps of a running program and counting
+//! identical stacks works surprisingly well ("poor man's profiling"). Inferno can collapse dumps
+//! from `gdb`, `eu-stack`, `jstack` and the goroutine dumps Go programs print on `SIGQUIT`:
+//!
+//! ```console
+//! $ for i in $(seq 50); do gdb -p $pid -batch -ex "thread apply all bt"; sleep 0.1; done > dumps.txt
+//! $ inferno-collapse-gdb --thread dumps.txt > stacks.folded
+//! ```
+//!
+//! `inferno-collapse-eu-stack` and `inferno-collapse-jstack` take `--thread` as well, and
+//! `inferno-collapse-goroutine` takes `--state` to start each stack with the goroutine's state.
+//!
+//! ### bpftrace and BCC (Linux)
+//!
+//! ```console
+//! # bpftrace -e 'profile:hz:99 { @[kstack, ustack, comm] = count(); }' > out.bpftrace
+//! $ inferno-collapse-bpftrace out.bpftrace > stacks.folded
+//! ```
+//!
+//! Keys that aren't stacks, like `comm`, become the first frames of each stack. When a key holds
+//! both a kernel and a user stack, they are joined by a `-` frame, which is drawn in grey. Pass
+//! `--user-stack-first` for keys like `@[ustack, kstack]`.
+//!
+//! The stacks printed by BCC's `profile` and `offcputime` tools without `-f` work the same way:
+//!
+//! ```console
+//! # /usr/share/bcc/tools/profile -d -F 99 30 > out.bcc
+//! $ inferno-collapse-bcc out.bcc > stacks.folded
+//! ```
+//!
 //! ## Producing a flame graph
 //!
 //! Once you have a folded stack file, you're ready to produce the flame graph SVG image. To do so,
@@ -94,6 +206,43 @@
 //!
 //! And then open `profile.svg` in your viewer of choice.
 //!
+//! Some viewers strip or sandbox the scripts embedded in SVG images, which breaks searching and
+//! zooming. Pass `--format html` to get a self-contained HTML page with the same frames instead:
+//!
+//! ```console
+//! $ cat stacks.folded | inferno-flamegraph --format html > profile.html
+//! ```
+//!
+//! To explore the profile in other tools, `--format d3` writes the nested JSON read by
+//! [d3-flame-graph], and `--format speedscope` writes a profile that can be opened in
+//! [speedscope].
+//!
+//! Over SSH, or anywhere else without a browser, `--format text` prints the flame graph to the
+//! terminal, using as many columns as the terminal is wide. Frames are only colored when printing
+//! to a terminal and `NO_COLOR` isn't set, so ask for colors when paging the output:
+//!
+//! ```console
+//! $ cat stacks.folded | inferno-flamegraph --format text --color always | less -RS
+//! ```
+//!
+//! To explore a profile interactively instead, open it with `inferno-tui`. It takes folded stacks
+//! or anything `inferno-collapse-guess` understands, and lets you move between frames with the
+//! arrow keys, zoom with Enter, search with `/`, and flip the graph with `i` (inverted) and `r`
+//! (reversed stacks):
+//!
+//! ```console
+//! $ inferno-tui stacks.folded
+//! ```
+//!
+//! A function that is called from many places is spread thinly across a flame graph. With
+//! `--focus`, only the stacks containing a function (given by name or as a regular expression)
+//! are drawn: everything it called is merged above it as usual, and everything it was called from
+//! is merged below it, towards the function:
+//!
+//! ```console
+//! $ cat stacks.folded | inferno-flamegraph --focus 'parse_.*' > parse.svg
+//! ```
+//!
 //! ## Differential flame graphs
 //!
 //! You can debug CPU performance regressions with the help of differential flame graphs.
@@ -116,6 +265,67 @@
 //! $ inferno-diff-folded folded2 folded1 | inferno-flamegraph --negate > diff1.svg
 //! ```
 //!
+//! Small changes in rarely sampled stacks are often just noise, but they're colored as brightly
+//! as real regressions. Pass `--significance 0.05` to `inferno-flamegraph` to only color the
+//! frames whose change is statistically significant at that level; the p-value of each changed
+//! frame is then shown in its details.
+//!
+//! To compare more than two profiles, such as the builds in an A/B/C experiment, give
+//! `inferno-diff-multi` a baseline and any number of variants. It writes one differential flame
+//! graph per variant, and prints the stacks with the largest absolute and relative changes in
+//! each:
+//!
+//! ```console
+//! $ inferno-diff-multi -o diffs base.folded build-b.folded build-c.folded
+//! ```
+//!
+//! The same comparisons are available in-process through [`differential::compare_files`].
+//!
+//! ## Merging profiles
+//!
+//! Profiles taken on several hosts, or in several runs, can be combined into one with
+//! `inferno-merge`, which sums the sample counts of identical stacks. Pass `--label` to keep the
+//! inputs apart by adding each file name as the bottom frame of its stacks, `--normalize` to give
+//! every input the same total weight, or one `--weight` per input to scale them yourself:
+//!
+//! ```console
+//! $ inferno-merge --label --normalize host1.folded host2.folded | inferno-flamegraph > all.svg
+//! ```
+//!
+//! ## Reports
+//!
+//! When all you want to know is which functions are the most expensive, `inferno-report` ranks
+//! functions by the samples spent in the function itself (self) or anywhere below it (total).
+//! Samples where a function is on the stack more than once, like in recursion, only count once
+//! towards its total. Pass `--callers` and `--callees` to also see where each function was called
+//! from and what it called:
+//!
+//! ```console
+//! $ inferno-report --top 20 --sort total --callers stacks.folded
+//! ```
+//!
+//! Use `--format csv` or `--format json` to process the report further.
+//!
+//! ## Filtering stacks
+//!
+//! `inferno-filter` rewrites folded stacks with an ordered list of rules, instead of a pile of
+//! `sed` and `awk` scripts. Rules can drop or keep whole stacks, remove or rename frames, cut off
+//! everything above or below a frame, and cap the stack depth:
+//!
+//! ```console
+//! $ inferno-filter -r 'drop ^swapper;' -r 'cut-below ^main$' stacks.folded > main.folded
+//! ```
+//!
+//! Longer lists of rules can be kept in a file and passed with `--rule-file`. See
+//! [`filter::Rule`] for all the rules. The same rules can be applied in-process through
+//! [`flamegraph::Options::filter`], or to the output of a collapser with
+//! [`filter::Filter::writer`].
+//!
+//! Deeply recursive code makes for tall towers of the same frames. The collapsers and
+//! `inferno-flamegraph` can merge them with `--fold-recursion direct`, which turns `a;a;a;b` into
+//! `a;b`, or `--fold-recursion cycles`, which also merges recursion through other functions (so
+//! `a;b;c;b;d` becomes `a;b;d`). No samples are lost; they're just attributed to shorter stacks.
+//!
 //! # Development
 //!
 //! This crate was initially developed through [a series of live coding sessions]. If you want to
@@ -131,8 +341,8 @@
 //!   [`perf`]: https://perf.wiki.kernel.org/index.php/Main_Page
 //!   [DTrace]: https://www.joyent.com/dtrace
 //!   [hopefully coming soon]: https://twitter.com/DanielLockyer/status/1094605231155900416
-//!   [native support]: https://github.com/jonhoo/inferno/issues/51#issuecomment-466732304
 //!   [`bpftrace`]: https://github.com/iovisor/bpftrace
+//!   [BCC]: https://github.com/iovisor/bcc
 //!   [perf examples]: http://www.brendangregg.com/perf.html
 //!   [DTrace examples]: http://www.brendangregg.com/FlameGraphs/cpuflamegraphs.html#DTrace
 //!   [NodeJS's ustack helper]: http://dtrace.org/blogs/dap/2012/01/05/where-does-your-node-program-spend-its-time/
@@ -140,6 +350,13 @@
 //!   [differential flame graphs]: http://www.brendangregg.com/blog/2014-11-09/differential-flame-graphs.html
 //!   [sample]: https://gist.github.com/loderunner/36724cc9ee8db66db305#profiling-with-sample
 //!   [VTune]: https://software.intel.com/en-us/vtune-amplifier-help-command-line-interface
+//!   [Chrome DevTools]: https://developer.chrome.com/docs/devtools/
+//!   [pprof]: https://github.com/google/pprof
+//!   [Firefox Profiler]: https://profiler.firefox.com/
+//!   [speedscope]: https://www.speedscope.app/
+//!   [callgrind]: https://valgrind.org/docs/manual/cl-manual.html
+//!   [d3-flame-graph]: https://github.com/spiermar/d3-flame-graph
+//!   [speedscope]: https://www.speedscope.app
 
 #![cfg_attr(doc, warn(rustdoc::all))]
 #![cfg_attr(doc, allow(rustdoc::missing_doc_code_examples))]
@@ -163,9 +380,32 @@ pub mod collapse;
 ///   [crate-level documentation]: ../index.html
 pub mod differential;
 
+/// Rules for rewriting and removing folded stacks.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../index.html
+pub mod filter;
+
 /// Tools for producing flame graphs from folded stack traces.
 ///
 /// See the [crate-level documentation] for details.
 ///
 ///   [crate-level documentation]: ../index.html
 pub mod flamegraph;
+
+/// Tools for combining several folded stack profiles into one.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../index.html
+pub mod merge;
+
+/// Tools for summarizing folded stack traces as per-function sample counts.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../index.html
+pub mod report;
+
+mod compression;
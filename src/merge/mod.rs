@@ -0,0 +1,247 @@
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::path::Path;
+
+use ahash::AHashMap;
+use log::{info, warn};
+
+use crate::compression;
+use crate::differential::parse_line;
+
+const READER_CAPACITY: usize = 128 * 1024;
+
+/// Configure how profiles are merged.
+///
+/// All options default to off.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    /// Scale each input so that its total sample count matches that of the largest input, before
+    /// applying its [`Input::weight`].
+    ///
+    /// This keeps a profile that happened to run for longer, or on a busier host, from drowning
+    /// out the others.
+    pub normalize: bool,
+
+    /// Strip hex numbers (addresses) of the form "0x45ef2173" and replace with "0x...".
+    ///
+    /// Addresses rarely match across hosts or runs, so this lets otherwise identical stacks
+    /// merge.
+    pub strip_hex: bool,
+}
+
+/// A folded stack profile to merge, along with how to weigh and label it.
+#[derive(Debug, Clone)]
+pub struct Input<S> {
+    /// Where to read the folded stack lines from.
+    ///
+    /// This is a reader for [`from_readers`], and a path for [`from_files`].
+    pub source: S,
+
+    /// Factor that the sample counts of this input are multiplied by.
+    ///
+    /// Default is `1.0`.
+    pub weight: f64,
+
+    /// Frame to add to the bottom of every stack from this input, such as a host name.
+    ///
+    /// Default is `None`.
+    pub label: Option<String>,
+}
+
+impl<S> Input<S> {
+    /// An input with a weight of 1 and no label.
+    pub fn new(source: S) -> Self {
+        Input {
+            source,
+            weight: 1.0,
+            label: None,
+        }
+    }
+}
+
+/// Merge folded stack profiles into one, summing the sample counts of identical stacks.
+///
+/// The readers are expected to contain folded stack lines with the following whitespace-separated
+/// fields:
+///
+///  - A semicolon-separated list of frame names (e.g., `main;foo;bar;baz`).
+///  - A sample count for the given stack.
+///
+/// The output written to the `writer` has the same format, sorted by stack. When inputs are
+/// weighted or normalized, the merged counts are rounded to the nearest integer, and stacks that
+/// round to zero are left out.
+pub fn from_readers<R, W>(opt: Options, inputs: Vec<Input<R>>, mut writer: W) -> io::Result<()>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut profiles = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let (stacks, total) = parse_stack_counts(opt, input.source, input.label.as_deref())?;
+        profiles.push((stacks, total, input.weight));
+    }
+
+    let max_total = profiles
+        .iter()
+        .map(|&(_, total, _)| total)
+        .max()
+        .unwrap_or(0);
+    let mut merged: AHashMap<String, f64> = AHashMap::default();
+    for (i, (stacks, total, weight)) in profiles.into_iter().enumerate() {
+        let factor = if opt.normalize && total != 0 {
+            weight * max_total as f64 / total as f64
+        } else {
+            weight
+        };
+        info!(
+            "Input {} has {} samples, scaling by {}",
+            i + 1,
+            total,
+            factor
+        );
+        for (stack, count) in stacks {
+            *merged.entry(stack).or_default() += count as f64 * factor;
+        }
+    }
+
+    let mut merged: Vec<_> = merged
+        .into_iter()
+        .map(|(stack, count)| (stack, count.round() as usize))
+        .filter(|&(_, count)| count != 0)
+        .collect();
+    merged.sort_unstable();
+    for (stack, count) in merged {
+        writeln!(writer, "{} {}", stack, count)?;
+    }
+    Ok(())
+}
+
+/// Merge the folded stack profiles at the given paths into one.
+///
+/// See [`from_readers`] for the input and output formats. A path of `-` reads from STDIN, which
+/// can only be given once. Input compressed with gzip, zstd or xz is decompressed on the fly.
+pub fn from_files<P, W>(opt: Options, inputs: &[Input<P>], writer: W) -> io::Result<()>
+where
+    P: AsRef<Path>,
+    W: Write,
+{
+    let stdin = io::stdin();
+    let mut stdin_added = false;
+    let mut readers: Vec<Input<Box<dyn BufRead + '_>>> = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        let path = input.source.as_ref();
+        let reader: Box<dyn BufRead> = if path == Path::new("-") {
+            if stdin_added {
+                // Its weight and label would be lost otherwise.
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "STDIN (-) can only be merged once",
+                ));
+            }
+            stdin_added = true;
+            compression::decompress(stdin.lock())?
+        } else {
+            let file = File::open(path)?;
+            compression::decompress(io::BufReader::with_capacity(READER_CAPACITY, file))?
+        };
+        readers.push(Input {
+            source: reader,
+            weight: input.weight,
+            label: input.label.clone(),
+        });
+    }
+    from_readers(opt, readers, writer)
+}
+
+// Sum the sample counts per stack of one input, prefixed with its label, if any. Also returns the
+// total sample count of the input.
+fn parse_stack_counts<R>(
+    opt: Options,
+    mut reader: R,
+    label: Option<&str>,
+) -> io::Result<(AHashMap<String, usize>, usize)>
+where
+    R: BufRead,
+{
+    let mut stacks: AHashMap<String, usize> = AHashMap::default();
+    let mut total = 0;
+    let mut line = Vec::new();
+    let mut stripped_fractional_samples = false;
+    loop {
+        line.clear();
+
+        if reader.read_until(0x0A, &mut line)? == 0 {
+            break;
+        }
+
+        let l = String::from_utf8_lossy(&line);
+        if l.trim().is_empty() {
+            continue;
+        }
+        if let Some((stack, count)) =
+            parse_line(&l, opt.strip_hex, &mut stripped_fractional_samples)
+        {
+            let stack = match label {
+                Some(label) => format!("{};{}", label, stack),
+                None => stack,
+            };
+            *stacks.entry(stack).or_default() += count;
+            total += count;
+        } else {
+            warn!("Unable to parse line: {}", l);
+        }
+    }
+
+    Ok((stacks, total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge(opt: Options, inputs: Vec<Input<&[u8]>>) -> String {
+        let mut out = Vec::new();
+        from_readers(opt, inputs, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sums_and_sorts() {
+        let inputs = vec![
+            Input::new(&b"main;b 2\nmain;a 1\n"[..]),
+            Input::new(&b"main;a 3\nmain;c 1\n"[..]),
+        ];
+        assert_eq!(
+            merge(Options::default(), inputs),
+            "main;a 4\nmain;b 2\nmain;c 1\n"
+        );
+    }
+
+    #[test]
+    fn weights_and_labels() {
+        let mut first = Input::new(&b"main;a 3\n"[..]);
+        first.weight = 0.5;
+        first.label = Some("host1".to_string());
+        let mut second = Input::new(&b"main;a 3\n"[..]);
+        second.label = Some("host2".to_string());
+        let mut third = Input::new(&b"main;a 3\n"[..]);
+        third.weight = 0.0;
+        assert_eq!(
+            merge(Options::default(), vec![first, second, third]),
+            "host1;main;a 2\nhost2;main;a 3\n"
+        );
+    }
+
+    #[test]
+    fn normalize() {
+        let inputs = vec![
+            Input::new(&b"main;a 10\nmain;b 30\n"[..]),
+            Input::new(&b"main;a 5\nmain;c 5\n"[..]),
+        ];
+        let opt = Options {
+            normalize: true,
+            ..Default::default()
+        };
+        assert_eq!(merge(opt, inputs), "main;a 30\nmain;b 30\nmain;c 20\n");
+    }
+}
@@ -0,0 +1,545 @@
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use ahash::AHashMap;
+use log::warn;
+use serde::Serialize;
+
+use crate::compression;
+
+const READER_CAPACITY: usize = 128 * 1024;
+
+/// The format of the written report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// A table aligned for reading in a terminal.
+    #[default]
+    Text,
+
+    /// Comma-separated values with a header row.
+    Csv,
+
+    /// A JSON object with the total number of samples and an array of functions.
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown report format: {}", s)),
+        }
+    }
+}
+
+/// The sample count functions are ranked by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortBy {
+    /// Samples in which the function itself was running.
+    #[default]
+    SelfSamples,
+
+    /// Samples in which the function was on the stack.
+    TotalSamples,
+}
+
+impl FromStr for SortBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "self" => Ok(SortBy::SelfSamples),
+            "total" => Ok(SortBy::TotalSamples),
+            _ => Err(format!("unknown sort order: {}", s)),
+        }
+    }
+}
+
+/// Configure the generated report.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[non_exhaustive]
+pub struct Options {
+    /// The format to write the report in.
+    ///
+    /// [`Format::Text`] by default.
+    pub format: Format,
+
+    /// Which count to rank functions by, highest first.
+    ///
+    /// [`SortBy::SelfSamples`] by default.
+    pub sort_by: SortBy,
+
+    /// Only report this many functions.
+    ///
+    /// All functions are reported by default.
+    pub top: Option<usize>,
+
+    /// List the functions each function was called from in text and CSV reports.
+    ///
+    /// JSON reports always include them. Default is `false`.
+    pub callers: bool,
+
+    /// List the functions each function called in text and CSV reports.
+    ///
+    /// JSON reports always include them. Default is `false`.
+    pub callees: bool,
+}
+
+/// Sample counts per function, as computed by [`report`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[non_exhaustive]
+pub struct Report {
+    /// The number of samples in the profile.
+    pub total_samples: usize,
+
+    /// The reported functions, ranked by [`Options::sort_by`].
+    pub functions: Vec<Function>,
+}
+
+/// The sample counts of a single function.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[non_exhaustive]
+pub struct Function {
+    /// The name of the function, as it appears in the folded stacks.
+    pub name: String,
+
+    /// The number of samples in which the function was at the top of the stack.
+    pub self_samples: usize,
+
+    /// The number of samples in which the function was anywhere on the stack.
+    ///
+    /// Samples in which the function is on the stack more than once, like when it's recursive,
+    /// are only counted once.
+    pub total_samples: usize,
+
+    /// The functions this function was called from, with the number of samples of each call.
+    pub callers: Vec<Call>,
+
+    /// The functions this function called, with the number of samples of each call.
+    pub callees: Vec<Call>,
+}
+
+/// The samples in which one function called another.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[non_exhaustive]
+pub struct Call {
+    /// The name of the calling or called function.
+    pub name: String,
+
+    /// The number of samples in which the call was on the stack, counting each sample once.
+    pub samples: usize,
+}
+
+#[derive(Default)]
+struct Counts {
+    self_samples: usize,
+    total_samples: usize,
+    /// The last line the function was counted for, to not count recursive calls twice.
+    last_line: usize,
+}
+
+/// Compute per-function sample counts from an iterator over folded stack lines.
+///
+/// Each line should have a semicolon-separated list of frame names followed by a sample count.
+/// For differential input, which has two sample counts, the second one is used. Functions are
+/// ranked and cut off as configured in `opt`.
+pub fn report<'a, I>(opt: &Options, lines: I) -> Report
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut names: Vec<&'a str> = Vec::new();
+    let mut ids: AHashMap<&'a str, usize> = AHashMap::default();
+    let mut counts: Vec<Counts> = Vec::new();
+    // (caller, callee) -> (samples, last line)
+    let mut calls: AHashMap<(usize, usize), (usize, usize)> = AHashMap::default();
+    let mut total_samples = 0;
+    let mut stripped_fractional_samples = false;
+    let mut ignored = 0;
+
+    let mut stack = Vec::new();
+    // Line numbers start at 1 so that no function counts as seen on the first line.
+    for (line_number, line) in (1..).zip(lines) {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (frames, samples) = match parse_line(line, &mut stripped_fractional_samples) {
+            Some(parsed) => parsed,
+            None => {
+                ignored += 1;
+                continue;
+            }
+        };
+        total_samples += samples;
+
+        stack.clear();
+        stack.extend(frames.split(';').map(|name| {
+            *ids.entry(name).or_insert_with(|| {
+                names.push(name);
+                counts.push(Counts::default());
+                names.len() - 1
+            })
+        }));
+
+        for &id in &stack {
+            let counts = &mut counts[id];
+            if counts.last_line != line_number {
+                counts.last_line = line_number;
+                counts.total_samples += samples;
+            }
+        }
+        if let Some(&leaf) = stack.last() {
+            counts[leaf].self_samples += samples;
+        }
+        for call in stack.windows(2) {
+            let (call_samples, last_line) = calls.entry((call[0], call[1])).or_default();
+            if *last_line != line_number {
+                *last_line = line_number;
+                *call_samples += samples;
+            }
+        }
+    }
+
+    if ignored != 0 {
+        warn!("Ignored {} lines with invalid format", ignored);
+    }
+
+    let mut callers: Vec<Vec<Call>> = names.iter().map(|_| Vec::new()).collect();
+    let mut callees: Vec<Vec<Call>> = names.iter().map(|_| Vec::new()).collect();
+    for (&(caller, callee), &(samples, _)) in &calls {
+        callers[callee].push(Call {
+            name: names[caller].to_string(),
+            samples,
+        });
+        callees[caller].push(Call {
+            name: names[callee].to_string(),
+            samples,
+        });
+    }
+
+    let mut functions: Vec<Function> = names
+        .iter()
+        .zip(counts)
+        .zip(callers.into_iter().zip(callees))
+        .map(|((name, counts), (mut callers, mut callees))| {
+            sort_calls(&mut callers);
+            sort_calls(&mut callees);
+            Function {
+                name: name.to_string(),
+                self_samples: counts.self_samples,
+                total_samples: counts.total_samples,
+                callers,
+                callees,
+            }
+        })
+        .collect();
+    functions.sort_unstable_by(|a, b| {
+        let key = |f: &Function| match opt.sort_by {
+            SortBy::SelfSamples => (f.self_samples, f.total_samples),
+            SortBy::TotalSamples => (f.total_samples, f.self_samples),
+        };
+        key(b).cmp(&key(a)).then_with(|| a.name.cmp(&b.name))
+    });
+    if let Some(top) = opt.top {
+        functions.truncate(top);
+    }
+
+    Report {
+        total_samples,
+        functions,
+    }
+}
+
+fn sort_calls(calls: &mut [Call]) {
+    calls.sort_unstable_by(|a, b| b.samples.cmp(&a.samples).then_with(|| a.name.cmp(&b.name)));
+}
+
+/// Write a report of per-function sample counts from a reader of folded stack lines.
+///
+/// See [`report`] for the expected input.
+pub fn from_reader<R, W>(opt: &Options, reader: R, writer: W) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    from_readers(opt, std::iter::once(reader), writer)
+}
+
+/// Write a report of per-function sample counts from readers of folded stack lines.
+///
+/// The samples of all readers are added up. See [`report`] for the expected input.
+pub fn from_readers<R, W>(opt: &Options, readers: R, writer: W) -> io::Result<()>
+where
+    R: IntoIterator,
+    R::Item: Read,
+    W: Write,
+{
+    let mut input = String::new();
+    for mut reader in readers {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        input.push_str(&String::from_utf8_lossy(&bytes));
+        if !input.is_empty() && !input.ends_with('\n') {
+            input.push('\n');
+        }
+    }
+    let report = report(opt, input.lines());
+    write_report(opt, &report, writer)
+}
+
+/// Write a report of per-function sample counts from folded stack files.
+///
+/// Reads from STDIN if `files` is empty or `-`. Files compressed with gzip, zstd or xz are
+/// decompressed on the fly. See [`report`] for the expected input.
+pub fn from_files<W: Write>(opt: &Options, files: &[PathBuf], writer: W) -> io::Result<()> {
+    if files.is_empty() || files.len() == 1 && files[0].to_str() == Some("-") {
+        let stdin = io::stdin();
+        let r = io::BufReader::with_capacity(READER_CAPACITY, stdin.lock());
+        return from_reader(opt, compression::decompress(r)?, writer);
+    }
+
+    let stdin = io::stdin();
+    let mut stdin_added = false;
+    let mut readers: Vec<Box<dyn Read>> = Vec::with_capacity(files.len());
+    for infile in files {
+        if infile.to_str() == Some("-") {
+            if !stdin_added {
+                let r = io::BufReader::with_capacity(READER_CAPACITY, stdin.lock());
+                readers.push(Box::new(compression::decompress(r)?));
+                stdin_added = true;
+            }
+        } else {
+            let r = io::BufReader::with_capacity(READER_CAPACITY, File::open(infile)?);
+            readers.push(Box::new(compression::decompress(r)?));
+        }
+    }
+    from_readers(opt, readers, writer)
+}
+
+/// Write `report` in the format selected by [`Options::format`].
+pub fn write_report<W: Write>(opt: &Options, report: &Report, mut writer: W) -> io::Result<()> {
+    match opt.format {
+        Format::Text => write_text(opt, report, &mut writer)?,
+        Format::Csv => write_csv(opt, report, &mut writer)?,
+        Format::Json => {
+            serde_json::to_writer(&mut writer, report)?;
+            writeln!(writer)?;
+        }
+    }
+    writer.flush()
+}
+
+fn write_text<W: Write>(opt: &Options, report: &Report, writer: &mut W) -> io::Result<()> {
+    let pct = |samples: usize| percent(samples, report.total_samples);
+    let width = report.total_samples.to_string().len().max("Total".len());
+    writeln!(writer, "Total samples: {}", report.total_samples)?;
+    writeln!(writer)?;
+    writeln!(
+        writer,
+        "{:>width$} {:>7} {:>width$} {:>7}  Function",
+        "Self",
+        "Self%",
+        "Total",
+        "Total%",
+        width = width
+    )?;
+    // The callers and callees are indented to line up with the function names.
+    let indent = 2 * width + 19;
+    for function in &report.functions {
+        writeln!(
+            writer,
+            "{:>width$} {:>6.2}% {:>width$} {:>6.2}%  {}",
+            function.self_samples,
+            pct(function.self_samples),
+            function.total_samples,
+            pct(function.total_samples),
+            function.name,
+            width = width
+        )?;
+        if opt.callers {
+            for call in &function.callers {
+                writeln!(
+                    writer,
+                    "{:indent$}<- {} {} ({:.2}%)",
+                    "",
+                    call.name,
+                    call.samples,
+                    pct(call.samples),
+                    indent = indent
+                )?;
+            }
+        }
+        if opt.callees {
+            for call in &function.callees {
+                writeln!(
+                    writer,
+                    "{:indent$}-> {} {} ({:.2}%)",
+                    "",
+                    call.name,
+                    call.samples,
+                    pct(call.samples),
+                    indent = indent
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_csv<W: Write>(opt: &Options, report: &Report, writer: &mut W) -> io::Result<()> {
+    let pct = |samples: usize| percent(samples, report.total_samples);
+    write!(writer, "function,self,self_percent,total,total_percent")?;
+    if opt.callers {
+        write!(writer, ",callers")?;
+    }
+    if opt.callees {
+        write!(writer, ",callees")?;
+    }
+    writeln!(writer)?;
+
+    for function in &report.functions {
+        write!(
+            writer,
+            "{},{},{:.2},{},{:.2}",
+            csv_field(&function.name),
+            function.self_samples,
+            pct(function.self_samples),
+            function.total_samples,
+            pct(function.total_samples),
+        )?;
+        // Frame names can't contain semicolons, so they separate the calls.
+        let calls = |calls: &[Call]| {
+            let calls: Vec<_> = calls
+                .iter()
+                .map(|call| format!("{} ({})", call.name, call.samples))
+                .collect();
+            csv_field(&calls.join(";")).into_owned()
+        };
+        if opt.callers {
+            write!(writer, ",{}", calls(&function.callers))?;
+        }
+        if opt.callees {
+            write!(writer, ",{}", calls(&function.callees))?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+fn percent(samples: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        samples as f64 * 100.0 / total as f64
+    }
+}
+
+/// Quotes `s` if it contains characters that are special in CSV.
+fn csv_field(s: &str) -> std::borrow::Cow<'_, str> {
+    if s.contains(&[',', '"', '\n', '\r'][..]) {
+        format!("\"{}\"", s.replace('"', "\"\"")).into()
+    } else {
+        s.into()
+    }
+}
+
+// Parse the stack and sample count from a line, using the second count of differential input.
+fn parse_line<'a>(
+    line: &'a str,
+    stripped_fractional_samples: &mut bool,
+) -> Option<(&'a str, usize)> {
+    let (stack, samples) = line.rsplit_once(' ')?;
+    let samples = parse_samples(samples, stripped_fractional_samples)?;
+    let stack = stack.trim_end();
+    // Differential input has a second count; skip the first.
+    let stack = match stack.rsplit_once(' ') {
+        Some((rest, first)) if parse_samples(first, &mut true).is_some() => rest.trim_end(),
+        _ => stack,
+    };
+    if stack.is_empty() {
+        None
+    } else {
+        Some((stack, samples))
+    }
+}
+
+fn parse_samples(samples: &str, stripped_fractional_samples: &mut bool) -> Option<usize> {
+    let mut samples = samples;
+    // Strip fractional part (if any), like inferno-flamegraph does.
+    if let Some(doti) = samples.find('.') {
+        // Warn if we're stripping a non-zero fractional part, but only the first time.
+        if !*stripped_fractional_samples && !samples[doti + 1..].chars().all(|c| c == '0') {
+            *stripped_fractional_samples = true;
+            warn!("The input data has fractional sample counts that will be truncated to integers");
+        }
+        if !samples[doti + 1..].chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        samples = &samples[..doti];
+    }
+    if samples.is_empty() || !samples.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    samples.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calls(calls: &[Call]) -> Vec<(&str, usize)> {
+        calls
+            .iter()
+            .map(|call| (call.name.as_str(), call.samples))
+            .collect()
+    }
+
+    #[test]
+    fn recursion_is_counted_once() {
+        let lines = "main;walk;walk;walk;visit 5\nmain;walk;visit;walk 3\nmain 2\n";
+        let report = report(&Options::default(), lines.lines());
+        assert_eq!(report.total_samples, 10);
+
+        let counts: Vec<_> = report
+            .functions
+            .iter()
+            .map(|f| (f.name.as_str(), f.self_samples, f.total_samples))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![("visit", 5, 8), ("walk", 3, 8), ("main", 2, 10)]
+        );
+
+        let walk = &report.functions[1];
+        assert_eq!(
+            calls(&walk.callers),
+            vec![("main", 8), ("walk", 5), ("visit", 3)]
+        );
+        assert_eq!(calls(&walk.callees), vec![("visit", 8), ("walk", 5)]);
+    }
+
+    #[test]
+    fn differential_input_uses_second_count() {
+        let mut stripped = false;
+        assert_eq!(parse_line("a;b 1 2", &mut stripped), Some(("a;b", 2)));
+        assert_eq!(parse_line("a;b 3.50", &mut stripped), Some(("a;b", 3)));
+        assert!(stripped);
+        assert_eq!(parse_line("a;b x", &mut stripped), None);
+        assert_eq!(parse_line("12", &mut stripped), None);
+    }
+
+    #[test]
+    fn quotes_csv_fields() {
+        assert_eq!(csv_field("std::vec::Vec<T>"), "std::vec::Vec<T>");
+        assert_eq!(
+            csv_field("HashMap<K, V>::get \"x\""),
+            "\"HashMap<K, V>::get \"\"x\"\"\""
+        );
+    }
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use std::fs::File;
+use std::io::{self, BufReader, Cursor};
+use std::process::{Command, Stdio};
+
+use assert_cmd::prelude::*;
+use inferno::collapse::callgrind::{Folder, Options};
+use pretty_assertions::assert_eq;
+
+fn test_collapse_callgrind(
+    test_file: &str,
+    expected_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+fn test_collapse_callgrind_error(test_file: &str, options: Options) -> io::Error {
+    common::test_collapse_error(Folder::from(options), test_file)
+}
+
+#[test]
+fn collapse_callgrind_default() {
+    let test_file = "./tests/data/collapse-callgrind/callgrind.out.4242";
+    let result_file = "./tests/data/collapse-callgrind/results/callgrind-default.txt";
+    test_collapse_callgrind(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_callgrind_event() {
+    let test_file = "./tests/data/collapse-callgrind/callgrind.out.4242";
+    let result_file = "./tests/data/collapse-callgrind/results/callgrind-dr.txt";
+
+    let mut options = Options::default();
+    options.event = Some("Dr".to_string());
+
+    test_collapse_callgrind(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_callgrind_instr_positions_and_jumps() {
+    let test_file = "./tests/data/collapse-callgrind/instr.callgrind";
+    let result_file = "./tests/data/collapse-callgrind/results/instr-default.txt";
+    test_collapse_callgrind(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_callgrind_should_return_error_for_unknown_event() {
+    let test_file = "./tests/data/collapse-callgrind/callgrind.out.4242";
+    let mut options = Options::default();
+    options.event = Some("cycles".to_string());
+    let error = test_collapse_callgrind_error(test_file, options);
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(
+        error.to_string(),
+        "Unknown event cycles; the file has events: Ir Dr Dw"
+    );
+}
+
+#[test]
+fn collapse_callgrind_cli() {
+    let input_file = "./tests/data/collapse-callgrind/callgrind.out.4242";
+    let expected_file = "./tests/data/collapse-callgrind/results/callgrind-dr.txt";
+
+    // Test with file passed in
+    let output = Command::cargo_bin("inferno-collapse-callgrind")
+        .unwrap()
+        .arg("--event")
+        .arg("Dr")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+
+    // Test with STDIN
+    let mut child = Command::cargo_bin("inferno-collapse-callgrind")
+        .unwrap()
+        .arg("--event")
+        .arg("Dr")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn child process");
+    let mut input = BufReader::new(File::open(input_file).unwrap());
+    let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+    io::copy(&mut input, stdin).unwrap();
+    let output = child.wait_with_output().expect("Failed to read stdout");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use std::fs::File;
+use std::io::{self, BufReader, Cursor};
+use std::process::{Command, Stdio};
+
+use assert_cmd::prelude::*;
+use inferno::collapse::chrome::{Folder, Options};
+use pretty_assertions::assert_eq;
+
+fn test_collapse_chrome(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+fn test_collapse_chrome_error(test_file: &str, options: Options) -> io::Error {
+    common::test_collapse_error(Folder::from(options), test_file)
+}
+
+#[test]
+fn collapse_chrome_default() {
+    let test_file = "./tests/data/collapse-chrome/node.cpuprofile";
+    let result_file = "./tests/data/collapse-chrome/results/node-default.txt";
+    test_collapse_chrome(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_chrome_no_urls() {
+    let test_file = "./tests/data/collapse-chrome/node.cpuprofile";
+    let result_file = "./tests/data/collapse-chrome/results/node-no-urls.txt";
+
+    let mut options = Options::default();
+    options.no_urls = true;
+
+    test_collapse_chrome(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_chrome_time_weighted() {
+    let test_file = "./tests/data/collapse-chrome/node.cpuprofile";
+    let result_file = "./tests/data/collapse-chrome/results/node-time.txt";
+
+    let mut options = Options::default();
+    options.time_weighted = true;
+
+    test_collapse_chrome(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_chrome_hit_counts() {
+    let test_file = "./tests/data/collapse-chrome/hit-counts.cpuprofile";
+    let result_file = "./tests/data/collapse-chrome/results/node-default.txt";
+    test_collapse_chrome(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_chrome_parent_links() {
+    let test_file = "./tests/data/collapse-chrome/parent-links.cpuprofile";
+    let result_file = "./tests/data/collapse-chrome/results/node-default.txt";
+    test_collapse_chrome(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_chrome_should_return_error_for_unknown_node() {
+    let test_file = "./tests/data/collapse-chrome/invalid-node.cpuprofile";
+    let error = test_collapse_chrome_error(test_file, Options::default());
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error
+        .to_string()
+        .starts_with("Sample refers to unknown node id"));
+}
+
+#[test]
+fn collapse_chrome_should_return_error_for_missing_time_deltas() {
+    let test_file = "./tests/data/collapse-chrome/missing-deltas.cpuprofile";
+    let mut options = Options::default();
+    options.time_weighted = true;
+    let error = test_collapse_chrome_error(test_file, options);
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error
+        .to_string()
+        .starts_with("Profile must have one time delta per sample"));
+}
+
+#[test]
+fn collapse_chrome_should_return_error_for_invalid_json() {
+    let test_file = "./tests/data/collapse-perf/single-event.txt";
+    let error = test_collapse_chrome_error(test_file, Options::default());
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().starts_with("Invalid .cpuprofile JSON"));
+}
+
+#[test]
+fn collapse_chrome_cli() {
+    let input_file = "./tests/data/collapse-chrome/node.cpuprofile";
+    let expected_file = "./tests/data/collapse-chrome/results/node-time.txt";
+
+    // Test with file passed in
+    let output = Command::cargo_bin("inferno-collapse-chrome")
+        .unwrap()
+        .arg("--time")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+
+    // Test with STDIN
+    let mut child = Command::cargo_bin("inferno-collapse-chrome")
+        .unwrap()
+        .arg("--time")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn child process");
+    let mut input = BufReader::new(File::open(input_file).unwrap());
+    let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+    io::copy(&mut input, stdin).unwrap();
+    let output = child.wait_with_output().expect("Failed to read stdout");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+}
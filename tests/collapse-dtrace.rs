@@ -117,6 +117,34 @@ fn collapse_dtrace_rust_names() {
     test_collapse_dtrace(test_file, result_file, Options::default()).unwrap()
 }
 
+#[test]
+fn collapse_dtrace_unknown_frames_without_symbol_path() {
+    let test_file = "./tests/data/collapse-dtrace/unknown-frames.txt";
+    let result_file = "./tests/data/collapse-dtrace/results/unknown-frames.txt";
+    test_collapse_dtrace(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+#[cfg(feature = "symbolize")]
+fn collapse_dtrace_symbolize_unknown_frames() {
+    let test_file = "./tests/data/collapse-dtrace/unknown-frames.txt";
+    let result_file = "./tests/data/collapse-dtrace/results/unknown-frames-symbolized.txt";
+    let mut options = Options::default();
+    options.symbol_path = vec!["./tests/data/symbolize".into()];
+    test_collapse_dtrace(test_file, result_file, options).unwrap()
+}
+
+#[test]
+#[cfg(feature = "symbolize")]
+fn collapse_dtrace_symbolize_unknown_frames_with_inlines() {
+    let test_file = "./tests/data/collapse-dtrace/unknown-frames.txt";
+    let result_file = "./tests/data/collapse-dtrace/results/unknown-frames-inlines.txt";
+    let mut options = Options::default();
+    options.symbol_path = vec!["./tests/data/symbolize".into()];
+    options.symbolize_inlines = true;
+    test_collapse_dtrace(test_file, result_file, options).unwrap()
+}
+
 #[test]
 fn collapse_dtrace_cli() {
     let input_file = "./flamegraph/example-dtrace-stacks.txt";
@@ -0,0 +1,103 @@
+mod common;
+
+use std::fs::File;
+use std::io::{self, BufReader, Cursor};
+use std::process::{Command, Stdio};
+
+use assert_cmd::prelude::*;
+use inferno::collapse::gecko::{Folder, Options};
+use pretty_assertions::assert_eq;
+
+fn test_collapse_gecko(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+fn test_collapse_gecko_error(test_file: &str, options: Options) -> io::Error {
+    common::test_collapse_error(Folder::from(options), test_file)
+}
+
+#[test]
+fn collapse_gecko_default() {
+    let test_file = "./tests/data/collapse-gecko/samply.json";
+    let result_file = "./tests/data/collapse-gecko/results/samply-default.txt";
+    test_collapse_gecko(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_gecko_thread() {
+    let test_file = "./tests/data/collapse-gecko/samply.json";
+    let result_file = "./tests/data/collapse-gecko/results/samply-thread.txt";
+
+    let mut options = Options::default();
+    options.include_thread = true;
+
+    test_collapse_gecko(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_gecko_process_and_thread() {
+    let test_file = "./tests/data/collapse-gecko/samply.json";
+    let result_file = "./tests/data/collapse-gecko/results/samply-process-thread.txt";
+
+    let mut options = Options::default();
+    options.include_process = true;
+    options.include_thread = true;
+
+    test_collapse_gecko(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_gecko_shared_strings_and_weights() {
+    let test_file = "./tests/data/collapse-gecko/shared-strings.json";
+    let result_file = "./tests/data/collapse-gecko/results/shared-strings.txt";
+    test_collapse_gecko(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_gecko_should_return_error_for_unknown_stack() {
+    let test_file = "./tests/data/collapse-gecko/invalid-stack.json";
+    let error = test_collapse_gecko_error(test_file, Options::default());
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error
+        .to_string()
+        .starts_with("Sample refers to unknown stack 3"));
+}
+
+#[test]
+fn collapse_gecko_should_return_error_for_invalid_json() {
+    let test_file = "./tests/data/collapse-chrome/node.cpuprofile";
+    let error = test_collapse_gecko_error(test_file, Options::default());
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().starts_with("Invalid Gecko profile JSON"));
+}
+
+#[test]
+fn collapse_gecko_cli() {
+    let input_file = "./tests/data/collapse-gecko/samply.json";
+    let expected_file = "./tests/data/collapse-gecko/results/samply-thread.txt";
+
+    // Test with file passed in
+    let output = Command::cargo_bin("inferno-collapse-gecko")
+        .unwrap()
+        .arg("--thread")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+
+    // Test with STDIN
+    let mut child = Command::cargo_bin("inferno-collapse-gecko")
+        .unwrap()
+        .arg("--thread")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn child process");
+    let mut input = BufReader::new(File::open(input_file).unwrap());
+    let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+    io::copy(&mut input, stdin).unwrap();
+    let output = child.wait_with_output().expect("Failed to read stdout");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+}
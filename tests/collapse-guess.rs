@@ -6,6 +6,8 @@ use std::process::{Command, Stdio};
 
 use assert_cmd::cargo::CommandCargoExt;
 use inferno::collapse::guess::Folder;
+#[cfg(feature = "compression")]
+use inferno::collapse::Collapse;
 use log::Level;
 use pretty_assertions::assert_eq;
 use testing_logger::CapturedLog;
@@ -85,6 +87,136 @@ fn collapse_guess_vtune() {
     test_collapse_guess(test_file, result_file, false).unwrap()
 }
 
+#[test]
+fn collapse_guess_chrome() {
+    let test_file = "./tests/data/collapse-chrome/node.cpuprofile";
+    let result_file = "./tests/data/collapse-chrome/results/node-default.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_chrome_pretty_printed() {
+    let test_file = "./tests/data/collapse-chrome/hit-counts.cpuprofile";
+    let result_file = "./tests/data/collapse-chrome/results/node-default.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_pprof() {
+    let test_file = "./tests/data/collapse-pprof/cpu.pb";
+    let result_file = "./tests/data/collapse-pprof/results/cpu-default.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+#[cfg(feature = "compression")]
+fn collapse_guess_pprof_gzipped() {
+    // `test_collapse_guess` would decompress the file for us, so read it directly.
+    let test_file = "./tests/data/collapse-pprof/cpu.pb.gz";
+    let result_file = "./tests/data/collapse-pprof/results/cpu-default.txt";
+    let mut result = Vec::new();
+    let input = BufReader::new(File::open(test_file).unwrap());
+    Folder::default().collapse(input, &mut result).unwrap();
+    let expected = BufReader::new(File::open(result_file).unwrap());
+    common::compare_results(Cursor::new(result), expected, result_file, false);
+}
+
+#[test]
+fn collapse_guess_gzipped_text_is_not_pprof() {
+    // Read without `collapse_file`, which would decompress the input before guessing.
+    test_collapse_guess_logs(
+        "./tests/data/collapse-perf/go-stacks.txt.gz",
+        |captured_logs| {
+            let npprof = captured_logs
+                .iter()
+                .filter(|log| log.body == "Using pprof collapser")
+                .count();
+            assert_eq!(
+                npprof, 0,
+                "gzipped perf output was taken for a pprof profile"
+            );
+        },
+    );
+}
+
+#[test]
+fn collapse_guess_perf_data() {
+    // Without a symfs, the test binary can't be found, so frames are named after their binary.
+    let test_file = "./tests/data/collapse-perf-data/perf.data";
+    let result_file = "./tests/data/collapse-perf-data/results/perf-data-unsymbolized.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_gecko() {
+    let test_file = "./tests/data/collapse-gecko/samply.json";
+    let result_file = "./tests/data/collapse-gecko/results/samply-default.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_gecko_shared_strings() {
+    let test_file = "./tests/data/collapse-gecko/shared-strings.json";
+    let result_file = "./tests/data/collapse-gecko/results/shared-strings.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_speedscope() {
+    let test_file = "./tests/data/collapse-speedscope/sampled.speedscope.json";
+    let result_file = "./tests/data/collapse-speedscope/results/sampled-default.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_callgrind() {
+    let test_file = "./tests/data/collapse-callgrind/callgrind.out.4242";
+    let result_file = "./tests/data/collapse-callgrind/results/callgrind-default.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_gdb() {
+    let test_file = "./tests/data/collapse-gdb/gdb.txt";
+    let result_file = "./tests/data/collapse-gdb/results/gdb-default.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_eu_stack() {
+    let test_file = "./tests/data/collapse-eu-stack/eu-stack.txt";
+    let result_file = "./tests/data/collapse-eu-stack/results/eu-stack-default.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_jstack() {
+    let test_file = "./tests/data/collapse-jstack/jstack.txt";
+    let result_file = "./tests/data/collapse-jstack/results/jstack-default.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_goroutine() {
+    let test_file = "./tests/data/collapse-goroutine/goroutine.txt";
+    let result_file = "./tests/data/collapse-goroutine/results/goroutine-default.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_bpftrace() {
+    let test_file = "./tests/data/collapse-bpftrace/profile.txt";
+    let result_file = "./tests/data/collapse-bpftrace/results/profile-default.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_bcc() {
+    let test_file = "./tests/data/collapse-bcc/profile.txt";
+    let result_file = "./tests/data/collapse-bcc/results/profile-default.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
 #[test]
 fn collapse_guess_unknown_format_should_log_error() {
     test_collapse_guess_logs(
@@ -0,0 +1,229 @@
+mod common;
+
+#[cfg(feature = "symbolize")]
+use std::fs::File;
+use std::io;
+#[cfg(feature = "symbolize")]
+use std::io::{BufReader, Cursor};
+use std::path::PathBuf;
+#[cfg(feature = "symbolize")]
+use std::process::{Command, Stdio};
+
+#[cfg(feature = "symbolize")]
+use assert_cmd::prelude::*;
+use inferno::collapse::perf_data::{Folder, Options};
+use inferno::collapse::{perf, Collapse};
+use pretty_assertions::assert_eq;
+
+const SYMFS: &str = "./tests/data/collapse-perf-data/symfs";
+const KALLSYMS: &str = "./tests/data/collapse-perf-data/kallsyms";
+
+fn symbolized() -> Options {
+    let mut options = Options::default();
+    options.symfs = Some(PathBuf::from(SYMFS));
+    options.kallsyms = Some(PathBuf::from(KALLSYMS));
+    options
+}
+
+fn test_collapse_perf_data(
+    test_file: &str,
+    expected_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    for &n in &[1, 2] {
+        let mut options = options.clone();
+        options.perf.nthreads = n;
+        common::test_collapse(Folder::from(options), test_file, expected_file, false)?;
+    }
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "symbolize")]
+fn collapse_perf_data_symbolized() {
+    let test_file = "./tests/data/collapse-perf-data/perf.data";
+    let result_file = "./tests/data/collapse-perf-data/results/perf-data-symbolized.txt";
+    test_collapse_perf_data(test_file, result_file, symbolized()).unwrap()
+}
+
+#[test]
+#[cfg(feature = "symbolize")]
+fn collapse_perf_data_annotated_with_pid() {
+    let test_file = "./tests/data/collapse-perf-data/perf.data";
+    let result_file = "./tests/data/collapse-perf-data/results/perf-data-all-pid.txt";
+
+    let mut options = symbolized();
+    options.perf.annotate_jit = true;
+    options.perf.annotate_kernel = true;
+    options.perf.include_pid = true;
+
+    test_collapse_perf_data(test_file, result_file, options).unwrap()
+}
+
+#[test]
+#[cfg(feature = "symbolize")]
+fn collapse_perf_data_period() {
+    let test_file = "./tests/data/collapse-perf-data/perf.data";
+    let result_file = "./tests/data/collapse-perf-data/results/perf-data-period.txt";
+
+    let mut options = symbolized();
+    options.perf.use_period = true;
+
+    test_collapse_perf_data(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_perf_data_without_symbols() {
+    let test_file = "./tests/data/collapse-perf-data/perf.data";
+    let result_file = "./tests/data/collapse-perf-data/results/perf-data-unsymbolized.txt";
+
+    let mut options = Options::default();
+    options.symfs = Some(PathBuf::from("./tests/data/collapse-perf-data/results"));
+
+    test_collapse_perf_data(test_file, result_file, options).unwrap()
+}
+
+#[test]
+#[cfg(feature = "symbolize")]
+fn collapse_perf_data_pipe_mode() {
+    let test_file = "./tests/data/collapse-perf-data/pipe.data";
+    let result_file = "./tests/data/collapse-perf-data/results/pipe-data.txt";
+    test_collapse_perf_data(test_file, result_file, symbolized()).unwrap()
+}
+
+#[test]
+#[cfg(feature = "symbolize")]
+fn collapse_perf_data_from_stream() {
+    // Streams can't be read out of order like files can, so they take a different path.
+    for &(test_file, result_file) in &[
+        (
+            "./tests/data/collapse-perf-data/perf.data",
+            "./tests/data/collapse-perf-data/results/perf-data-symbolized.txt",
+        ),
+        (
+            "./tests/data/collapse-perf-data/pipe.data",
+            "./tests/data/collapse-perf-data/results/pipe-data.txt",
+        ),
+    ] {
+        let input = BufReader::new(File::open(test_file).unwrap());
+        let mut result = Vec::new();
+        Folder::from(symbolized())
+            .collapse(input, &mut result)
+            .unwrap();
+        let expected = BufReader::new(File::open(result_file).unwrap());
+        common::compare_results(Cursor::new(result), expected, result_file, false);
+    }
+}
+
+#[test]
+fn collapse_perf_data_should_match_perf_script() {
+    // perf.data.txt is what `perf script` prints for perf.data.
+    let result_file = "./tests/data/collapse-perf-data/results/perf-data-symbolized.txt";
+    common::test_collapse(
+        perf::Folder::default(),
+        "./tests/data/collapse-perf-data/perf.data.txt",
+        result_file,
+        false,
+    )
+    .unwrap()
+}
+
+#[test]
+fn collapse_perf_data_should_warn_about_missing_binaries() {
+    let mut options = Options::default();
+    options.symfs = Some(PathBuf::from("./tests/data/collapse-perf-data/results"));
+    common::test_collapse_logs(
+        Folder::from(options),
+        "./tests/data/collapse-perf-data/perf.data",
+        |captured_logs| {
+            let warnings = captured_logs
+                .iter()
+                .filter(|log| log.body.starts_with("Unable to read symbols from"))
+                .count();
+            assert_eq!(warnings, 1, "expected one warning per missing binary");
+        },
+    );
+}
+
+#[test]
+fn collapse_perf_data_should_return_error_for_truncated_file() {
+    let error = common::test_collapse_error(
+        Folder::from(symbolized()),
+        "./tests/data/collapse-perf-data/truncated.data",
+    );
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(error.to_string(), "perf.data file is truncated");
+}
+
+#[test]
+fn collapse_perf_data_should_return_error_for_overflowing_section() {
+    // Make the data section end past the largest possible offset.
+    let mut input = std::fs::read("./tests/data/collapse-perf-data/perf.data").unwrap();
+    input[48..56].copy_from_slice(&u64::MAX.to_le_bytes());
+    let error = Folder::default()
+        .collapse(io::Cursor::new(input), io::sink())
+        .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(
+        error.to_string().starts_with("Invalid section"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn collapse_perf_data_should_return_error_for_text_input() {
+    let error = common::test_collapse_error(
+        Folder::default(),
+        "./tests/data/collapse-perf/single-event.txt",
+    );
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn collapse_perf_data_is_applicable() {
+    let mut folder = Folder::default();
+    assert_eq!(folder.is_applicable("PERF"), None);
+    assert_eq!(folder.is_applicable("PERFILE2\u{68}"), Some(true));
+    assert_eq!(folder.is_applicable("perf 1234 cycles:"), Some(false));
+}
+
+#[test]
+#[cfg(feature = "symbolize")]
+fn collapse_perf_data_cli() {
+    let input_file = "./tests/data/collapse-perf-data/perf.data";
+    let expected_file = "./tests/data/collapse-perf-data/results/perf-data-period.txt";
+
+    // Test with file passed in
+    let output = Command::cargo_bin("inferno-collapse-perf-data")
+        .unwrap()
+        .arg("--period")
+        .arg("--symfs")
+        .arg(SYMFS)
+        .arg("--kallsyms")
+        .arg(KALLSYMS)
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+
+    // Test with STDIN
+    let mut child = Command::cargo_bin("inferno-collapse-perf-data")
+        .unwrap()
+        .arg("--period")
+        .arg("--symfs")
+        .arg(SYMFS)
+        .arg("--kallsyms")
+        .arg(KALLSYMS)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn child process");
+    let mut input = BufReader::new(File::open(input_file).unwrap());
+    let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+    io::copy(&mut input, stdin).unwrap();
+    let output = child.wait_with_output().expect("Failed to read stdout");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+}
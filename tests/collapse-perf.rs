@@ -7,6 +7,7 @@ use std::process::{Command, Stdio};
 
 use assert_cmd::cargo::CommandCargoExt;
 use inferno::collapse::perf::{Folder, Options};
+use inferno::filter::FoldRecursion;
 use log::Level;
 use pretty_assertions::assert_eq;
 use testing_logger::CapturedLog;
@@ -55,6 +56,9 @@ fn options_from_vec(opt_vec: Vec<&str>) -> Options {
             "addrs" => options.include_addrs = true,
             "jit" => options.annotate_jit = true,
             "kernel" => options.annotate_kernel = true,
+            "period" => options.use_period = true,
+            "direct" => options.fold_recursion = Some(FoldRecursion::Direct),
+            "cycles" => options.fold_recursion = Some(FoldRecursion::Cycles),
             "all" => {
                 options.annotate_jit = true;
                 options.annotate_kernel = true;
@@ -232,9 +236,13 @@ collapse_perf_tests! {
     collapse_perf_single_line_stacks,
     collapse_perf_single_event,
     collapse_perf_go_stacks,
+    collapse_perf_go_stacks__cycles,
     collapse_perf_java_inline,
+    collapse_perf_java_inline__direct,
     collapse_perf_versioned_vmlinux__kernel,
-    collapse_perf_sourcepawn_jitdump__jit
+    collapse_perf_sourcepawn_jitdump__jit,
+    collapse_perf_weighted_periods,
+    collapse_perf_weighted_periods__period
 }
 
 #[test]
@@ -248,6 +256,156 @@ fn collapse_perf_example_perf_stacks() {
     .unwrap();
 }
 
+#[test]
+fn collapse_perf_unknown_frames_without_symbol_path() {
+    test_collapse_perf(
+        "./tests/data/collapse-perf/unknown-frames.txt",
+        "./tests/data/collapse-perf/results/unknown-frames-collapsed.txt",
+        Default::default(),
+        false,
+    )
+    .unwrap();
+}
+
+#[test]
+#[cfg(feature = "symbolize")]
+fn collapse_perf_symbolize_unknown_frames() {
+    let mut options = Options::default();
+    options.symbol_path = vec!["./tests/data/symbolize".into()];
+    test_collapse_perf(
+        "./tests/data/collapse-perf/unknown-frames.txt",
+        "./tests/data/collapse-perf/results/unknown-frames-collapsed-symbolized.txt",
+        options,
+        false,
+    )
+    .unwrap();
+}
+
+#[test]
+#[cfg(feature = "symbolize")]
+fn collapse_perf_symbolize_unknown_frames_with_inlines() {
+    let mut options = Options::default();
+    options.symbol_path = vec!["./tests/data/symbolize".into()];
+    options.symbolize_inlines = true;
+    test_collapse_perf(
+        "./tests/data/collapse-perf/unknown-frames.txt",
+        "./tests/data/collapse-perf/results/unknown-frames-collapsed-inlines.txt",
+        options,
+        false,
+    )
+    .unwrap();
+}
+
+#[test]
+#[cfg(feature = "symbolize")]
+fn collapse_perf_symbolize_with_binary_in_symbol_path() {
+    let mut options = Options::default();
+    options.symbol_path = vec![
+        "./tests/data/symbolize/demo".into(),
+        "./tests/data/symbolize/libdemo.so".into(),
+    ];
+    test_collapse_perf(
+        "./tests/data/collapse-perf/unknown-frames.txt",
+        "./tests/data/collapse-perf/results/unknown-frames-collapsed-symbolized.txt",
+        options,
+        false,
+    )
+    .unwrap();
+}
+
+#[test]
+fn collapse_perf_jit_frames_with_perf_maps() {
+    let mut options = Options::default();
+    options.perf_map_dir = Some("./tests/data/collapse-perf/perf-maps".into());
+    options.annotate_jit = true;
+    test_collapse_perf(
+        "./tests/data/collapse-perf/jit-unknown.txt",
+        "./tests/data/collapse-perf/results/jit-unknown-collapsed-perf-maps.txt",
+        options,
+        false,
+    )
+    .unwrap();
+}
+
+#[test]
+#[cfg(feature = "compression")]
+fn collapse_perf_gzip_input() {
+    test_collapse_perf(
+        "./tests/data/collapse-perf/go-stacks.txt.gz",
+        "./tests/data/collapse-perf/results/go-stacks-collapsed.txt",
+        Default::default(),
+        false,
+    )
+    .unwrap()
+}
+
+#[test]
+#[cfg(feature = "compression")]
+fn collapse_perf_zstd_input() {
+    test_collapse_perf(
+        "./tests/data/collapse-perf/go-stacks.txt.zst",
+        "./tests/data/collapse-perf/results/go-stacks-collapsed.txt",
+        Default::default(),
+        false,
+    )
+    .unwrap()
+}
+
+#[test]
+#[cfg(feature = "compression")]
+fn collapse_perf_xz_input() {
+    test_collapse_perf(
+        "./tests/data/collapse-perf/go-stacks.txt.xz",
+        "./tests/data/collapse-perf/results/go-stacks-collapsed.txt",
+        Default::default(),
+        false,
+    )
+    .unwrap()
+}
+
+#[test]
+#[cfg(feature = "compression")]
+fn collapse_perf_cli_compressed_stdin() {
+    let input_file = "./tests/data/collapse-perf/go-stacks.txt.zst";
+    let expected_file = "./tests/data/collapse-perf/results/go-stacks-collapsed.txt";
+
+    let mut child = Command::cargo_bin("inferno-collapse-perf")
+        .unwrap()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn child process");
+    let mut input = BufReader::new(File::open(input_file).unwrap());
+    let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+    io::copy(&mut input, stdin).unwrap();
+    let output = child.wait_with_output().expect("Failed to read stdout");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+}
+
+#[test]
+fn collapse_perf_should_warn_about_missing_perf_maps_once() {
+    let mut options = Options::default();
+    options.perf_map_dir = Some("./tests/data/collapse-perf/perf-maps".into());
+    test_collapse_perf_logs_with_options(
+        "./tests/data/collapse-perf/jit-unknown.txt",
+        |captured_logs| {
+            let nwarnings = captured_logs
+                .iter()
+                .filter(|log| {
+                    log.body.starts_with("Unable to read perf map") && log.level == Level::Warn
+                })
+                .count();
+            assert_eq!(
+                nwarnings, 1,
+                "missing perf map warning logged {} times, but should be logged exactly once",
+                nwarnings
+            );
+        },
+        options,
+    );
+}
+
 #[test]
 fn collapse_perf_should_warn_about_empty_input_lines() {
     test_collapse_perf_logs(
@@ -288,6 +446,30 @@ fn collapse_perf_should_warn_about_weird_input_lines() {
     );
 }
 
+#[test]
+fn collapse_perf_should_warn_about_missing_periods_once() {
+    let mut options = Options::default();
+    options.use_period = true;
+    test_collapse_perf_logs_with_options(
+        "./tests/data/collapse-perf/weighted-periods.txt",
+        |captured_logs| {
+            let nwarnings = captured_logs
+                .iter()
+                .filter(|log| {
+                    log.body.starts_with("Event line has no sample period")
+                        && log.level == Level::Warn
+                })
+                .count();
+            assert_eq!(
+                nwarnings, 1,
+                "missing period warning logged {} times, but should be logged exactly once",
+                nwarnings
+            );
+        },
+        options,
+    );
+}
+
 #[test]
 fn collapse_perf_cli() {
     let input_file = "./flamegraph/test/perf-vertx-stacks-01.txt";
@@ -0,0 +1,156 @@
+mod common;
+
+#[cfg(feature = "compression")]
+use std::fs::File;
+use std::io;
+#[cfg(feature = "compression")]
+use std::io::{BufReader, Cursor};
+#[cfg(feature = "compression")]
+use std::process::{Command, Stdio};
+
+#[cfg(feature = "compression")]
+use assert_cmd::prelude::*;
+use inferno::collapse::pprof::{Folder, Options};
+#[cfg(feature = "compression")]
+use pretty_assertions::assert_eq;
+
+fn test_collapse_pprof(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+fn test_collapse_pprof_error(test_file: &str, options: Options) -> io::Error {
+    common::test_collapse_error(Folder::from(options), test_file)
+}
+
+#[test]
+#[cfg(feature = "compression")]
+fn collapse_pprof_default() {
+    let test_file = "./tests/data/collapse-pprof/cpu.pb.gz";
+    let result_file = "./tests/data/collapse-pprof/results/cpu-default.txt";
+    test_collapse_pprof(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_pprof_uncompressed_unpacked() {
+    let test_file = "./tests/data/collapse-pprof/cpu.pb";
+    let result_file = "./tests/data/collapse-pprof/results/cpu-default.txt";
+    test_collapse_pprof(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+#[cfg(feature = "compression")]
+fn collapse_pprof_sample_index_by_number() {
+    let test_file = "./tests/data/collapse-pprof/cpu.pb.gz";
+    let result_file = "./tests/data/collapse-pprof/results/cpu-samples.txt";
+
+    let mut options = Options::default();
+    options.sample_index = Some("0".to_string());
+
+    test_collapse_pprof(test_file, result_file, options).unwrap()
+}
+
+#[test]
+#[cfg(feature = "compression")]
+fn collapse_pprof_sample_index_by_name() {
+    let test_file = "./tests/data/collapse-pprof/cpu.pb.gz";
+    let result_file = "./tests/data/collapse-pprof/results/cpu-samples.txt";
+
+    let mut options = Options::default();
+    options.sample_index = Some("samples".to_string());
+
+    test_collapse_pprof(test_file, result_file, options).unwrap()
+}
+
+#[test]
+#[cfg(feature = "compression")]
+fn collapse_pprof_default_sample_type() {
+    let test_file = "./tests/data/collapse-pprof/heap.pb.gz";
+    let result_file = "./tests/data/collapse-pprof/results/heap-inuse-space.txt";
+    test_collapse_pprof(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+#[cfg(feature = "compression")]
+fn collapse_pprof_alloc_space() {
+    let test_file = "./tests/data/collapse-pprof/heap.pb.gz";
+    let result_file = "./tests/data/collapse-pprof/results/heap-alloc-space.txt";
+
+    let mut options = Options::default();
+    options.sample_index = Some("alloc_space".to_string());
+
+    test_collapse_pprof(test_file, result_file, options).unwrap()
+}
+
+#[test]
+#[cfg(feature = "compression")]
+fn collapse_pprof_should_return_error_for_unknown_sample_type() {
+    let test_file = "./tests/data/collapse-pprof/heap.pb.gz";
+    let mut options = Options::default();
+    options.sample_index = Some("cpu".to_string());
+    let error = test_collapse_pprof_error(test_file, options);
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(
+        error.to_string(),
+        "Profile has no sample type named cpu; available types are: \
+         alloc_objects, alloc_space, inuse_objects, inuse_space"
+    );
+}
+
+#[test]
+#[cfg(feature = "compression")]
+fn collapse_pprof_should_return_error_for_out_of_range_sample_index() {
+    let test_file = "./tests/data/collapse-pprof/cpu.pb.gz";
+    let mut options = Options::default();
+    options.sample_index = Some("2".to_string());
+    let error = test_collapse_pprof_error(test_file, options);
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().starts_with("Sample index 2 is out of range"));
+}
+
+#[test]
+#[cfg(feature = "compression")]
+fn collapse_pprof_should_return_error_for_truncated_profile() {
+    let test_file = "./tests/data/collapse-pprof/truncated.pb.gz";
+    let error = test_collapse_pprof_error(test_file, Options::default());
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+#[cfg(not(feature = "compression"))]
+fn collapse_pprof_should_return_error_for_gzipped_profile_without_compression() {
+    let test_file = "./tests/data/collapse-pprof/cpu.pb.gz";
+    let error = test_collapse_pprof_error(test_file, Options::default());
+    assert!(error.to_string().contains("`compression` feature"));
+}
+
+#[test]
+#[cfg(feature = "compression")]
+fn collapse_pprof_cli() {
+    let input_file = "./tests/data/collapse-pprof/cpu.pb.gz";
+    let expected_file = "./tests/data/collapse-pprof/results/cpu-samples.txt";
+
+    // Test with file passed in
+    let output = Command::cargo_bin("inferno-collapse-pprof")
+        .unwrap()
+        .arg("--sample-index=samples")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+
+    // Test with STDIN
+    let mut child = Command::cargo_bin("inferno-collapse-pprof")
+        .unwrap()
+        .arg("--sample-index=samples")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn child process");
+    let mut input = BufReader::new(File::open(input_file).unwrap());
+    let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+    io::copy(&mut input, stdin).unwrap();
+    let output = child.wait_with_output().expect("Failed to read stdout");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+}
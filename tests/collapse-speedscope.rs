@@ -0,0 +1,95 @@
+mod common;
+
+use std::fs::File;
+use std::io::{self, BufReader, Cursor};
+use std::process::{Command, Stdio};
+
+use assert_cmd::prelude::*;
+use inferno::collapse::speedscope::{Folder, Options};
+use pretty_assertions::assert_eq;
+
+fn test_collapse_speedscope(
+    test_file: &str,
+    expected_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+fn test_collapse_speedscope_error(test_file: &str, options: Options) -> io::Error {
+    common::test_collapse_error(Folder::from(options), test_file)
+}
+
+#[test]
+fn collapse_speedscope_sampled() {
+    let test_file = "./tests/data/collapse-speedscope/sampled.speedscope.json";
+    let result_file = "./tests/data/collapse-speedscope/results/sampled-default.txt";
+    test_collapse_speedscope(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_speedscope_sampled_no_profile_names() {
+    let test_file = "./tests/data/collapse-speedscope/sampled.speedscope.json";
+    let result_file = "./tests/data/collapse-speedscope/results/sampled-no-profile-names.txt";
+
+    let mut options = Options::default();
+    options.no_profile_names = true;
+
+    test_collapse_speedscope(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_speedscope_evented() {
+    let test_file = "./tests/data/collapse-speedscope/evented.speedscope.json";
+    let result_file = "./tests/data/collapse-speedscope/results/evented-default.txt";
+    test_collapse_speedscope(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_speedscope_should_return_error_for_mismatched_close_event() {
+    let test_file = "./tests/data/collapse-speedscope/mismatched-close.speedscope.json";
+    let error = test_collapse_speedscope_error(test_file, Options::default());
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error
+        .to_string()
+        .starts_with("Close event for frame 0 while frame 1 is open"));
+}
+
+#[test]
+fn collapse_speedscope_should_return_error_for_invalid_json() {
+    let test_file = "./tests/data/collapse-chrome/node.cpuprofile";
+    let error = test_collapse_speedscope_error(test_file, Options::default());
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().starts_with("Invalid speedscope JSON"));
+}
+
+#[test]
+fn collapse_speedscope_cli() {
+    let input_file = "./tests/data/collapse-speedscope/sampled.speedscope.json";
+    let expected_file = "./tests/data/collapse-speedscope/results/sampled-no-profile-names.txt";
+
+    // Test with file passed in
+    let output = Command::cargo_bin("inferno-collapse-speedscope")
+        .unwrap()
+        .arg("--no-profile-names")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+
+    // Test with STDIN
+    let mut child = Command::cargo_bin("inferno-collapse-speedscope")
+        .unwrap()
+        .arg("--no-profile-names")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn child process");
+    let mut input = BufReader::new(File::open(input_file).unwrap());
+    let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+    io::copy(&mut input, stdin).unwrap();
+    let output = child.wait_with_output().expect("Failed to read stdout");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    common::compare_results(Cursor::new(output.stdout), expected, expected_file, false);
+}
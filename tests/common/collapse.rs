@@ -2,7 +2,6 @@ use std::fs::{self, File};
 use std::io::{self, BufRead, BufReader, Cursor};
 
 use inferno::collapse::Collapse;
-use libflate::gzip::Decoder;
 use pretty_assertions::assert_eq;
 use testing_logger::CapturedLog;
 
@@ -52,15 +51,8 @@ where
         return Err(e);
     }
 
-    let mut collapse = move |out: &mut dyn io::Write| {
-        if test_filename.ends_with(".gz") {
-            let test_file = File::open(test_filename)?;
-            let r = BufReader::new(Decoder::new(test_file).unwrap());
-            collapser.collapse(r, out)
-        } else {
-            collapser.collapse_file(Some(test_filename), out)
-        }
-    };
+    let mut collapse =
+        move |out: &mut dyn io::Write| collapser.collapse_file(Some(test_filename), out);
 
     let metadata = match fs::metadata(expected_filename) {
         Ok(m) => m,
@@ -112,15 +104,8 @@ where
         panic!("Failed to open input file '{}'", test_filename);
     }
 
-    let mut collapse = move |out: &mut dyn io::Write| {
-        if test_filename.ends_with(".gz") {
-            let test_file = File::open(test_filename)?;
-            let r = BufReader::new(Decoder::new(test_file).unwrap());
-            collapser.collapse(r, out)
-        } else {
-            collapser.collapse_file(Some(test_filename), out)
-        }
-    };
+    let mut collapse =
+        move |out: &mut dyn io::Write| collapser.collapse_file(Some(test_filename), out);
 
     collapse(&mut io::sink()).expect_err("Expected an error")
 }
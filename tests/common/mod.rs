@@ -2,4 +2,6 @@
 
 mod collapse;
 
+// Each test crate only uses some of these.
+#[allow(unused_imports)]
 pub use self::collapse::{compare_results, test_collapse, test_collapse_error, test_collapse_logs};
@@ -0,0 +1,23 @@
+
+
+				  ((""""""("""7""""""
+ "	"*
+		 
+(
+*
+ 
+(
+*
+ (
+*
+ (
+*
+ (
+*
+ (
+*
+ (
+*
+ (
+HP؎oZ`2 2samples2count2cpu2nanoseconds2thread2main2/usr/local/bin/server2/lib/x86_64-linux-gnu/libc.so.62	main.main2/src/main.go2main.handle2
+main.parse2/src/parse.go2strings.Index2/go/src/strings/strings.go2runtime.mallocgc2/go/src/runtime/malloc.go2runtime.main2/go/src/runtime/proc.go2#encoding/json.(*decodeState).object2/go/src/encoding/json/decode.go2main.render;html2/src/render.go
\ No newline at end of file
@@ -0,0 +1,17 @@
+#!/bin/sh
+# Rebuilds the binaries that the symbolization tests look up frames in. The expected results
+# depend on the addresses gcc picks, so update them (and the inputs that refer to those
+# addresses) after rebuilding.
+#
+# libdemo.so is stripped, and points to its debug info in libdemo.so.debug through a
+# .gnu_debuglink section. The committed binaries were built with gcc 12.2.
+set -eu
+cd "$(dirname "$0")"
+
+CFLAGS="-g -O2 -fno-asynchronous-unwind-tables"
+
+gcc $CFLAGS -o demo demo.c
+
+gcc $CFLAGS -fPIC -shared -o libdemo.so libdemo.c
+objcopy --only-keep-debug libdemo.so libdemo.so.debug
+objcopy --strip-all --add-gnu-debuglink=libdemo.so.debug libdemo.so
@@ -0,0 +1,13 @@
+static inline __attribute__((always_inline)) int square(int x) { return x * x; }
+
+__attribute__((noinline)) int compute(int x)
+{
+	int s = 0;
+	for (int i = 0; i < x; i++)
+		s += square(i) ^ s;
+	return s;
+}
+
+__attribute__((noinline)) int run(int n) { return compute(n) + 1; }
+
+int main(int argc, char **argv) { (void)argv; return run(argc * 1000); }
@@ -0,0 +1,11 @@
+static inline __attribute__((always_inline)) long mix(long x) { return x ^ (x >> 3); }
+
+__attribute__((noinline, visibility("hidden"))) long lib_hash(long x)
+{
+	long h = 0;
+	for (long i = 0; i < x; i++)
+		h = mix(h + i) * 31;
+	return h;
+}
+
+long lib_entry(long x) { return lib_hash(x) * 7; }
@@ -148,6 +148,16 @@ fn diff_folded_fractional_samples() {
     test_diff_folded(infile1, infile2, expected_result_file, Default::default()).unwrap();
 }
 
+#[test]
+#[cfg(feature = "compression")]
+fn diff_folded_compressed_input() {
+    let infile1 = "./tests/data/diff-folded/before.txt.gz";
+    let infile2 = "./tests/data/diff-folded/after.txt.xz";
+    let expected_result_file = "./tests/data/diff-folded/results/default.txt";
+
+    test_diff_folded(infile1, infile2, expected_result_file, Default::default()).unwrap();
+}
+
 #[test]
 fn diff_folded_should_log_warning_on_bad_input_line() {
     test_diff_folded_logs(
@@ -206,3 +216,105 @@ fn diff_folded_cli() {
     let expected = BufReader::new(File::open(expected_file).unwrap());
     compare_results(Cursor::new(output.stdout), expected, expected_file);
 }
+
+fn test_diff_multi_summary(
+    baseline: &str,
+    variants: &[&str],
+    expected_result_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    let comparisons = differential::compare_files(options, baseline, variants)?;
+    if fs::metadata(expected_result_file).is_err() {
+        // be nice to the dev and make the file
+        let f = File::create(expected_result_file).unwrap();
+        differential::write_summary(&comparisons, 5, f)?;
+    }
+
+    let mut result = Vec::new();
+    differential::write_summary(&comparisons, 5, &mut result)?;
+    let expected = fs::read_to_string(expected_result_file).unwrap();
+    assert_eq!(String::from_utf8(result).unwrap(), expected);
+    Ok(())
+}
+
+#[test]
+fn diff_multi_summary() {
+    let baseline = "./tests/data/diff-folded/before.txt";
+    let variants = [
+        "./tests/data/diff-folded/after.txt",
+        "./tests/data/diff-folded/after_c.txt",
+    ];
+    let expected_result_file = "./tests/data/diff-folded/results/multi_summary.txt";
+
+    test_diff_multi_summary(
+        baseline,
+        &variants,
+        expected_result_file,
+        Default::default(),
+    )
+    .unwrap();
+}
+
+#[test]
+fn diff_multi_summary_normalize() {
+    let baseline = "./tests/data/diff-folded/before.txt";
+    let variants = [
+        "./tests/data/diff-folded/after.txt",
+        "./tests/data/diff-folded/after_c.txt",
+    ];
+    let expected_result_file = "./tests/data/diff-folded/results/multi_summary_normalize.txt";
+
+    let opt = Options {
+        normalize: true,
+        ..Default::default()
+    };
+    test_diff_multi_summary(baseline, &variants, expected_result_file, opt).unwrap();
+}
+
+#[test]
+#[cfg(feature = "compression")]
+fn diff_multi_matches_diff_folded() {
+    let baseline = "./tests/data/diff-folded/before.txt";
+    let variants = ["./tests/data/diff-folded/after.txt.xz"];
+    let expected_file = "./tests/data/diff-folded/results/normalize.txt";
+
+    let opt = Options {
+        normalize: true,
+        ..Default::default()
+    };
+    let comparisons = differential::compare_files(opt, baseline, &variants).unwrap();
+    assert_eq!(comparisons[0].name, "after");
+
+    let mut result = Cursor::new(Vec::new());
+    comparisons[0].write_folded(&mut result).unwrap();
+    result.set_position(0);
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    compare_results(result, expected, expected_file);
+}
+
+#[test]
+fn diff_multi_cli() {
+    let out_dir = std::env::temp_dir().join(format!("diff-multi-{}", rand::random::<u64>()));
+
+    let output = Command::cargo_bin("inferno-diff-multi")
+        .unwrap()
+        .arg("--top")
+        .arg("5")
+        .arg("--output-dir")
+        .arg(&out_dir)
+        .arg("./tests/data/diff-folded/before.txt")
+        .arg("./tests/data/diff-folded/after.txt")
+        .arg("./tests/data/diff-folded/after_c.txt")
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+    let expected =
+        fs::read_to_string("./tests/data/diff-folded/results/multi_summary.txt").unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+
+    for name in &["after", "after_c"] {
+        let svg = fs::read_to_string(out_dir.join(format!("{}.svg", name))).unwrap();
+        assert!(svg.contains(&format!("Differential: {}", name)));
+    }
+    fs::remove_dir_all(&out_dir).unwrap();
+}
@@ -0,0 +1,139 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Cursor};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use assert_cmd::cargo::CommandCargoExt;
+use inferno::collapse::perf::Folder;
+use inferno::collapse::Collapse;
+use inferno::filter::{self, Filter, Rule};
+use pretty_assertions::assert_eq;
+
+fn test_filter(infile: &str, expected_result_file: &str, filter: &Filter) -> io::Result<()> {
+    let infiles = vec![PathBuf::from(infile)];
+    if let Err(e) = fs::metadata(expected_result_file) {
+        if e.kind() == io::ErrorKind::NotFound {
+            // be nice to the dev and make the file
+            let f = File::create(expected_result_file).unwrap();
+            filter::from_files(filter, &infiles, f)?;
+        } else {
+            return Err(e);
+        }
+    }
+
+    let mut result = Cursor::new(Vec::new());
+    filter::from_files(filter, &infiles, &mut result)?;
+    result.set_position(0);
+    let expected = BufReader::new(File::open(expected_result_file).unwrap());
+    compare_results(result, expected, expected_result_file);
+    Ok(())
+}
+
+fn compare_results<R, E>(result: R, expected: E, expected_file: &str)
+where
+    R: BufRead,
+    E: BufRead,
+{
+    let result_lines: Vec<String> = result.lines().collect::<Result<_, _>>().unwrap();
+    let expected_lines: Vec<String> = expected.lines().collect::<Result<_, _>>().unwrap();
+    assert_eq!(
+        result_lines, expected_lines,
+        "filter output does not match {}",
+        expected_file
+    );
+}
+
+#[test]
+fn filter_rule_file() {
+    let infile = "./tests/data/collapse-perf/results/go-stacks-collapsed.txt";
+    let expected_result_file = "./tests/data/filter/results/go-stacks-filtered.txt";
+    let filter = Filter::from_file(Path::new("./tests/data/filter/go.rules")).unwrap();
+    test_filter(infile, expected_result_file, &filter).unwrap();
+}
+
+#[test]
+fn filter_keep_max_depth() {
+    let infile = "./tests/data/collapse-perf/results/go-stacks-collapsed.txt";
+    let mut filter = Filter::default();
+    filter.push("keep ;main\\.init;".parse().unwrap());
+    filter.push(Rule::MaxDepth(5));
+    let mut result = Vec::new();
+    filter::from_files(&filter, &[infile.into()], &mut result).unwrap();
+    let result = String::from_utf8(result).unwrap();
+    assert_eq!(
+        result.lines().collect::<Vec<_>>(),
+        vec![
+            "go;[unknown];x_cgo_notify_runtime_init_done;runtime.main;main.init 1",
+            "go;[unknown];x_cgo_notify_runtime_init_done;runtime.main;main.init 1",
+            "go;[unknown];x_cgo_notify_runtime_init_done;runtime.main;main.init 1",
+        ]
+    );
+}
+
+#[test]
+fn filter_collapse_output() {
+    // Cutting below a frame while collapsing works like `perf::Options::skip_after`.
+    let mut filter = Filter::default();
+    filter.push("cut-below ^main\\.init$".parse().unwrap());
+    let mut result = Vec::new();
+    Folder::default()
+        .collapse_file(
+            Some("./tests/data/collapse-perf/go-stacks.txt"),
+            filter.writer(&mut result),
+        )
+        .unwrap();
+    let result = String::from_utf8(result).unwrap();
+    assert_eq!(result.lines().count(), 5);
+    for line in result.lines() {
+        if line.contains("main.init") {
+            assert!(line.starts_with("main.init;"), "{}", line);
+        } else {
+            assert!(line.starts_with("go;"), "{}", line);
+        }
+    }
+}
+
+#[test]
+fn filter_invalid_rule_file() {
+    let err = Filter::from_reader("drop ^idle\nkeep-frames main\n".as_bytes()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(err.to_string(), "line 2: unknown filter rule: keep-frames");
+}
+
+#[test]
+fn filter_cli() {
+    let infile = "./tests/data/collapse-perf/results/go-stacks-collapsed.txt";
+    let expected_file = "./tests/data/filter/results/go-stacks-filtered.txt";
+
+    let output = Command::cargo_bin("inferno-filter")
+        .unwrap()
+        .arg("--rule")
+        .arg("cut-below ^runtime\\.main$")
+        .arg("-r")
+        .arg("drop ;main\\.init;")
+        .arg("-r")
+        .arg("replace ^cmd/go/internal/")
+        .arg("-r")
+        .arg("max-depth 6")
+        .arg(infile)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    compare_results(Cursor::new(output.stdout), expected, expected_file);
+}
+
+#[test]
+fn filter_cli_rule_file() {
+    let infile = "./tests/data/collapse-perf/results/go-stacks-collapsed.txt";
+    let expected_file = "./tests/data/filter/results/go-stacks-filtered.txt";
+
+    let output = Command::cargo_bin("inferno-filter")
+        .unwrap()
+        .arg("--rule-file")
+        .arg("./tests/data/filter/go.rules")
+        .arg(infile)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    compare_results(Cursor::new(output.stdout), expected, expected_file);
+}
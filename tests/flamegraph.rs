@@ -7,8 +7,11 @@ use std::process::{Command, Stdio};
 use std::str::FromStr;
 
 use assert_cmd::cargo::CommandCargoExt;
+use inferno::filter::FoldRecursion;
 use inferno::flamegraph::color::{BackgroundColor, PaletteMap};
-use inferno::flamegraph::{self, Direction, Options, Palette, TextTruncateDirection};
+use inferno::flamegraph::{
+    self, DifferentialColors, Direction, Options, OutputFormat, Palette, TextTruncateDirection,
+};
 use log::Level;
 use pretty_assertions::assert_eq;
 use testing_logger::CapturedLog;
@@ -187,6 +190,26 @@ fn flamegraph_differential_negated() {
     test_flamegraph(input_file, expected_result_file, options).unwrap();
 }
 
+#[test]
+fn flamegraph_differential_significance() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/differential/diff-significance.svg";
+    let mut options = flamegraph::Options::default();
+    options.significance = Some(0.05);
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_differential_ratio() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/differential/diff-ratio.svg";
+    let mut options = flamegraph::Options::default();
+    options.differential_colors = DifferentialColors::Ratio;
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
 #[test]
 fn flamegraph_collor_diffusion() {
     let input_file = "./flamegraph/test/results/perf-vertx-stacks-01-collapsed-all.txt";
@@ -465,6 +488,22 @@ fn flamegraph_unsorted_multiple_input_files() {
     test_flamegraph_multiple_files(input_files, expected_result_file, options).unwrap();
 }
 
+#[test]
+#[cfg(feature = "compression")]
+fn flamegraph_compressed_input_files() {
+    let input_files = vec![
+        "./tests/data/flamegraph/multiple-inputs/perf-vertx-stacks-01-collapsed-all-unsorted-1.txt.zst"
+            .into(),
+        "./tests/data/flamegraph/multiple-inputs/perf-vertx-stacks-01-collapsed-all-unsorted-2.txt.xz"
+            .into(),
+    ];
+    let expected_result_file =
+        "./tests/data/flamegraph/perf-vertx-stacks/perf-vertx-stacks-01-collapsed-all.svg";
+    let mut options = flamegraph::Options::default();
+    options.hash = true;
+    test_flamegraph_multiple_files(input_files, expected_result_file, options).unwrap();
+}
+
 #[test]
 fn flamegraph_should_prune_narrow_blocks() {
     let input_file = "./tests/data/flamegraph/narrow-blocks/narrow-blocks.txt";
@@ -883,3 +922,601 @@ fn flamegraph_flamechart() {
 
     test_flamegraph(input_file, expected_result_file, opts).unwrap();
 }
+
+#[test]
+fn flamegraph_html() {
+    let input_files = vec![
+        "./tests/data/flamegraph/multiple-inputs/perf-vertx-stacks-01-collapsed-all-unsorted-1.txt"
+            .into(),
+        "./tests/data/flamegraph/multiple-inputs/perf-vertx-stacks-01-collapsed-all-unsorted-2.txt"
+            .into(),
+    ];
+    let expected_result_file =
+        "./tests/data/flamegraph/html/perf-vertx-stacks-01-collapsed-all.html";
+    let mut options = flamegraph::Options::default();
+    options.hash = true;
+    options.format = OutputFormat::Html;
+    test_flamegraph_multiple_files(input_files, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_html_differential_inverted() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/html/diff-inverted.html";
+    let mut options = flamegraph::Options::default();
+    options.format = OutputFormat::Html;
+    options.direction = Direction::Inverted;
+    options.title = "<Icicle> & \"Graph\"".to_string();
+    options.subtitle = Some("cycles vs. instructions".to_string());
+    options.text_truncate_direction = TextTruncateDirection::Right;
+    options.image_width = Some(800);
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_html_empty_input() {
+    let input_file = "./tests/data/flamegraph/empty/empty.txt";
+    let expected_result_file = "./tests/data/flamegraph/html/empty.html";
+    let mut options = flamegraph::Options::default();
+    options.format = OutputFormat::Html;
+    assert!(test_flamegraph(input_file, expected_result_file, options).is_err());
+}
+
+#[test]
+fn flamegraph_html_cli() {
+    let input_file =
+        "./tests/data/flamegraph/multiple-inputs/perf-vertx-stacks-01-collapsed-all-unsorted-1.txt";
+    let input_file_part2 =
+        "./tests/data/flamegraph/multiple-inputs/perf-vertx-stacks-01-collapsed-all-unsorted-2.txt";
+    let expected_file = "./tests/data/flamegraph/html/perf-vertx-stacks-01-collapsed-all.html";
+    let output = Command::cargo_bin("inferno-flamegraph")
+        .unwrap()
+        .arg("--format")
+        .arg("html")
+        .arg("--no-javascript")
+        .arg("--hash")
+        .arg(input_file)
+        .arg(input_file_part2)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    compare_results(Cursor::new(output.stdout), expected, expected_file);
+}
+
+#[test]
+fn flamegraph_d3_differential() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/json/diff-negated.d3.json";
+    let mut options = flamegraph::Options::default();
+    options.format = OutputFormat::D3;
+    options.negate_differentials = true;
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_d3_reversed_min_width() {
+    let input_files = vec![
+        "./tests/data/flamegraph/multiple-inputs/perf-vertx-stacks-01-collapsed-all-unsorted-1.txt"
+            .into(),
+        "./tests/data/flamegraph/multiple-inputs/perf-vertx-stacks-01-collapsed-all-unsorted-2.txt"
+            .into(),
+    ];
+    let expected_result_file = "./tests/data/flamegraph/json/reversed-min-width.d3.json";
+    let mut options = flamegraph::Options::default();
+    options.format = OutputFormat::D3;
+    options.reverse_stack_order = true;
+    options.min_width = 1.0;
+    test_flamegraph_multiple_files(input_files, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_speedscope() {
+    let input_files = vec![
+        "./tests/data/flamegraph/multiple-inputs/perf-vertx-stacks-01-collapsed-all-unsorted-1.txt"
+            .into(),
+        "./tests/data/flamegraph/multiple-inputs/perf-vertx-stacks-01-collapsed-all-unsorted-2.txt"
+            .into(),
+    ];
+    let expected_result_file =
+        "./tests/data/flamegraph/json/perf-vertx-stacks-01-collapsed-all.speedscope.json";
+    let mut options = flamegraph::Options::default();
+    options.format = OutputFormat::Speedscope;
+    options.min_width = 0.5;
+    test_flamegraph_multiple_files(input_files, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_json_empty_input() {
+    let input_file = "./tests/data/flamegraph/empty/empty.txt";
+    for format in [OutputFormat::D3, OutputFormat::Speedscope] {
+        let mut options = flamegraph::Options::default();
+        options.format = format;
+        let mut result = Vec::new();
+        assert!(flamegraph::from_files(&mut options, &[input_file.into()], &mut result).is_err());
+        assert!(result.is_empty());
+    }
+}
+
+#[test]
+fn flamegraph_speedscope_cli() {
+    let input_file_part1 =
+        "./tests/data/flamegraph/multiple-inputs/perf-vertx-stacks-01-collapsed-all-unsorted-1.txt";
+    let input_file_part2 =
+        "./tests/data/flamegraph/multiple-inputs/perf-vertx-stacks-01-collapsed-all-unsorted-2.txt";
+    let expected_file =
+        "./tests/data/flamegraph/json/perf-vertx-stacks-01-collapsed-all.speedscope.json";
+    let output = Command::cargo_bin("inferno-flamegraph")
+        .unwrap()
+        .arg("--format")
+        .arg("speedscope")
+        .arg("--pretty-xml")
+        .arg("--minwidth")
+        .arg("0.5")
+        .arg(input_file_part1)
+        .arg(input_file_part2)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    compare_results(Cursor::new(output.stdout), expected, expected_file);
+}
+
+#[test]
+fn flamegraph_text() {
+    let input_files = vec![
+        "./tests/data/flamegraph/multiple-inputs/perf-vertx-stacks-01-collapsed-all-unsorted-1.txt"
+            .into(),
+        "./tests/data/flamegraph/multiple-inputs/perf-vertx-stacks-01-collapsed-all-unsorted-2.txt"
+            .into(),
+    ];
+    let expected_result_file =
+        "./tests/data/flamegraph/text/perf-vertx-stacks-01-collapsed-all.txt";
+    let mut options = flamegraph::Options::default();
+    options.format = OutputFormat::Text;
+    options.hash = true;
+    options.image_width = Some(120);
+    options.min_width = 1.0;
+    test_flamegraph_multiple_files(input_files, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_text_inverted_truncate_right() {
+    let input_file =
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt";
+    let expected_result_file = "./tests/data/flamegraph/text/diff-inverted-truncate-right.txt";
+    let mut options = flamegraph::Options::default();
+    options.format = OutputFormat::Text;
+    options.direction = Direction::Inverted;
+    options.text_truncate_direction = TextTruncateDirection::Right;
+    options.subtitle = Some("cycles vs. instructions".to_string());
+    options.image_width = Some(60);
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_text_cli() {
+    let input_file_part1 =
+        "./tests/data/flamegraph/multiple-inputs/perf-vertx-stacks-01-collapsed-all-unsorted-1.txt";
+    let input_file_part2 =
+        "./tests/data/flamegraph/multiple-inputs/perf-vertx-stacks-01-collapsed-all-unsorted-2.txt";
+    let expected_file = "./tests/data/flamegraph/text/perf-vertx-stacks-01-collapsed-all.txt";
+    let output = Command::cargo_bin("inferno-flamegraph")
+        .unwrap()
+        .arg("--format")
+        .arg("text")
+        .arg("--color")
+        .arg("always")
+        .arg("--hash")
+        .arg("--width")
+        .arg("120")
+        .arg("--minwidth")
+        .arg("1")
+        .arg(input_file_part1)
+        .arg(input_file_part2)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    compare_results(Cursor::new(output.stdout), expected, expected_file);
+}
+
+#[test]
+fn flamegraph_text_no_colors() {
+    let input = "main;foo 3\nmain;bar 2\n";
+    let mut options = flamegraph::Options::default();
+    options.format = OutputFormat::Text;
+    options.no_text_colors = true;
+    options.image_width = Some(40);
+    let mut result = Vec::new();
+    flamegraph::from_lines(&mut options, input.lines(), &mut result).unwrap();
+    assert_eq!(
+        String::from_utf8(result).unwrap(),
+        "Flame Graph\n\
+         bar 40.00%      foo 60.00%\n\
+         main 100.00%\n\
+         all 100.00%\n"
+    );
+}
+
+#[test]
+fn flamegraph_text_cli_piped() {
+    // Text written anywhere but a terminal isn't colored, unless asked for.
+    let output = Command::cargo_bin("inferno-flamegraph")
+        .unwrap()
+        .arg("--format")
+        .arg("text")
+        .arg("./tests/data/flamegraph/focus/stacks.txt")
+        .output()
+        .expect("failed to execute process");
+    let output = String::from_utf8(output.stdout).unwrap();
+    assert!(output.starts_with("Flame Graph\n"), "{}", output);
+    assert!(!output.contains('\x1b'), "{}", output);
+}
+
+#[test]
+fn flamegraph_layout() {
+    let input = "main;parse 30\nmain;render 50\nidle 20\nmain;render;draw_[k] 10\n";
+    let mut options = flamegraph::Options::default();
+    options.hash = true;
+    options.min_width = 5.0;
+    let frames = flamegraph::layout(&mut options, input.lines()).unwrap();
+    let frames: Vec<_> = frames
+        .iter()
+        .map(|frame| {
+            (
+                frame.name.as_str(),
+                frame.depth,
+                frame.start,
+                frame.end,
+                frame.info.as_str(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        frames,
+        vec![
+            ("all", 0, 0, 110, "all (110 samples, 100%)"),
+            ("idle", 1, 0, 20, "idle (20 samples, 18.18%)"),
+            ("main", 1, 20, 110, "main (90 samples, 81.82%)"),
+            ("parse", 2, 20, 50, "parse (30 samples, 27.27%)"),
+            ("render", 2, 50, 110, "render (60 samples, 54.55%)"),
+            ("draw", 3, 100, 110, "draw (10 samples, 9.09%)"),
+        ]
+    );
+}
+
+#[test]
+fn flamegraph_layout_filter() {
+    let input = "main;parse 30\nmain;render 50\nidle 20\nmain;render;draw_[k] 10\n";
+    let mut options = flamegraph::Options::default();
+    options.filter.push("drop ^idle$".parse().unwrap());
+    options
+        .filter
+        .push("replace ^render$ paint".parse().unwrap());
+    options.filter.push("cut-above ^paint$".parse().unwrap());
+    let frames = flamegraph::layout(&mut options, input.lines()).unwrap();
+    let frames: Vec<_> = frames
+        .iter()
+        .map(|frame| (frame.name.as_str(), frame.depth, frame.start, frame.end))
+        .collect();
+    assert_eq!(
+        frames,
+        vec![
+            ("all", 0, 0, 90),
+            ("main", 1, 0, 90),
+            ("paint", 2, 0, 60),
+            ("parse", 2, 60, 90),
+        ]
+    );
+}
+
+#[test]
+fn flamegraph_layout_fold_recursion() {
+    let input = "main;walk;walk;walk;visit 40\nmain;walk;visit;walk;visit 20\nmain;walk 10\n";
+    let mut options = flamegraph::Options::default();
+    options.fold_recursion = Some(FoldRecursion::Direct);
+    let frames = flamegraph::layout(&mut options, input.lines()).unwrap();
+    assert_eq!(frames.iter().map(|frame| frame.depth).max(), Some(5));
+
+    options.fold_recursion = Some(FoldRecursion::Cycles);
+    let frames = flamegraph::layout(&mut options, input.lines()).unwrap();
+    let frames: Vec<_> = frames
+        .iter()
+        .map(|frame| (frame.name.as_str(), frame.depth, frame.start, frame.end))
+        .collect();
+    assert_eq!(
+        frames,
+        vec![
+            ("all", 0, 0, 70),
+            ("main", 1, 0, 70),
+            ("walk", 2, 0, 70),
+            ("visit", 3, 10, 70),
+        ]
+    );
+}
+
+#[test]
+fn flamegraph_layout_empty_input() {
+    let mut options = flamegraph::Options::default();
+    assert!(flamegraph::layout(&mut options, "".lines()).is_err());
+}
+
+#[test]
+fn flamegraph_focus() {
+    let input_file = "./tests/data/flamegraph/focus/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/focus/parse.svg";
+    let mut options = flamegraph::Options::default();
+    options.focus = Some("parse".to_string());
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_focus_regex_inverted_text() {
+    let input_file = "./tests/data/flamegraph/focus/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/focus/alloc-inverted.txt";
+    let mut options = flamegraph::Options::default();
+    options.focus = Some("^al+oc$".to_string());
+    options.format = OutputFormat::Text;
+    options.direction = Direction::Inverted;
+    options.title = "Icicle Graph".to_string();
+    options.image_width = Some(80);
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_focus_d3() {
+    let input_file = "./tests/data/flamegraph/focus/stacks.txt";
+    let expected_result_file = "./tests/data/flamegraph/focus/parse.d3.json";
+    let mut options = flamegraph::Options::default();
+    options.focus = Some("parse".to_string());
+    options.format = OutputFormat::D3;
+    test_flamegraph(input_file, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn flamegraph_focus_html() {
+    let input_file = "./tests/data/flamegraph/focus/stacks.txt";
+    let mut options = flamegraph::Options::default();
+    options.focus = Some("render".to_string());
+    options.format = OutputFormat::Html;
+    let mut result = Vec::new();
+    flamegraph::from_files(&mut options, &[input_file.into()], &mut result).unwrap();
+    let result = String::from_utf8(result).unwrap();
+    assert!(result.contains("\"focus_depth\":2,"));
+}
+
+#[test]
+fn flamegraph_focus_no_match() {
+    let input_file = "./tests/data/flamegraph/focus/stacks.txt";
+    let mut options = flamegraph::Options::default();
+    options.focus = Some("missing".to_string());
+    let mut result = Vec::new();
+    assert!(flamegraph::from_files(&mut options, &[input_file.into()], &mut result).is_err());
+}
+
+#[test]
+fn flamegraph_focus_root_function() {
+    // The focused function has no callers, so it is the root of the graph.
+    let input = "main;foo 3\nmain;bar 2\n";
+    let mut options = flamegraph::Options::default();
+    options.focus = Some("main".to_string());
+    let frames = flamegraph::layout(&mut options, input.lines()).unwrap();
+    let frames: Vec<_> = frames
+        .iter()
+        .map(|frame| (frame.name.as_str(), frame.depth, frame.start, frame.end))
+        .collect();
+    assert_eq!(
+        frames,
+        vec![("main", 0, 0, 5), ("bar", 1, 0, 2), ("foo", 1, 2, 5)]
+    );
+}
+
+#[test]
+fn flamegraph_focus_cli() {
+    let input_file = "./tests/data/flamegraph/focus/stacks.txt";
+    let expected_file = "./tests/data/flamegraph/focus/alloc-inverted.txt";
+    let output = Command::cargo_bin("inferno-flamegraph")
+        .unwrap()
+        .arg("--format")
+        .arg("text")
+        .arg("--color")
+        .arg("always")
+        .arg("--inverted")
+        .arg("--width")
+        .arg("80")
+        .arg("--focus")
+        .arg("^al+oc$")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    compare_results(Cursor::new(output.stdout), expected, expected_file);
+}
+
+#[test]
+fn flamegraph_layout_significance() {
+    let input = "main;a 1000 1000\nmain;b 2 5\nmain;c 100 300\n";
+    let mut options = flamegraph::Options::default();
+    options.significance = Some(0.05);
+    let frames = flamegraph::layout(&mut options, input.lines()).unwrap();
+    let frame = |name| frames.iter().find(|frame| frame.name == name).unwrap();
+
+    let unchanged = frame("a").color;
+    assert_eq!(frame("a").delta, Some(0));
+    assert!(frame("a").p_value.unwrap() < 0.05);
+
+    // Three more samples out of seven is well within noise.
+    assert_eq!(frame("b").delta, Some(3));
+    assert!(frame("b").p_value.unwrap() > 0.5);
+    assert_eq!(frame("b").color, unchanged);
+    assert_eq!(frame("b").info, "b (5 samples, 0.38%; +0.23%, p = 0.593)");
+
+    assert_eq!(frame("c").delta, Some(200));
+    assert_ne!(frame("c").color, unchanged);
+    assert_eq!(
+        frame("c").info,
+        "c (300 samples, 22.99%; +15.33%, p < 0.001)"
+    );
+
+    // Without a threshold, every change is colored and no p-values are shown.
+    let mut options = flamegraph::Options::default();
+    let frames = flamegraph::layout(&mut options, input.lines()).unwrap();
+    let b = frames.iter().find(|frame| frame.name == "b").unwrap();
+    assert_ne!(b.color, unchanged);
+    assert_eq!(b.info, "b (5 samples, 0.38%; +0.23%)");
+}
+
+#[test]
+fn flamegraph_layout_significance_differential_colors() {
+    // `x` has as many self samples in both profiles, but three times the samples with callees.
+    let input = "main;x 100 100\nmain;x;y 0 200\nmain;z 300 100\n";
+    let x = |differential_colors| {
+        let mut options = flamegraph::Options::default();
+        options.differential_colors = differential_colors;
+        options.significance = Some(0.05);
+        let frames = flamegraph::layout(&mut options, input.lines()).unwrap();
+        let x = frames.into_iter().find(|frame| frame.name == "x").unwrap();
+        (x.delta, x.p_value.unwrap(), x.info)
+    };
+
+    // The threshold applies to the change in self samples...
+    let (delta, p_value, info) = x(DifferentialColors::SelfDelta);
+    assert_eq!(delta, Some(0));
+    assert!(p_value > 0.5, "{}", p_value);
+    assert_eq!(info, "x (300 samples, 75.00%; 0.00%)");
+
+    // ...or to the change in samples including callees.
+    let (delta, p_value, info) = x(DifferentialColors::Absolute);
+    assert_eq!(delta, Some(200));
+    assert!(p_value < 0.001, "{}", p_value);
+    assert_eq!(info, "x (300 samples, 75.00%; +50.00% total, p < 0.001)");
+    let (delta, ratio_p_value, info) = x(DifferentialColors::Ratio);
+    assert_eq!(delta, Some(200));
+    assert_eq!(ratio_p_value, p_value);
+    assert_eq!(info, "x (300 samples, 75.00%; 3.00x, p < 0.001)");
+}
+
+#[test]
+fn flamegraph_layout_differential_colors() {
+    let input = "main;a 10 10\nmain;a;b 10 30\nmain;c 20 10\nmain;d 0 5\n";
+    let layout = |differential_colors| {
+        let mut options = flamegraph::Options::default();
+        options.differential_colors = differential_colors;
+        flamegraph::layout(&mut options, input.lines())
+            .unwrap()
+            .into_iter()
+            .map(|frame| (frame.name, frame.delta, frame.info))
+            .collect::<Vec<_>>()
+    };
+
+    let frames = layout(DifferentialColors::SelfDelta);
+    assert_eq!(
+        frames[1..],
+        [
+            (
+                "main".to_string(),
+                Some(0),
+                "main (55 samples, 100.00%; 0.00%)".to_string()
+            ),
+            (
+                "a".to_string(),
+                Some(0),
+                "a (40 samples, 72.73%; 0.00%)".to_string()
+            ),
+            (
+                "c".to_string(),
+                Some(-10),
+                "c (10 samples, 18.18%; -18.18%)".to_string()
+            ),
+            (
+                "d".to_string(),
+                Some(5),
+                "d (5 samples, 9.09%; +9.09%)".to_string()
+            ),
+            (
+                "b".to_string(),
+                Some(20),
+                "b (30 samples, 54.55%; +36.36%)".to_string()
+            ),
+        ]
+    );
+
+    let frames = layout(DifferentialColors::Absolute);
+    assert_eq!(
+        frames[1..],
+        [
+            (
+                "main".to_string(),
+                Some(15),
+                "main (55 samples, 100.00%; +27.27% total)".to_string()
+            ),
+            (
+                "a".to_string(),
+                Some(20),
+                "a (40 samples, 72.73%; +36.36% total)".to_string()
+            ),
+            (
+                "c".to_string(),
+                Some(-10),
+                "c (10 samples, 18.18%; -18.18% total)".to_string()
+            ),
+            (
+                "d".to_string(),
+                Some(5),
+                "d (5 samples, 9.09%; +9.09% total)".to_string()
+            ),
+            (
+                "b".to_string(),
+                Some(20),
+                "b (30 samples, 54.55%; +36.36% total)".to_string()
+            ),
+        ]
+    );
+
+    let frames = layout(DifferentialColors::Ratio);
+    assert_eq!(
+        frames[1..],
+        [
+            (
+                "main".to_string(),
+                Some(15),
+                "main (55 samples, 100.00%; 1.38x)".to_string()
+            ),
+            (
+                "a".to_string(),
+                Some(20),
+                "a (40 samples, 72.73%; 2.00x)".to_string()
+            ),
+            (
+                "c".to_string(),
+                Some(-10),
+                "c (10 samples, 18.18%; 0.50x)".to_string()
+            ),
+            (
+                "d".to_string(),
+                Some(5),
+                "d (5 samples, 9.09%; new)".to_string()
+            ),
+            (
+                "b".to_string(),
+                Some(20),
+                "b (30 samples, 54.55%; 3.00x)".to_string()
+            ),
+        ]
+    );
+
+    let mut options = flamegraph::Options::default();
+    options.differential_colors = DifferentialColors::Ratio;
+    let frames = flamegraph::layout(&mut options, input.lines()).unwrap();
+    let color = |name| {
+        let color = frames
+            .iter()
+            .find(|frame| frame.name == name)
+            .unwrap()
+            .color;
+        (color.r, color.g, color.b)
+    };
+    assert_eq!(color("a"), (255, 175, 175));
+    assert_eq!(color("c"), (175, 175, 255));
+    assert_eq!(color("d"), (255, 100, 100));
+}
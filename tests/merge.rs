@@ -0,0 +1,173 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Cursor};
+use std::process::Command;
+
+use assert_cmd::cargo::CommandCargoExt;
+use inferno::merge::{self, Input, Options};
+use pretty_assertions::assert_eq;
+
+fn test_merge(
+    inputs: &[Input<&str>],
+    expected_result_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    let metadata = match fs::metadata(expected_result_file) {
+        Ok(m) => m,
+        Err(e) => {
+            if e.kind() == io::ErrorKind::NotFound {
+                // be nice to the dev and make the file
+                let mut f = File::create(expected_result_file).unwrap();
+                merge::from_files(options, inputs, &mut f)?;
+                fs::metadata(expected_result_file).unwrap()
+            } else {
+                return Err(e);
+            }
+        }
+    };
+
+    let expected_len = metadata.len() as usize;
+    let mut result = Cursor::new(Vec::with_capacity(expected_len));
+    merge::from_files(options, inputs, &mut result)?;
+    let expected = BufReader::new(File::open(expected_result_file).unwrap());
+    result.set_position(0);
+    compare_results(result, expected, expected_result_file);
+    Ok(())
+}
+
+fn compare_results<R, E>(result: R, expected: E, expected_file: &str)
+where
+    R: BufRead,
+    E: BufRead,
+{
+    let result_lines: Vec<String> = result.lines().collect::<Result<_, _>>().unwrap();
+    let expected_lines: Vec<String> = expected.lines().collect::<Result<_, _>>().unwrap();
+
+    assert_eq!(
+        result_lines.len(),
+        expected_lines.len(),
+        "\nresult has {} lines, expected {} lines",
+        result_lines.len(),
+        expected_lines.len()
+    );
+
+    // The output is sorted, so the lines are compared in order.
+    for (line_num, (result_line, expected_line)) in
+        result_lines.into_iter().zip(expected_lines).enumerate()
+    {
+        assert_eq!(
+            result_line, expected_line,
+            "\n{}:{}",
+            expected_file, line_num
+        );
+    }
+}
+
+#[test]
+fn merge_default() {
+    let inputs = [
+        Input::new("./tests/data/diff-folded/before.txt"),
+        Input::new("./tests/data/diff-folded/after.txt"),
+    ];
+    let expected_result_file = "./tests/data/merge/results/default.txt";
+
+    test_merge(&inputs, expected_result_file, Default::default()).unwrap();
+}
+
+#[test]
+#[cfg(feature = "compression")]
+fn merge_compressed_input() {
+    let inputs = [
+        Input::new("./tests/data/diff-folded/before.txt.gz"),
+        Input::new("./tests/data/diff-folded/after.txt.xz"),
+    ];
+    let expected_result_file = "./tests/data/merge/results/default.txt";
+
+    test_merge(&inputs, expected_result_file, Default::default()).unwrap();
+}
+
+#[test]
+fn merge_weights_and_labels() {
+    let mut before = Input::new("./tests/data/diff-folded/before.txt");
+    before.weight = 2.5;
+    before.label = Some("before".to_string());
+    let mut after = Input::new("./tests/data/diff-folded/after.txt");
+    after.label = Some("after".to_string());
+    let expected_result_file = "./tests/data/merge/results/weights_and_labels.txt";
+
+    test_merge(&[before, after], expected_result_file, Default::default()).unwrap();
+}
+
+#[test]
+fn merge_normalize_strip_hex() {
+    let inputs = [
+        Input::new("./tests/data/diff-folded/before.txt"),
+        Input::new("./tests/data/diff-folded/after.txt"),
+    ];
+    let expected_result_file = "./tests/data/merge/results/normalize_strip_hex.txt";
+
+    let opt = Options {
+        normalize: true,
+        strip_hex: true,
+    };
+    test_merge(&inputs, expected_result_file, opt).unwrap();
+}
+
+#[test]
+fn merge_cli() {
+    let expected_file = "./tests/data/merge/results/weights_and_labels.txt";
+
+    let output = Command::cargo_bin("inferno-merge")
+        .unwrap()
+        .arg("--label")
+        .args(["--weight", "2.5", "--weight", "1"])
+        .arg("./tests/data/diff-folded/before.txt")
+        .arg("./tests/data/diff-folded/after.txt")
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    compare_results(Cursor::new(output.stdout), expected, expected_file);
+}
+
+#[test]
+fn merge_stdin_given_twice() {
+    let inputs = [Input::new("-"), Input::new("-")];
+    let error = merge::from_files(Options::default(), &inputs, io::sink()).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(error.to_string(), "STDIN (-) can only be merged once");
+}
+
+#[test]
+#[cfg(feature = "compression")]
+fn merge_cli_compressed_stdin() {
+    let expected_file = "./tests/data/merge/results/default.txt";
+
+    let output = Command::cargo_bin("inferno-merge")
+        .unwrap()
+        .arg("./tests/data/diff-folded/before.txt")
+        .arg("-")
+        .stdin(File::open("./tests/data/diff-folded/after.txt.xz").unwrap())
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    compare_results(Cursor::new(output.stdout), expected, expected_file);
+}
+
+#[test]
+fn merge_cli_wrong_number_of_weights() {
+    let output = Command::cargo_bin("inferno-merge")
+        .unwrap()
+        .args(["--weight", "2"])
+        .arg("./tests/data/diff-folded/before.txt")
+        .arg("./tests/data/diff-folded/after.txt")
+        .output()
+        .expect("failed to execute process");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("got 1 --weight values for 2 inputs"),
+        "{}",
+        stderr
+    );
+}
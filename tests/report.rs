@@ -0,0 +1,111 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Cursor};
+use std::path::PathBuf;
+use std::process::Command;
+
+use assert_cmd::cargo::CommandCargoExt;
+use inferno::report::{self, Format, Options, SortBy};
+use pretty_assertions::assert_eq;
+
+fn test_report(infile: &str, expected_result_file: &str, options: Options) -> io::Result<()> {
+    let infiles = vec![PathBuf::from(infile)];
+    if let Err(e) = fs::metadata(expected_result_file) {
+        if e.kind() == io::ErrorKind::NotFound {
+            // be nice to the dev and make the file
+            let f = File::create(expected_result_file).unwrap();
+            report::from_files(&options, &infiles, f)?;
+        } else {
+            return Err(e);
+        }
+    }
+
+    let mut result = Cursor::new(Vec::new());
+    report::from_files(&options, &infiles, &mut result)?;
+    result.set_position(0);
+    let expected = BufReader::new(File::open(expected_result_file).unwrap());
+    compare_results(result, expected, expected_result_file);
+    Ok(())
+}
+
+fn compare_results<R, E>(result: R, expected: E, expected_file: &str)
+where
+    R: BufRead,
+    E: BufRead,
+{
+    let result_lines: Vec<String> = result.lines().collect::<Result<_, _>>().unwrap();
+    let expected_lines: Vec<String> = expected.lines().collect::<Result<_, _>>().unwrap();
+    assert_eq!(
+        result_lines, expected_lines,
+        "report output does not match {}",
+        expected_file
+    );
+}
+
+#[test]
+fn report_text() {
+    let infile = "./tests/data/report/recursive.txt";
+    let expected_result_file = "./tests/data/report/results/recursive-text.txt";
+    test_report(infile, expected_result_file, Options::default()).unwrap();
+}
+
+#[test]
+fn report_text_callers_and_callees() {
+    let infile = "./tests/data/report/recursive.txt";
+    let expected_result_file = "./tests/data/report/results/recursive-text-calls.txt";
+    let mut options = Options::default();
+    options.sort_by = SortBy::TotalSamples;
+    options.callers = true;
+    options.callees = true;
+    test_report(infile, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn report_csv_top() {
+    let infile = "./tests/data/report/recursive.txt";
+    let expected_result_file = "./tests/data/report/results/recursive-top-3.csv";
+    let mut options = Options::default();
+    options.format = Format::Csv;
+    options.top = Some(3);
+    options.callers = true;
+    test_report(infile, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn report_json() {
+    let infile = "./tests/data/report/recursive.txt";
+    let expected_result_file = "./tests/data/report/results/recursive.json";
+    let mut options = Options::default();
+    options.format = Format::Json;
+    test_report(infile, expected_result_file, options).unwrap();
+}
+
+#[test]
+fn report_empty_input() {
+    let mut result = Cursor::new(Vec::new());
+    let mut options = Options::default();
+    options.format = Format::Json;
+    report::from_reader(&options, "".as_bytes(), &mut result).unwrap();
+    assert_eq!(
+        String::from_utf8(result.into_inner()).unwrap(),
+        "{\"total_samples\":0,\"functions\":[]}\n"
+    );
+}
+
+#[test]
+fn report_cli() {
+    let infile = "./tests/data/report/recursive.txt";
+    let expected_file = "./tests/data/report/results/recursive-top-3.csv";
+
+    let output = Command::cargo_bin("inferno-report")
+        .unwrap()
+        .arg("--format")
+        .arg("csv")
+        .arg("--top")
+        .arg("3")
+        .arg("--callers")
+        .arg(infile)
+        .output()
+        .expect("failed to execute process");
+    let expected = BufReader::new(File::open(expected_file).unwrap());
+    compare_results(Cursor::new(output.stdout), expected, expected_file);
+}